@@ -0,0 +1,8 @@
+alter table users drop column calendar_token;
+
+drop trigger set_updated_at on todos;
+drop index todos_user_id_ical_uid;
+alter table todos drop column completed_at;
+alter table todos drop column updated_at;
+alter table todos drop column created_at;
+alter table todos drop column ical_uid;
@@ -0,0 +1,8 @@
+alter table todos add column ical_uid varchar not null default gen_random_uuid()::text;
+alter table todos add column created_at timestamp not null default now();
+alter table todos add column updated_at timestamp not null default now();
+alter table todos add column completed_at timestamp;
+create unique index todos_user_id_ical_uid on todos (user_id, ical_uid);
+select diesel_manage_updated_at('todos');
+
+alter table users add column calendar_token varchar unique;
@@ -0,0 +1 @@
+alter table todos add column priority varchar check (priority ~ '^[A-Z]$');
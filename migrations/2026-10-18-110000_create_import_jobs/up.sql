@@ -0,0 +1,13 @@
+create table import_jobs (
+    id serial primary key,
+    user_id integer not null references users (id) on delete cascade,
+    provider varchar not null,
+    status varchar not null default 'pending'
+        check (status in ('pending', 'running', 'succeeded', 'failed')),
+    created_count integer not null default 0,
+    error text,
+    created_at timestamp not null default now(),
+    finished_at timestamp
+);
+
+create index import_jobs_user_id on import_jobs (user_id);
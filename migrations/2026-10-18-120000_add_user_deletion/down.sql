@@ -0,0 +1,3 @@
+alter table users drop column deleted_at;
+
+alter table todos drop constraint todos_user_id_fkey;
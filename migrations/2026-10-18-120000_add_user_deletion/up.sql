@@ -0,0 +1,10 @@
+-- The initial migration never added this foreign key, so clear out todos
+-- whose user is already gone before adding it.
+delete from todos where user_id not in (select id from users);
+
+alter table todos
+    add constraint todos_user_id_fkey
+    foreign key (user_id) references users (id) on delete cascade;
+
+-- Set while an account waits out its deletion grace period.
+alter table users add column deleted_at timestamp;
@@ -0,0 +1,4 @@
+drop table password_reset_tokens;
+
+alter table users drop column password_changed_at;
+alter table users drop column email;
@@ -0,0 +1,14 @@
+-- Where password reset links are sent.
+alter table users add column email varchar unique;
+-- Tokens issued before this are no longer accepted.
+alter table users add column password_changed_at timestamp;
+
+create table password_reset_tokens (
+    id serial primary key,
+    user_id integer not null references users (id) on delete cascade,
+    -- Only a SHA-256 hash is kept, so a leaked table can't reset passwords.
+    token_hash varchar not null unique,
+    created_at timestamp not null default now(),
+    expires_at timestamp not null,
+    used_at timestamp
+);
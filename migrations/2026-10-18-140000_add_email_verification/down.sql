@@ -0,0 +1,3 @@
+drop table email_verification_tokens;
+
+alter table users drop column email_verified_at;
@@ -0,0 +1,12 @@
+alter table users add column email_verified_at timestamp;
+
+create table email_verification_tokens (
+    id serial primary key,
+    user_id integer not null references users (id) on delete cascade,
+    token_hash varchar not null unique,
+    -- The address the token was sent to; it only verifies that one.
+    email varchar not null,
+    created_at timestamp not null default now(),
+    expires_at timestamp not null,
+    used_at timestamp
+);
@@ -0,0 +1,14 @@
+-- Set when enrolling; only enforced once `totp_enabled_at` is set too.
+alter table users add column totp_secret varchar;
+alter table users add column totp_enabled_at timestamp;
+-- The last time step a code was accepted for, so that no code works twice.
+alter table users add column totp_last_step bigint;
+
+create table totp_recovery_codes (
+    id serial primary key,
+    user_id integer not null references users (id) on delete cascade,
+    code_hash varchar not null,
+    used_at timestamp
+);
+
+create index totp_recovery_codes_user_id on totp_recovery_codes (user_id);
@@ -0,0 +1,10 @@
+-- Failed logins, counted per `username` and per client `ip`. Unknown
+-- usernames are counted too, so that lockouts don't reveal which exist.
+create table login_throttles (
+    scope varchar not null,
+    subject varchar not null,
+    failures integer not null default 0,
+    last_failed_at timestamp not null default now(),
+    locked_until timestamp,
+    primary key (scope, subject)
+);
@@ -0,0 +1,3 @@
+drop table oauth_tokens;
+drop table oauth_authorization_codes;
+drop table oauth_clients;
@@ -0,0 +1,42 @@
+-- Third-party apps, registered by one of our users.
+create table oauth_clients (
+    id serial primary key,
+    client_id varchar not null unique,
+    -- Null for public clients (native and browser apps), which rely on PKCE
+    -- alone.
+    client_secret_hash varchar,
+    name varchar not null,
+    redirect_uris text[] not null,
+    user_id integer not null references users (id) on delete cascade,
+    created_at timestamp not null default now()
+);
+
+create table oauth_authorization_codes (
+    id serial primary key,
+    code_hash varchar not null unique,
+    oauth_client_id integer not null references oauth_clients (id) on delete cascade,
+    user_id integer not null references users (id) on delete cascade,
+    redirect_uri varchar not null,
+    scope varchar not null,
+    code_challenge varchar not null,
+    expires_at timestamp not null,
+    used_at timestamp
+);
+
+-- An access token and the refresh token that replaces it. Refreshing
+-- revokes the row and adds a new one.
+create table oauth_tokens (
+    id serial primary key,
+    oauth_client_id integer not null references oauth_clients (id) on delete cascade,
+    user_id integer not null references users (id) on delete cascade,
+    authorization_code_id integer references oauth_authorization_codes (id) on delete set null,
+    access_token_hash varchar not null unique,
+    refresh_token_hash varchar not null unique,
+    scope varchar not null,
+    access_expires_at timestamp not null,
+    refresh_expires_at timestamp not null,
+    created_at timestamp not null default now(),
+    revoked_at timestamp
+);
+
+create index oauth_tokens_authorization_code_id on oauth_tokens (authorization_code_id);
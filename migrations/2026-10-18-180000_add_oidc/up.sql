@@ -0,0 +1,18 @@
+-- An account at an external OpenID Connect provider, by its issuer and
+-- the subject the provider knows the user as.
+create table oidc_identities (
+    id serial primary key,
+    user_id integer not null references users (id) on delete cascade,
+    issuer varchar not null,
+    subject varchar not null,
+    created_at timestamp not null default now(),
+    unique (issuer, subject)
+);
+
+-- Logins that were sent to the provider and haven't come back yet.
+create table oidc_login_states (
+    state_hash varchar primary key,
+    nonce varchar not null,
+    code_verifier varchar not null,
+    expires_at timestamp not null
+);
@@ -0,0 +1,6 @@
+alter table users
+    drop column password_reset_required,
+    drop column sessions_revoked_at,
+    drop column disabled_at,
+    drop column role;
+drop table roles;
@@ -0,0 +1,24 @@
+-- Roles grant permissions (see src/roles.rs). `user` and `admin` can't be
+-- deleted.
+create table roles (
+    name varchar primary key,
+    description text not null default '',
+    permissions text[] not null default '{}',
+    built_in boolean not null default false,
+    created_at timestamp not null default now()
+);
+
+insert into roles (name, description, permissions, built_in) values
+    ('user', 'Uses their own account.', '{}', true),
+    ('admin', 'Manages users and roles.', '{users:read,users:manage,roles:manage}', true);
+
+alter table users
+    add column role varchar not null default 'user' references roles (name),
+    -- Set by an administrator; the account can't be used until it is cleared.
+    add column disabled_at timestamp,
+    -- Tokens issued before this are revoked, like after a password change.
+    add column sessions_revoked_at timestamp,
+    -- The password can't be used to log in until it is reset.
+    add column password_reset_required boolean not null default false;
+
+create index users_role_idx on users (role);
@@ -0,0 +1,9 @@
+alter table import_jobs drop column workspace_id;
+delete from todos where workspace_id is not null;
+drop index todos_workspace_id_ical_uid;
+drop index todos_user_id_ical_uid;
+alter table todos drop column workspace_id;
+create unique index todos_user_id_ical_uid on todos (user_id, ical_uid);
+drop table workspace_invitations;
+drop table workspace_members;
+drop table workspaces;
@@ -0,0 +1,41 @@
+-- Workspaces share their todos between their members. Todos without a
+-- workspace belong to their user alone.
+create table workspaces (
+    id serial primary key,
+    name varchar not null,
+    -- Selects the workspace in the X-Workspace header, like its id.
+    slug varchar not null unique,
+    created_at timestamp not null default now()
+);
+
+create table workspace_members (
+    workspace_id integer not null references workspaces (id) on delete cascade,
+    user_id integer not null references users (id) on delete cascade,
+    role varchar not null check (role in ('owner', 'admin', 'member')),
+    joined_at timestamp not null default now(),
+    primary key (workspace_id, user_id)
+);
+
+create index workspace_members_user_id on workspace_members (user_id);
+
+create table workspace_invitations (
+    id serial primary key,
+    workspace_id integer not null references workspaces (id) on delete cascade,
+    token_hash varchar not null unique,
+    email varchar not null,
+    role varchar not null check (role in ('admin', 'member')),
+    invited_by integer not null references users (id) on delete cascade,
+    created_at timestamp not null default now(),
+    expires_at timestamp not null,
+    accepted_at timestamp
+);
+
+alter table todos add column workspace_id integer references workspaces (id) on delete cascade;
+drop index todos_user_id_ical_uid;
+create unique index todos_user_id_ical_uid on todos (user_id, ical_uid)
+    where workspace_id is null;
+create unique index todos_workspace_id_ical_uid on todos (workspace_id, ical_uid)
+    where workspace_id is not null;
+
+alter table import_jobs
+    add column workspace_id integer references workspaces (id) on delete cascade;
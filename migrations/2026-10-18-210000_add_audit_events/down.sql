@@ -0,0 +1,3 @@
+update roles set permissions = array_remove(permissions, 'audit:read');
+drop table audit_events;
+drop function audit_events_append_only();
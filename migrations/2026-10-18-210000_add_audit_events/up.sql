@@ -0,0 +1,31 @@
+-- The security log. Rows are only ever inserted; the trigger refuses
+-- anything else.
+create table audit_events (
+    id bigserial primary key,
+    occurred_at timestamp not null default now(),
+    event varchar not null,
+    -- Not foreign keys: events outlive the accounts they are about.
+    user_id integer,
+    -- The account's name at the time, or the login that was tried.
+    username varchar,
+    -- The administrator who did it, for events about someone else.
+    actor_id integer,
+    ip varchar,
+    user_agent varchar,
+    details varchar
+);
+
+create index audit_events_user_id_idx on audit_events (user_id, occurred_at);
+create index audit_events_occurred_at_idx on audit_events (occurred_at);
+
+create function audit_events_append_only() returns trigger as $$
+begin
+    raise exception 'audit_events is append-only';
+end;
+$$ language plpgsql;
+
+create trigger audit_events_append_only
+    before update or delete or truncate on audit_events
+    for each statement execute procedure audit_events_append_only();
+
+update roles set permissions = array_append(permissions, 'audit:read') where name = 'admin';
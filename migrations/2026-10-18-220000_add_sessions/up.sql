@@ -0,0 +1,18 @@
+-- One row per login. Tokens carry the id of their session as `sid`, and stop
+-- working once it is revoked.
+create table sessions (
+    id serial primary key,
+    user_id integer not null references users (id) on delete cascade,
+    created_at timestamp not null default now(),
+    -- Updated at most once a minute.
+    last_seen_at timestamp not null default now(),
+    -- When the token handed out at login expires.
+    expires_at timestamp not null,
+    ip varchar,
+    user_agent varchar,
+    -- What the client called itself in the X-Client-Name header.
+    client_name varchar,
+    revoked_at timestamp
+);
+
+create index sessions_user_id_idx on sessions (user_id);
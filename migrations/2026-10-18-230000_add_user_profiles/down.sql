@@ -0,0 +1,7 @@
+alter table users
+    drop column display_name,
+    drop column avatar_url,
+    drop column time_zone,
+    drop column locale,
+    drop column default_sort,
+    drop column week_start;
@@ -0,0 +1,11 @@
+alter table users
+    add column display_name varchar,
+    add column avatar_url varchar,
+    -- An IANA time zone such as Europe/Berlin.
+    add column time_zone varchar,
+    -- A BCP 47 language tag such as en-GB.
+    add column locale varchar,
+    add column default_sort varchar not null default 'created'
+        check (default_sort in ('created', 'updated', 'priority', 'text')),
+    add column week_start varchar not null default 'monday'
+        check (week_start in ('monday', 'sunday', 'saturday'));
@@ -0,0 +1,4 @@
+-- The hashes can't be turned back into tokens, so every feed URL has to be
+-- created again.
+update users set calendar_token_hash = null;
+alter table users rename column calendar_token_hash to calendar_token;
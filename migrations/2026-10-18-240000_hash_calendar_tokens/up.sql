@@ -0,0 +1,5 @@
+-- Existing feed URLs keep working: their tokens are hashed in place.
+alter table users rename column calendar_token to calendar_token_hash;
+update users
+set calendar_token_hash = encode(sha256(convert_to(calendar_token_hash, 'UTF8')), 'hex')
+where calendar_token_hash is not null;
@@ -0,0 +1,19 @@
+-- The todo.txt `+project` and `@context` tags of each todo's text, kept in
+-- step with the text by every write.
+alter table todos
+    add column projects text[] not null default '{}',
+    add column contexts text[] not null default '{}';
+
+update todos set
+    projects = array(
+        select substr(word, 2)
+        from regexp_split_to_table(text, '\s+') with ordinality as words(word, n)
+        where word like '+_%'
+        order by n
+    ),
+    contexts = array(
+        select substr(word, 2)
+        from regexp_split_to_table(text, '\s+') with ordinality as words(word, n)
+        where word like '@_%'
+        order by n
+    );
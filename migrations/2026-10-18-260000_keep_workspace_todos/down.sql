@@ -0,0 +1,10 @@
+alter table todos drop constraint todos_has_owner;
+
+delete from todos where user_id is null;
+
+alter table todos drop constraint todos_user_id_fkey;
+alter table todos
+    add constraint todos_user_id_fkey
+    foreign key (user_id) references users (id) on delete cascade;
+
+alter table todos alter column user_id set not null;
@@ -0,0 +1,13 @@
+-- Workspace todos belong to the workspace, so they outlive the member who
+-- created them. Personal todos are deleted along with their user before the
+-- user is, which the check makes sure of.
+alter table todos alter column user_id drop not null;
+
+alter table todos drop constraint todos_user_id_fkey;
+alter table todos
+    add constraint todos_user_id_fkey
+    foreign key (user_id) references users (id) on delete set null;
+
+alter table todos
+    add constraint todos_has_owner
+    check (user_id is not null or workspace_id is not null);
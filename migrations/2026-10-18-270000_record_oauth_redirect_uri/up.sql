@@ -0,0 +1,6 @@
+-- Whether the authorization request named its redirect URI, in which case
+-- the token request has to repeat it (RFC 6749 section 4.1.3). Codes issued
+-- before this are treated as if it had been named.
+alter table oauth_authorization_codes
+    add column redirect_uri_sent boolean not null default true;
+alter table oauth_authorization_codes alter column redirect_uri_sent drop default;
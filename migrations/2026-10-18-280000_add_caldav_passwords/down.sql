@@ -0,0 +1 @@
+alter table users drop column caldav_password_hash;
@@ -0,0 +1,4 @@
+-- CalDAV clients log in with an app password of their own rather than the
+-- calendar feed token, which only grants read access. Only its hash is
+-- stored.
+alter table users add column caldav_password_hash varchar;
@@ -0,0 +1,3 @@
+-- Usernames are unique regardless of case, so that `Alice` and `alice`
+-- can't be two accounts.
+create unique index users_username_lower on users (lower(username));
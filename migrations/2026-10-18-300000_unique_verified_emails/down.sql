@@ -0,0 +1,2 @@
+drop index users_verified_email;
+alter table users add constraint users_email_key unique (email);
@@ -0,0 +1,5 @@
+-- An address belongs to the account that verifies it. Until then, any
+-- number of accounts may claim it, so that registering with someone else's
+-- address doesn't keep its owner from using it.
+alter table users drop constraint users_email_key;
+create unique index users_verified_email on users (email) where email_verified_at is not null;
@@ -0,0 +1,12 @@
+-- Password reset requests of the last hour, counted per account and per
+-- client address, so that nobody can flood an inbox with reset emails.
+-- Requests for unknown logins have no `user_id`.
+create table password_reset_requests (
+    id serial primary key,
+    user_id integer references users (id) on delete cascade,
+    ip varchar,
+    requested_at timestamp not null default now()
+);
+
+create index password_reset_requests_user_id on password_reset_requests (user_id, requested_at);
+create index password_reset_requests_ip on password_reset_requests (ip, requested_at);
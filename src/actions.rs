@@ -1,16 +1,36 @@
-use crate::{auth, error::TodosError, models, schema};
+use std::sync::OnceLock;
 
-use diesel::{ExpressionMethods, PgConnection, QueryDsl, RunQueryDsl};
+use crate::{
+    archive, audit, auth, csv_io, error::TodosError, ical, importers, mail, markdown, models,
+    oauth, oidc, password, roles, schema, todotxt, totp,
+};
 
-use argon2::{
-    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
-    Argon2,
+use chrono::NaiveDateTime;
+use diesel::{
+    BoolExpressionMethods, Connection, ExpressionMethods, NullableExpressionMethods,
+    OptionalExtension, PgConnection, PgTextExpressionMethods, QueryDsl, QueryResult, RunQueryDsl,
 };
 
-pub fn get_all_todos(uid: i32, conn: &PgConnection) -> Result<Vec<models::Todo>, TodosError> {
+/// The tenant's todos: the user's personal ones, or all of a workspace's.
+/// Every query for todos starts here.
+fn todos_of(tenant: models::Tenant) -> schema::todos::BoxedQuery<'static, diesel::pg::Pg> {
     use schema::todos::dsl::*;
-    let todos_list = todos
-        .filter(user_id.eq(uid))
+    match tenant {
+        models::Tenant::Personal { user_id: uid } => todos
+            .filter(user_id.eq(uid))
+            .filter(workspace_id.is_null())
+            .into_boxed(),
+        models::Tenant::Workspace {
+            workspace_id: wid, ..
+        } => todos.filter(workspace_id.eq(wid)).into_boxed(),
+    }
+}
+
+pub fn get_all_todos(
+    tenant: models::Tenant,
+    conn: &PgConnection,
+) -> Result<Vec<models::Todo>, TodosError> {
+    let todos_list = todos_of(tenant)
         .load::<models::Todo>(conn)
         .map_err(|e| match e {
             diesel::result::Error::NotFound => TodosError::TodoNotFoundError,
@@ -19,16 +39,51 @@ pub fn get_all_todos(uid: i32, conn: &PgConnection) -> Result<Vec<models::Todo>,
     Ok(todos_list)
 }
 
+pub fn get_todo_of_tenant(
+    tenant: models::Tenant,
+    todo_id: i32,
+    conn: &PgConnection,
+) -> Result<models::Todo, TodosError> {
+    use schema::todos::dsl::*;
+    let todo = todos_of(tenant)
+        .filter(id.eq(todo_id))
+        .first::<models::Todo>(conn)
+        .map_err(|e| match e {
+            diesel::result::Error::NotFound => TodosError::TodoNotFoundError,
+            _ => TodosError::DieselCrudError,
+        })?;
+    Ok(todo)
+}
+
 pub fn create_new_todo(
-    uid: i32,
+    tenant: models::Tenant,
     data: models::NewTodoReq,
     conn: &PgConnection,
 ) -> Result<models::Todo, TodosError> {
     use schema::todos::dsl::*;
+    if let Some(p) = &data.priority {
+        if !todotxt::is_valid_priority(p) {
+            return Err(TodosError::InvalidPriority);
+        }
+    }
+    let new_completed_at = match data.done {
+        Some(true) => data
+            .completed_at
+            .or_else(|| Some(chrono::Utc::now().naive_utc())),
+        _ => None,
+    };
     let todo = diesel::insert_into(todos)
         .values(models::NewTodo {
+            projects: todotxt::projects(&data.text),
+            contexts: todotxt::contexts(&data.text),
             text: data.text,
-            user_id: uid,
+            user_id: tenant.user_id(),
+            workspace_id: tenant.workspace_id(),
+            ical_uid: None,
+            done: data.done,
+            created_at: data.created_at,
+            completed_at: new_completed_at,
+            priority: data.priority,
         })
         .get_result(conn)
         .map_err(|e| match e {
@@ -38,37 +93,308 @@ pub fn create_new_todo(
     Ok(todo)
 }
 
+/// Creates a todo per task, all or nothing. Dry runs only parse the file.
+pub fn import_todotxt(
+    tenant: models::Tenant,
+    input: &str,
+    dry_run: bool,
+    conn: &PgConnection,
+) -> Result<models::TodoTxtImport, TodosError> {
+    let tasks = todotxt::parse(input)?;
+    let created = if dry_run {
+        Vec::new()
+    } else {
+        create_new_todos(
+            tenant,
+            tasks.iter().cloned().map(todotxt::Task::into_new_todo),
+            conn,
+        )?
+    };
+    Ok(models::TodoTxtImport {
+        dry_run,
+        tasks,
+        created,
+    })
+}
+
+/// Creates all of the todos or, if one of them fails, none.
+fn create_new_todos(
+    tenant: models::Tenant,
+    new_todos: impl IntoIterator<Item = models::NewTodoReq>,
+    conn: &PgConnection,
+) -> Result<Vec<models::Todo>, TodosError> {
+    conn.transaction(|| {
+        new_todos
+            .into_iter()
+            .map(|new_todo| create_new_todo(tenant, new_todo, conn))
+            .collect()
+    })
+}
+
+/// Imports a CSV or Markdown file. Every line is checked before anything is
+/// written, so a file with a bad line creates nothing.
+pub fn import_file(
+    tenant: models::Tenant,
+    input: &str,
+    options: &models::FileImportOptions,
+    conn: &PgConnection,
+) -> Result<Vec<models::Todo>, TodosError> {
+    let new_todos = match options.format {
+        models::FileFormat::Csv => csv_io::read(input, options)?,
+        models::FileFormat::Markdown => markdown::read(input)?,
+    };
+    create_new_todos(tenant, new_todos, conn)
+}
+
+pub fn create_import_job(
+    tenant: models::Tenant,
+    provider: models::ImportProvider,
+    conn: &PgConnection,
+) -> Result<models::ImportJob, TodosError> {
+    use schema::import_jobs;
+    let job = diesel::insert_into(import_jobs::table)
+        .values(models::NewImportJob {
+            user_id: tenant.user_id(),
+            workspace_id: tenant.workspace_id(),
+            provider: provider.as_str(),
+        })
+        .get_result(conn)?;
+    Ok(job)
+}
+
+/// A job of the tenant's: workspace imports are seen by all members.
+pub fn get_import_job(
+    tenant: models::Tenant,
+    job_id: i32,
+    conn: &PgConnection,
+) -> Result<models::ImportJob, TodosError> {
+    use schema::import_jobs::dsl::*;
+    let query = import_jobs.filter(id.eq(job_id)).into_boxed();
+    let query = match tenant {
+        models::Tenant::Personal { user_id: uid } => {
+            query.filter(user_id.eq(uid)).filter(workspace_id.is_null())
+        }
+        models::Tenant::Workspace {
+            workspace_id: wid, ..
+        } => query.filter(workspace_id.eq(wid)),
+    };
+    query.first(conn).map_err(|e| match e {
+        diesel::result::Error::NotFound => TodosError::ImportJobNotFound,
+        _ => TodosError::DieselCrudError,
+    })
+}
+
+/// Parses an export and creates its todos, recording the outcome on the job.
+/// Returns the created todos; a failure is only stored on the job.
+pub fn run_import_job(
+    job: &models::ImportJob,
+    provider: models::ImportProvider,
+    input: &[u8],
+    conn: &PgConnection,
+) -> Result<Vec<models::Todo>, TodosError> {
+    use schema::import_jobs::dsl::{created_count, error, finished_at, status};
+    diesel::update(job)
+        .set(status.eq("running"))
+        .execute(conn)?;
+
+    let result = importers::parse(provider, input)
+        .and_then(|new_todos| create_new_todos(job.tenant(), new_todos, conn));
+    let now = chrono::Utc::now().naive_utc();
+    match &result {
+        Ok(created) => diesel::update(job)
+            .set((
+                status.eq("succeeded"),
+                created_count.eq(created.len() as i32),
+                finished_at.eq(now),
+            ))
+            .execute(conn)?,
+        Err(e) => diesel::update(job)
+            .set((
+                status.eq("failed"),
+                error.eq(e.to_string()),
+                finished_at.eq(now),
+            ))
+            .execute(conn)?,
+    };
+    result
+}
+
+/// Jobs don't survive a restart; marks the ones that were cut short as failed.
+pub fn fail_interrupted_import_jobs(conn: &PgConnection) -> Result<usize, TodosError> {
+    use schema::import_jobs::dsl::*;
+    let count = diesel::update(import_jobs.filter(status.eq_any(vec!["pending", "running"])))
+        .set((
+            status.eq("failed"),
+            error.eq("interrupted by a server restart"),
+            finished_at.eq(chrono::Utc::now().naive_utc()),
+        ))
+        .execute(conn)?;
+    Ok(count)
+}
+
+pub fn export_account(
+    uid: i32,
+    conn: &PgConnection,
+) -> Result<archive::AccountArchive, TodosError> {
+    let user: models::User = schema::users::table
+        .find(uid)
+        .first(conn)
+        .map_err(|e| match e {
+            diesel::result::Error::NotFound => TodosError::AccountNotFound,
+            _ => TodosError::DieselCrudError,
+        })?;
+    let todos = get_all_todos(models::Tenant::Personal { user_id: uid }, conn)?;
+    let import_jobs = schema::import_jobs::table
+        .filter(schema::import_jobs::user_id.eq(uid))
+        .filter(schema::import_jobs::workspace_id.is_null())
+        .order(schema::import_jobs::id)
+        .load(conn)?;
+    Ok(archive::AccountArchive {
+        format: archive::FORMAT.to_owned(),
+        version: archive::VERSION,
+        exported_at: chrono::Utc::now().naive_utc(),
+        profile: archive::ArchivedProfile {
+            id: user.id,
+            username: user.username,
+        },
+        todos: todos.into_iter().map(Into::into).collect(),
+        import_jobs,
+    })
+}
+
+/// Restores an archive's todos into an account or workspace, matching them
+/// to existing todos by UID. Ids are always assigned anew; the profile and
+/// import history are left as they are. Runs in one transaction.
+pub fn import_account(
+    tenant: models::Tenant,
+    input: &[u8],
+    strategy: models::ConflictStrategy,
+    conn: &PgConnection,
+) -> Result<models::AccountImportSummary, TodosError> {
+    use schema::todos::dsl::*;
+    let account_archive = archive::read(input)?;
+    let mut summary = models::AccountImportSummary {
+        created: Vec::new(),
+        overwritten: Vec::new(),
+        skipped: 0,
+        ids: Vec::new(),
+    };
+
+    conn.transaction::<_, TodosError, _>(|| {
+        for archived in account_archive.todos {
+            if let Some(p) = &archived.priority {
+                if !todotxt::is_valid_priority(p) {
+                    return Err(TodosError::InvalidPriority);
+                }
+            }
+            let existing = match get_todo_by_ical_uid(tenant, &archived.ical_uid, conn) {
+                Ok(todo) => Some(todo),
+                Err(TodosError::TodoNotFoundError) => None,
+                Err(e) => return Err(e),
+            };
+            let (todo, overwritten): (models::Todo, bool) = match (existing, strategy) {
+                (Some(_), models::ConflictStrategy::Skip) => {
+                    summary.skipped += 1;
+                    continue;
+                }
+                (Some(existing), models::ConflictStrategy::Overwrite) => (
+                    diesel::update(&existing)
+                        .set((
+                            projects.eq(todotxt::projects(&archived.text)),
+                            contexts.eq(todotxt::contexts(&archived.text)),
+                            text.eq(archived.text),
+                            done.eq(archived.done),
+                            created_at.eq(archived.created_at),
+                            completed_at.eq(archived.completed_at),
+                            priority.eq(archived.priority),
+                        ))
+                        .get_result(conn)
+                        .map_err(|e| match e {
+                            diesel::result::Error::NotFound => TodosError::TodoNotFoundError,
+                            _ => TodosError::DieselCrudError,
+                        })?,
+                    true,
+                ),
+                (existing, _) => (
+                    diesel::insert_into(todos)
+                        .values(models::NewTodo {
+                            projects: todotxt::projects(&archived.text),
+                            contexts: todotxt::contexts(&archived.text),
+                            text: archived.text,
+                            user_id: tenant.user_id(),
+                            workspace_id: tenant.workspace_id(),
+                            // A duplicate needs a UID of its own.
+                            ical_uid: existing.map_or(Some(archived.ical_uid), |_| None),
+                            done: Some(archived.done),
+                            created_at: Some(archived.created_at),
+                            completed_at: archived.completed_at,
+                            priority: archived.priority,
+                        })
+                        .get_result(conn)?,
+                    false,
+                ),
+            };
+            summary.ids.push(models::IdMapping {
+                archived: archived.id,
+                id: todo.id,
+            });
+            if overwritten {
+                summary.overwritten.push(todo);
+            } else {
+                summary.created.push(todo);
+            }
+        }
+        Ok(())
+    })?;
+    Ok(summary)
+}
+
+/// Keeps the original completion time of todos that stay done.
+fn completed_at_for(exisiting_todo: &models::Todo, new_done: bool) -> Option<NaiveDateTime> {
+    match (exisiting_todo.done, new_done) {
+        (true, true) => exisiting_todo.completed_at,
+        (false, true) => Some(chrono::Utc::now().naive_utc()),
+        (_, false) => None,
+    }
+}
+
 pub fn update_existing_todo(
     exisiting_todo: models::Todo,
     data: models::UpdateTodo,
     conn: &PgConnection,
 ) -> Result<models::Todo, TodosError> {
-    use schema::todos::dsl::*;
-    let todo: models::Todo;
     use models::UpdateTodo::*;
-    match data {
+    use schema::todos::dsl::*;
+    let todo: models::Todo = match data {
         Both {
             done: new_done,
             text: new_text,
-        } => {
-            todo = diesel::update(&exisiting_todo)
-                .set((text.eq(new_text), done.eq(new_done)))
-                .get_result(conn)
-                .map_err(|_| TodosError::DieselCrudError)?;
-        }
-        DoneOnly { done: new_done } => {
-            todo = diesel::update(&exisiting_todo)
-                .set(done.eq(new_done))
-                .get_result(conn)
-                .map_err(|_| TodosError::DieselCrudError)?;
-        }
-        TextOnly { text: new_text } => {
-            todo = diesel::update(&exisiting_todo)
-                .set(text.eq(new_text))
-                .get_result(conn)
-                .map_err(|_| TodosError::DieselCrudError)?;
-        }
-    }
+        } => diesel::update(&exisiting_todo)
+            .set((
+                projects.eq(todotxt::projects(&new_text)),
+                contexts.eq(todotxt::contexts(&new_text)),
+                text.eq(new_text),
+                done.eq(new_done),
+                completed_at.eq(completed_at_for(&exisiting_todo, new_done)),
+            ))
+            .get_result(conn)
+            .map_err(|_| TodosError::DieselCrudError)?,
+        DoneOnly { done: new_done } => diesel::update(&exisiting_todo)
+            .set((
+                done.eq(new_done),
+                completed_at.eq(completed_at_for(&exisiting_todo, new_done)),
+            ))
+            .get_result(conn)
+            .map_err(|_| TodosError::DieselCrudError)?,
+        TextOnly { text: new_text } => diesel::update(&exisiting_todo)
+            .set((
+                projects.eq(todotxt::projects(&new_text)),
+                contexts.eq(todotxt::contexts(&new_text)),
+                text.eq(new_text),
+            ))
+            .get_result(conn)
+            .map_err(|_| TodosError::DieselCrudError)?,
+    };
     Ok(todo)
 }
 
@@ -83,61 +409,3916 @@ pub fn delete_existing_todo(
     Ok(todo)
 }
 
+fn insert_vtodo(
+    tenant: models::Tenant,
+    vtodo: ical::VTodo,
+    conn: &PgConnection,
+) -> Result<models::Todo, diesel::result::Error> {
+    use schema::todos::dsl::*;
+    diesel::insert_into(todos)
+        .values(models::NewTodo {
+            projects: todotxt::projects(&vtodo.summary),
+            contexts: todotxt::contexts(&vtodo.summary),
+            text: vtodo.summary,
+            user_id: tenant.user_id(),
+            workspace_id: tenant.workspace_id(),
+            ical_uid: Some(vtodo.uid),
+            done: Some(vtodo.done),
+            created_at: None,
+            completed_at: vtodo.completed_at,
+            priority: None,
+        })
+        .get_result(conn)
+}
+
+pub fn get_todo_by_ical_uid(
+    tenant: models::Tenant,
+    resource_uid: &str,
+    conn: &PgConnection,
+) -> Result<models::Todo, TodosError> {
+    use schema::todos::dsl::*;
+    let todo = todos_of(tenant)
+        .filter(ical_uid.eq(resource_uid))
+        .first::<models::Todo>(conn)
+        .map_err(|e| match e {
+            diesel::result::Error::NotFound => TodosError::TodoNotFoundError,
+            _ => TodosError::DieselCrudError,
+        })?;
+    Ok(todo)
+}
+
+/// Creates the todo described by a `VTODO`, or updates the one with its UID.
+pub fn upsert_vtodo(
+    tenant: models::Tenant,
+    vtodo: ical::VTodo,
+    conn: &PgConnection,
+) -> Result<models::Todo, TodosError> {
+    match get_todo_by_ical_uid(tenant, &vtodo.uid, conn) {
+        Ok(exisiting_todo) => update_existing_todo(
+            exisiting_todo,
+            models::UpdateTodo::Both {
+                text: vtodo.summary,
+                done: vtodo.done,
+            },
+            conn,
+        ),
+        Err(TodosError::TodoNotFoundError) => {
+            insert_vtodo(tenant, vtodo, conn).map_err(|_| TodosError::DieselCrudError)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Creates a todo per `VTODO`, skipping those whose UID the user already has.
+/// Runs in one transaction, so a failed insert leaves no partial import.
+pub fn import_vtodos(
+    tenant: models::Tenant,
+    vtodos: Vec<ical::VTodo>,
+    conn: &PgConnection,
+) -> Result<models::ImportSummary, TodosError> {
+    use schema::todos::dsl;
+    conn.transaction(|| {
+        let mut known_uids = todos_of(tenant)
+            .select(dsl::ical_uid)
+            .load::<String>(conn)?
+            .into_iter()
+            .collect::<std::collections::HashSet<_>>();
+        let mut summary = models::ImportSummary {
+            created: Vec::new(),
+            skipped: 0,
+        };
+        for vtodo in vtodos {
+            if !known_uids.insert(vtodo.uid.clone()) {
+                summary.skipped += 1;
+                continue;
+            }
+            summary.created.push(insert_vtodo(tenant, vtodo, conn)?);
+        }
+        Ok(summary)
+    })
+    .map_err(|_: diesel::result::Error| TodosError::DieselCrudError)
+}
+
+/// Replaces the user's calendar feed token, invalidating the old feed URL.
+/// Only its hash is stored, so the token can't be shown again.
+pub fn regenerate_calendar_token(uid: i32, conn: &PgConnection) -> Result<String, TodosError> {
+    use schema::users::dsl::*;
+    let token = auth::generate_token();
+    diesel::update(users.filter(id.eq(uid)))
+        .set(calendar_token_hash.eq(hash_token(&token)))
+        .execute(conn)
+        .map_err(|_| TodosError::DieselCrudError)?;
+    Ok(token)
+}
+
+/// Replaces the user's CalDAV app password, invalidating the old one. Like
+/// the feed token, only its hash is stored.
+pub fn regenerate_caldav_password(uid: i32, conn: &PgConnection) -> Result<String, TodosError> {
+    use schema::users::dsl::*;
+    let app_password = auth::generate_token();
+    diesel::update(users.filter(id.eq(uid)))
+        .set(caldav_password_hash.eq(hash_token(&app_password)))
+        .execute(conn)
+        .map_err(|_| TodosError::DieselCrudError)?;
+    Ok(app_password)
+}
+
+pub fn revoke_caldav_password(uid: i32, conn: &PgConnection) -> Result<(), TodosError> {
+    use schema::users::dsl::*;
+    diesel::update(users.filter(id.eq(uid)))
+        .set(caldav_password_hash.eq(None::<String>))
+        .execute(conn)
+        .map_err(|_| TodosError::DieselCrudError)?;
+    Ok(())
+}
+
+/// The usable account named `uname` whose CalDAV app password is
+/// `app_password`. CalDAV clients may log in with it instead of the account
+/// password, which costs a hash lookup rather than an Argon2 verification per
+/// request.
+pub fn verify_caldav_password(
+    uname: &str,
+    app_password: &str,
+    conn: &PgConnection,
+) -> Result<Option<models::User>, TodosError> {
+    use schema::users::dsl::*;
+    users
+        .filter(username.eq(uname))
+        .filter(caldav_password_hash.eq(hash_token(app_password)))
+        .filter(deleted_at.is_null())
+        .filter(disabled_at.is_null())
+        .first::<models::User>(conn)
+        .optional()
+        .map_err(|_| TodosError::DieselCrudError)
+}
+
+pub fn get_todos_by_calendar_token(
+    token: &str,
+    conn: &PgConnection,
+) -> Result<Vec<models::Todo>, TodosError> {
+    use schema::users::dsl::*;
+    let uid = users
+        .filter(calendar_token_hash.eq(hash_token(token)))
+        .filter(deleted_at.is_null())
+        .filter(disabled_at.is_null())
+        .select(id)
+        .first::<i32>(conn)
+        .map_err(|e| match e {
+            diesel::result::Error::NotFound => TodosError::CalendarTokenNotFound,
+            _ => TodosError::DieselCrudError,
+        })?;
+    get_all_todos(models::Tenant::Personal { user_id: uid }, conn)
+}
+
+pub fn get_users_by_ids(
+    uids: &[i32],
+    conn: &PgConnection,
+) -> Result<Vec<models::User>, TodosError> {
+    use schema::users::dsl::*;
+    let users_list = users
+        .filter(id.eq_any(uids))
+        .load::<models::User>(conn)
+        .map_err(|_| TodosError::DieselCrudError)?;
+    Ok(users_list)
+}
+
+fn hash_password(pass: &str) -> String {
+    password::hash(pass)
+}
+
+fn normalize_email(address: &str) -> Result<String, TodosError> {
+    let address = address.trim();
+    address
+        .parse::<lettre::Address>()
+        .map_err(|_| TodosError::InvalidEmail)?;
+    Ok(address.to_lowercase())
+}
+
+/// Whether registration requires an email address, from `REQUIRE_EMAIL`.
+pub fn email_required() -> bool {
+    std::env::var("REQUIRE_EMAIL")
+        .map(|required| required == "true" || required == "1")
+        .unwrap_or(false)
+}
+
 pub fn register_user(
     data: models::NewUser,
+    request: &audit::RequestInfo,
+    mailer: &dyn mail::Mailer,
     conn: &PgConnection,
 ) -> Result<auth::RegisterResponse, TodosError> {
     use schema::users::dsl::*;
 
-    let salt = SaltString::generate(&mut rand_core::OsRng);
+    let new_email = data.email.as_deref().map(normalize_email).transpose()?;
+    if new_email.is_none() && email_required() {
+        return Err(TodosError::EmailRequired);
+    }
+    // Registering fails if the verification email can't be sent, so that
+    // nobody ends up with an account they can't verify.
+    let user = conn.transaction::<_, TodosError, _>(|| {
+        if username_taken(&data.username, None, conn)? {
+            return Err(TodosError::UsernameTaken);
+        }
+        let user = diesel::insert_into(users)
+            .values(models::NewUser {
+                password: hash_password(&data.password),
+                username: data.username,
+                email: new_email,
+            })
+            .get_result::<models::User>(conn)
+            .map_err(|e| {
+                log::error!("Creating a user failed: {}", e);
+                TodosError::DieselCrudError
+            })?;
+        if user.email.is_some() {
+            send_verification_email(&user, mailer, conn)?;
+        }
+        record_event(
+            models::NewAuditEvent {
+                user_id: Some(user.id),
+                username: Some(user.username.clone()),
+                ..request.event(audit::AuditEventKind::UserRegistered)
+            },
+            conn,
+        )?;
+        Ok(user)
+    })?;
+    let jwt_user = open_session(&user, request, conn)?;
+    Ok(auth::RegisterResponse {
+        id: jwt_user.id,
+        token: jwt_user.token,
+        username: jwt_user.username,
+    })
+}
+
+/// Finds a user by username or, failing that, by verified email address.
+/// Unverified addresses don't count: anyone can claim one at registration.
+fn find_user_by_login(login: &str, conn: &PgConnection) -> QueryResult<models::User> {
+    use schema::users::dsl::*;
+    users
+        .filter(username.eq(login))
+        .or_filter(
+            email
+                .eq(login.trim().to_lowercase())
+                .and(email_verified_at.is_not_null()),
+        )
+        .order(username.eq(login).desc())
+        .first::<models::User>(conn)
+}
+
+/// Checks a password for an active account. `ip` is the client's address,
+/// for counting failed logins.
+pub fn verify_credentials(
+    uname: &str,
+    pass: &str,
+    ip: Option<&str>,
+    conn: &PgConnection,
+) -> Result<models::User, TodosError> {
+    let user = authenticate(uname, pass, ip, conn)?;
+    ensure_usable(&user)?;
+    ensure_password_usable(&user)?;
+    Ok(user)
+}
+
+/// Refuses accounts scheduled for deletion or disabled by an administrator.
+fn ensure_usable(user: &models::User) -> Result<(), TodosError> {
+    if user.deleted_at.is_some() {
+        return Err(TodosError::AccountDisabled);
+    }
+    if user.disabled_at.is_some() {
+        return Err(TodosError::AccountSuspended);
+    }
+    Ok(())
+}
+
+/// Refuses passwords an administrator asked the user to replace. Only
+/// called once the password is known to be right.
+fn ensure_password_usable(user: &models::User) -> Result<(), TodosError> {
+    if user.password_reset_required {
+        return Err(TodosError::PasswordResetRequired);
+    }
+    Ok(())
+}
+
+/// Checks a password, unless the account or the client has failed too often
+/// lately. Unknown logins take as long and are counted like wrong passwords,
+/// so neither the answer nor its timing tells whether an account exists.
+fn authenticate(
+    login: &str,
+    pass: &str,
+    ip: Option<&str>,
+    conn: &PgConnection,
+) -> Result<models::User, TodosError> {
+    let user = match find_user_by_login(login, conn) {
+        Ok(user) => Some(user),
+        Err(diesel::result::Error::NotFound) => None,
+        Err(_) => return Err(TodosError::DieselCrudError),
+    };
+    let subject = user
+        .as_ref()
+        .map_or_else(|| login.trim().to_lowercase(), |user| user.username.clone());
+    check_login_throttle(&subject, ip, conn)?;
+
+    let checked = match &user {
+        Some(user) => check_password(user, pass),
+        None => {
+            // Hashing costs as much as verifying.
+            hash_password(pass);
+            Err(TodosError::BadCreds)
+        }
+    };
+    match (checked, user) {
+        (Ok(()), Some(user)) => {
+            rehash_password_if_outdated(&user, pass, conn)?;
+            // With a second factor, only passing that one counts as a login.
+            if user.totp_enabled_at.is_none() {
+                clear_login_failures(&user.username, conn)?;
+            }
+            Ok(user)
+        }
+        // A misconfigured server is no reason to lock the account out.
+        (Err(e @ TodosError::UnknownPepper(_)), _) => Err(e),
+        _ => {
+            record_login_failure(&subject, ip, conn)?;
+            Err(TodosError::BadCreds)
+        }
+    }
+}
+
+/// Checks the password of a logged-in user again before a sensitive change.
+/// Failures count like failed logins, so that a stolen token can't be used
+/// to guess the password.
+fn recheck_password(
+    user: &models::User,
+    pass: &str,
+    ip: Option<&str>,
+    conn: &PgConnection,
+) -> Result<(), TodosError> {
+    check_login_throttle(&user.username, ip, conn)?;
+    match check_password(user, pass) {
+        Err(TodosError::BadCreds) => {
+            record_login_failure(&user.username, ip, conn)?;
+            Err(TodosError::BadCreds)
+        }
+        checked => checked,
+    }
+}
+
+fn check_password(user: &models::User, pass: &str) -> Result<(), TodosError> {
+    if password::verify(&user.password, pass)? {
+        Ok(())
+    } else {
+        Err(TodosError::BadCreds)
+    }
+}
+
+/// Replaces a hash made with outdated parameters, now that the password is
+/// known. It is the same password, so no tokens are revoked.
+fn rehash_password_if_outdated(
+    user: &models::User,
+    pass: &str,
+    conn: &PgConnection,
+) -> Result<(), TodosError> {
+    if password::needs_rehash(&user.password) {
+        diesel::update(schema::users::table.find(user.id))
+            .set(schema::users::password.eq(hash_password(pass)))
+            .execute(conn)?;
+    }
+    Ok(())
+}
+
+const USERNAME_SCOPE: &str = "username";
+const IP_SCOPE: &str = "ip";
+
+/// How many failed logins lock out an account or a client address, and for
+/// how long.
+struct LoginLimits {
+    max_failures: i32,
+    max_failures_per_ip: i32,
+    /// Failures older than this are forgotten, too.
+    lockout: chrono::Duration,
+}
 
-    let argon2 = Argon2::default();
+static LOGIN_LIMITS: OnceLock<LoginLimits> = OnceLock::new();
 
-    let password_hash = argon2
-        .hash_password_simple(data.password.as_bytes(), salt.as_ref())
-        .unwrap()
-        .to_string();
-    let user = diesel::insert_into(users)
-        .values(models::NewUser {
-            password: password_hash,
-            username: data.username,
+impl LoginLimits {
+    /// From `LOGIN_MAX_FAILURES` (10 by default), `LOGIN_MAX_FAILURES_PER_IP`
+    /// (100) and `LOGIN_LOCKOUT_MINUTES` (15), read the first time they are
+    /// needed. A value that isn't a number is reported and replaced by the
+    /// default, rather than failing every login.
+    fn get() -> &'static Self {
+        LOGIN_LIMITS.get_or_init(|| {
+            let var = |name: &str, default: i32| match std::env::var(name) {
+                Ok(value) => value.parse().unwrap_or_else(|_| {
+                    log::warn!("{} is not a number: {:?}; using {}.", name, value, default);
+                    default
+                }),
+                Err(_) => default,
+            };
+            Self {
+                max_failures: var("LOGIN_MAX_FAILURES", 10),
+                max_failures_per_ip: var("LOGIN_MAX_FAILURES_PER_IP", 100),
+                lockout: chrono::Duration::minutes(var("LOGIN_LOCKOUT_MINUTES", 15).into()),
+            }
         })
-        .get_result::<models::User>(conn)
-        .map_err(|a| {
-            eprintln!("{:?}", a);
-            TodosError::DieselCrudError
+    }
+
+    fn max_failures(&self, scope: &str) -> i32 {
+        if scope == IP_SCOPE {
+            self.max_failures_per_ip
+        } else {
+            self.max_failures
+        }
+    }
+}
+
+/// When the next login may be tried. The first half of the allowed failures
+/// are free; after that, the wait doubles with every failure until the
+/// lockout.
+fn next_login_allowed_at(
+    throttle: &models::LoginThrottle,
+    limits: &LoginLimits,
+    now: NaiveDateTime,
+) -> NaiveDateTime {
+    let lockout = limits.lockout;
+    if let Some(locked_until) = throttle.locked_until {
+        return locked_until;
+    }
+    if throttle.last_failed_at < now - lockout {
+        return throttle.last_failed_at;
+    }
+    let free = limits.max_failures(&throttle.scope) / 2;
+    let backoff = match throttle.failures - free {
+        excess if excess < 0 => chrono::Duration::zero(),
+        excess => chrono::Duration::seconds(1i64 << excess.min(30)).min(lockout),
+    };
+    throttle.last_failed_at + backoff
+}
+
+fn check_login_throttle(
+    username: &str,
+    ip: Option<&str>,
+    conn: &PgConnection,
+) -> Result<(), TodosError> {
+    use schema::login_throttles::dsl::*;
+    let subjects = std::iter::once((USERNAME_SCOPE, username))
+        .chain(ip.map(|ip| (IP_SCOPE, ip)))
+        .collect::<Vec<_>>();
+    let now = chrono::Utc::now().naive_utc();
+    let mut retry_at = now;
+    for (throttle_scope, throttle_subject) in subjects {
+        let throttle = login_throttles
+            .find((throttle_scope, throttle_subject))
+            .first::<models::LoginThrottle>(conn)
+            .optional()?;
+        if let Some(throttle) = throttle {
+            retry_at = retry_at.max(next_login_allowed_at(&throttle, LoginLimits::get(), now));
+        }
+    }
+    if retry_at > now {
+        // Rounded up, so that clients retrying on time aren't turned away.
+        let seconds = (retry_at - now).num_milliseconds() as f64 / 1000.0;
+        return Err(TodosError::TooManyLoginAttempts(seconds.ceil() as i64));
+    }
+    Ok(())
+}
+
+fn record_login_failure(
+    username: &str,
+    ip: Option<&str>,
+    conn: &PgConnection,
+) -> Result<(), TodosError> {
+    use schema::login_throttles::dsl::*;
+    let subjects = std::iter::once((USERNAME_SCOPE, username))
+        .chain(ip.map(|ip| (IP_SCOPE, ip)))
+        .collect::<Vec<_>>();
+    let now = chrono::Utc::now().naive_utc();
+    conn.transaction::<_, TodosError, _>(|| {
+        for (throttle_scope, throttle_subject) in subjects {
+            diesel::insert_into(login_throttles)
+                .values((scope.eq(throttle_scope), subject.eq(throttle_subject)))
+                .on_conflict_do_nothing()
+                .execute(conn)?;
+            let throttle = login_throttles
+                .find((throttle_scope, throttle_subject))
+                .for_update()
+                .first::<models::LoginThrottle>(conn)?;
+            let limits = LoginLimits::get();
+            let stale = throttle.last_failed_at < now - limits.lockout
+                || matches!(throttle.locked_until, Some(until) if until <= now);
+            let count = if stale { 1 } else { throttle.failures + 1 };
+            let lock = if count >= limits.max_failures(throttle_scope) {
+                Some(now + limits.lockout)
+            } else {
+                None
+            };
+            diesel::update(login_throttles.find((throttle_scope, throttle_subject)))
+                .set((
+                    failures.eq(count),
+                    last_failed_at.eq(now),
+                    locked_until.eq(lock),
+                ))
+                .execute(conn)?;
+        }
+        Ok(())
+    })
+}
+
+fn clear_login_failures(username: &str, conn: &PgConnection) -> Result<(), TodosError> {
+    use schema::login_throttles::dsl::*;
+    diesel::delete(login_throttles.find((USERNAME_SCOPE, username))).execute(conn)?;
+    Ok(())
+}
+
+/// Lifts the lockout of a username, or of a client address if given one.
+/// Returns whether there was anything to lift.
+pub fn unlock_login(username_or_ip: &str, conn: &PgConnection) -> Result<bool, TodosError> {
+    use schema::login_throttles::dsl::*;
+    let throttle_scope = if username_or_ip.parse::<std::net::IpAddr>().is_ok() {
+        IP_SCOPE
+    } else {
+        USERNAME_SCOPE
+    };
+    let count =
+        diesel::delete(login_throttles.find((throttle_scope, username_or_ip))).execute(conn)?;
+    Ok(count > 0)
+}
+
+/// Forgets failed logins that no longer count.
+pub fn purge_login_throttles(conn: &PgConnection) -> Result<usize, TodosError> {
+    use schema::login_throttles::dsl::*;
+    let now = chrono::Utc::now().naive_utc();
+    let count = diesel::delete(
+        login_throttles
+            .filter(last_failed_at.lt(now - LoginLimits::get().lockout))
+            .filter(locked_until.is_null().or(locked_until.lt(now))),
+    )
+    .execute(conn)?;
+    Ok(count)
+}
+
+/// Forgets password reset requests too old to count against any limit.
+pub fn purge_password_reset_requests(conn: &PgConnection) -> Result<usize, TodosError> {
+    use schema::password_reset_requests::dsl::*;
+    let now = chrono::Utc::now().naive_utc();
+    let count = diesel::delete(
+        password_reset_requests.filter(requested_at.lt(now - password_reset_window())),
+    )
+    .execute(conn)?;
+    Ok(count)
+}
+
+/// Tokens outlive the accounts they were issued for, and the passwords they
+/// were issued with; this rejects those of deleted and disabled accounts and
+/// those issued before the last password change.
+pub fn check_claims(
+    claims: &auth::Claims,
+    conn: &PgConnection,
+) -> Result<models::User, TodosError> {
+    let user = find_user(claims.id, conn)?;
+    ensure_usable(&user)?;
+    let revoked_at = user.password_changed_at.max(user.sessions_revoked_at);
+    if let Some(revoked_at) = revoked_at {
+        if (claims.iat as i64) < revoked_at.timestamp() {
+            return Err(TodosError::TokenRevoked);
+        }
+    }
+    touch_session(claims.id, claims.sid, conn)?;
+    Ok(user)
+}
+
+/// How long deleted accounts stay recoverable, from
+/// `ACCOUNT_DELETION_GRACE_DAYS` (30 by default).
+pub fn account_deletion_grace_period() -> chrono::Duration {
+    let days = std::env::var("ACCOUNT_DELETION_GRACE_DAYS")
+        .map(|days| days.parse().expect("ACCOUNT_DELETION_GRACE_DAYS"))
+        .unwrap_or(30);
+    chrono::Duration::days(days)
+}
+
+/// Disables the account after re-checking its password. It is purged once
+/// the grace period has passed, unless it is restored first.
+pub fn schedule_account_deletion(
+    uid: i32,
+    pass: &str,
+    request: &audit::RequestInfo,
+    conn: &PgConnection,
+) -> Result<models::AccountDeletion, TodosError> {
+    use schema::users::dsl::*;
+    let user = users
+        .find(uid)
+        .first::<models::User>(conn)
+        .map_err(|e| match e {
+            diesel::result::Error::NotFound => TodosError::AccountNotFound,
+            _ => TodosError::DieselCrudError,
         })?;
-    let token = auth::create_jwt(user.id, user.username.clone())
-        .map_err(|_| TodosError::JwtTokenCreationError)?;
-    Ok(auth::RegisterResponse {
-        id: user.id,
-        token,
-        username: user.username,
+    recheck_password(&user, pass, request.ip.as_deref(), conn)?;
+    let now = chrono::Utc::now().naive_utc();
+    diesel::update(users.find(uid))
+        .set(deleted_at.eq(now))
+        .execute(conn)?;
+    record_event(
+        models::NewAuditEvent {
+            user_id: Some(uid),
+            username: Some(user.username),
+            ..request.event(audit::AuditEventKind::AccountDeletionScheduled)
+        },
+        conn,
+    )?;
+    Ok(models::AccountDeletion {
+        message: "The account is disabled and will be deleted.".to_owned(),
+        purge_after: now + account_deletion_grace_period(),
+    })
+}
+
+/// Re-enables an account that is waiting to be purged, logging the user in.
+pub fn restore_account(
+    data: auth::LoginBody,
+    request: &audit::RequestInfo,
+    mailer: &dyn mail::Mailer,
+    conn: &PgConnection,
+) -> Result<models::LoginOutcome, TodosError> {
+    use schema::users::dsl::*;
+    let user = authenticate(&data.username, &data.password, request.ip.as_deref(), conn).and_then(
+        |user| {
+            if user.disabled_at.is_some() {
+                return Err(TodosError::AccountSuspended);
+            }
+            ensure_password_usable(&user)?;
+            Ok(user)
+        },
+    );
+    let user = match user {
+        Ok(user) => user,
+        Err(e) => return Err(audit_login_failure(&data.username, e, request, conn)),
+    };
+    diesel::update(users.find(user.id))
+        .set(deleted_at.eq(None::<NaiveDateTime>))
+        .execute(conn)?;
+    record_event(
+        models::NewAuditEvent {
+            user_id: Some(user.id),
+            username: Some(user.username.clone()),
+            ..request.event(audit::AuditEventKind::AccountRestored)
+        },
+        conn,
+    )?;
+    start_login(user, "password", request, mailer, conn)
+}
+
+/// Deletes the accounts whose grace period is over, along with everything
+/// that references them.
+pub fn purge_deleted_accounts(conn: &PgConnection) -> Result<usize, TodosError> {
+    use schema::users::dsl::*;
+    let cutoff = chrono::Utc::now().naive_utc() - account_deletion_grace_period();
+    conn.transaction::<_, TodosError, _>(|| {
+        let purged = users.filter(deleted_at.lt(cutoff)).select(id.nullable());
+        // Workspace todos stay with the workspace, without a creator.
+        diesel::delete(
+            schema::todos::table
+                .filter(schema::todos::user_id.eq_any(purged))
+                .filter(schema::todos::workspace_id.is_null()),
+        )
+        .execute(conn)?;
+        let count = diesel::delete(users.filter(deleted_at.lt(cutoff))).execute(conn)?;
+        Ok(count)
     })
 }
 
 pub fn login_user(
     data: auth::LoginBody,
+    request: &audit::RequestInfo,
+    mailer: &dyn mail::Mailer,
+    conn: &PgConnection,
+) -> Result<models::LoginOutcome, TodosError> {
+    match verify_credentials(&data.username, &data.password, request.ip.as_deref(), conn) {
+        Ok(user) => start_login(user, "password", request, mailer, conn),
+        Err(e) => Err(audit_login_failure(&data.username, e, request, conn)),
+    }
+}
+
+/// Records a refused login in the security log, under the account that
+/// `login` names if there is one, and hands back the error.
+fn audit_login_failure(
+    login: &str,
+    error: TodosError,
+    request: &audit::RequestInfo,
+    conn: &PgConnection,
+) -> TodosError {
+    let reason = match &error {
+        TodosError::BadCreds => "wrong password or unknown login",
+        TodosError::InvalidTotpCode => "wrong second factor",
+        TodosError::TooManyLoginAttempts(_) => "too many failed logins",
+        TodosError::AccountDisabled => "account scheduled for deletion",
+        TodosError::AccountSuspended => "account disabled by an administrator",
+        TodosError::PasswordResetRequired => "password reset required",
+        _ => return error,
+    };
+    let user = find_user_by_login(login, conn).ok();
+    let recorded = record_event(
+        models::NewAuditEvent {
+            user_id: user.as_ref().map(|user| user.id),
+            username: Some(login.to_owned()),
+            details: Some(reason.to_owned()),
+            ..request.event(audit::AuditEventKind::LoginFailed)
+        },
+        conn,
+    );
+    match recorded {
+        Ok(()) => error,
+        Err(e) => e,
+    }
+}
+
+/// Logs in a user whose password was right, unless they still have to
+/// enter a second factor.
+fn start_login(
+    user: models::User,
+    method: &str,
+    request: &audit::RequestInfo,
+    mailer: &dyn mail::Mailer,
+    conn: &PgConnection,
+) -> Result<models::LoginOutcome, TodosError> {
+    if user.totp_enabled_at.is_some() {
+        let (challenge_token, expires_at) = auth::create_challenge_token(user.id)?;
+        return Ok(models::LoginOutcome::TwoFactorRequired(
+            models::TwoFactorChallenge {
+                challenge_token,
+                expires_at,
+            },
+        ));
+    }
+    log_in(&user, method, request, mailer, conn).map(models::LoginOutcome::LoggedIn)
+}
+
+/// Hands out a token to a user who proved who they are, recording the login
+/// with the `method` used. Users with a verified address are mailed about
+/// logins from a device they haven't used before.
+fn log_in(
+    user: &models::User,
+    method: &str,
+    request: &audit::RequestInfo,
+    mailer: &dyn mail::Mailer,
     conn: &PgConnection,
 ) -> Result<models::JwtUser, TodosError> {
-    use schema::users::dsl::*;
+    let new_device = is_new_device(user.id, request, conn)?;
+    let jwt_user = open_session(user, request, conn)?;
+    record_login(user, method, request, conn)?;
+    if new_device {
+        notify_new_device(user, method, request, mailer);
+    }
+    Ok(jwt_user)
+}
+
+fn record_login(
+    user: &models::User,
+    method: &str,
+    request: &audit::RequestInfo,
+    conn: &PgConnection,
+) -> Result<(), TodosError> {
+    record_event(
+        models::NewAuditEvent {
+            user_id: Some(user.id),
+            username: Some(user.username.clone()),
+            details: Some(method.to_owned()),
+            ..request.event(audit::AuditEventKind::LoginSucceeded)
+        },
+        conn,
+    )
+}
 
-    let argon2 = Argon2::default();
+/// Sets a new password after checking the current one. Every token issued
+/// before is revoked, so the caller gets a fresh one.
+pub fn change_password(
+    uid: i32,
+    current_password: &str,
+    new_password: &str,
+    request: &audit::RequestInfo,
+    conn: &PgConnection,
+) -> Result<models::JwtUser, TodosError> {
+    use schema::users::dsl::*;
     let user = users
-        .filter(username.eq(data.username))
+        .find(uid)
         .first::<models::User>(conn)
-        .map_err(|_| TodosError::DieselCrudError)?;
-
-    let parsed_hash = PasswordHash::new(user.password.as_str()).unwrap();
-    argon2
-        .verify_password(data.password.as_bytes(), &parsed_hash)
-        .map_err(|_| TodosError::BadCreds)?;
+        .map_err(|e| match e {
+            diesel::result::Error::NotFound => TodosError::AccountNotFound,
+            _ => TodosError::DieselCrudError,
+        })?;
+    recheck_password(&user, current_password, request.ip.as_deref(), conn)?;
+    set_password(uid, new_password, conn)?;
+    record_event(
+        models::NewAuditEvent {
+            user_id: Some(uid),
+            username: Some(user.username.clone()),
+            ..request.event(audit::AuditEventKind::PasswordChanged)
+        },
+        conn,
+    )?;
+    open_session(&user, request, conn)
+}
+
+fn set_password(uid: i32, new_password: &str, conn: &PgConnection) -> Result<(), TodosError> {
+    use schema::users::dsl::*;
+    diesel::update(users.find(uid))
+        .set((
+            password.eq(hash_password(new_password)),
+            password_changed_at.eq(chrono::Utc::now().naive_utc()),
+            password_reset_required.eq(false),
+            caldav_password_hash.eq(None::<String>),
+        ))
+        .execute(conn)?;
+    end_sessions(uid, conn)?;
+    Ok(())
+}
+
+pub(crate) fn hash_token(token: &str) -> String {
+    use sha2::Digest;
+    hex::encode(sha2::Sha256::digest(token.as_bytes()))
+}
+
+/// How long password reset tokens are valid, from
+/// `PASSWORD_RESET_TOKEN_MINUTES` (60 by default).
+fn password_reset_token_lifetime() -> chrono::Duration {
+    let minutes = std::env::var("PASSWORD_RESET_TOKEN_MINUTES")
+        .map(|minutes| minutes.parse().expect("PASSWORD_RESET_TOKEN_MINUTES"))
+        .unwrap_or(60);
+    chrono::Duration::minutes(minutes)
+}
+
+/// How many password resets may be asked for per hour for one account, and
+/// from one client address.
+const PASSWORD_RESETS_PER_ACCOUNT: i64 = 3;
+const PASSWORD_RESETS_PER_IP: i64 = 20;
+
+fn password_reset_window() -> chrono::Duration {
+    chrono::Duration::hours(1)
+}
+
+/// Mails a reset token to the user's address. Succeeds without sending
+/// anything for unknown users, users without a verified address and users
+/// who asked too often lately, so that callers can't find out which accounts
+/// exist. Client addresses that ask too often are turned away.
+pub fn request_password_reset(
+    login: &str,
+    ip: Option<&str>,
+    mailer: &dyn mail::Mailer,
+    conn: &PgConnection,
+) -> Result<(), TodosError> {
+    use schema::password_reset_requests::dsl as requests;
+    let now = chrono::Utc::now().naive_utc();
+    let since = now - password_reset_window();
+    if let Some(address) = ip {
+        let recent = requests::password_reset_requests
+            .filter(requests::ip.eq(address))
+            .filter(requests::requested_at.gt(since))
+            .select(requests::requested_at)
+            .order(requests::requested_at.desc())
+            .limit(PASSWORD_RESETS_PER_IP)
+            .load::<NaiveDateTime>(conn)?;
+        if recent.len() as i64 >= PASSWORD_RESETS_PER_IP {
+            let retry_at = recent[recent.len() - 1] + password_reset_window();
+            let seconds = (retry_at - now).num_milliseconds() as f64 / 1000.0;
+            return Err(TodosError::TooManyPasswordResets(seconds.ceil() as i64));
+        }
+    }
+    let user = match find_user_by_login(login, conn) {
+        Ok(user) => Some(user),
+        Err(diesel::result::Error::NotFound) => None,
+        Err(_) => return Err(TodosError::DieselCrudError),
+    };
+    diesel::insert_into(requests::password_reset_requests)
+        .values(models::NewPasswordResetRequest {
+            user_id: user.as_ref().map(|user| user.id),
+            ip: ip.map(str::to_owned),
+        })
+        .execute(conn)?;
+    let user = match user {
+        Some(user) => user,
+        None => return Ok(()),
+    };
+    let asked = requests::password_reset_requests
+        .filter(requests::user_id.eq(user.id))
+        .filter(requests::requested_at.gt(since))
+        .count()
+        .get_result::<i64>(conn)?;
+    if asked > PASSWORD_RESETS_PER_ACCOUNT {
+        return Ok(());
+    }
+    send_password_reset(
+        &user,
+        "someone asked to reset the password of your todos account. If it was you, \
+         send this token with your new password to POST /users/password-reset/confirm",
+        "Otherwise, you can ignore this email.\n",
+        mailer,
+        conn,
+    )?;
+    Ok(())
+}
+
+/// Mails a reset token to the user's verified address, between `intro` and
+/// `outro`. Returns whether the user has such an address.
+fn send_password_reset(
+    user: &models::User,
+    intro: &str,
+    outro: &str,
+    mailer: &dyn mail::Mailer,
+    conn: &PgConnection,
+) -> Result<bool, TodosError> {
+    let address = match (&user.email, user.email_verified_at) {
+        (Some(address), Some(_)) => address.clone(),
+        _ => return Ok(false),
+    };
+
+    let token = auth::generate_token();
+    let lifetime = password_reset_token_lifetime();
+    diesel::insert_into(schema::password_reset_tokens::table)
+        .values(models::NewPasswordResetToken {
+            user_id: user.id,
+            token_hash: hash_token(&token),
+            expires_at: chrono::Utc::now().naive_utc() + lifetime,
+        })
+        .execute(conn)?;
+    mailer.send(&mail::Email {
+        to: address,
+        subject: "Reset your password".to_owned(),
+        body: format!(
+            "Hi {},\n\n{} within {} minutes:\n\n{}\n\n{}",
+            user.username,
+            intro,
+            lifetime.num_minutes(),
+            token,
+            outro
+        ),
+    })?;
+    Ok(true)
+}
+
+/// Sets a new password with a reset token. The token, and every other token
+/// the user was sent, can't be used again.
+pub fn reset_password(
+    token: &str,
+    new_password: &str,
+    request: &audit::RequestInfo,
+    conn: &PgConnection,
+) -> Result<(), TodosError> {
+    use schema::password_reset_tokens::dsl::*;
+    let now = chrono::Utc::now().naive_utc();
+    conn.transaction(|| {
+        let reset_token = password_reset_tokens
+            .filter(token_hash.eq(hash_token(token)))
+            .filter(used_at.is_null())
+            .filter(expires_at.gt(now))
+            .first::<models::PasswordResetToken>(conn)
+            .map_err(|e| match e {
+                diesel::result::Error::NotFound => TodosError::InvalidResetToken,
+                _ => TodosError::DieselCrudError,
+            })?;
+        diesel::update(
+            password_reset_tokens
+                .filter(user_id.eq(reset_token.user_id))
+                .filter(used_at.is_null()),
+        )
+        .set(used_at.eq(now))
+        .execute(conn)?;
+        set_password(reset_token.user_id, new_password, conn)?;
+        record_event(
+            models::NewAuditEvent {
+                user_id: Some(reset_token.user_id),
+                ..request.event(audit::AuditEventKind::PasswordReset)
+            },
+            conn,
+        )
+    })
+}
+
+/// How long email verification tokens are valid, from
+/// `EMAIL_VERIFICATION_TOKEN_HOURS` (48 by default).
+fn email_verification_token_lifetime() -> chrono::Duration {
+    let hours = std::env::var("EMAIL_VERIFICATION_TOKEN_HOURS")
+        .map(|hours| hours.parse().expect("EMAIL_VERIFICATION_TOKEN_HOURS"))
+        .unwrap_or(48);
+    chrono::Duration::hours(hours)
+}
+
+fn send_verification_email(
+    user: &models::User,
+    mailer: &dyn mail::Mailer,
+    conn: &PgConnection,
+) -> Result<(), TodosError> {
+    let address = user.email.clone().ok_or(TodosError::NoEmail)?;
+    let token = auth::generate_token();
+    let lifetime = email_verification_token_lifetime();
+    diesel::insert_into(schema::email_verification_tokens::table)
+        .values(models::NewEmailVerificationToken {
+            user_id: user.id,
+            token_hash: hash_token(&token),
+            email: address.clone(),
+            expires_at: chrono::Utc::now().naive_utc() + lifetime,
+        })
+        .execute(conn)?;
+    mailer.send(&mail::Email {
+        to: address,
+        subject: "Verify your email address".to_owned(),
+        body: format!(
+            "Hi {},\n\n\
+             please confirm that this is your address by sending this token to \
+             POST /users/verify-email within {} hours:\n\n{}\n",
+            user.username,
+            lifetime.num_hours(),
+            token
+        ),
+    })
+}
+
+/// Sends a new verification token to an account's unverified address.
+pub fn resend_email_verification(
+    uid: i32,
+    mailer: &dyn mail::Mailer,
+    conn: &PgConnection,
+) -> Result<(), TodosError> {
+    let user = schema::users::table
+        .find(uid)
+        .first::<models::User>(conn)
+        .map_err(|e| match e {
+            diesel::result::Error::NotFound => TodosError::AccountNotFound,
+            _ => TodosError::DieselCrudError,
+        })?;
+    match (&user.email, user.email_verified_at) {
+        (None, _) => Err(TodosError::NoEmail),
+        (Some(_), Some(_)) => Err(TodosError::EmailAlreadyVerified),
+        (Some(_), None) => send_verification_email(&user, mailer, conn),
+    }
+}
+
+/// Marks the address a token was sent to as verified, as long as it is still
+/// the account's address.
+pub fn verify_email(token: &str, conn: &PgConnection) -> Result<(), TodosError> {
+    use schema::email_verification_tokens::dsl::*;
+    let now = chrono::Utc::now().naive_utc();
+    conn.transaction(|| {
+        let verification_token = email_verification_tokens
+            .filter(token_hash.eq(hash_token(token)))
+            .filter(used_at.is_null())
+            .filter(expires_at.gt(now))
+            .first::<models::EmailVerificationToken>(conn)
+            .map_err(|e| match e {
+                diesel::result::Error::NotFound => TodosError::InvalidVerificationToken,
+                _ => TodosError::DieselCrudError,
+            })?;
+        let taken = diesel::select(diesel::dsl::exists(
+            schema::users::table
+                .filter(schema::users::email.eq(&verification_token.email))
+                .filter(schema::users::email_verified_at.is_not_null()),
+        ))
+        .get_result::<bool>(conn)?;
+        if taken {
+            return Err(TodosError::EmailTaken);
+        }
+        let verified = diesel::update(
+            schema::users::table
+                .find(verification_token.user_id)
+                .filter(schema::users::email.eq(&verification_token.email)),
+        )
+        .set(schema::users::email_verified_at.eq(now))
+        .execute(conn)?;
+        if verified == 0 {
+            return Err(TodosError::InvalidVerificationToken);
+        }
+        release_unverified_email(&verification_token.email, conn)?;
+        diesel::update(&verification_token)
+            .set(used_at.eq(now))
+            .execute(conn)?;
+        Ok(())
+    })
+}
+
+/// Takes a newly verified address away from the other accounts that claimed
+/// it without verifying it.
+fn release_unverified_email(address: &str, conn: &PgConnection) -> QueryResult<usize> {
+    use schema::users::dsl::*;
+    diesel::update(
+        users
+            .filter(email.eq(address))
+            .filter(email_verified_at.is_null()),
+    )
+    .set(email.eq(None::<String>))
+    .execute(conn)
+}
+
+/// Rejects accounts that have to verify an email address first.
+pub fn ensure_verified(uid: i32, conn: &PgConnection) -> Result<(), TodosError> {
+    let user = schema::users::table
+        .find(uid)
+        .first::<models::User>(conn)
+        .map_err(|e| match e {
+            diesel::result::Error::NotFound => TodosError::AccountNotFound,
+            _ => TodosError::DieselCrudError,
+        })?;
+    if user.is_verified(email_required()) {
+        Ok(())
+    } else {
+        Err(TodosError::EmailNotVerified)
+    }
+}
+
+/// The name authenticator apps show next to the code, from `TOTP_ISSUER`
+/// ("Todos" by default).
+fn totp_issuer() -> String {
+    std::env::var("TOTP_ISSUER").unwrap_or_else(|_| "Todos".to_owned())
+}
+
+fn find_user(uid: i32, conn: &PgConnection) -> Result<models::User, TodosError> {
+    schema::users::table
+        .find(uid)
+        .first::<models::User>(conn)
+        .map_err(|e| match e {
+            diesel::result::Error::NotFound => TodosError::AccountNotFound,
+            _ => TodosError::DieselCrudError,
+        })
+}
+
+/// Gives the user a new TOTP secret to add to their authenticator app. It
+/// isn't asked for at login until a code from it is confirmed.
+pub fn start_totp_enrollment(
+    uid: i32,
+    conn: &PgConnection,
+) -> Result<models::TotpEnrollment, TodosError> {
+    let user = find_user(uid, conn)?;
+    if user.totp_enabled_at.is_some() {
+        return Err(TodosError::TotpAlreadyEnabled);
+    }
+    let secret = totp::generate_secret();
+    diesel::update(schema::users::table.find(uid))
+        .set(schema::users::totp_secret.eq(&secret))
+        .execute(conn)?;
+    let account = user.email.as_deref().unwrap_or(&user.username);
+    Ok(models::TotpEnrollment {
+        provisioning_uri: totp::provisioning_uri(&totp_issuer(), account, &secret),
+        secret,
+    })
+}
+
+/// The `otpauth://` URI of an enrollment that hasn't been confirmed yet.
+pub fn pending_totp_uri(uid: i32, conn: &PgConnection) -> Result<String, TodosError> {
+    let user = find_user(uid, conn)?;
+    if user.totp_enabled_at.is_some() {
+        return Err(TodosError::TotpAlreadyEnabled);
+    }
+    let secret = user.totp_secret.ok_or(TodosError::TotpNotEnrolled)?;
+    let account = user.email.as_deref().unwrap_or(&user.username);
+    Ok(totp::provisioning_uri(&totp_issuer(), account, &secret))
+}
+
+/// Turns two-factor authentication on once the user proves their app
+/// generates the right codes, and hands out a fresh set of recovery codes.
+pub fn confirm_totp(
+    uid: i32,
+    code: &str,
+    request: &audit::RequestInfo,
+    conn: &PgConnection,
+) -> Result<models::RecoveryCodes, TodosError> {
+    let user = find_user(uid, conn)?;
+    if user.totp_enabled_at.is_some() {
+        return Err(TodosError::TotpAlreadyEnabled);
+    }
+    let secret = user
+        .totp_secret
+        .as_deref()
+        .ok_or(TodosError::TotpNotEnrolled)?;
+    let step = totp::verify(secret, code, chrono::Utc::now().timestamp())
+        .ok_or(TodosError::InvalidTotpCode)?;
+
+    let recovery_codes = totp::generate_recovery_codes();
+    conn.transaction::<_, TodosError, _>(|| {
+        diesel::update(schema::users::table.find(uid))
+            .set((
+                schema::users::totp_enabled_at.eq(chrono::Utc::now().naive_utc()),
+                schema::users::totp_last_step.eq(step),
+            ))
+            .execute(conn)?;
+        diesel::delete(
+            schema::totp_recovery_codes::table.filter(schema::totp_recovery_codes::user_id.eq(uid)),
+        )
+        .execute(conn)?;
+        let new_codes = recovery_codes
+            .iter()
+            .map(|recovery_code| models::NewRecoveryCode {
+                user_id: uid,
+                code_hash: hash_token(recovery_code),
+            })
+            .collect::<Vec<_>>();
+        diesel::insert_into(schema::totp_recovery_codes::table)
+            .values(&new_codes)
+            .execute(conn)?;
+        record_event(
+            models::NewAuditEvent {
+                user_id: Some(uid),
+                username: Some(user.username.clone()),
+                ..request.event(audit::AuditEventKind::TwoFactorEnabled)
+            },
+            conn,
+        )
+    })?;
+    Ok(models::RecoveryCodes { recovery_codes })
+}
+
+/// Finishes a login that was waiting for a second factor.
+pub fn complete_two_factor_login(
+    data: models::TwoFactorLoginBody,
+    request: &audit::RequestInfo,
+    mailer: &dyn mail::Mailer,
+    conn: &PgConnection,
+) -> Result<models::JwtUser, TodosError> {
+    let uid = auth::authorize_challenge(&data.challenge_token)?;
+    let user = find_user(uid, conn).map_err(|e| match e {
+        TodosError::AccountNotFound => TodosError::InvalidChallenge,
+        e => e,
+    })?;
+    if user.totp_enabled_at.is_none() || ensure_usable(&user).is_err() {
+        return Err(TodosError::InvalidChallenge);
+    }
+    if let Err(e) = check_second_factor(&user, &data.code, request.ip.as_deref(), conn) {
+        return Err(audit_login_failure(&user.username, e, request, conn));
+    }
+    log_in(&user, "second factor", request, mailer, conn)
+}
+
+/// Checks the second factor of a user whose password was right: a TOTP code
+/// that hasn't been used yet, or an unused recovery code. Wrong codes count
+/// as failed logins.
+fn check_second_factor(
+    user: &models::User,
+    code: &str,
+    ip: Option<&str>,
+    conn: &PgConnection,
+) -> Result<(), TodosError> {
+    let secret = user
+        .totp_secret
+        .as_deref()
+        .ok_or(TodosError::TotpNotEnrolled)?;
+    check_login_throttle(&user.username, ip, conn)?;
+
+    let checked = conn.transaction::<_, TodosError, _>(|| {
+        if let Some(step) = totp::verify(secret, code, chrono::Utc::now().timestamp()) {
+            // Each code only works once, even within its time window.
+            let accepted = diesel::update(
+                schema::users::table.find(user.id).filter(
+                    schema::users::totp_last_step
+                        .is_null()
+                        .or(schema::users::totp_last_step.lt(step)),
+                ),
+            )
+            .set(schema::users::totp_last_step.eq(step))
+            .execute(conn)?;
+            if accepted == 1 {
+                return Ok(());
+            }
+            return Err(TodosError::InvalidTotpCode);
+        }
+
+        use schema::totp_recovery_codes::dsl::*;
+        let recovery_code = code.trim().to_lowercase();
+        let used = diesel::update(
+            totp_recovery_codes
+                .filter(user_id.eq(user.id))
+                .filter(code_hash.eq(hash_token(&recovery_code)))
+                .filter(used_at.is_null()),
+        )
+        .set(used_at.eq(chrono::Utc::now().naive_utc()))
+        .execute(conn)?;
+        if used == 1 {
+            Ok(())
+        } else {
+            Err(TodosError::InvalidTotpCode)
+        }
+    });
+    match checked {
+        Ok(()) => clear_login_failures(&user.username, conn),
+        Err(TodosError::InvalidTotpCode) => {
+            record_login_failure(&user.username, ip, conn)?;
+            Err(TodosError::InvalidTotpCode)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// How long OAuth access tokens are valid, from `OAUTH_ACCESS_TOKEN_MINUTES`
+/// (60 by default).
+fn oauth_access_token_lifetime() -> chrono::Duration {
+    let minutes = std::env::var("OAUTH_ACCESS_TOKEN_MINUTES")
+        .map(|minutes| minutes.parse().expect("OAUTH_ACCESS_TOKEN_MINUTES"))
+        .unwrap_or(60);
+    chrono::Duration::minutes(minutes)
+}
+
+/// How long OAuth refresh tokens are valid, from `OAUTH_REFRESH_TOKEN_DAYS`
+/// (30 by default).
+fn oauth_refresh_token_lifetime() -> chrono::Duration {
+    let days = std::env::var("OAUTH_REFRESH_TOKEN_DAYS")
+        .map(|days| days.parse().expect("OAUTH_REFRESH_TOKEN_DAYS"))
+        .unwrap_or(30);
+    chrono::Duration::days(days)
+}
+
+fn oauth_error(code: oauth::OAuthErrorCode, description: &str) -> TodosError {
+    TodosError::OAuth(oauth::OAuthError::new(code, description))
+}
+
+pub fn register_oauth_client(
+    uid: i32,
+    data: models::NewOAuthClientBody,
+    conn: &PgConnection,
+) -> Result<models::OAuthClientInfo, TodosError> {
+    let name = data.name.trim();
+    if name.is_empty() {
+        return Err(oauth_error(
+            oauth::OAuthErrorCode::InvalidRequest,
+            "The name is empty.",
+        ));
+    }
+    if data.redirect_uris.is_empty() {
+        return Err(oauth_error(
+            oauth::OAuthErrorCode::InvalidRequest,
+            "Register at least one redirect URI.",
+        ));
+    }
+    if let Some(uri) = data
+        .redirect_uris
+        .iter()
+        .find(|uri| !oauth::is_valid_redirect_uri(uri))
+    {
+        return Err(TodosError::InvalidRedirectUri(uri.clone()));
+    }
+
+    let client_secret = if data.confidential {
+        Some(auth::generate_token())
+    } else {
+        None
+    };
+    let client = diesel::insert_into(schema::oauth_clients::table)
+        .values(models::NewOAuthClient {
+            client_id: auth::generate_token()[..32].to_owned(),
+            client_secret_hash: client_secret.as_deref().map(hash_token),
+            name: name.to_owned(),
+            redirect_uris: data.redirect_uris,
+            user_id: uid,
+        })
+        .get_result::<models::OAuthClient>(conn)?;
+    Ok(models::OAuthClientInfo {
+        client_secret,
+        ..client.into()
+    })
+}
+
+pub fn list_oauth_clients(
+    uid: i32,
+    conn: &PgConnection,
+) -> Result<Vec<models::OAuthClientInfo>, TodosError> {
+    use schema::oauth_clients::dsl::*;
+    let clients = oauth_clients
+        .filter(user_id.eq(uid))
+        .order(id)
+        .load::<models::OAuthClient>(conn)?;
+    Ok(clients.into_iter().map(Into::into).collect())
+}
+
+/// Deletes a client the user registered, along with every token issued to
+/// it.
+pub fn delete_oauth_client(
+    uid: i32,
+    public_id: &str,
+    conn: &PgConnection,
+) -> Result<(), TodosError> {
+    use schema::oauth_clients::dsl::*;
+    let count = diesel::delete(
+        oauth_clients
+            .filter(user_id.eq(uid))
+            .filter(client_id.eq(public_id)),
+    )
+    .execute(conn)?;
+    if count == 0 {
+        return Err(TodosError::OAuthClientNotFound);
+    }
+    Ok(())
+}
+
+/// The client an authorization request is for and the redirect URI to answer
+/// it at. Without both, the answer can't go back to the app.
+fn find_authorization_target(
+    request: &models::AuthorizeRequest,
+    conn: &PgConnection,
+) -> Result<(models::OAuthClient, String), TodosError> {
+    let client = schema::oauth_clients::table
+        .filter(schema::oauth_clients::client_id.eq(&request.client_id))
+        .first::<models::OAuthClient>(conn)
+        .map_err(|e| match e {
+            diesel::result::Error::NotFound => TodosError::OAuthClientNotFound,
+            _ => TodosError::DieselCrudError,
+        })?;
+    let redirect_uri = match &request.redirect_uri {
+        Some(uri) if client.redirect_uris.contains(uri) => uri.clone(),
+        Some(uri) => return Err(TodosError::InvalidRedirectUri(uri.clone())),
+        None if client.redirect_uris.len() == 1 => client.redirect_uris[0].clone(),
+        None => return Err(TodosError::InvalidRedirectUri(String::new())),
+    };
+    Ok((client, redirect_uri))
+}
+
+/// The scopes and the PKCE code challenge of an authorization request.
+fn check_authorization_request(
+    request: &models::AuthorizeRequest,
+) -> Result<(Vec<oauth::Scope>, String), oauth::OAuthError> {
+    use oauth::{OAuthError, OAuthErrorCode};
+    if request.response_type != "code" {
+        return Err(OAuthError::new(
+            OAuthErrorCode::UnsupportedResponseType,
+            "Only the authorization code flow is supported.",
+        ));
+    }
+    let code_challenge = match (&request.code_challenge, &request.code_challenge_method) {
+        (Some(challenge), Some(method)) if method == "S256" => challenge.clone(),
+        _ => {
+            return Err(OAuthError::new(
+                OAuthErrorCode::InvalidRequest,
+                "PKCE with the S256 method is required.",
+            ))
+        }
+    };
+    let scopes = oauth::Scope::parse_list(request.scope.as_deref())?;
+    Ok((scopes, code_challenge))
+}
+
+fn authorization_error_redirect(
+    redirect_uri: &str,
+    error: oauth::OAuthError,
+    state: Option<&str>,
+) -> models::AuthorizeOutcome {
+    let mut params = vec![
+        ("error", error.error.as_str()),
+        ("error_description", error.error_description.as_str()),
+    ];
+    if let Some(state) = state {
+        params.push(("state", state));
+    }
+    models::AuthorizeOutcome::Redirect(oauth::redirect_uri_with(redirect_uri, &params))
+}
+
+/// Checks an authorization request and shows the consent page for it.
+pub fn start_oauth_authorization(
+    request: models::AuthorizeRequest,
+    conn: &PgConnection,
+) -> Result<models::AuthorizeOutcome, TodosError> {
+    let (client, redirect_uri) = find_authorization_target(&request, conn)?;
+    match check_authorization_request(&request) {
+        Ok((scopes, _)) => Ok(models::AuthorizeOutcome::Consent(oauth::consent_page(
+            &client.name,
+            &request,
+            &scopes,
+            None,
+        ))),
+        Err(error) => Ok(authorization_error_redirect(
+            &redirect_uri,
+            error,
+            request.state.as_deref(),
+        )),
+    }
+}
+
+/// Handles the consent page's answer. Once the user has logged in and
+/// allowed access, the app gets an authorization code; mistakes show the
+/// page again.
+pub fn finish_oauth_authorization(
+    form: models::ConsentForm,
+    ip: Option<&str>,
+    conn: &PgConnection,
+) -> Result<models::AuthorizeOutcome, TodosError> {
+    let request = form.request();
+    let (client, redirect_uri) = find_authorization_target(&request, conn)?;
+    let state = request.state.as_deref();
+    let (scopes, code_challenge) = match check_authorization_request(&request) {
+        Ok(checked) => checked,
+        Err(error) => return Ok(authorization_error_redirect(&redirect_uri, error, state)),
+    };
+    if form.decision != "approve" {
+        let error = oauth::OAuthError::new(
+            oauth::OAuthErrorCode::AccessDenied,
+            "The user denied access.",
+        );
+        return Ok(authorization_error_redirect(&redirect_uri, error, state));
+    }
+    let retry = |message: &str| {
+        Ok(models::AuthorizeOutcome::Consent(oauth::consent_page(
+            &client.name,
+            &request,
+            &scopes,
+            Some(message),
+        )))
+    };
+    let too_many_attempts =
+        |seconds: i64| format!("Too many failed logins; try again in {} seconds.", seconds);
+
+    let (login, pass) = match (form.username.as_deref(), form.password.as_deref()) {
+        (Some(login), Some(pass)) if !login.is_empty() => (login, pass),
+        _ => return retry("Enter your username and password."),
+    };
+    let user = match verify_credentials(login, pass, ip, conn) {
+        Ok(user) => user,
+        Err(TodosError::BadCreds) => return retry("Wrong username or password."),
+        Err(TodosError::AccountDisabled) => return retry("The account is scheduled for deletion."),
+        Err(TodosError::AccountSuspended) => return retry("The account is disabled."),
+        Err(TodosError::PasswordResetRequired) => {
+            return retry("Reset your password before logging in.")
+        }
+        Err(TodosError::TooManyLoginAttempts(seconds)) => {
+            return retry(&too_many_attempts(seconds))
+        }
+        Err(e) => return Err(e),
+    };
+    if user.totp_enabled_at.is_some() {
+        let code = match form.two_factor_code.as_deref() {
+            Some(code) if !code.trim().is_empty() => code,
+            _ => return retry("Enter a code from your authenticator app or a recovery code."),
+        };
+        match check_second_factor(&user, code, ip, conn) {
+            Ok(()) => {}
+            Err(TodosError::InvalidTotpCode) => {
+                return retry("The two-factor code is wrong or was already used.")
+            }
+            Err(TodosError::TooManyLoginAttempts(seconds)) => {
+                return retry(&too_many_attempts(seconds))
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    let code = auth::generate_token();
+    diesel::insert_into(schema::oauth_authorization_codes::table)
+        .values(models::NewOAuthAuthorizationCode {
+            code_hash: hash_token(&code),
+            oauth_client_id: client.id,
+            user_id: user.id,
+            redirect_uri: redirect_uri.clone(),
+            scope: oauth::Scope::join(&scopes),
+            code_challenge,
+            expires_at: chrono::Utc::now().naive_utc() + chrono::Duration::minutes(10),
+            redirect_uri_sent: request.redirect_uri.is_some(),
+        })
+        .execute(conn)?;
+    let mut params = vec![("code", code.as_str())];
+    if let Some(state) = state {
+        params.push(("state", state));
+    }
+    Ok(models::AuthorizeOutcome::Redirect(
+        oauth::redirect_uri_with(&redirect_uri, &params),
+    ))
+}
+
+/// The client calling the token, introspection or revocation endpoint.
+/// Confidential clients have to send their secret.
+fn authenticate_oauth_client(
+    public_id: Option<&str>,
+    secret: Option<&str>,
+    conn: &PgConnection,
+) -> Result<models::OAuthClient, TodosError> {
+    let invalid_client = || {
+        oauth_error(
+            oauth::OAuthErrorCode::InvalidClient,
+            "The client is unknown or its secret is wrong.",
+        )
+    };
+    let public_id = public_id.ok_or_else(invalid_client)?;
+    let client = schema::oauth_clients::table
+        .filter(schema::oauth_clients::client_id.eq(public_id))
+        .first::<models::OAuthClient>(conn)
+        .optional()?
+        .ok_or_else(invalid_client)?;
+    match (&client.client_secret_hash, secret) {
+        (None, _) => Ok(client),
+        (Some(secret_hash), Some(secret)) if *secret_hash == hash_token(secret) => Ok(client),
+        _ => Err(invalid_client()),
+    }
+}
+
+fn issue_oauth_tokens(
+    client: &models::OAuthClient,
+    uid: i32,
+    authorization_code_id: Option<i32>,
+    scopes: &[oauth::Scope],
+    conn: &PgConnection,
+) -> Result<models::TokenResponse, TodosError> {
+    let access_token = format!("{}{}", oauth::ACCESS_TOKEN_PREFIX, auth::generate_token());
+    let refresh_token = format!("{}{}", oauth::REFRESH_TOKEN_PREFIX, auth::generate_token());
+    let now = chrono::Utc::now().naive_utc();
+    let access_lifetime = oauth_access_token_lifetime();
+    let scope = oauth::Scope::join(scopes);
+    diesel::insert_into(schema::oauth_tokens::table)
+        .values(models::NewOAuthToken {
+            oauth_client_id: client.id,
+            user_id: uid,
+            authorization_code_id,
+            access_token_hash: hash_token(&access_token),
+            refresh_token_hash: hash_token(&refresh_token),
+            scope: scope.clone(),
+            access_expires_at: now + access_lifetime,
+            refresh_expires_at: now + oauth_refresh_token_lifetime(),
+        })
+        .execute(conn)?;
+    Ok(models::TokenResponse {
+        access_token,
+        token_type: "Bearer".to_owned(),
+        expires_in: access_lifetime.num_seconds(),
+        refresh_token,
+        scope,
+    })
+}
+
+fn is_active_user(uid: i32, conn: &PgConnection) -> Result<bool, TodosError> {
+    let user = find_user(uid, conn)?;
+    Ok(ensure_usable(&user).is_ok())
+}
+
+/// The token endpoint: trades an authorization code or a refresh token for
+/// new tokens.
+pub fn exchange_oauth_token(
+    data: models::TokenRequest,
+    request: &audit::RequestInfo,
+    conn: &PgConnection,
+) -> Result<models::TokenResponse, TodosError> {
+    use oauth::OAuthErrorCode::{InvalidGrant, InvalidRequest, InvalidScope, UnsupportedGrantType};
+    let client = authenticate_oauth_client(
+        data.client_id.as_deref(),
+        data.client_secret.as_deref(),
+        conn,
+    )?;
+    let now = chrono::Utc::now().naive_utc();
+
+    match data.grant_type.as_str() {
+        "authorization_code" => {
+            use schema::oauth_authorization_codes::dsl::*;
+            let code = data
+                .code
+                .as_deref()
+                .ok_or_else(|| oauth_error(InvalidRequest, "The code is missing."))?;
+            let invalid_code = || oauth_error(InvalidGrant, "The code is invalid or expired.");
+            let authorization_code = oauth_authorization_codes
+                .filter(code_hash.eq(hash_token(code)))
+                .first::<models::OAuthAuthorizationCode>(conn)
+                .optional()?
+                .ok_or_else(invalid_code)?;
+            if authorization_code.oauth_client_id != client.id {
+                return Err(invalid_code());
+            }
+            if authorization_code.used_at.is_some() {
+                // A code used twice may have been stolen; the tokens issued
+                // for it can't be trusted either (RFC 6749, section 4.1.2).
+                diesel::update(
+                    schema::oauth_tokens::table
+                        .filter(
+                            schema::oauth_tokens::authorization_code_id.eq(authorization_code.id),
+                        )
+                        .filter(schema::oauth_tokens::revoked_at.is_null()),
+                )
+                .set(schema::oauth_tokens::revoked_at.eq(now))
+                .execute(conn)?;
+                return Err(invalid_code());
+            }
+            if authorization_code.expires_at <= now
+                || !is_active_user(authorization_code.user_id, conn)?
+            {
+                return Err(invalid_code());
+            }
+            let redirect_uri_matches = match &data.redirect_uri {
+                Some(uri) => *uri == authorization_code.redirect_uri,
+                None => !authorization_code.redirect_uri_sent,
+            };
+            if !redirect_uri_matches {
+                return Err(oauth_error(
+                    InvalidGrant,
+                    "The redirect URI is missing or doesn't match.",
+                ));
+            }
+            let verified = matches!(
+                data.code_verifier.as_deref(),
+                Some(verifier) if oauth::verify_pkce(&authorization_code.code_challenge, verifier)
+            );
+            if !verified {
+                return Err(oauth_error(InvalidGrant, "The code verifier is wrong."));
+            }
+            let scopes = oauth::Scope::parse_list(Some(&authorization_code.scope))
+                .map_err(TodosError::OAuth)?;
+
+            conn.transaction::<_, TodosError, _>(|| {
+                let claimed = diesel::update(
+                    oauth_authorization_codes
+                        .find(authorization_code.id)
+                        .filter(used_at.is_null()),
+                )
+                .set(used_at.eq(now))
+                .execute(conn)?;
+                if claimed == 0 {
+                    return Err(invalid_code());
+                }
+                issue_oauth_tokens(
+                    &client,
+                    authorization_code.user_id,
+                    Some(authorization_code.id),
+                    &scopes,
+                    conn,
+                )
+            })
+        }
+        "refresh_token" => {
+            use schema::oauth_tokens::dsl::*;
+            let token = data
+                .refresh_token
+                .as_deref()
+                .ok_or_else(|| oauth_error(InvalidRequest, "The refresh token is missing."))?;
+            let invalid_token = || {
+                oauth_error(
+                    InvalidGrant,
+                    "The refresh token is invalid, expired or revoked.",
+                )
+            };
+            let old_token = oauth_tokens
+                .filter(refresh_token_hash.eq(hash_token(token)))
+                .first::<models::OAuthToken>(conn)
+                .optional()?
+                .ok_or_else(invalid_token)?;
+            if old_token.oauth_client_id != client.id
+                || old_token.revoked_at.is_some()
+                || old_token.refresh_expires_at <= now
+                || !is_active_user(old_token.user_id, conn)?
+            {
+                return Err(invalid_token());
+            }
+            let granted =
+                oauth::Scope::parse_list(Some(&old_token.scope)).map_err(TodosError::OAuth)?;
+            let scopes = match &data.scope {
+                Some(requested) => {
+                    let requested =
+                        oauth::Scope::parse_list(Some(requested)).map_err(TodosError::OAuth)?;
+                    if !requested.iter().all(|wanted| granted.contains(wanted)) {
+                        return Err(oauth_error(
+                            InvalidScope,
+                            "The scope is broader than the one granted.",
+                        ));
+                    }
+                    requested
+                }
+                None => granted,
+            };
+
+            conn.transaction::<_, TodosError, _>(|| {
+                let claimed =
+                    diesel::update(oauth_tokens.find(old_token.id).filter(revoked_at.is_null()))
+                        .set(revoked_at.eq(now))
+                        .execute(conn)?;
+                if claimed == 0 {
+                    return Err(invalid_token());
+                }
+                record_event(
+                    models::NewAuditEvent {
+                        user_id: Some(old_token.user_id),
+                        details: Some(format!("OAuth client {}", client.client_id)),
+                        ..request.event(audit::AuditEventKind::TokenRefreshed)
+                    },
+                    conn,
+                )?;
+                issue_oauth_tokens(
+                    &client,
+                    old_token.user_id,
+                    old_token.authorization_code_id,
+                    &scopes,
+                    conn,
+                )
+            })
+        }
+        _ => Err(oauth_error(
+            UnsupportedGrantType,
+            "Only `authorization_code` and `refresh_token` grants are supported.",
+        )),
+    }
+}
+
+/// Finds the token pair an access or refresh token belongs to, and whether
+/// it was the access token.
+fn find_oauth_token(
+    token: &str,
+    conn: &PgConnection,
+) -> Result<Option<(models::OAuthToken, bool)>, TodosError> {
+    use schema::oauth_tokens::dsl::*;
+    let found = if token.starts_with(oauth::ACCESS_TOKEN_PREFIX) {
+        oauth_tokens
+            .filter(access_token_hash.eq(hash_token(token)))
+            .first::<models::OAuthToken>(conn)
+            .optional()?
+            .map(|found| (found, true))
+    } else if token.starts_with(oauth::REFRESH_TOKEN_PREFIX) {
+        oauth_tokens
+            .filter(refresh_token_hash.eq(hash_token(token)))
+            .first::<models::OAuthToken>(conn)
+            .optional()?
+            .map(|found| (found, false))
+    } else {
+        None
+    };
+    Ok(found)
+}
+
+/// Token introspection (RFC 7662), for a client's own tokens.
+pub fn introspect_oauth_token(
+    data: models::TokenForm,
+    conn: &PgConnection,
+) -> Result<models::IntrospectionResponse, TodosError> {
+    let client = authenticate_oauth_client(
+        data.client_id.as_deref(),
+        data.client_secret.as_deref(),
+        conn,
+    )?;
+    let (token, is_access_token) = match find_oauth_token(&data.token, conn)? {
+        Some(found) => found,
+        None => return Ok(Default::default()),
+    };
+    let expires_at = if is_access_token {
+        token.access_expires_at
+    } else {
+        token.refresh_expires_at
+    };
+    let user = find_user(token.user_id, conn)?;
+    if token.oauth_client_id != client.id
+        || token.revoked_at.is_some()
+        || expires_at <= chrono::Utc::now().naive_utc()
+        || ensure_usable(&user).is_err()
+    {
+        return Ok(Default::default());
+    }
+    Ok(models::IntrospectionResponse {
+        active: true,
+        scope: Some(token.scope),
+        client_id: Some(client.client_id),
+        username: Some(user.username),
+        token_type: if is_access_token {
+            Some("Bearer".to_owned())
+        } else {
+            None
+        },
+        exp: Some(expires_at.timestamp()),
+        iat: Some(token.created_at.timestamp()),
+        sub: Some(user.id.to_string()),
+    })
+}
+
+/// Token revocation (RFC 7009). Revoking either token of a pair revokes
+/// both; unknown tokens are ignored.
+pub fn revoke_oauth_token(data: models::TokenForm, conn: &PgConnection) -> Result<(), TodosError> {
+    let client = authenticate_oauth_client(
+        data.client_id.as_deref(),
+        data.client_secret.as_deref(),
+        conn,
+    )?;
+    if let Some((token, _)) = find_oauth_token(&data.token, conn)? {
+        if token.oauth_client_id == client.id && token.revoked_at.is_none() {
+            diesel::update(schema::oauth_tokens::table.find(token.id))
+                .set(schema::oauth_tokens::revoked_at.eq(chrono::Utc::now().naive_utc()))
+                .execute(conn)?;
+        }
+    }
+    Ok(())
+}
+
+/// The user an OAuth access token acts for and the token's id, if it is
+/// still valid and was granted `scope`.
+pub fn authenticate_oauth_token(
+    token: &str,
+    scope: oauth::Scope,
+    conn: &PgConnection,
+) -> Result<(models::User, i32), TodosError> {
+    let token = match find_oauth_token(token, conn)? {
+        Some((token, true)) => token,
+        _ => return Err(TodosError::InvalidOAuthToken),
+    };
+    if token.revoked_at.is_some() || token.access_expires_at <= chrono::Utc::now().naive_utc() {
+        return Err(TodosError::InvalidOAuthToken);
+    }
+    let granted = oauth::Scope::parse_list(Some(&token.scope)).map_err(TodosError::OAuth)?;
+    if !granted.contains(&scope) {
+        return Err(TodosError::InsufficientScope);
+    }
+    let user = find_user(token.user_id, conn).map_err(|e| match e {
+        TodosError::AccountNotFound => TodosError::InvalidOAuthToken,
+        e => e,
+    })?;
+    ensure_usable(&user)?;
+    Ok((user, token.id))
+}
+
+/// Remembers a login about to be sent to the OpenID Connect provider and
+/// returns the URL to send the browser to.
+/// Starts a login at the provider. Returns where to send the browser, and
+/// the hash of the login's `state` for `auth::oidc_state_cookie`.
+pub fn start_oidc_login(
+    provider: &oidc::Provider,
+    discovery: &oidc::Discovery,
+    conn: &PgConnection,
+) -> Result<(String, String), TodosError> {
+    use schema::oidc_login_states::dsl::*;
+    let state = auth::generate_token();
+    let login_nonce = auth::generate_token();
+    let verifier = auth::generate_token();
+    let now = chrono::Utc::now().naive_utc();
+    diesel::delete(oidc_login_states.filter(expires_at.le(now))).execute(conn)?;
+    diesel::insert_into(oidc_login_states)
+        .values(models::OidcLoginState {
+            state_hash: hash_token(&state),
+            nonce: login_nonce.clone(),
+            code_verifier: verifier.clone(),
+            expires_at: now + chrono::Duration::minutes(10),
+        })
+        .execute(conn)?;
+    let location = provider.authorization_url(
+        discovery,
+        &state,
+        &login_nonce,
+        &oauth::pkce_challenge(&verifier),
+    )?;
+    Ok((location, hash_token(&state)))
+}
+
+/// Takes back the login a callback's `state` belongs to. Each one can only
+/// be used once, and only by the browser that started it, which sends the
+/// hash of the state in `state_cookie`; otherwise anyone could have a victim
+/// finish a login they started themselves.
+pub fn take_oidc_login_state(
+    state: &str,
+    state_cookie: Option<&str>,
+    conn: &PgConnection,
+) -> Result<models::OidcLoginState, TodosError> {
+    use schema::oidc_login_states::dsl::*;
+    let hash = hash_token(state);
+    if state_cookie != Some(hash.as_str()) {
+        return Err(TodosError::InvalidOidcState);
+    }
+    let login_state = diesel::delete(oidc_login_states.find(hash))
+        .get_result::<models::OidcLoginState>(conn)
+        .optional()?
+        .ok_or(TodosError::InvalidOidcState)?;
+    if login_state.expires_at <= chrono::Utc::now().naive_utc() {
+        return Err(TodosError::InvalidOidcState);
+    }
+    Ok(login_state)
+}
+
+/// Whether users logging in with the OpenID Connect provider for the first
+/// time get an account, from `OIDC_AUTO_PROVISION` (on by default).
+fn oidc_auto_provision() -> bool {
+    std::env::var("OIDC_AUTO_PROVISION")
+        .map(|provision| provision == "true" || provision == "1")
+        .unwrap_or(true)
+}
+
+/// How many names `unused_username` tries.
+const USERNAME_ATTEMPTS: u32 = 100;
+
+/// `base`, or `base-2`, `base-3` and so on if it is taken, up to
+/// `base-100`.
+fn unused_username(base: &str, conn: &PgConnection) -> Result<String, TodosError> {
+    let base = base
+        .chars()
+        .filter(|c| c.is_alphanumeric() || matches!(c, '.' | '_' | '-'))
+        .collect::<String>();
+    let base = if base.is_empty() { "user" } else { &base };
+    for n in 1..=USERNAME_ATTEMPTS {
+        let candidate = if n == 1 {
+            base.to_owned()
+        } else {
+            format!("{}-{}", base, n)
+        };
+        if !username_taken(&candidate, None, conn)? {
+            return Ok(candidate);
+        }
+    }
+    Err(TodosError::UsernameTaken)
+}
+
+/// The account an external identity belongs to. Identities seen before are
+/// linked already; a new one is linked to the account with the same
+/// verified email address, or gets an account of its own.
+fn find_oidc_user(
+    issuer: &str,
+    claims: &oidc::IdTokenClaims,
+    conn: &PgConnection,
+) -> Result<models::User, TodosError> {
+    let linked = schema::oidc_identities::table
+        .inner_join(schema::users::table)
+        .filter(schema::oidc_identities::issuer.eq(issuer))
+        .filter(schema::oidc_identities::subject.eq(&claims.sub))
+        .select(schema::users::all_columns)
+        .first::<models::User>(conn)
+        .optional()?;
+    if let Some(user) = linked {
+        return Ok(user);
+    }
+
+    // Only an address both sides have verified proves it's the same person.
+    let verified_email = claims
+        .email
+        .as_deref()
+        .filter(|_| claims.email_verified)
+        .and_then(|address| normalize_email(address).ok());
+    let existing = match &verified_email {
+        Some(address) => schema::users::table
+            .filter(schema::users::email.eq(address))
+            .filter(schema::users::email_verified_at.is_not_null())
+            .first::<models::User>(conn)
+            .optional()?,
+        None => None,
+    };
+    let user = match existing {
+        Some(user) => user,
+        None if !oidc_auto_provision() => return Err(TodosError::OidcAccountNotLinked),
+        None => {
+            let base = claims
+                .preferred_username
+                .as_deref()
+                .or_else(|| {
+                    claims
+                        .email
+                        .as_deref()
+                        .and_then(|address| address.split('@').next())
+                })
+                .unwrap_or("user");
+            let user = diesel::insert_into(schema::users::table)
+                .values(models::NewUser {
+                    username: unused_username(base, conn)?,
+                    // Nobody knows this password; the account logs in with
+                    // the provider until its user resets it.
+                    password: hash_password(&auth::generate_token()),
+                    email: verified_email.clone(),
+                })
+                .get_result::<models::User>(conn)?;
+            match &verified_email {
+                Some(address) => {
+                    release_unverified_email(address, conn)?;
+                    diesel::update(schema::users::table.find(user.id))
+                        .set(schema::users::email_verified_at.eq(chrono::Utc::now().naive_utc()))
+                        .get_result::<models::User>(conn)?
+                }
+                None => user,
+            }
+        }
+    };
+    diesel::insert_into(schema::oidc_identities::table)
+        .values(models::NewOidcIdentity {
+            user_id: user.id,
+            issuer: issuer.to_owned(),
+            subject: claims.sub.clone(),
+        })
+        .execute(conn)?;
+    Ok(user)
+}
+
+/// Logs in the user an ID token from `issuer` identifies, as `login_user`
+/// would after checking a password.
+pub fn complete_oidc_login(
+    issuer: &str,
+    claims: oidc::IdTokenClaims,
+    request: &audit::RequestInfo,
+    mailer: &dyn mail::Mailer,
+    conn: &PgConnection,
+) -> Result<models::LoginOutcome, TodosError> {
+    let user = conn.transaction::<_, TodosError, _>(|| find_oidc_user(issuer, &claims, conn))?;
+    if let Err(e) = ensure_usable(&user) {
+        return Err(audit_login_failure(&user.username, e, request, conn));
+    }
+    start_login(user, "single sign-on", request, mailer, conn)
+}
+
+/// The permissions the user's role grants.
+pub fn user_permissions(
+    uid: i32,
+    conn: &PgConnection,
+) -> Result<Vec<roles::Permission>, TodosError> {
+    let names = schema::users::table
+        .inner_join(schema::roles::table)
+        .filter(schema::users::id.eq(uid))
+        .select(schema::roles::permissions)
+        .first::<Vec<String>>(conn)
+        .map_err(|e| match e {
+            diesel::result::Error::NotFound => TodosError::AccountNotFound,
+            _ => TodosError::DieselCrudError,
+        })?;
+    Ok(names
+        .iter()
+        .filter_map(|name| roles::Permission::parse(name))
+        .collect())
+}
+
+/// Fails unless the user's role grants `permission`.
+pub fn ensure_permission(
+    uid: i32,
+    permission: roles::Permission,
+    conn: &PgConnection,
+) -> Result<(), TodosError> {
+    if user_permissions(uid, conn)?.contains(&permission) {
+        Ok(())
+    } else {
+        Err(TodosError::MissingPermission(permission))
+    }
+}
+
+/// A page of the users matching `query`, by id.
+pub fn search_users(
+    query: models::UserSearchQuery,
+    conn: &PgConnection,
+) -> Result<models::UserPage, TodosError> {
+    use schema::users::dsl::*;
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query.per_page.unwrap_or(50).clamp(1, 200);
+    let pattern = query.q.as_deref().map(|q| {
+        let escaped = q
+            .trim()
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_");
+        format!("%{}%", escaped)
+    });
+    let filtered = || {
+        let mut filtered = users.into_boxed();
+        if let Some(pattern) = &pattern {
+            filtered = filtered.filter(username.ilike(pattern).or(email.ilike(pattern)));
+        }
+        if let Some(wanted) = &query.role {
+            filtered = filtered.filter(role.eq(wanted));
+        }
+        match query.disabled {
+            Some(true) => filtered = filtered.filter(disabled_at.is_not_null()),
+            Some(false) => filtered = filtered.filter(disabled_at.is_null()),
+            None => {}
+        }
+        filtered
+    };
+    let total = filtered().count().get_result::<i64>(conn)?;
+    let found = filtered()
+        .order(id)
+        .limit(per_page)
+        .offset((page - 1) * per_page)
+        .load::<models::User>(conn)?;
+    Ok(models::UserPage {
+        users: found.into_iter().map(models::AdminUser::from).collect(),
+        total,
+        page,
+        per_page,
+    })
+}
+
+pub fn get_user_for_admin(uid: i32, conn: &PgConnection) -> Result<models::AdminUser, TodosError> {
+    find_user(uid, conn).map(models::AdminUser::from)
+}
+
+/// Finds the id of the user with `name`.
+pub fn find_user_id(name: &str, conn: &PgConnection) -> Result<i32, TodosError> {
+    use schema::users::dsl::*;
+    users
+        .filter(username.eq(name))
+        .select(id)
+        .first::<i32>(conn)
+        .map_err(|e| match e {
+            diesel::result::Error::NotFound => TodosError::AccountNotFound,
+            _ => TodosError::DieselCrudError,
+        })
+}
+
+/// How much the user keeps in their account, for spotting abuse.
+pub fn user_stats(uid: i32, conn: &PgConnection) -> Result<models::UserStats, TodosError> {
+    find_user(uid, conn)?;
+    let now = chrono::Utc::now().naive_utc();
+    let todo_count = schema::todos::table
+        .filter(schema::todos::user_id.eq(uid))
+        .count()
+        .get_result::<i64>(conn)?;
+    let done_todo_count = schema::todos::table
+        .filter(schema::todos::user_id.eq(uid))
+        .filter(schema::todos::done.eq(true))
+        .count()
+        .get_result::<i64>(conn)?;
+    let last_todo_change_at = schema::todos::table
+        .filter(schema::todos::user_id.eq(uid))
+        .select(diesel::dsl::max(schema::todos::updated_at))
+        .first::<Option<NaiveDateTime>>(conn)?;
+    let import_job_count = schema::import_jobs::table
+        .filter(schema::import_jobs::user_id.eq(uid))
+        .count()
+        .get_result::<i64>(conn)?;
+    let oauth_client_count = schema::oauth_clients::table
+        .filter(schema::oauth_clients::user_id.eq(uid))
+        .count()
+        .get_result::<i64>(conn)?;
+    let active_oauth_grant_count = schema::oauth_tokens::table
+        .filter(schema::oauth_tokens::user_id.eq(uid))
+        .filter(schema::oauth_tokens::revoked_at.is_null())
+        .filter(schema::oauth_tokens::refresh_expires_at.gt(now))
+        .count()
+        .get_result::<i64>(conn)?;
+    let linked_identity_count = schema::oidc_identities::table
+        .filter(schema::oidc_identities::user_id.eq(uid))
+        .count()
+        .get_result::<i64>(conn)?;
+    Ok(models::UserStats {
+        user_id: uid,
+        todo_count,
+        done_todo_count,
+        import_job_count,
+        oauth_client_count,
+        active_oauth_grant_count,
+        linked_identity_count,
+        last_todo_change_at,
+    })
+}
+
+/// Disables or re-enables an account. While it is disabled, nothing can
+/// log in to it and its tokens are refused.
+pub fn set_user_disabled(
+    admin_id: i32,
+    uid: i32,
+    disabled: bool,
+    request: &audit::RequestInfo,
+    conn: &PgConnection,
+) -> Result<models::AdminUser, TodosError> {
+    use schema::users::dsl::*;
+    if admin_id == uid {
+        return Err(TodosError::OwnAccount);
+    }
+    let now = chrono::Utc::now().naive_utc();
+    let user = diesel::update(users.find(uid))
+        .set(disabled_at.eq(if disabled { Some(now) } else { None }))
+        .get_result::<models::User>(conn)
+        .map_err(|e| match e {
+            diesel::result::Error::NotFound => TodosError::AccountNotFound,
+            _ => TodosError::DieselCrudError,
+        })?;
+    let kind = if disabled {
+        audit::AuditEventKind::UserDisabled
+    } else {
+        audit::AuditEventKind::UserEnabled
+    };
+    record_event(
+        models::NewAuditEvent {
+            user_id: Some(uid),
+            username: Some(user.username.clone()),
+            actor_id: Some(admin_id),
+            ..request.event(kind)
+        },
+        conn,
+    )?;
+    Ok(user.into())
+}
+
+/// Revokes every token issued to the user so far: their logins and the
+/// grants of their OAuth apps.
+pub fn revoke_user_sessions(
+    admin_id: i32,
+    uid: i32,
+    request: &audit::RequestInfo,
+    conn: &PgConnection,
+) -> Result<(), TodosError> {
+    let user = find_user(uid, conn)?;
+    conn.transaction::<_, TodosError, _>(|| {
+        revoke_all_tokens(uid, conn)?;
+        record_event(
+            models::NewAuditEvent {
+                user_id: Some(uid),
+                username: Some(user.username),
+                actor_id: Some(admin_id),
+                ..request.event(audit::AuditEventKind::SessionsRevoked)
+            },
+            conn,
+        )
+    })
+}
+
+fn revoke_all_tokens(uid: i32, conn: &PgConnection) -> Result<(), TodosError> {
+    let now = chrono::Utc::now().naive_utc();
+    conn.transaction::<_, TodosError, _>(|| {
+        diesel::update(schema::users::table.find(uid))
+            .set(schema::users::sessions_revoked_at.eq(now))
+            .execute(conn)?;
+        end_sessions(uid, conn)?;
+        diesel::update(
+            schema::oauth_tokens::table
+                .filter(schema::oauth_tokens::user_id.eq(uid))
+                .filter(schema::oauth_tokens::revoked_at.is_null()),
+        )
+        .set(schema::oauth_tokens::revoked_at.eq(now))
+        .execute(conn)?;
+        Ok(())
+    })
+}
+
+/// Makes the user pick a new password: the current one stops working, the
+/// user is logged out everywhere and, if they have a verified address, is
+/// mailed a reset token.
+pub fn force_password_reset(
+    admin_id: i32,
+    uid: i32,
+    request: &audit::RequestInfo,
+    mailer: &dyn mail::Mailer,
+    conn: &PgConnection,
+) -> Result<models::ForcedPasswordReset, TodosError> {
+    let user = find_user(uid, conn)?;
+    conn.transaction::<_, TodosError, _>(|| {
+        diesel::update(schema::users::table.find(uid))
+            .set(schema::users::password_reset_required.eq(true))
+            .execute(conn)?;
+        revoke_all_tokens(uid, conn)?;
+        record_event(
+            models::NewAuditEvent {
+                user_id: Some(uid),
+                username: Some(user.username.clone()),
+                actor_id: Some(admin_id),
+                ..request.event(audit::AuditEventKind::PasswordResetForced)
+            },
+            conn,
+        )?;
+        let email_sent = send_password_reset(
+            &user,
+            "an administrator asked you to choose a new password for your todos account. \
+             Until you do, your current one can't be used to log in. Send this token with \
+             your new password to POST /users/password-reset/confirm",
+            "",
+            mailer,
+            conn,
+        )?;
+        Ok(models::ForcedPasswordReset { email_sent })
+    })
+}
+
+pub fn list_roles(conn: &PgConnection) -> Result<Vec<models::Role>, TodosError> {
+    schema::roles::table
+        .order(schema::roles::name)
+        .load::<models::Role>(conn)
+        .map_err(|_| TodosError::DieselCrudError)
+}
+
+fn permission_names(permissions: &[roles::Permission]) -> Vec<String> {
+    let mut permissions = permissions.to_vec();
+    permissions.sort();
+    permissions.dedup();
+    permissions
+        .iter()
+        .map(|permission| permission.as_str().to_owned())
+        .collect()
+}
+
+pub fn create_role(
+    admin_id: i32,
+    data: models::NewRoleBody,
+    request: &audit::RequestInfo,
+    conn: &PgConnection,
+) -> Result<models::Role, TodosError> {
+    use schema::roles::dsl::*;
+    if !crate::roles::is_valid_role_name(&data.name) {
+        return Err(TodosError::InvalidRoleName);
+    }
+    conn.transaction::<_, TodosError, _>(|| {
+        let existing = roles
+            .find(&data.name)
+            .first::<models::Role>(conn)
+            .optional()?;
+        if existing.is_some() {
+            return Err(TodosError::RoleExists);
+        }
+        let role = diesel::insert_into(roles)
+            .values(models::NewRole {
+                name: data.name,
+                description: data.description,
+                permissions: permission_names(&data.permissions),
+            })
+            .get_result::<models::Role>(conn)?;
+        record_role_event(
+            admin_id,
+            audit::AuditEventKind::RoleCreated,
+            &role,
+            request,
+            conn,
+        )?;
+        Ok(role)
+    })
+}
+
+fn record_role_event(
+    admin_id: i32,
+    kind: audit::AuditEventKind,
+    role: &models::Role,
+    request: &audit::RequestInfo,
+    conn: &PgConnection,
+) -> Result<(), TodosError> {
+    record_event(
+        models::NewAuditEvent {
+            actor_id: Some(admin_id),
+            details: Some(format!("{}: {}", role.name, role.permissions.join(" "))),
+            ..request.event(kind)
+        },
+        conn,
+    )
+}
+
+fn find_role(role_name: &str, conn: &PgConnection) -> Result<models::Role, TodosError> {
+    schema::roles::table
+        .find(role_name)
+        .first::<models::Role>(conn)
+        .map_err(|e| match e {
+            diesel::result::Error::NotFound => TodosError::RoleNotFound,
+            _ => TodosError::DieselCrudError,
+        })
+}
+
+pub fn update_role(
+    admin_id: i32,
+    role_name: &str,
+    data: models::UpdateRoleBody,
+    request: &audit::RequestInfo,
+    conn: &PgConnection,
+) -> Result<models::Role, TodosError> {
+    use schema::roles::dsl::*;
+    let role = find_role(role_name, conn)?;
+    if role.built_in {
+        return Err(TodosError::RoleBuiltIn);
+    }
+    let new_description = data.description.unwrap_or(role.description);
+    let new_permissions = match &data.permissions {
+        Some(new_permissions) => permission_names(new_permissions),
+        None => role.permissions,
+    };
+    let role = diesel::update(roles.find(role_name))
+        .set((
+            description.eq(new_description),
+            permissions.eq(new_permissions),
+        ))
+        .get_result::<models::Role>(conn)
+        .map_err(|e| match e {
+            diesel::result::Error::NotFound => TodosError::RoleNotFound,
+            _ => TodosError::DieselCrudError,
+        })?;
+    record_role_event(
+        admin_id,
+        audit::AuditEventKind::RoleUpdated,
+        &role,
+        request,
+        conn,
+    )?;
+    Ok(role)
+}
+
+/// Deletes a custom role nobody has any more.
+pub fn delete_role(
+    admin_id: i32,
+    role_name: &str,
+    request: &audit::RequestInfo,
+    conn: &PgConnection,
+) -> Result<(), TodosError> {
+    let role = find_role(role_name, conn)?;
+    if role.built_in {
+        return Err(TodosError::RoleBuiltIn);
+    }
+    conn.transaction::<_, TodosError, _>(|| {
+        let holders = schema::users::table
+            .filter(schema::users::role.eq(role_name))
+            .count()
+            .get_result::<i64>(conn)?;
+        if holders > 0 {
+            return Err(TodosError::RoleInUse);
+        }
+        diesel::delete(schema::roles::table.find(role_name)).execute(conn)?;
+        record_role_event(
+            admin_id,
+            audit::AuditEventKind::RoleDeleted,
+            &role,
+            request,
+            conn,
+        )
+    })
+}
+
+/// Gives the user a role. `admin_id` is the administrator doing it, who
+/// can't change their own role; `None` for the `set-role` command.
+pub fn set_user_role(
+    admin_id: Option<i32>,
+    uid: i32,
+    role_name: &str,
+    request: &audit::RequestInfo,
+    conn: &PgConnection,
+) -> Result<models::AdminUser, TodosError> {
+    if admin_id == Some(uid) {
+        return Err(TodosError::OwnAccount);
+    }
+    find_role(role_name, conn)?;
+    let user = diesel::update(schema::users::table.find(uid))
+        .set(schema::users::role.eq(role_name))
+        .get_result::<models::User>(conn)
+        .map_err(|e| match e {
+            diesel::result::Error::NotFound => TodosError::AccountNotFound,
+            _ => TodosError::DieselCrudError,
+        })?;
+    record_event(
+        models::NewAuditEvent {
+            user_id: Some(uid),
+            username: Some(user.username.clone()),
+            actor_id: admin_id,
+            details: Some(role_name.to_owned()),
+            ..request.event(audit::AuditEventKind::RoleChanged)
+        },
+        conn,
+    )?;
+    Ok(user.into())
+}
+
+/// Whether `slug` can select a workspace: lowercase letters, digits and
+/// inner `-`, and not only digits, so that it can't be mistaken for an id.
+fn is_valid_workspace_slug(slug: &str) -> bool {
+    (1..=40).contains(&slug.len())
+        && slug
+            .bytes()
+            .all(|byte| byte.is_ascii_lowercase() || byte.is_ascii_digit() || byte == b'-')
+        && !slug.starts_with('-')
+        && !slug.ends_with('-')
+        && !slug.bytes().all(|byte| byte.is_ascii_digit())
+}
+
+/// A slug made from a workspace name, such as `design-team` for "Design Team".
+fn slugify(name: &str) -> String {
+    let mut slug = String::new();
+    for c in name.chars().flat_map(char::to_lowercase) {
+        if c.is_ascii_lowercase() || c.is_ascii_digit() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.truncate(40);
+    slug.trim_end_matches('-').to_owned()
+}
+
+/// The user's role in the workspace; `WorkspaceNotFound` if they aren't a
+/// member, so that non-members can't tell which workspaces exist.
+fn workspace_role(
+    uid: i32,
+    wid: i32,
+    conn: &PgConnection,
+) -> Result<models::WorkspaceRole, TodosError> {
+    use schema::workspace_members::dsl::*;
+    let member_role = workspace_members
+        .find((wid, uid))
+        .select(role)
+        .first::<String>(conn)
+        .map_err(|e| match e {
+            diesel::result::Error::NotFound => TodosError::WorkspaceNotFound,
+            _ => TodosError::DieselCrudError,
+        })?;
+    Ok(models::WorkspaceRole::parse(&member_role).unwrap_or_default())
+}
+
+/// Fails unless the user is a member with at least the role `least`.
+fn ensure_workspace_role(
+    uid: i32,
+    wid: i32,
+    least: models::WorkspaceRole,
+    conn: &PgConnection,
+) -> Result<models::WorkspaceRole, TodosError> {
+    let member_role = workspace_role(uid, wid, conn)?;
+    if member_role >= least {
+        Ok(member_role)
+    } else {
+        Err(TodosError::WorkspaceForbidden)
+    }
+}
+
+/// The tenant the `X-Workspace` header selects, by id or slug, or the user's
+/// personal todos without it.
+pub fn resolve_tenant(
+    uid: i32,
+    selector: Option<&str>,
+    conn: &PgConnection,
+) -> Result<models::Tenant, TodosError> {
+    use schema::workspaces::dsl::*;
+    let selector = match selector.map(str::trim) {
+        None | Some("") => return Ok(models::Tenant::Personal { user_id: uid }),
+        Some(selector) => selector,
+    };
+    let wid = match selector.parse::<i32>() {
+        Ok(wid) => wid,
+        Err(_) => workspaces
+            .filter(slug.eq(selector))
+            .select(id)
+            .first::<i32>(conn)
+            .map_err(|e| match e {
+                diesel::result::Error::NotFound => TodosError::WorkspaceNotFound,
+                _ => TodosError::DieselCrudError,
+            })?,
+    };
+    workspace_role(uid, wid, conn)?;
+    Ok(models::Tenant::Workspace {
+        workspace_id: wid,
+        user_id: uid,
+    })
+}
+
+fn workspace_info(
+    workspace: models::Workspace,
+    role: models::WorkspaceRole,
+) -> models::WorkspaceInfo {
+    models::WorkspaceInfo {
+        id: workspace.id,
+        name: workspace.name,
+        slug: workspace.slug,
+        role,
+        created_at: workspace.created_at,
+    }
+}
+
+/// Creates a workspace with the user as its owner.
+pub fn create_workspace(
+    uid: i32,
+    data: models::NewWorkspaceBody,
+    conn: &PgConnection,
+) -> Result<models::WorkspaceInfo, TodosError> {
+    use schema::workspaces::dsl::*;
+    let new_name = data.name.trim().to_owned();
+    let new_slug = data.slug.unwrap_or_else(|| slugify(&new_name));
+    if new_name.is_empty() || !is_valid_workspace_slug(&new_slug) {
+        return Err(TodosError::InvalidWorkspace);
+    }
+    conn.transaction::<_, TodosError, _>(|| {
+        let taken = workspaces
+            .filter(slug.eq(&new_slug))
+            .select(id)
+            .first::<i32>(conn)
+            .optional()?;
+        if taken.is_some() {
+            return Err(TodosError::WorkspaceSlugTaken);
+        }
+        let workspace = diesel::insert_into(workspaces)
+            .values(models::NewWorkspace {
+                name: new_name,
+                slug: new_slug,
+            })
+            .get_result::<models::Workspace>(conn)?;
+        diesel::insert_into(schema::workspace_members::table)
+            .values((
+                schema::workspace_members::workspace_id.eq(workspace.id),
+                schema::workspace_members::user_id.eq(uid),
+                schema::workspace_members::role.eq(models::WorkspaceRole::Owner.as_str()),
+            ))
+            .execute(conn)?;
+        Ok(workspace_info(workspace, models::WorkspaceRole::Owner))
+    })
+}
+
+/// The workspaces the user is a member of.
+pub fn list_workspaces(
+    uid: i32,
+    conn: &PgConnection,
+) -> Result<Vec<models::WorkspaceInfo>, TodosError> {
+    let rows = schema::workspaces::table
+        .inner_join(schema::workspace_members::table)
+        .filter(schema::workspace_members::user_id.eq(uid))
+        .order(schema::workspaces::id)
+        .select((
+            schema::workspaces::all_columns,
+            schema::workspace_members::role,
+        ))
+        .load::<(models::Workspace, String)>(conn)
+        .map_err(|_| TodosError::DieselCrudError)?;
+    Ok(rows
+        .into_iter()
+        .map(|(workspace, role)| {
+            workspace_info(
+                workspace,
+                models::WorkspaceRole::parse(&role).unwrap_or_default(),
+            )
+        })
+        .collect())
+}
+
+pub fn get_workspace(
+    uid: i32,
+    wid: i32,
+    conn: &PgConnection,
+) -> Result<models::WorkspaceInfo, TodosError> {
+    let role = workspace_role(uid, wid, conn)?;
+    let workspace = schema::workspaces::table
+        .find(wid)
+        .first::<models::Workspace>(conn)
+        .map_err(|e| match e {
+            diesel::result::Error::NotFound => TodosError::WorkspaceNotFound,
+            _ => TodosError::DieselCrudError,
+        })?;
+    Ok(workspace_info(workspace, role))
+}
+
+/// Deletes a workspace with all of its todos. Only owners can.
+pub fn delete_workspace(uid: i32, wid: i32, conn: &PgConnection) -> Result<(), TodosError> {
+    ensure_workspace_role(uid, wid, models::WorkspaceRole::Owner, conn)?;
+    diesel::delete(schema::workspaces::table.find(wid)).execute(conn)?;
+    Ok(())
+}
+
+pub fn list_workspace_members(
+    uid: i32,
+    wid: i32,
+    conn: &PgConnection,
+) -> Result<Vec<models::MemberInfo>, TodosError> {
+    workspace_role(uid, wid, conn)?;
+    let rows = schema::workspace_members::table
+        .inner_join(schema::users::table)
+        .filter(schema::workspace_members::workspace_id.eq(wid))
+        .order(schema::workspace_members::joined_at)
+        .select((
+            schema::workspace_members::all_columns,
+            schema::users::username,
+        ))
+        .load::<(models::WorkspaceMember, String)>(conn)
+        .map_err(|_| TodosError::DieselCrudError)?;
+    Ok(rows
+        .into_iter()
+        .map(|(member, username)| models::MemberInfo {
+            user_id: member.user_id,
+            username,
+            role: models::WorkspaceRole::parse(&member.role).unwrap_or_default(),
+            joined_at: member.joined_at,
+        })
+        .collect())
+}
+
+/// Fails if `member_id` is the workspace's only owner.
+fn ensure_other_owner(wid: i32, member_id: i32, conn: &PgConnection) -> Result<(), TodosError> {
+    use schema::workspace_members::dsl::*;
+    let other_owners = workspace_members
+        .filter(workspace_id.eq(wid))
+        .filter(role.eq(models::WorkspaceRole::Owner.as_str()))
+        .filter(user_id.ne(member_id))
+        .count()
+        .get_result::<i64>(conn)?;
+    if other_owners > 0 {
+        Ok(())
+    } else {
+        Err(TodosError::LastOwner)
+    }
+}
+
+/// Gives a member another role. Only owners can, and the last owner can't
+/// step down.
+pub fn update_workspace_member(
+    uid: i32,
+    wid: i32,
+    member_id: i32,
+    new_role: models::WorkspaceRole,
+    conn: &PgConnection,
+) -> Result<models::MemberInfo, TodosError> {
+    use schema::workspace_members::dsl::*;
+    ensure_workspace_role(uid, wid, models::WorkspaceRole::Owner, conn)?;
+    conn.transaction::<_, TodosError, _>(|| {
+        let current_role = workspace_role(member_id, wid, conn)?;
+        if current_role == models::WorkspaceRole::Owner && new_role != current_role {
+            ensure_other_owner(wid, member_id, conn)?;
+        }
+        let member = diesel::update(workspace_members.find((wid, member_id)))
+            .set(role.eq(new_role.as_str()))
+            .get_result::<models::WorkspaceMember>(conn)
+            .map_err(|e| match e {
+                diesel::result::Error::NotFound => TodosError::WorkspaceNotFound,
+                _ => TodosError::DieselCrudError,
+            })?;
+        let username = schema::users::table
+            .find(member_id)
+            .select(schema::users::username)
+            .first::<String>(conn)
+            .map_err(|e| match e {
+                diesel::result::Error::NotFound => TodosError::AccountNotFound,
+                _ => TodosError::DieselCrudError,
+            })?;
+        Ok(models::MemberInfo {
+            user_id: member.user_id,
+            username,
+            role: new_role,
+            joined_at: member.joined_at,
+        })
+    })
+}
+
+/// Removes a member. Members can leave on their own; admins can remove
+/// members and other admins, and owners anyone. The last owner can't leave.
+pub fn remove_workspace_member(
+    uid: i32,
+    wid: i32,
+    member_id: i32,
+    conn: &PgConnection,
+) -> Result<(), TodosError> {
+    let own_role = workspace_role(uid, wid, conn)?;
+    conn.transaction::<_, TodosError, _>(|| {
+        let member_role = workspace_role(member_id, wid, conn)?;
+        if member_id != uid && (own_role < models::WorkspaceRole::Admin || own_role < member_role) {
+            return Err(TodosError::WorkspaceForbidden);
+        }
+        if member_role == models::WorkspaceRole::Owner {
+            ensure_other_owner(wid, member_id, conn)?;
+        }
+        diesel::delete(schema::workspace_members::table.find((wid, member_id))).execute(conn)?;
+        Ok(())
+    })
+}
+
+/// How long workspace invitations are valid, from
+/// `WORKSPACE_INVITATION_DAYS` (7 by default).
+fn workspace_invitation_lifetime() -> chrono::Duration {
+    let days = std::env::var("WORKSPACE_INVITATION_DAYS")
+        .map(|days| days.parse().expect("WORKSPACE_INVITATION_DAYS"))
+        .unwrap_or(7);
+    chrono::Duration::days(days)
+}
+
+/// Mails an invitation token to an address. Admins and owners can invite,
+/// as members or admins; owners are made by changing a member's role.
+pub fn invite_to_workspace(
+    uid: i32,
+    wid: i32,
+    data: models::NewInvitationBody,
+    mailer: &dyn mail::Mailer,
+    conn: &PgConnection,
+) -> Result<models::InvitationInfo, TodosError> {
+    let workspace = get_workspace(uid, wid, conn)?;
+    if workspace.role < models::WorkspaceRole::Admin || data.role == models::WorkspaceRole::Owner {
+        return Err(TodosError::WorkspaceForbidden);
+    }
+    let address = normalize_email(&data.email)?;
+    let inviter = find_user(uid, conn)?;
+    let token = auth::generate_token();
+    let lifetime = workspace_invitation_lifetime();
+    let invitation = diesel::insert_into(schema::workspace_invitations::table)
+        .values(models::NewWorkspaceInvitation {
+            workspace_id: wid,
+            token_hash: hash_token(&token),
+            email: address.clone(),
+            role: data.role.as_str().to_owned(),
+            invited_by: uid,
+            expires_at: chrono::Utc::now().naive_utc() + lifetime,
+        })
+        .get_result::<models::WorkspaceInvitation>(conn)?;
+    mailer.send(&mail::Email {
+        to: address,
+        subject: format!("Join {} on todos", workspace.name),
+        body: format!(
+            "Hi,\n\n\
+             {} invited you to the workspace {}. To join it, verify this address on \
+             your todos account and send this token to POST /workspaces/invitations/accept \
+             within {} days:\n\n{}\n",
+            inviter.username,
+            workspace.name,
+            lifetime.num_days(),
+            token
+        ),
+    })?;
+    Ok(invitation.into())
+}
+
+/// The workspace's invitations, newest first. Only admins and owners see
+/// them.
+pub fn list_workspace_invitations(
+    uid: i32,
+    wid: i32,
+    conn: &PgConnection,
+) -> Result<Vec<models::InvitationInfo>, TodosError> {
+    use schema::workspace_invitations::dsl::*;
+    ensure_workspace_role(uid, wid, models::WorkspaceRole::Admin, conn)?;
+    let invitations = workspace_invitations
+        .filter(workspace_id.eq(wid))
+        .order(id.desc())
+        .load::<models::WorkspaceInvitation>(conn)
+        .map_err(|_| TodosError::DieselCrudError)?;
+    Ok(invitations.into_iter().map(Into::into).collect())
+}
+
+/// Joins the workspace an invitation is for. The user's verified address
+/// must be the one the invitation was sent to.
+pub fn accept_workspace_invitation(
+    uid: i32,
+    token: &str,
+    conn: &PgConnection,
+) -> Result<models::WorkspaceInfo, TodosError> {
+    use schema::workspace_invitations::dsl::*;
+    let user = find_user(uid, conn)?;
+    let now = chrono::Utc::now().naive_utc();
+    let invitation_result = conn.transaction::<_, TodosError, _>(|| {
+        let invitation = workspace_invitations
+            .filter(token_hash.eq(hash_token(token)))
+            .filter(accepted_at.is_null())
+            .filter(expires_at.gt(now))
+            .first::<models::WorkspaceInvitation>(conn)
+            .map_err(|e| match e {
+                diesel::result::Error::NotFound => TodosError::InvalidInvitation,
+                _ => TodosError::DieselCrudError,
+            })?;
+        match (&user.email, user.email_verified_at) {
+            (Some(address), Some(_)) if *address == invitation.email => {}
+            _ => return Err(TodosError::InvalidInvitation),
+        }
+        match workspace_role(uid, invitation.workspace_id, conn) {
+            Ok(_) => return Err(TodosError::AlreadyMember),
+            Err(TodosError::WorkspaceNotFound) => {}
+            Err(e) => return Err(e),
+        }
+        diesel::insert_into(schema::workspace_members::table)
+            .values((
+                schema::workspace_members::workspace_id.eq(invitation.workspace_id),
+                schema::workspace_members::user_id.eq(uid),
+                schema::workspace_members::role.eq(&invitation.role),
+            ))
+            .execute(conn)?;
+        diesel::update(&invitation)
+            .set(accepted_at.eq(now))
+            .execute(conn)?;
+        Ok(invitation)
+    })?;
+    get_workspace(uid, invitation_result.workspace_id, conn)
+}
+
+/// Appends an event to the security log.
+fn record_event(event: models::NewAuditEvent, conn: &PgConnection) -> Result<(), TodosError> {
+    diesel::insert_into(schema::audit_events::table)
+        .values(event)
+        .execute(conn)
+        .map_err(|_| TodosError::DieselCrudError)?;
+    Ok(())
+}
+
+/// The events about the user, newest first.
+pub fn security_log(
+    uid: i32,
+    query: models::SecurityLogQuery,
+    conn: &PgConnection,
+) -> Result<models::AuditEventPage, TodosError> {
+    search_audit_events(
+        models::AuditEventQuery {
+            user_id: None,
+            event: None,
+            ip: None,
+            since: None,
+            until: None,
+            page: query.page,
+            per_page: query.per_page,
+        },
+        Some(uid),
+        conn,
+    )
+}
+
+/// A page of the events matching `query`, newest first. `subject` limits
+/// them to the events about one user.
+pub fn search_audit_events(
+    query: models::AuditEventQuery,
+    subject: Option<i32>,
+    conn: &PgConnection,
+) -> Result<models::AuditEventPage, TodosError> {
+    use schema::audit_events::dsl::*;
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query.per_page.unwrap_or(50).clamp(1, 200);
+    let filtered = || {
+        let mut filtered = audit_events.into_boxed();
+        if let Some(uid) = subject {
+            filtered = filtered.filter(user_id.eq(uid));
+        }
+        if let Some(uid) = query.user_id {
+            filtered = filtered.filter(user_id.eq(uid).or(actor_id.eq(uid)));
+        }
+        if let Some(wanted) = &query.event {
+            filtered = filtered.filter(event.eq(wanted));
+        }
+        if let Some(wanted) = &query.ip {
+            filtered = filtered.filter(ip.eq(wanted));
+        }
+        if let Some(since) = query.since {
+            filtered = filtered.filter(occurred_at.ge(since));
+        }
+        if let Some(until) = query.until {
+            filtered = filtered.filter(occurred_at.lt(until));
+        }
+        filtered
+    };
+    let total = filtered().count().get_result::<i64>(conn)?;
+    let events = filtered()
+        .order(id.desc())
+        .limit(per_page)
+        .offset((page - 1) * per_page)
+        .load::<models::AuditEvent>(conn)?;
+    Ok(models::AuditEventPage {
+        events,
+        total,
+        page,
+        per_page,
+    })
+}
+
+/// How often, in seconds, a session's `last_seen_at` is written down.
+const SESSION_TOUCH_INTERVAL: i64 = 60;
 
-    let token = auth::create_jwt(user.id, user.username.clone())
+/// Starts a session for a login from `request` and hands out its token.
+fn open_session(
+    user: &models::User,
+    request: &audit::RequestInfo,
+    conn: &PgConnection,
+) -> Result<models::JwtUser, TodosError> {
+    let session = diesel::insert_into(schema::sessions::table)
+        .values(models::NewSession {
+            user_id: user.id,
+            expires_at: chrono::Utc::now().naive_utc()
+                + chrono::Duration::minutes(auth::TOKEN_LIFETIME_MINUTES),
+            ip: request.ip.clone(),
+            user_agent: request.user_agent.clone(),
+            client_name: request.client_name.clone(),
+        })
+        .get_result::<models::Session>(conn)?;
+    let token = auth::create_jwt(user.id, user.username.clone(), session.id)
         .map_err(|_| TodosError::JwtTokenCreationError)?;
     Ok(models::JwtUser {
         id: user.id,
         token,
-        username: user.username,
+        username: user.username.clone(),
+    })
+}
+
+/// Checks that the session a token belongs to wasn't revoked, and notes
+/// that it was used.
+fn touch_session(uid: i32, sid: i32, conn: &PgConnection) -> Result<(), TodosError> {
+    use schema::sessions::dsl::*;
+    let session = sessions
+        .find(sid)
+        .filter(user_id.eq(uid))
+        .filter(revoked_at.is_null())
+        .first::<models::Session>(conn)
+        .optional()?
+        .ok_or(TodosError::TokenRevoked)?;
+    let now = chrono::Utc::now().naive_utc();
+    if (now - session.last_seen_at).num_seconds() >= SESSION_TOUCH_INTERVAL {
+        diesel::update(sessions.find(sid))
+            .set(last_seen_at.eq(now))
+            .execute(conn)?;
+    }
+    Ok(())
+}
+
+/// Checks again that the session or OAuth token `user` was authenticated
+/// with hasn't been revoked, and that the account can still be used, for
+/// responses such as event streams that outlive their request.
+pub fn ensure_still_signed_in(
+    user: &auth::AuthUser,
+    conn: &PgConnection,
+) -> Result<(), TodosError> {
+    ensure_usable(&find_user(user.id, conn)?)?;
+    let open = if let Some(sid) = user.session_id {
+        use schema::sessions::dsl::*;
+        sessions
+            .find(sid)
+            .filter(revoked_at.is_null())
+            .select(id)
+            .first::<i32>(conn)
+            .optional()?
+            .is_some()
+    } else if let Some(token_id) = user.oauth_token_id {
+        use schema::oauth_tokens::dsl::*;
+        oauth_tokens
+            .find(token_id)
+            .filter(revoked_at.is_null())
+            .select(id)
+            .first::<i32>(conn)
+            .optional()?
+            .is_some()
+    } else {
+        true
+    };
+    if open {
+        Ok(())
+    } else {
+        Err(TodosError::TokenRevoked)
+    }
+}
+
+/// Revokes every session of the user.
+fn end_sessions(uid: i32, conn: &PgConnection) -> QueryResult<usize> {
+    use schema::sessions::dsl::*;
+    diesel::update(
+        sessions
+            .filter(user_id.eq(uid))
+            .filter(revoked_at.is_null()),
+    )
+    .set(revoked_at.eq(chrono::Utc::now().naive_utc()))
+    .execute(conn)
+}
+
+/// Whether the user registered or logged in before, but never with the user
+/// agent of `request`. The first login of an account isn't from a new
+/// device.
+fn is_new_device(
+    uid: i32,
+    request: &audit::RequestInfo,
+    conn: &PgConnection,
+) -> Result<bool, TodosError> {
+    use schema::audit_events::dsl::*;
+    let earlier = || {
+        audit_events
+            .select(id)
+            .filter(user_id.eq(uid))
+            .filter(event.eq_any(vec![
+                audit::AuditEventKind::UserRegistered.as_str(),
+                audit::AuditEventKind::LoginSucceeded.as_str(),
+            ]))
+            .into_boxed()
+    };
+    if earlier().first::<i64>(conn).optional()?.is_none() {
+        return Ok(false);
+    }
+    let same_agent = match &request.user_agent {
+        Some(agent) => earlier().filter(user_agent.eq(agent)),
+        None => earlier().filter(user_agent.is_null()),
+    };
+    Ok(same_agent.first::<i64>(conn).optional()?.is_none())
+}
+
+/// Mails the user about a login from a new device. The login goes ahead
+/// when the message can't be sent.
+fn notify_new_device(
+    user: &models::User,
+    method: &str,
+    request: &audit::RequestInfo,
+    mailer: &dyn mail::Mailer,
+) {
+    let address = match (&user.email, user.email_verified_at) {
+        (Some(address), Some(_)) => address.clone(),
+        _ => return,
+    };
+    let sent = mailer.send(&mail::Email {
+        to: address,
+        subject: "New login to your account".to_owned(),
+        body: format!(
+            "Hi {},\n\n\
+             someone just logged into your account, by {}, from a device it wasn't \
+             used on before:\n\n\
+             Address: {}\n\
+             Browser or app: {}\n\n\
+             If this wasn't you, change your password and end the session; \
+             GET /users/me/sessions lists them.\n",
+            user.username,
+            method,
+            request.ip.as_deref().unwrap_or("unknown"),
+            request.user_agent.as_deref().unwrap_or("unknown"),
+        ),
+    });
+    if let Err(e) = sent {
+        log::warn!("Mailing {} about a new device failed: {}", user.username, e);
+    }
+}
+
+/// The sessions the user is logged in with, most recently used first.
+/// `current` marks the session of the token asking.
+pub fn list_sessions(
+    uid: i32,
+    current: Option<i32>,
+    conn: &PgConnection,
+) -> Result<Vec<models::SessionInfo>, TodosError> {
+    use schema::sessions::dsl::*;
+    let active = sessions
+        .filter(user_id.eq(uid))
+        .filter(revoked_at.is_null())
+        .filter(expires_at.gt(chrono::Utc::now().naive_utc()))
+        .order(last_seen_at.desc())
+        .load::<models::Session>(conn)?;
+    Ok(active
+        .into_iter()
+        .map(|session| models::SessionInfo {
+            current: current == Some(session.id),
+            id: session.id,
+            created_at: session.created_at,
+            last_seen_at: session.last_seen_at,
+            expires_at: session.expires_at,
+            ip: session.ip,
+            user_agent: session.user_agent,
+            client_name: session.client_name,
+        })
+        .collect())
+}
+
+/// Ends one of the user's sessions; its token stops working right away.
+pub fn revoke_session(
+    uid: i32,
+    username: &str,
+    sid: i32,
+    request: &audit::RequestInfo,
+    conn: &PgConnection,
+) -> Result<(), TodosError> {
+    use schema::sessions::dsl::*;
+    conn.transaction::<_, TodosError, _>(|| {
+        let count = diesel::update(
+            sessions
+                .find(sid)
+                .filter(user_id.eq(uid))
+                .filter(revoked_at.is_null()),
+        )
+        .set(revoked_at.eq(chrono::Utc::now().naive_utc()))
+        .execute(conn)?;
+        if count == 0 {
+            return Err(TodosError::SessionNotFound);
+        }
+        record_event(
+            models::NewAuditEvent {
+                user_id: Some(uid),
+                username: Some(username.to_owned()),
+                details: Some(format!("session {}", sid)),
+                ..request.event(audit::AuditEventKind::SessionRevoked)
+            },
+            conn,
+        )
     })
 }
+
+/// Ends the session of a token being logged out with. Tokens that are
+/// invalid or expired have nothing left to end.
+pub fn log_out(token: &str, conn: &PgConnection) -> Result<(), TodosError> {
+    use schema::sessions::dsl::*;
+    if let Ok(claims) = auth::authorize(token) {
+        diesel::update(
+            sessions
+                .find(claims.sid)
+                .filter(user_id.eq(claims.id))
+                .filter(revoked_at.is_null()),
+        )
+        .set(revoked_at.eq(chrono::Utc::now().naive_utc()))
+        .execute(conn)?;
+    }
+    Ok(())
+}
+
+/// Deletes the sessions whose tokens have expired.
+pub fn purge_expired_sessions(conn: &PgConnection) -> Result<usize, TodosError> {
+    use schema::sessions::dsl::*;
+    let count = diesel::delete(sessions.filter(expires_at.lt(chrono::Utc::now().naive_utc())))
+        .execute(conn)?;
+    Ok(count)
+}
+
+sql_function!(fn lower(x: diesel::sql_types::Text) -> diesel::sql_types::Text);
+
+const MAX_DISPLAY_NAME_LEN: usize = 100;
+const MAX_AVATAR_URL_LEN: usize = 2048;
+
+pub fn get_profile(uid: i32, conn: &PgConnection) -> Result<models::Profile, TodosError> {
+    Ok(find_user(uid, conn)?.into())
+}
+
+/// Trims a text field of `PATCH /users/me`; empty ones are cleared.
+fn profile_text(value: String) -> Option<String> {
+    let value = value.trim();
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.to_owned())
+    }
+}
+
+/// Names such as `UTC`, `Europe/Berlin` or `America/Argentina/Buenos_Aires`.
+/// Whether the zone exists is up to the apps using it.
+fn is_time_zone(name: &str) -> bool {
+    name.len() <= 64
+        && name.split('/').all(|part| {
+            part.starts_with(|c: char| c.is_ascii_alphabetic())
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '+'))
+        })
+}
+
+/// Language tags such as `en`, `en-GB` or `zh-Hant-TW`.
+fn is_language_tag(tag: &str) -> bool {
+    let mut subtags = tag.split('-');
+    let language = subtags.next().unwrap_or_default();
+    tag.len() <= 35
+        && (2..=8).contains(&language.len())
+        && language.chars().all(|c| c.is_ascii_alphabetic())
+        && subtags.all(|subtag| {
+            (1..=8).contains(&subtag.len()) && subtag.chars().all(|c| c.is_ascii_alphanumeric())
+        })
+}
+
+fn is_avatar_url(value: &str) -> bool {
+    value.len() <= MAX_AVATAR_URL_LEN
+        && url::Url::parse(value)
+            .map(|url| matches!(url.scheme(), "http" | "https") && url.host().is_some())
+            .unwrap_or(false)
+}
+
+/// Changes the fields of the user's profile that `data` has.
+pub fn update_profile(
+    uid: i32,
+    data: models::UpdateProfileBody,
+    conn: &PgConnection,
+) -> Result<models::Profile, TodosError> {
+    use schema::users::dsl::*;
+    let invalid = |reason: &str| Err(TodosError::InvalidProfile(reason.to_owned()));
+    let new_display_name = data.display_name.map(profile_text);
+    if let Some(Some(name)) = &new_display_name {
+        if name.chars().count() > MAX_DISPLAY_NAME_LEN {
+            return invalid("display_name is longer than 100 characters");
+        }
+    }
+    let new_avatar_url = data.avatar_url.map(profile_text);
+    if let Some(Some(url)) = &new_avatar_url {
+        if !is_avatar_url(url) {
+            return invalid("avatar_url isn't an http or https URL");
+        }
+    }
+    let new_time_zone = data.time_zone.map(profile_text);
+    if let Some(Some(zone)) = &new_time_zone {
+        if !is_time_zone(zone) {
+            return invalid("time_zone isn't a time zone name such as Europe/Berlin");
+        }
+    }
+    let new_locale = data.locale.map(profile_text);
+    if let Some(Some(tag)) = &new_locale {
+        if !is_language_tag(tag) {
+            return invalid("locale isn't a language tag such as en-GB");
+        }
+    }
+    let preferences = data.preferences.unwrap_or_default();
+    let changes = models::ProfileChanges {
+        display_name: new_display_name,
+        avatar_url: new_avatar_url,
+        time_zone: new_time_zone,
+        locale: new_locale,
+        default_sort: preferences
+            .default_sort
+            .map(|sort| sort.as_str().to_owned()),
+        week_start: preferences.week_start.map(|day| day.as_str().to_owned()),
+    };
+    if changes.is_empty() {
+        return get_profile(uid, conn);
+    }
+    let user = diesel::update(users.find(uid))
+        .set(&changes)
+        .get_result::<models::User>(conn)
+        .optional()?
+        .ok_or(TodosError::AccountNotFound)?;
+    Ok(user.into())
+}
+
+/// Usernames picked after registering: 3 to 32 letters, digits, dots,
+/// underscores and dashes.
+fn is_valid_username(name: &str) -> bool {
+    (3..=32).contains(&name.chars().count())
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '.' | '_' | '-'))
+}
+
+/// Renames the user, unless another account has the name in some case.
+/// Tokens carry the username, so the caller gets a new one for the session
+/// `sid`, or for a new session if it has none.
+/// Whether an account other than `except` has the username, in any case.
+fn username_taken(name: &str, except: Option<i32>, conn: &PgConnection) -> QueryResult<bool> {
+    use schema::users::dsl::*;
+    let mut query = users
+        .filter(lower(username).eq(name.to_lowercase()))
+        .into_boxed();
+    if let Some(except) = except {
+        query = query.filter(id.ne(except));
+    }
+    diesel::select(diesel::dsl::exists(query)).get_result(conn)
+}
+
+pub fn change_username(
+    uid: i32,
+    sid: Option<i32>,
+    new_username: &str,
+    request: &audit::RequestInfo,
+    conn: &PgConnection,
+) -> Result<models::JwtUser, TodosError> {
+    use schema::users::dsl::*;
+    let new_username = new_username.trim();
+    if !is_valid_username(new_username) {
+        return Err(TodosError::InvalidUsername);
+    }
+    let user = conn.transaction::<_, TodosError, _>(|| {
+        let user = find_user(uid, conn)?;
+        if user.username == new_username {
+            return Ok(user);
+        }
+        if username_taken(new_username, Some(uid), conn)? {
+            return Err(TodosError::UsernameTaken);
+        }
+        let renamed = diesel::update(users.find(uid))
+            .set(username.eq(new_username))
+            .get_result::<models::User>(conn)
+            .map_err(|e| match e {
+                diesel::result::Error::NotFound => TodosError::AccountNotFound,
+                _ => TodosError::DieselCrudError,
+            })?;
+        record_event(
+            models::NewAuditEvent {
+                user_id: Some(uid),
+                username: Some(renamed.username.clone()),
+                details: Some(user.username),
+                ..request.event(audit::AuditEventKind::UsernameChanged)
+            },
+            conn,
+        )?;
+        // The new token is valid for a full lifetime, so the session it
+        // belongs to has to last as long.
+        if let Some(sid) = sid {
+            use schema::sessions::dsl as sessions;
+            let extended = diesel::update(
+                sessions::sessions
+                    .find(sid)
+                    .filter(sessions::user_id.eq(uid))
+                    .filter(sessions::revoked_at.is_null()),
+            )
+            .set(sessions::expires_at.eq(chrono::Utc::now().naive_utc()
+                + chrono::Duration::minutes(auth::TOKEN_LIFETIME_MINUTES)))
+            .execute(conn)?;
+            if extended == 0 {
+                return Err(TodosError::TokenRevoked);
+            }
+        }
+        Ok(renamed)
+    })?;
+    match sid {
+        Some(sid) => {
+            let token = auth::create_jwt(user.id, user.username.clone(), sid)
+                .map_err(|_| TodosError::JwtTokenCreationError)?;
+            Ok(models::JwtUser {
+                id: user.id,
+                token,
+                username: user.username,
+            })
+        }
+        None => open_session(&user, request, conn),
+    }
+}
+
+/// The profile other users see of an active account.
+pub fn get_public_profile(
+    name: &str,
+    conn: &PgConnection,
+) -> Result<models::PublicProfile, TodosError> {
+    use schema::users::dsl::*;
+    users
+        .filter(username.eq(name))
+        .filter(deleted_at.is_null())
+        .filter(disabled_at.is_null())
+        .first::<models::User>(conn)
+        .optional()?
+        .map(Into::into)
+        .ok_or(TodosError::AccountNotFound)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A connection to `DATABASE_URL`, or none when it isn't set, in which
+    /// case tests that need the database are skipped.
+    fn connection() -> Option<PgConnection> {
+        let url = std::env::var("DATABASE_URL").ok()?;
+        Some(PgConnection::establish(&url).expect("Could not connect to DATABASE_URL."))
+    }
+
+    /// Drops every message, for tests that don't read them.
+    struct NoMail;
+
+    impl mail::Mailer for NoMail {
+        fn send(&self, _: &mail::Email) -> Result<(), TodosError> {
+            Ok(())
+        }
+    }
+
+    /// Counts the messages instead of sending them.
+    #[derive(Default)]
+    struct CountingMail(std::sync::atomic::AtomicUsize);
+
+    impl mail::Mailer for CountingMail {
+        fn send(&self, _: &mail::Email) -> Result<(), TodosError> {
+            self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    fn register(name: &str, conn: &PgConnection) -> Result<auth::RegisterResponse, TodosError> {
+        crate::keys::init_for_tests();
+        register_user(
+            models::NewUser {
+                username: name.to_owned(),
+                password: "Correct-horse-battery-9".to_owned(),
+                email: None,
+            },
+            &audit::RequestInfo::default(),
+            &NoMail,
+            conn,
+        )
+    }
+
+    #[test]
+    fn logins_wait_twice_as_long_past_half_the_failures_until_the_lockout() {
+        let limits = LoginLimits {
+            max_failures: 10,
+            max_failures_per_ip: 100,
+            lockout: chrono::Duration::minutes(15),
+        };
+        let now = chrono::Utc::now().naive_utc();
+        let throttle = |scope: &str, failures: i32, seconds_ago: i64| models::LoginThrottle {
+            scope: scope.to_owned(),
+            subject: "alice".to_owned(),
+            failures,
+            last_failed_at: now - chrono::Duration::seconds(seconds_ago),
+            locked_until: None,
+        };
+        let wait = |throttle: models::LoginThrottle| {
+            (next_login_allowed_at(&throttle, &limits, now) - now).num_seconds()
+        };
+
+        // Half of the allowed failures are free, then the wait doubles.
+        assert_eq!(wait(throttle(USERNAME_SCOPE, 4, 0)), 0);
+        assert_eq!(wait(throttle(USERNAME_SCOPE, 5, 0)), 1);
+        assert_eq!(wait(throttle(USERNAME_SCOPE, 6, 0)), 2);
+        assert_eq!(wait(throttle(USERNAME_SCOPE, 9, 3)), 13);
+        // Addresses are allowed more.
+        assert_eq!(wait(throttle(IP_SCOPE, 49, 0)), 0);
+        assert_eq!(wait(throttle(IP_SCOPE, 52, 0)), 4);
+        // The wait never exceeds the lockout, however many failures.
+        assert_eq!(wait(throttle(USERNAME_SCOPE, 40, 0)), 15 * 60);
+        assert_eq!(wait(throttle(USERNAME_SCOPE, i32::MAX, 0)), 15 * 60);
+        // Failures older than the lockout are forgotten.
+        assert!(wait(throttle(USERNAME_SCOPE, 9, 16 * 60)) < 0);
+
+        let locked_until = now + chrono::Duration::minutes(10);
+        let locked = models::LoginThrottle {
+            locked_until: Some(locked_until),
+            ..throttle(USERNAME_SCOPE, 10, 5 * 60)
+        };
+        assert_eq!(next_login_allowed_at(&locked, &limits, now), locked_until);
+    }
+
+    fn insert_todo(text: &str, uid: i32, wid: Option<i32>, conn: &PgConnection) -> models::Todo {
+        diesel::insert_into(schema::todos::table)
+            .values(models::NewTodo {
+                text: text.to_owned(),
+                user_id: uid,
+                workspace_id: wid,
+                ical_uid: None,
+                done: None,
+                created_at: None,
+                completed_at: None,
+                priority: None,
+                projects: Vec::new(),
+                contexts: Vec::new(),
+            })
+            .get_result(conn)
+            .unwrap()
+    }
+
+    #[test]
+    fn purging_a_member_keeps_the_workspace_todos() {
+        let conn = match connection() {
+            Some(conn) => conn,
+            None => return,
+        };
+        conn.test_transaction::<_, TodosError, _>(|| {
+            let member = diesel::insert_into(schema::users::table)
+                .values(models::NewUser {
+                    username: "purged-member".to_owned(),
+                    password: hash_password("Correct-horse-battery-9"),
+                    email: None,
+                })
+                .get_result::<models::User>(&conn)?;
+            let workspace = diesel::insert_into(schema::workspaces::table)
+                .values(models::NewWorkspace {
+                    name: "Purge test".to_owned(),
+                    slug: "purge-test".to_owned(),
+                })
+                .get_result::<models::Workspace>(&conn)?;
+            diesel::insert_into(schema::workspace_members::table)
+                .values(models::WorkspaceMember {
+                    workspace_id: workspace.id,
+                    user_id: member.id,
+                    role: models::WorkspaceRole::Member.as_str().to_owned(),
+                    joined_at: chrono::Utc::now().naive_utc(),
+                })
+                .execute(&conn)?;
+            let shared = insert_todo("Shared", member.id, Some(workspace.id), &conn);
+            let personal = insert_todo("Personal", member.id, None, &conn);
+
+            let long_ago = chrono::Utc::now().naive_utc()
+                - account_deletion_grace_period()
+                - chrono::Duration::days(1);
+            diesel::update(schema::users::table.find(member.id))
+                .set(schema::users::deleted_at.eq(long_ago))
+                .execute(&conn)?;
+            assert!(purge_deleted_accounts(&conn)? >= 1);
+
+            let todos = schema::todos::table
+                .filter(schema::todos::id.eq_any(vec![shared.id, personal.id]))
+                .load::<models::Todo>(&conn)?;
+            assert_eq!(todos.len(), 1);
+            assert_eq!(todos[0].id, shared.id);
+            assert_eq!(todos[0].user_id, None);
+            let tenant = models::Tenant::Workspace {
+                workspace_id: workspace.id,
+                user_id: 0,
+            };
+            assert_eq!(get_all_todos(tenant, &conn)?.len(), 1);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn caldav_accepts_only_the_current_app_password() {
+        let conn = match connection() {
+            Some(conn) => conn,
+            None => return,
+        };
+        conn.test_transaction::<_, TodosError, _>(|| {
+            let user = diesel::insert_into(schema::users::table)
+                .values(models::NewUser {
+                    username: "caldav-user".to_owned(),
+                    password: hash_password("Correct-horse-battery-9"),
+                    email: None,
+                })
+                .get_result::<models::User>(&conn)?;
+            let feed_token = regenerate_calendar_token(user.id, &conn)?;
+            assert!(verify_caldav_password("caldav-user", &feed_token, &conn)?.is_none());
+
+            let old_password = regenerate_caldav_password(user.id, &conn)?;
+            let app_password = regenerate_caldav_password(user.id, &conn)?;
+            assert!(verify_caldav_password("caldav-user", &old_password, &conn)?.is_none());
+            assert!(verify_caldav_password("someone-else", &app_password, &conn)?.is_none());
+            let found = verify_caldav_password("caldav-user", &app_password, &conn)?;
+            assert_eq!(found.map(|found| found.id), Some(user.id));
+            assert!(get_todos_by_calendar_token(&app_password, &conn).is_err());
+
+            revoke_caldav_password(user.id, &conn)?;
+            assert!(verify_caldav_password("caldav-user", &app_password, &conn)?.is_none());
+
+            let app_password = regenerate_caldav_password(user.id, &conn)?;
+            set_password(user.id, "Another-horse-battery-9", &conn)?;
+            assert!(verify_caldav_password("caldav-user", &app_password, &conn)?.is_none());
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn renaming_extends_the_session_of_the_new_token() {
+        let conn = match connection() {
+            Some(conn) => conn,
+            None => return,
+        };
+        conn.test_transaction::<_, TodosError, _>(|| {
+            let registered = register("rename-me", &conn)?;
+            let session = schema::sessions::table
+                .filter(schema::sessions::user_id.eq(registered.id))
+                .first::<models::Session>(&conn)?;
+            let almost_expired = chrono::Utc::now().naive_utc() + chrono::Duration::minutes(1);
+            diesel::update(schema::sessions::table.find(session.id))
+                .set(schema::sessions::expires_at.eq(almost_expired))
+                .execute(&conn)?;
+
+            let renamed = change_username(
+                registered.id,
+                Some(session.id),
+                " Renamed-User ",
+                &audit::RequestInfo::default(),
+                &conn,
+            )?;
+            assert_eq!(renamed.username, "Renamed-User");
+            assert_eq!(find_user(registered.id, &conn)?.username, "Renamed-User");
+            let claims = auth::authorize(&renamed.token)?;
+            assert_eq!(
+                (claims.username.as_str(), claims.sid),
+                ("Renamed-User", session.id)
+            );
+            let session = schema::sessions::table
+                .find(session.id)
+                .first::<models::Session>(&conn)?;
+            assert!((session.expires_at.timestamp() - claims.exp as i64).abs() <= 1);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn usernames_collide_in_any_case() {
+        let conn = match connection() {
+            Some(conn) => conn,
+            None => return,
+        };
+        conn.test_transaction::<_, TodosError, _>(|| {
+            register("Taken-Name", &conn)?;
+            let other = register("other-name", &conn)?;
+            assert!(matches!(
+                register("taken-NAME", &conn),
+                Err(TodosError::UsernameTaken)
+            ));
+            let request = audit::RequestInfo::default();
+            assert!(matches!(
+                change_username(other.id, None, "TAKEN-name", &request, &conn),
+                Err(TodosError::UsernameTaken)
+            ));
+            assert_eq!(unused_username("taken-name", &conn)?, "taken-name-2");
+            // Changing only the case of one's own name is fine.
+            let renamed = change_username(other.id, None, "Other-Name", &request, &conn)?;
+            assert_eq!(renamed.username, "Other-Name");
+
+            let numbered = (2..=USERNAME_ATTEMPTS).map(|n| models::NewUser {
+                username: format!("taken-name-{}", n),
+                password: String::new(),
+                email: None,
+            });
+            diesel::insert_into(schema::users::table)
+                .values(numbered.collect::<Vec<_>>())
+                .execute(&conn)?;
+            assert!(matches!(
+                unused_username("taken-name", &conn),
+                Err(TodosError::UsernameTaken)
+            ));
+
+            // The index holds even when the checks are bypassed; the failed
+            // insert aborts the transaction, so this comes last.
+            let duplicate = diesel::insert_into(schema::users::table)
+                .values(models::NewUser {
+                    username: "TAKEN-NAME".to_owned(),
+                    password: hash_password("Correct-horse-battery-9"),
+                    email: None,
+                })
+                .execute(&conn);
+            assert!(duplicate.is_err());
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn only_verified_addresses_log_in_and_the_first_to_verify_keeps_it() {
+        let conn = match connection() {
+            Some(conn) => conn,
+            None => return,
+        };
+        conn.test_transaction::<_, TodosError, _>(|| {
+            let claim = |name: &str, token: &str| -> Result<models::User, TodosError> {
+                let user = diesel::insert_into(schema::users::table)
+                    .values(models::NewUser {
+                        username: name.to_owned(),
+                        password: hash_password("Correct-horse-battery-9"),
+                        email: Some("shared@example.com".to_owned()),
+                    })
+                    .get_result::<models::User>(&conn)?;
+                diesel::insert_into(schema::email_verification_tokens::table)
+                    .values(models::NewEmailVerificationToken {
+                        user_id: user.id,
+                        token_hash: hash_token(token),
+                        email: "shared@example.com".to_owned(),
+                        expires_at: chrono::Utc::now().naive_utc() + chrono::Duration::hours(1),
+                    })
+                    .execute(&conn)?;
+                Ok(user)
+            };
+            let first = claim("first-claimant", "first-token")?;
+            let second = claim("second-claimant", "second-token")?;
+            assert!(matches!(
+                find_user_by_login("shared@example.com", &conn),
+                Err(diesel::result::Error::NotFound)
+            ));
+
+            verify_email("second-token", &conn)?;
+            let found = find_user_by_login(" Shared@Example.com", &conn)?;
+            assert_eq!(found.id, second.id);
+            assert_eq!(find_user(first.id, &conn)?.email, None);
+            assert!(matches!(
+                verify_email("first-token", &conn),
+                Err(TodosError::EmailTaken)
+            ));
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn password_rechecks_count_as_failed_logins() {
+        let conn = match connection() {
+            Some(conn) => conn,
+            None => return,
+        };
+        conn.test_transaction::<_, TodosError, _>(|| {
+            let user = register("recheck-user", &conn)?;
+            let request = audit::RequestInfo {
+                ip: Some("192.0.2.10".to_owned()),
+                ..Default::default()
+            };
+            let free = LoginLimits::get().max_failures / 2;
+            for _ in 1..free {
+                assert!(matches!(
+                    change_password(user.id, "wrong", "New-horse-battery-9", &request, &conn),
+                    Err(TodosError::BadCreds)
+                ));
+            }
+            assert!(matches!(
+                schedule_account_deletion(user.id, "wrong", &request, &conn),
+                Err(TodosError::BadCreds)
+            ));
+            // Now even the right password has to wait.
+            assert!(matches!(
+                change_password(
+                    user.id,
+                    "Correct-horse-battery-9",
+                    "New-horse-battery-9",
+                    &request,
+                    &conn
+                ),
+                Err(TodosError::TooManyLoginAttempts(_))
+            ));
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn password_resets_are_limited_per_account_and_address() {
+        let conn = match connection() {
+            Some(conn) => conn,
+            None => return,
+        };
+        conn.test_transaction::<_, TodosError, _>(|| {
+            let user = diesel::insert_into(schema::users::table)
+                .values(models::NewUser {
+                    username: "forgetful".to_owned(),
+                    password: hash_password("Correct-horse-battery-9"),
+                    email: Some("forgetful@example.com".to_owned()),
+                })
+                .get_result::<models::User>(&conn)?;
+            diesel::update(schema::users::table.find(user.id))
+                .set(schema::users::email_verified_at.eq(chrono::Utc::now().naive_utc()))
+                .execute(&conn)?;
+            let mailer = CountingMail::default();
+            let ip = Some("192.0.2.20");
+
+            for _ in 0..PASSWORD_RESETS_PER_ACCOUNT + 2 {
+                request_password_reset("forgetful", ip, &mailer, &conn)?;
+            }
+            let sent = mailer.0.load(std::sync::atomic::Ordering::SeqCst);
+            assert_eq!(sent as i64, PASSWORD_RESETS_PER_ACCOUNT);
+
+            // Unknown logins count against the address too.
+            for _ in PASSWORD_RESETS_PER_ACCOUNT + 2..PASSWORD_RESETS_PER_IP {
+                request_password_reset("nobody-at-all", ip, &mailer, &conn)?;
+            }
+            match request_password_reset("forgetful", ip, &mailer, &conn) {
+                Err(TodosError::TooManyPasswordResets(seconds)) => {
+                    assert!(seconds > 0 && seconds <= 60 * 60)
+                }
+                other => panic!("expected a rate limit, got {:?}", other),
+            }
+            request_password_reset("nobody-at-all", Some("192.0.2.21"), &mailer, &conn)?;
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn streams_notice_revoked_sessions_and_disabled_accounts() {
+        let conn = match connection() {
+            Some(conn) => conn,
+            None => return,
+        };
+        conn.test_transaction::<_, TodosError, _>(|| {
+            let registered = register("stream-reader", &conn)?;
+            let sid = auth::authorize(&registered.token)?.sid;
+            let user = auth::AuthUser {
+                id: registered.id,
+                username: registered.username.clone(),
+                session_id: Some(sid),
+                oauth_token_id: None,
+            };
+            ensure_still_signed_in(&user, &conn)?;
+
+            let request = audit::RequestInfo::default();
+            revoke_session(registered.id, &registered.username, sid, &request, &conn)?;
+            assert!(matches!(
+                ensure_still_signed_in(&user, &conn),
+                Err(TodosError::TokenRevoked)
+            ));
+
+            let other = register("stream-reader-2", &conn)?;
+            let user = auth::AuthUser {
+                id: other.id,
+                username: other.username.clone(),
+                session_id: Some(auth::authorize(&other.token)?.sid),
+                oauth_token_id: None,
+            };
+            diesel::update(schema::users::table.find(other.id))
+                .set(schema::users::disabled_at.eq(chrono::Utc::now().naive_utc()))
+                .execute(&conn)?;
+            assert!(matches!(
+                ensure_still_signed_in(&user, &conn),
+                Err(TodosError::AccountSuspended)
+            ));
+            Ok(())
+        });
+    }
+}
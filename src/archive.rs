@@ -0,0 +1,205 @@
+//! The account archive behind `GET /account/export` and
+//! `POST /account/import`: a versioned JSON document, optionally zipped.
+
+use std::io::{Cursor, Read, Write};
+
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{error::TodosError, models};
+
+/// Identifies the document, so that other JSON is rejected early.
+pub const FORMAT: &str = "todos-account-archive";
+/// Bumped whenever a field is removed or changes meaning; readers accept
+/// every version up to their own.
+pub const VERSION: u32 = 1;
+/// The name of the document inside zipped archives.
+pub const ZIP_ENTRY: &str = "account.json";
+
+/// The largest archive document that is read, zipped or not.
+const MAX_DOCUMENT_SIZE: u64 = 256 * 1024 * 1024;
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct AccountArchive {
+    pub format: String,
+    pub version: u32,
+    pub exported_at: NaiveDateTime,
+    pub profile: ArchivedProfile,
+    pub todos: Vec<ArchivedTodo>,
+    #[serde(default)]
+    pub import_jobs: Vec<models::ImportJob>,
+}
+
+/// Secrets such as the password hash and calendar token are left out.
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct ArchivedProfile {
+    pub id: i32,
+    pub username: String,
+}
+
+/// A todo without its owner. `id` is only used to report how ids were
+/// remapped on import.
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct ArchivedTodo {
+    pub id: i32,
+    pub text: String,
+    pub done: bool,
+    pub ical_uid: String,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub completed_at: Option<NaiveDateTime>,
+    #[serde(default)]
+    pub priority: Option<String>,
+}
+
+impl From<models::Todo> for ArchivedTodo {
+    fn from(todo: models::Todo) -> Self {
+        Self {
+            id: todo.id,
+            text: todo.text,
+            done: todo.done,
+            ical_uid: todo.ical_uid,
+            created_at: todo.created_at,
+            updated_at: todo.updated_at,
+            completed_at: todo.completed_at,
+            priority: todo.priority,
+        }
+    }
+}
+
+fn invalid(message: impl std::fmt::Display) -> TodosError {
+    TodosError::InvalidArchive(message.to_string())
+}
+
+pub fn write_json(archive: &AccountArchive) -> Vec<u8> {
+    serde_json::to_vec_pretty(archive).expect("Archives are always serializable.")
+}
+
+pub fn write_zip(archive: &AccountArchive) -> Result<Vec<u8>, TodosError> {
+    let options =
+        zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    let zipped = writer
+        .start_file(ZIP_ENTRY, options)
+        .and_then(|_| Ok(writer.write_all(&write_json(archive))?))
+        .and_then(|_| writer.finish());
+    zipped
+        .map(Cursor::into_inner)
+        .map_err(|_| TodosError::DieselCrudError)
+}
+
+/// Reads an archive, unzipping it first if need be.
+pub fn read(input: &[u8]) -> Result<AccountArchive, TodosError> {
+    let archive: AccountArchive = if input.starts_with(b"PK\x03\x04") {
+        let mut zip = zip::ZipArchive::new(Cursor::new(input)).map_err(invalid)?;
+        let entry = zip.by_name(ZIP_ENTRY).map_err(invalid)?;
+        let mut json = Vec::new();
+        entry
+            .take(MAX_DOCUMENT_SIZE)
+            .read_to_end(&mut json)
+            .map_err(invalid)?;
+        serde_json::from_slice(&json).map_err(invalid)?
+    } else {
+        serde_json::from_slice(input).map_err(invalid)?
+    };
+    if archive.format != FORMAT {
+        return Err(invalid(format!("this is not a {}", FORMAT)));
+    }
+    if archive.version > VERSION {
+        return Err(invalid(format!(
+            "version {} is newer than the supported version {}",
+            archive.version, VERSION
+        )));
+    }
+    Ok(archive)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn archive(version: u32) -> AccountArchive {
+        let created_at = NaiveDate::from_ymd(2026, 10, 1).and_hms(8, 0, 0);
+        AccountArchive {
+            format: FORMAT.to_owned(),
+            version,
+            exported_at: NaiveDate::from_ymd(2026, 10, 18).and_hms(9, 30, 0),
+            profile: ArchivedProfile {
+                id: 7,
+                username: "alice".to_owned(),
+            },
+            todos: vec![ArchivedTodo {
+                id: 3,
+                text: "Buy milk".to_owned(),
+                done: false,
+                ical_uid: "uid-3@todos".to_owned(),
+                created_at,
+                updated_at: created_at,
+                completed_at: None,
+                priority: Some("A".to_owned()),
+            }],
+            import_jobs: Vec::new(),
+        }
+    }
+
+    fn reason(result: Result<AccountArchive, TodosError>) -> String {
+        match result {
+            Err(TodosError::InvalidArchive(reason)) => reason,
+            other => panic!("expected an invalid archive, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reads_what_it_writes() {
+        for bytes in &[
+            write_json(&archive(VERSION)),
+            write_zip(&archive(VERSION)).unwrap(),
+        ] {
+            let read = read(bytes).unwrap();
+            assert_eq!(read.profile.username, "alice");
+            assert_eq!(read.todos.len(), 1);
+            assert_eq!(read.todos[0].ical_uid, "uid-3@todos");
+            assert_eq!(read.todos[0].priority.as_deref(), Some("A"));
+        }
+    }
+
+    #[test]
+    fn reads_older_versions_without_newer_fields() {
+        let json = r#"{
+            "format": "todos-account-archive",
+            "version": 0,
+            "exported_at": "2026-10-18T09:30:00",
+            "profile": {"id": 7, "username": "alice"},
+            "todos": [{"id": 3, "text": "Buy milk", "done": true, "ical_uid": "u",
+                       "created_at": "2026-10-01T08:00:00", "updated_at": "2026-10-01T08:00:00",
+                       "completed_at": "2026-10-02T08:00:00"}]
+        }"#;
+        let read = read(json.as_bytes()).unwrap();
+        assert_eq!(read.version, 0);
+        assert_eq!(read.todos[0].priority, None);
+        assert!(read.import_jobs.is_empty());
+    }
+
+    #[test]
+    fn rejects_newer_versions_and_other_documents() {
+        assert_eq!(
+            reason(read(&write_json(&archive(VERSION + 1)))),
+            format!(
+                "version {} is newer than the supported version {}",
+                VERSION + 1,
+                VERSION
+            )
+        );
+        let mut other = archive(VERSION);
+        "something-else".clone_into(&mut other.format);
+        assert_eq!(
+            reason(read(&write_json(&other))),
+            "this is not a todos-account-archive"
+        );
+        reason(read(b"not json"));
+        reason(read(b"PK\x03\x04 not a zip"));
+    }
+}
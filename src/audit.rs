@@ -0,0 +1,173 @@
+//! The security log: an append-only record of registrations, logins, token
+//! refreshes, password changes, permission changes and administrators'
+//! actions, with the client's address and user agent. Users read their own
+//! at `GET /users/me/security-log`, and holders of `audit:read` everyone's
+//! at `GET /admin/audit-events`.
+
+use std::future::{ready, Ready};
+
+use actix_web::{dev, http::header, Error, FromRequest, HttpRequest};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{auth, models};
+
+/// User agents are cut to this many characters.
+const MAX_USER_AGENT_LEN: usize = 512;
+
+/// Client names are cut to this many characters.
+const MAX_CLIENT_NAME_LEN: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub enum AuditEventKind {
+    #[serde(rename = "user.registered")]
+    UserRegistered,
+    /// A login that handed out a token, by password, second factor, single
+    /// sign-on or restoring the account.
+    #[serde(rename = "login.succeeded")]
+    LoginSucceeded,
+    /// A login refused for a wrong password or second factor, a lockout or
+    /// the state of the account; `details` says which.
+    #[serde(rename = "login.failed")]
+    LoginFailed,
+    /// An OAuth client traded a refresh token for new tokens.
+    #[serde(rename = "token.refreshed")]
+    TokenRefreshed,
+    /// The user picked a new username; `details` has the old one.
+    #[serde(rename = "user.username_changed")]
+    UsernameChanged,
+    #[serde(rename = "password.changed")]
+    PasswordChanged,
+    /// A password set with a reset token.
+    #[serde(rename = "password.reset")]
+    PasswordReset,
+    #[serde(rename = "two_factor.enabled")]
+    TwoFactorEnabled,
+    #[serde(rename = "account.deletion_scheduled")]
+    AccountDeletionScheduled,
+    #[serde(rename = "account.restored")]
+    AccountRestored,
+    /// The user ended one of their sessions; `details` says which.
+    #[serde(rename = "session.revoked")]
+    SessionRevoked,
+    #[serde(rename = "user.disabled")]
+    UserDisabled,
+    #[serde(rename = "user.enabled")]
+    UserEnabled,
+    #[serde(rename = "user.password_reset_forced")]
+    PasswordResetForced,
+    #[serde(rename = "user.sessions_revoked")]
+    SessionsRevoked,
+    /// A user was given a role; `details` names it.
+    #[serde(rename = "user.role_changed")]
+    RoleChanged,
+    #[serde(rename = "role.created")]
+    RoleCreated,
+    #[serde(rename = "role.updated")]
+    RoleUpdated,
+    #[serde(rename = "role.deleted")]
+    RoleDeleted,
+}
+
+impl AuditEventKind {
+    pub const ALL: &'static [AuditEventKind] = &[
+        AuditEventKind::UserRegistered,
+        AuditEventKind::LoginSucceeded,
+        AuditEventKind::LoginFailed,
+        AuditEventKind::TokenRefreshed,
+        AuditEventKind::UsernameChanged,
+        AuditEventKind::PasswordChanged,
+        AuditEventKind::PasswordReset,
+        AuditEventKind::TwoFactorEnabled,
+        AuditEventKind::AccountDeletionScheduled,
+        AuditEventKind::AccountRestored,
+        AuditEventKind::SessionRevoked,
+        AuditEventKind::UserDisabled,
+        AuditEventKind::UserEnabled,
+        AuditEventKind::PasswordResetForced,
+        AuditEventKind::SessionsRevoked,
+        AuditEventKind::RoleChanged,
+        AuditEventKind::RoleCreated,
+        AuditEventKind::RoleUpdated,
+        AuditEventKind::RoleDeleted,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::UserRegistered => "user.registered",
+            Self::LoginSucceeded => "login.succeeded",
+            Self::LoginFailed => "login.failed",
+            Self::TokenRefreshed => "token.refreshed",
+            Self::UsernameChanged => "user.username_changed",
+            Self::PasswordChanged => "password.changed",
+            Self::PasswordReset => "password.reset",
+            Self::TwoFactorEnabled => "two_factor.enabled",
+            Self::AccountDeletionScheduled => "account.deletion_scheduled",
+            Self::AccountRestored => "account.restored",
+            Self::SessionRevoked => "session.revoked",
+            Self::UserDisabled => "user.disabled",
+            Self::UserEnabled => "user.enabled",
+            Self::PasswordResetForced => "user.password_reset_forced",
+            Self::SessionsRevoked => "user.sessions_revoked",
+            Self::RoleChanged => "user.role_changed",
+            Self::RoleCreated => "role.created",
+            Self::RoleUpdated => "role.updated",
+            Self::RoleDeleted => "role.deleted",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<AuditEventKind> {
+        Self::ALL.iter().copied().find(|kind| kind.as_str() == name)
+    }
+}
+
+/// Where a request came from, for the security log and the list of
+/// sessions. Never fails to extract; the parts are left out when unknown.
+/// Commands run from a shell use the default, which has none.
+#[derive(Debug, Clone, Default)]
+pub struct RequestInfo {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    /// From the `X-Client-Name` header.
+    pub client_name: Option<String>,
+}
+
+impl RequestInfo {
+    pub fn of(req: &HttpRequest) -> Self {
+        Self {
+            ip: auth::client_ip(req),
+            user_agent: req
+                .headers()
+                .get(header::USER_AGENT)
+                .and_then(|value| value.to_str().ok())
+                .map(|agent| agent.chars().take(MAX_USER_AGENT_LEN).collect()),
+            client_name: req
+                .headers()
+                .get(auth::CLIENT_NAME_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(|name| name.chars().take(MAX_CLIENT_NAME_LEN).collect()),
+        }
+    }
+
+    /// An event of `kind` from this request, to fill in further.
+    pub fn event(&self, kind: AuditEventKind) -> models::NewAuditEvent {
+        models::NewAuditEvent {
+            event: kind.as_str().to_owned(),
+            ip: self.ip.clone(),
+            user_agent: self.user_agent.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+impl FromRequest for RequestInfo {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _payload: &mut dev::Payload) -> Self::Future {
+        ready(Ok(Self::of(req)))
+    }
+}
@@ -1,16 +1,39 @@
 use std::{
     error::Error as StdError,
     future::{ready, Ready},
+    marker::PhantomData,
 };
 
-use actix_web::{dev, web, Error, FromRequest, HttpRequest, HttpResponse};
-use diesel::{ExpressionMethods, QueryDsl, RunQueryDsl};
-use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use actix_web::{
+    cookie::{Cookie, SameSite},
+    dev,
+    http::Method,
+    web, Error, FromRequest, HttpMessage, HttpRequest, HttpResponse,
+};
+use chrono::NaiveDateTime;
+use rand_core::RngCore;
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
-use crate::error::TodosError;
+use crate::{
+    error::TodosError,
+    keys, models,
+    oauth::{self, Scope},
+    roles::RequiredPermission,
+    DbConn, DbPool,
+};
 
-#[derive(Deserialize, Serialize, Debug)]
+/// Selects a workspace, by id or slug, for the todo endpoints.
+pub const WORKSPACE_HEADER: &str = "X-Workspace";
+
+/// Names the client logging in, such as "Work laptop", in the list of
+/// sessions.
+pub const CLIENT_NAME_HEADER: &str = "X-Client-Name";
+
+/// How long tokens handed out at login last.
+pub const TOKEN_LIFETIME_MINUTES: i64 = 60;
+
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
 pub struct RegisterResponse {
     pub token: String,
     pub id: i32,
@@ -22,46 +45,408 @@ pub struct Claims {
     pub username: String,
     pub id: i32,
     pub exp: usize,
+    /// When the token was issued; tokens from before a password change are
+    /// revoked.
+    #[serde(default)]
+    pub iat: usize,
+    /// The session the token belongs to; it stops working once the session
+    /// is revoked.
+    pub sid: i32,
+    /// The hash of the CSRF token, for tokens kept in the session cookie.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub csrf: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct LoginBody {
+    /// The username or the email address.
     pub username: String,
     pub password: String,
 }
 
-pub fn create_jwt(uid: i32, uname: String) -> Result<String, Box<dyn StdError>> {
-    let expiration = (chrono::Utc::now() + chrono::Duration::minutes(60)).timestamp();
+pub fn create_jwt(uid: i32, uname: String, sid: i32) -> Result<String, Box<dyn StdError>> {
+    Ok(encode_claims(uid, uname, sid, None)?)
+}
+
+fn encode_claims(
+    uid: i32,
+    uname: String,
+    sid: i32,
+    csrf: Option<String>,
+) -> Result<String, TodosError> {
+    let now = chrono::Utc::now();
+    let expiration = (now + chrono::Duration::minutes(TOKEN_LIFETIME_MINUTES)).timestamp();
 
     let claims = Claims {
         username: uname,
         id: uid,
         exp: expiration as usize,
+        iat: now.timestamp() as usize,
+        sid,
+        csrf,
     };
-    let header = Header::new(Algorithm::HS512);
-    jsonwebtoken::encode(
-        &header,
-        &claims,
-        &EncodingKey::from_secret(std::env::var("JWT_SECRET").unwrap().as_bytes()),
-    )
-    .map_err(|_| TodosError::JwtTokenCreationError.into())
+    keys::keyring().encode(&claims)
+}
+
+pub const SESSION_COOKIE: &str = "todos_session";
+pub const CSRF_COOKIE: &str = "todos_csrf";
+pub const OIDC_STATE_COOKIE: &str = "todos_oidc_state";
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+
+/// A token for the session cookie, and the CSRF token that state-changing
+/// requests authenticated by it have to send in `X-CSRF-Token`.
+pub fn create_session(uid: i32, uname: String, sid: i32) -> Result<(String, String), TodosError> {
+    let csrf_token = generate_token();
+    let token = encode_claims(
+        uid,
+        uname,
+        sid,
+        Some(super::actions::hash_token(&csrf_token)),
+    )?;
+    Ok((token, csrf_token))
+}
+
+/// Whether cookies are only sent over HTTPS, from `SESSION_COOKIE_SECURE`
+/// (on by default).
+fn secure_cookies() -> bool {
+    std::env::var("SESSION_COOKIE_SECURE")
+        .map(|secure| secure == "true" || secure == "1")
+        .unwrap_or(true)
+}
+
+/// The session cookie, which scripts can't read, and the CSRF cookie, which
+/// scripts of our own origin can, for the double-submit pattern. Both last
+/// until the browser is closed; the token in the session cookie expires
+/// sooner.
+pub fn session_cookies(token: String, csrf_token: String) -> [Cookie<'static>; 2] {
+    let secure = secure_cookies();
+    [
+        Cookie::build(SESSION_COOKIE, token)
+            .path("/")
+            .http_only(true)
+            .secure(secure)
+            .same_site(SameSite::Strict)
+            .finish(),
+        Cookie::build(CSRF_COOKIE, csrf_token)
+            .path("/")
+            .secure(secure)
+            .same_site(SameSite::Strict)
+            .finish(),
+    ]
+}
+
+/// Ties an OpenID Connect login to the browser that started it: the callback
+/// is only accepted along with the hash of its `state`. `Lax`, since the
+/// provider's redirect back is a cross-site navigation.
+pub fn oidc_state_cookie(state_hash: String) -> Cookie<'static> {
+    Cookie::build(OIDC_STATE_COOKIE, state_hash)
+        .path("/oidc")
+        .http_only(true)
+        .secure(secure_cookies())
+        .same_site(SameSite::Lax)
+        .finish()
+}
+
+/// Cookies that, once sent, make the browser forget the session cookies.
+/// They keep the attributes of the originals, or some clients would keep
+/// the `HttpOnly` one.
+pub fn cleared_session_cookies() -> [Cookie<'static>; 2] {
+    session_cookies(String::new(), String::new())
+}
+
+/// Browsers send cookies along with requests other sites trigger, so
+/// state-changing requests authenticated by the session cookie have to prove
+/// they come from a page that could read the CSRF token.
+fn passes_csrf_check(req: &HttpRequest, claims: &Claims) -> bool {
+    if matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+        return true;
+    }
+    let expected = match &claims.csrf {
+        Some(expected) => expected,
+        None => return false,
+    };
+    match req.headers().get(CSRF_HEADER).map(|header| header.to_str()) {
+        Some(Ok(csrf_token)) => super::actions::hash_token(csrf_token) == *expected,
+        _ => false,
+    }
 }
 
 pub fn authorize(jwt: &str) -> Result<Claims, TodosError> {
-    let decoded = jsonwebtoken::decode::<Claims>(
-        jwt,
-        &DecodingKey::from_secret(std::env::var("JWT_SECRET").unwrap().as_bytes()),
-        &Validation::new(Algorithm::HS512),
-    )
-    .map_err(|_| TodosError::JwtTokenDecodeError)?;
+    keys::keyring().decode::<Claims>(jwt)
+}
+
+/// What a two-factor challenge token carries. Lacking `username` and `id`, it
+/// can't be mistaken for the `Claims` of a full token.
+#[derive(Deserialize, Serialize, Debug)]
+struct ChallengeClaims {
+    sub: i32,
+    purpose: String,
+    exp: usize,
+}
+
+const CHALLENGE_PURPOSE: &str = "2fa";
 
-    Ok(decoded.claims)
+/// A token proving that a user got their password right, good for five
+/// minutes of trying to enter a second factor.
+pub fn create_challenge_token(uid: i32) -> Result<(String, NaiveDateTime), TodosError> {
+    let expiration = chrono::Utc::now() + chrono::Duration::minutes(5);
+    let claims = ChallengeClaims {
+        sub: uid,
+        purpose: CHALLENGE_PURPOSE.to_owned(),
+        exp: expiration.timestamp() as usize,
+    };
+    let token = keys::keyring().encode(&claims)?;
+    Ok((token, expiration.naive_utc()))
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Returns the id of the user a challenge token was issued to.
+pub fn authorize_challenge(token: &str) -> Result<i32, TodosError> {
+    let claims = keys::keyring()
+        .decode::<ChallengeClaims>(token)
+        .map_err(|_| TodosError::InvalidChallenge)?;
+    if claims.purpose != CHALLENGE_PURPOSE {
+        return Err(TodosError::InvalidChallenge);
+    }
+    Ok(claims.sub)
+}
+
+/// The client's address, for counting failed logins. Behind a reverse proxy,
+/// set `TRUST_PROXY_HEADERS=true` to take it from `Forwarded` or
+/// `X-Forwarded-For` instead of the proxy's own.
+pub fn client_ip(req: &HttpRequest) -> Option<String> {
+    let trust_proxy = std::env::var("TRUST_PROXY_HEADERS")
+        .map(|trust| trust == "true" || trust == "1")
+        .unwrap_or(false);
+    let ip = if trust_proxy {
+        let address = req.connection_info().realip_remote_addr()?.to_owned();
+        address
+            .parse::<std::net::SocketAddr>()
+            .map(|address| address.ip())
+            .or_else(|_| address.parse::<std::net::IpAddr>())
+            .ok()?
+    } else {
+        req.peer_addr()?.ip()
+    };
+    Some(ip.to_string())
+}
+
+/// The client id and secret of an OAuth client authenticating with HTTP
+/// Basic.
+pub fn basic_credentials(req: &HttpRequest) -> Option<(String, String)> {
+    let header = req.headers().get("Authorization")?.to_str().ok()?;
+    let encoded = header.strip_prefix("Basic ")?;
+    let decoded = String::from_utf8(base64::decode(encoded).ok()?).ok()?;
+    let (id, secret) = decoded.split_once(':')?;
+    Some((id.to_owned(), secret.to_owned()))
+}
+
+/// A random, URL-safe token for capability URLs such as calendar feeds.
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand_core::OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct AuthUser {
     pub id: i32,
     pub username: String,
+    /// The session of the token; OAuth tokens have none.
+    pub session_id: Option<i32>,
+    /// The OAuth token the request was made with, if any.
+    pub oauth_token_id: Option<i32>,
+}
+
+/// The scope an OAuth access token needs for the route a request matched.
+/// OAuth tokens only work on the routes listed here.
+fn required_scope(req: &HttpRequest) -> Option<Scope> {
+    let pattern = req.match_pattern()?;
+    let read = req.method() == Method::GET;
+    match pattern.as_str() {
+        "/todos" | "/todos/{todo_id}" if read => Some(Scope::TodosRead),
+        "/todos" | "/todos/{todo_id}" => Some(Scope::TodosWrite),
+        "/todos.ics" | "/events" if read => Some(Scope::TodosRead),
+        _ => None,
+    }
+}
+
+/// Where a request's token was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CredentialSource {
+    /// `Authorization: Bearer <token>` (RFC 6750, section 2.1).
+    Header,
+    /// The session cookie of a cookie-mode login.
+    Cookie,
+    /// The `access_token` query parameter (RFC 6750, section 2.3), for
+    /// clients such as `EventSource` that can't send headers.
+    Query,
+}
+
+pub struct Credential {
+    pub token: String,
+    pub source: CredentialSource,
+}
+
+/// One place a request may carry its token. `AuthUser` asks each of
+/// `RESOLVERS` in turn and takes the first token found, so supporting
+/// another way of sending tokens means adding a resolver there.
+pub trait CredentialResolver: Sync {
+    /// `Ok(None)` if the request has no token here, or why the one it has
+    /// is malformed.
+    fn resolve(&self, req: &HttpRequest) -> Result<Option<Credential>, &'static str>;
+}
+
+struct AuthorizationHeader;
+
+impl CredentialResolver for AuthorizationHeader {
+    fn resolve(&self, req: &HttpRequest) -> Result<Option<Credential>, &'static str> {
+        let header = match req.headers().get("Authorization") {
+            Some(header) => header,
+            None => return Ok(None),
+        };
+        let value = header
+            .to_str()
+            .map_err(|_| "Auth header is malformed or contains non-ASCII characters.")?;
+        Ok(Some(Credential {
+            token: bearer_token(value)?.to_owned(),
+            source: CredentialSource::Header,
+        }))
+    }
+}
+
+struct SessionCookie;
+
+impl CredentialResolver for SessionCookie {
+    fn resolve(&self, req: &HttpRequest) -> Result<Option<Credential>, &'static str> {
+        Ok(req.cookie(SESSION_COOKIE).map(|cookie| Credential {
+            token: cookie.value().to_owned(),
+            source: CredentialSource::Cookie,
+        }))
+    }
+}
+
+struct QueryParameter;
+
+/// Tokens in URLs end up in logs and browser history, so only the routes
+/// listed here take them.
+fn accepts_query_token(req: &HttpRequest) -> bool {
+    req.method() == Method::GET
+        && matches!(
+            req.match_pattern().as_deref(),
+            Some("/events") | Some("/graphql/stream")
+        )
+}
+
+impl CredentialResolver for QueryParameter {
+    fn resolve(&self, req: &HttpRequest) -> Result<Option<Credential>, &'static str> {
+        let token = match url::form_urlencoded::parse(req.query_string().as_bytes())
+            .find(|(name, _)| name == "access_token")
+        {
+            Some((_, token)) => token.into_owned(),
+            None => return Ok(None),
+        };
+        if !accepts_query_token(req) {
+            return Err("Send the token in the Authorization header.");
+        }
+        if !is_b64token(&token) {
+            return Err("The access_token parameter is malformed.");
+        }
+        Ok(Some(Credential {
+            token,
+            source: CredentialSource::Query,
+        }))
+    }
+}
+
+/// The header wins over the cookie: browsers never add it on their own.
+pub static RESOLVERS: &[&dyn CredentialResolver] =
+    &[&AuthorizationHeader, &SessionCookie, &QueryParameter];
+
+/// The token of a request, from the first resolver that finds one.
+pub fn resolve_credential(req: &HttpRequest) -> Result<Option<Credential>, &'static str> {
+    for resolver in RESOLVERS {
+        if let Some(credential) = resolver.resolve(req)? {
+            return Ok(Some(credential));
+        }
+    }
+    Ok(None)
+}
+
+/// The `b64token` syntax of RFC 6750 bearer tokens.
+fn is_b64token(token: &str) -> bool {
+    let token = token.trim_end_matches('=');
+    !token.is_empty()
+        && token
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"-._~+/".contains(&byte))
+}
+
+/// The token of an `Authorization` header value, `Bearer <token>` with the
+/// scheme in any case. A bare token is taken as well, as older clients send
+/// them.
+pub fn bearer_token(value: &str) -> Result<&str, &'static str> {
+    let token = match value.split_once(' ') {
+        Some((scheme, token)) if scheme.eq_ignore_ascii_case("Bearer") => token.trim_start(),
+        Some(_) => return Err("Only the Bearer authorization scheme is supported."),
+        None if value.eq_ignore_ascii_case("Bearer") => {
+            return Err("The Bearer authorization has no token.")
+        }
+        None => value,
+    };
+    if is_b64token(token) {
+        Ok(token)
+    } else {
+        Err("The bearer token is malformed.")
+    }
+}
+
+/// The error codes of RFC 6750, section 3.1.
+#[derive(Debug, Clone, Copy)]
+enum BearerError {
+    InvalidRequest,
+    InvalidToken,
+    InsufficientScope(Scope),
+}
+
+/// The response to a request whose token is missing or was refused, with a
+/// `WWW-Authenticate` challenge saying why. Without an `error`, the request
+/// had no token at all.
+fn challenge(error: Option<BearerError>, message: &str) -> Error {
+    let mut header = r#"Bearer realm="todos""#.to_owned();
+    let mut response = match error {
+        None => HttpResponse::Unauthorized(),
+        Some(BearerError::InvalidRequest) => HttpResponse::BadRequest(),
+        Some(BearerError::InvalidToken) => HttpResponse::Unauthorized(),
+        Some(BearerError::InsufficientScope(_)) => HttpResponse::Forbidden(),
+    };
+    if let Some(error) = error {
+        let code = match error {
+            BearerError::InvalidRequest => "invalid_request",
+            BearerError::InvalidToken => "invalid_token",
+            BearerError::InsufficientScope(_) => "insufficient_scope",
+        };
+        // Quotes and backslashes aren't allowed in the description.
+        let description = message.replace(['"', '\\'], "'");
+        header.push_str(&format!(
+            r#", error="{}", error_description="{}""#,
+            code, description
+        ));
+        if let BearerError::InsufficientScope(scope) = error {
+            header.push_str(&format!(r#", scope="{}""#, scope.as_str()));
+        }
+    }
+    response
+        .header("WWW-Authenticate", header)
+        .json(serde_json::json!({ "message": message }))
+        .into()
+}
+
+/// A connection from the app's pool.
+fn db_conn(req: &HttpRequest) -> Result<DbConn, TodosError> {
+    let pool = req
+        .app_data::<web::Data<DbPool>>()
+        .expect("The database pool is registered as app data.");
+    Ok(pool.get()?)
 }
 
 impl FromRequest for AuthUser {
@@ -69,31 +454,228 @@ impl FromRequest for AuthUser {
     type Future = Ready<Result<Self, Self::Error>>;
     type Config = ();
 
-    fn from_request(req: &HttpRequest, _payload: &mut dev::Payload) -> Self::Future {
-        let unauth_err = |message| {
-            ready(Err(HttpResponse::Unauthorized()
-                .json(serde_json::json!({ "message": message }))
-                .into()))
+    /// Authenticates the request once: the extractors built on `AuthUser`
+    /// and handlers taking several of them reuse the user kept in the
+    /// request's extensions.
+    fn from_request(req: &HttpRequest, _: &mut dev::Payload) -> Self::Future {
+        if let Some(user) = req.extensions().get::<Self>() {
+            return ready(Ok(user.clone()));
+        }
+        let result = authenticate(req);
+        if let Ok(user) = &result {
+            req.extensions_mut().insert(user.clone());
+        }
+        ready(result)
+    }
+}
+
+fn authenticate(req: &HttpRequest) -> Result<AuthUser, Error> {
+    let invalid_token = |message| Err(challenge(Some(BearerError::InvalidToken), message));
+    let forbidden_err = |message| {
+        Err(HttpResponse::Forbidden()
+            .json(serde_json::json!({ "message": message }))
+            .into())
+    };
+    let internal_err = || {
+        Err(HttpResponse::InternalServerError()
+            .json(serde_json::json!({
+                "message": "Something went wrong while checking the account."
+            }))
+            .into())
+    };
+    let credential = match resolve_credential(req) {
+        Ok(Some(credential)) => credential,
+        Ok(None) => return Err(challenge(None, "Auth header not present.")),
+        Err(message) => return Err(challenge(Some(BearerError::InvalidRequest), message)),
+    };
+    let token = credential.token;
+    if credential.source != CredentialSource::Cookie
+        && token.starts_with(oauth::ACCESS_TOKEN_PREFIX)
+    {
+        let scope = match required_scope(req) {
+            Some(scope) => scope,
+            None => return forbidden_err("OAuth tokens can't be used here."),
         };
-        if let Some(header) = req.headers().get("Authorization") {
-            if let Ok(token) = header.to_str() {
-                if let Ok(claims) = authorize(token) {
-                    ready(Ok(Self {
-                        id: claims.id,
-                        username: claims.username,
-                    }))
-                } else {
-                    unauth_err("Token is invalid or expired.")
+        return match db_conn(req)
+            .and_then(|conn| super::actions::authenticate_oauth_token(&token, scope, &conn))
+        {
+            Ok((user, token_id)) => Ok(AuthUser {
+                id: user.id,
+                username: user.username,
+                session_id: None,
+                oauth_token_id: Some(token_id),
+            }),
+            Err(TodosError::InvalidOAuthToken) => {
+                invalid_token("Token is invalid, expired or revoked.")
+            }
+            Err(TodosError::InsufficientScope) => Err(challenge(
+                Some(BearerError::InsufficientScope(scope)),
+                "The token wasn't granted the scope this needs.",
+            )),
+            Err(TodosError::AccountDisabled) => {
+                forbidden_err("The account is scheduled for deletion.")
+            }
+            Err(TodosError::AccountSuspended) => {
+                forbidden_err("The account was disabled by an administrator.")
+            }
+            Err(_) => internal_err(),
+        };
+    }
+    if let Ok(claims) = authorize(&token) {
+        if credential.source == CredentialSource::Cookie && !passes_csrf_check(req, &claims) {
+            return forbidden_err("The X-CSRF-Token header is missing or wrong.");
+        }
+        // The username comes from the account, as tokens issued before
+        // a rename still carry the old one.
+        match db_conn(req).and_then(|conn| super::actions::check_claims(&claims, &conn)) {
+            Ok(user) => Ok(AuthUser {
+                id: user.id,
+                username: user.username,
+                session_id: Some(claims.sid),
+                oauth_token_id: None,
+            }),
+            Err(TodosError::AccountNotFound) => invalid_token("The account no longer exists."),
+            Err(TodosError::TokenRevoked) => invalid_token(
+                "Token was revoked by logging out, a password change or an administrator.",
+            ),
+            Err(TodosError::AccountDisabled) => forbidden_err(
+                "The account is scheduled for deletion; restore it with POST /users/restore.",
+            ),
+            Err(TodosError::AccountSuspended) => {
+                forbidden_err("The account was disabled by an administrator.")
+            }
+            Err(_) => internal_err(),
+        }
+    } else {
+        invalid_token("Token is invalid or expired.")
+    }
+}
+
+/// An `AuthUser` whose account may use everything: it has verified its email
+/// address, or it has none and `REQUIRE_EMAIL` is off.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct VerifiedUser {
+    pub id: i32,
+    pub username: String,
+}
+
+impl FromRequest for VerifiedUser {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, payload: &mut dev::Payload) -> Self::Future {
+        match futures::executor::block_on(AuthUser::from_request(req, payload)) {
+            Err(e) => ready(Err(e)),
+            Ok(user) => {
+                match db_conn(req).and_then(|conn| super::actions::ensure_verified(user.id, &conn))
+                {
+                    Ok(()) => ready(Ok(Self {
+                        id: user.id,
+                        username: user.username,
+                    })),
+                    Err(TodosError::EmailNotVerified) => ready(Err(HttpResponse::Forbidden()
+                        .json(serde_json::json!({
+                            "message": "Verify your email address first."
+                        }))
+                        .into())),
+                    Err(_) => ready(Err(HttpResponse::InternalServerError()
+                        .json(serde_json::json!({
+                            "message": "Something went wrong while checking the account."
+                        }))
+                        .into())),
                 }
-            } else {
-                unauth_err("Auth header is malformed or contains non-ASCII characters.")
             }
-        } else {
-            unauth_err("Auth header not present.")
         }
     }
 }
 
+/// An `AuthUser` whose role grants `P::PERMISSION`.
+#[derive(Debug)]
+pub struct Authorized<P> {
+    pub id: i32,
+    pub username: String,
+    permission: PhantomData<P>,
+}
+
+impl<P: RequiredPermission> FromRequest for Authorized<P> {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, payload: &mut dev::Payload) -> Self::Future {
+        let user = match futures::executor::block_on(AuthUser::from_request(req, payload)) {
+            Ok(user) => user,
+            Err(e) => return ready(Err(e)),
+        };
+        match db_conn(req)
+            .and_then(|conn| super::actions::ensure_permission(user.id, P::PERMISSION, &conn))
+        {
+            Ok(()) => ready(Ok(Self {
+                id: user.id,
+                username: user.username,
+                permission: PhantomData,
+            })),
+            Err(TodosError::MissingPermission(permission)) => ready(Err(HttpResponse::Forbidden()
+                .json(serde_json::json!({
+                    "message": format!("This needs the `{}` permission.", permission.as_str())
+                }))
+                .into())),
+            Err(_) => ready(Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while checking the account."
+                }))
+                .into())),
+        }
+    }
+}
+
+/// The tenant the `X-Workspace` header selects, by workspace id or slug, for
+/// an `AuthUser`; without the header, the user's personal todos. Users get a
+/// 404 for workspaces they aren't a member of.
+impl FromRequest for models::Tenant {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, payload: &mut dev::Payload) -> Self::Future {
+        let user = match futures::executor::block_on(AuthUser::from_request(req, payload)) {
+            Ok(user) => user,
+            Err(e) => return ready(Err(e)),
+        };
+        let selector = match req
+            .headers()
+            .get(WORKSPACE_HEADER)
+            .map(|value| value.to_str())
+        {
+            None => None,
+            Some(Ok(selector)) => Some(selector),
+            Some(Err(_)) => {
+                return ready(Err(HttpResponse::BadRequest()
+                    .json(serde_json::json!({
+                        "message": "The X-Workspace header must be a workspace id or slug."
+                    }))
+                    .into()))
+            }
+        };
+        match db_conn(req).and_then(|conn| super::actions::resolve_tenant(user.id, selector, &conn))
+        {
+            Ok(tenant) => ready(Ok(tenant)),
+            Err(TodosError::WorkspaceNotFound) => ready(Err(HttpResponse::NotFound()
+                .json(serde_json::json!({
+                    "message": "The workspace does not exist or you are not a member."
+                }))
+                .into())),
+            Err(_) => ready(Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while checking the workspace."
+                }))
+                .into())),
+        }
+    }
+}
+
+/// A todo of the request's `models::Tenant`, by the `todo_id` in the path.
 pub struct TodoIsOfUser {
     pub result: Result<super::models::Todo, TodosError>,
 }
@@ -104,43 +686,45 @@ impl FromRequest for TodoIsOfUser {
     type Config = ();
 
     fn from_request(req: &HttpRequest, payload: &mut dev::Payload) -> Self::Future {
-        match futures::executor::block_on(AuthUser::from_request(req, payload)) {
+        match futures::executor::block_on(models::Tenant::from_request(req, payload)) {
             Err(e) => ready(Err(e)),
-            Ok(claims) => {
-                let unauth_err = |message| {
-                    ready(Err(HttpResponse::NotFound()
-                        .json(serde_json::json!({ "message": message }))
-                        .into()))
-                };
+            Ok(tenant) => {
                 if let Ok(todo_id) =
                     futures::executor::block_on(web::Path::<i32>::from_request(req, payload))
                 {
-                    let pool = futures::executor::block_on(
-                        web::Data::<super::DbPool>::from_request(req, payload),
-                    )
-                    .unwrap();
-                    let conn = pool.get().expect("Failed to get db conn from pool.");
-                    use super::schema::todos::dsl::*;
-                    let result = todos
-                        .filter(id.eq(todo_id.into_inner()))
-                        .first::<super::models::Todo>(&conn)
-                        .map_err(|e| match e {
-                            diesel::result::Error::NotFound => TodosError::TodoNotFoundError,
-                            _ => TodosError::DieselCrudError,
-                        });
-                    if let Ok(todo) = &result {
-                        if todo.user_id == claims.id {
-                            ready(Ok(Self { result }))
-                        } else {
-                            unauth_err("The todo that you were trying to find does not exist.")
-                        }
-                    } else {
-                        ready(Ok(Self { result }))
-                    }
+                    let result = db_conn(req).and_then(|conn| {
+                        super::actions::get_todo_of_tenant(tenant, todo_id.into_inner(), &conn)
+                    });
+                    ready(Ok(Self { result }))
                 } else {
-                    unreachable!("This should never happen. `todo_id` should always exist when this is used, or else...")
+                    // Only numbers are todo ids.
+                    ready(Ok(Self {
+                        result: Err(TodosError::TodoNotFoundError),
+                    }))
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    #[test]
+    fn requests_are_authenticated_once() {
+        // Without a pool or a token, only the cached user can be extracted.
+        let (req, mut payload) = TestRequest::default().to_http_parts();
+        req.extensions_mut().insert(AuthUser {
+            id: 7,
+            username: "cached".to_owned(),
+            session_id: Some(3),
+            oauth_token_id: None,
+        });
+        let user = futures::executor::block_on(AuthUser::from_request(&req, &mut payload))
+            .expect("the cached user");
+        assert_eq!((user.id, user.username.as_str()), (7, "cached"));
+    }
+}
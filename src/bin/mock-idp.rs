@@ -0,0 +1,183 @@
+//! A stand-in OpenID Connect provider for trying out `/oidc/login` locally.
+//! Every login succeeds at once, as `MOCK_IDP_USER` (`alice` by default)
+//! with the verified address `{user}@example.com`:
+//!
+//! ```sh
+//! mkdir idp-keys
+//! openssl genpkey -algorithm ed25519 -out idp-keys/mock.pem
+//! openssl pkey -in idp-keys/mock.pem -pubout -out idp-keys/mock.pub.pem
+//! MOCK_IDP_KEYS_DIR=idp-keys cargo run --bin mock-idp
+//! ```
+//!
+//! Then run the server with `OIDC_ISSUER=http://127.0.0.1:9090`,
+//! `OIDC_CLIENT_ID=todos` and
+//! `OIDC_REDIRECT_URI=http://127.0.0.1:8080/oidc/callback`.
+
+use std::{collections::HashMap, sync::Mutex};
+
+use actix_web::{get, post, web, App, HttpRequest, HttpResponse, HttpServer};
+use serde::{Deserialize, Serialize};
+use todos::{
+    auth::{basic_credentials, generate_token},
+    keys::Keyring,
+    oauth::{redirect_uri_with, verify_pkce},
+};
+
+struct Login {
+    client_id: String,
+    redirect_uri: String,
+    nonce: Option<String>,
+    code_challenge: String,
+}
+
+struct Idp {
+    issuer: String,
+    user: String,
+    keyring: Keyring,
+    /// Codes that haven't been traded for tokens yet.
+    logins: Mutex<HashMap<String, Login>>,
+}
+
+#[derive(Deserialize)]
+struct AuthorizeQuery {
+    client_id: String,
+    redirect_uri: String,
+    state: Option<String>,
+    nonce: Option<String>,
+    code_challenge: String,
+}
+
+#[derive(Deserialize)]
+struct TokenForm {
+    code: String,
+    redirect_uri: String,
+    code_verifier: String,
+    client_id: Option<String>,
+}
+
+#[derive(Serialize)]
+struct IdTokenClaims<'a> {
+    iss: &'a str,
+    sub: &'a str,
+    aud: &'a str,
+    exp: i64,
+    iat: i64,
+    nonce: Option<String>,
+    email: String,
+    email_verified: bool,
+    preferred_username: &'a str,
+}
+
+#[get("/.well-known/openid-configuration")]
+async fn discovery(idp: web::Data<Idp>) -> HttpResponse {
+    HttpResponse::Ok().json(serde_json::json!({
+        "issuer": idp.issuer,
+        "authorization_endpoint": format!("{}/authorize", idp.issuer),
+        "token_endpoint": format!("{}/token", idp.issuer),
+        "jwks_uri": format!("{}/jwks", idp.issuer),
+        "response_types_supported": ["code"],
+        "subject_types_supported": ["public"],
+        "id_token_signing_alg_values_supported": ["RS256", "EdDSA"],
+        "code_challenge_methods_supported": ["S256"],
+    }))
+}
+
+#[get("/authorize")]
+async fn authorize(idp: web::Data<Idp>, query: web::Query<AuthorizeQuery>) -> HttpResponse {
+    let query = query.into_inner();
+    let code = generate_token();
+    let mut params = vec![("code", code.as_str())];
+    if let Some(state) = &query.state {
+        params.push(("state", state));
+    }
+    let location = redirect_uri_with(&query.redirect_uri, &params);
+    idp.logins.lock().unwrap().insert(
+        code.clone(),
+        Login {
+            client_id: query.client_id,
+            redirect_uri: query.redirect_uri,
+            nonce: query.nonce,
+            code_challenge: query.code_challenge,
+        },
+    );
+    HttpResponse::SeeOther()
+        .header("Location", location)
+        .finish()
+}
+
+#[post("/token")]
+async fn token(req: HttpRequest, idp: web::Data<Idp>, form: web::Form<TokenForm>) -> HttpResponse {
+    let form = form.into_inner();
+    let invalid_grant =
+        || HttpResponse::BadRequest().json(serde_json::json!({ "error": "invalid_grant" }));
+    let login = match idp.logins.lock().unwrap().remove(&form.code) {
+        Some(login) => login,
+        None => return invalid_grant(),
+    };
+    let client_id = basic_credentials(&req)
+        .map(|(client_id, _)| client_id)
+        .or(form.client_id);
+    if client_id.as_deref() != Some(login.client_id.as_str())
+        || form.redirect_uri != login.redirect_uri
+        || !verify_pkce(&login.code_challenge, &form.code_verifier)
+    {
+        return invalid_grant();
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    let id_token = idp
+        .keyring
+        .encode(&IdTokenClaims {
+            iss: &idp.issuer,
+            sub: &idp.user,
+            aud: &login.client_id,
+            exp: now + 300,
+            iat: now,
+            nonce: login.nonce,
+            email: format!("{}@example.com", idp.user),
+            email_verified: true,
+            preferred_username: &idp.user,
+        })
+        .expect("Signing the ID token failed.");
+    HttpResponse::Ok().json(serde_json::json!({
+        "access_token": generate_token(),
+        "token_type": "Bearer",
+        "expires_in": 300,
+        "id_token": id_token,
+    }))
+}
+
+#[get("/jwks")]
+async fn jwks(idp: web::Data<Idp>) -> HttpResponse {
+    HttpResponse::Ok().json(idp.keyring.jwks())
+}
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    dotenv::dotenv().ok();
+    let port = std::env::var("MOCK_IDP_PORT")
+        .map(|port| port.parse::<u16>().expect("MOCK_IDP_PORT"))
+        .unwrap_or(9090);
+    let dir = std::env::var("MOCK_IDP_KEYS_DIR").expect("MOCK_IDP_KEYS_DIR");
+    let kid = std::env::var("MOCK_IDP_KID").unwrap_or_else(|_| "mock".to_owned());
+    let idp = web::Data::new(Idp {
+        issuer: std::env::var("MOCK_IDP_ISSUER")
+            .unwrap_or_else(|_| format!("http://127.0.0.1:{}", port)),
+        user: std::env::var("MOCK_IDP_USER").unwrap_or_else(|_| "alice".to_owned()),
+        keyring: Keyring::from_dir(&dir, &kid).unwrap_or_else(|e| panic!("Keys: {}", e)),
+        logins: Mutex::new(HashMap::new()),
+    });
+    println!("Mock identity provider at {}", idp.issuer);
+
+    HttpServer::new(move || {
+        App::new()
+            .app_data(idp.clone())
+            .service(discovery)
+            .service(authorize)
+            .service(token)
+            .service(jwks)
+    })
+    .bind(("127.0.0.1", port))?
+    .run()
+    .await
+}
@@ -0,0 +1,34 @@
+//! Gives a user a role, such as the first administrator theirs:
+//!
+//! ```sh
+//! cargo run --bin set-role -- alice admin
+//! ```
+
+use diesel::{Connection, PgConnection};
+use todos::{
+    actions::{find_user_id, set_user_role},
+    audit::RequestInfo,
+};
+
+fn main() {
+    dotenv::dotenv().ok();
+    let mut args = std::env::args().skip(1);
+    let (username, role) = match (args.next(), args.next()) {
+        (Some(username), Some(role)) => (username, role),
+        _ => {
+            eprintln!("Usage: set-role <username> <role>");
+            std::process::exit(2);
+        }
+    };
+    let db_url = std::env::var("DATABASE_URL").expect("DATABASE_URL");
+    let conn = PgConnection::establish(&db_url).expect("Failed to connect to the database.");
+    match find_user_id(&username, &conn)
+        .and_then(|uid| set_user_role(None, uid, &role, &RequestInfo::default(), &conn))
+    {
+        Ok(_) => println!("{} is now {}.", username, role),
+        Err(e) => {
+            eprintln!("Giving {} the role {} failed: {}", username, role, e);
+            std::process::exit(1);
+        }
+    }
+}
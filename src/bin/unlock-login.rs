@@ -0,0 +1,30 @@
+//! Lifts the login lockout of a username or client address:
+//!
+//! ```sh
+//! cargo run --bin unlock-login -- alice
+//! cargo run --bin unlock-login -- 203.0.113.7
+//! ```
+
+use diesel::{Connection, PgConnection};
+use todos::actions::unlock_login;
+
+fn main() {
+    dotenv::dotenv().ok();
+    let subject = match std::env::args().nth(1) {
+        Some(subject) => subject,
+        None => {
+            eprintln!("Usage: unlock-login <username or IP address>");
+            std::process::exit(2);
+        }
+    };
+    let db_url = std::env::var("DATABASE_URL").expect("DATABASE_URL");
+    let conn = PgConnection::establish(&db_url).expect("Failed to connect to the database.");
+    match unlock_login(&subject, &conn) {
+        Ok(true) => println!("Unlocked {}.", subject),
+        Ok(false) => println!("{} wasn't locked out.", subject),
+        Err(e) => {
+            eprintln!("Unlocking {} failed: {}", subject, e);
+            std::process::exit(1);
+        }
+    }
+}
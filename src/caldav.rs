@@ -0,0 +1,793 @@
+//! A minimal CalDAV server (RFC 4791) that exposes each user's todos as one
+//! `VTODO` collection at `/dav/{username}/todos/`, one `{uid}.ics` resource
+//! per todo. Clients authenticate with HTTP Basic and either the CalDAV app
+//! password or the account password.
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    future::{ready, Ready},
+    hash::{Hash, Hasher},
+};
+
+use actix_web::{
+    dev,
+    http::{header, Method, StatusCode},
+    web, Error, FromRequest, HttpRequest, HttpResponse,
+};
+use quick_xml::{events::Event, Reader};
+
+use crate::{
+    actions, auth,
+    error::TodosError,
+    events::{Broadcaster, TodoEventKind},
+    ical, models, DbPool,
+};
+
+const COLLECTION: &str = "todos";
+
+pub struct DavUser {
+    pub id: i32,
+    pub username: String,
+}
+
+impl FromRequest for DavUser {
+    type Error = Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, payload: &mut dev::Payload) -> Self::Future {
+        let unauth_err = |message| {
+            ready(Err(HttpResponse::Unauthorized()
+                .header(header::WWW_AUTHENTICATE, "Basic realm=\"todos\"")
+                .json(serde_json::json!({ "message": message }))
+                .into()))
+        };
+        let credentials = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|header| header.to_str().ok())
+            .and_then(|header| header.strip_prefix("Basic "))
+            .and_then(|encoded| base64::decode(encoded).ok())
+            .and_then(|decoded| String::from_utf8(decoded).ok());
+        if let Some((username, password)) = credentials.as_deref().and_then(|c| c.split_once(':')) {
+            let pool = futures::executor::block_on(web::Data::<DbPool>::from_request(req, payload))
+                .unwrap();
+            let conn = match pool.get().map_err(TodosError::from) {
+                Ok(conn) => conn,
+                Err(_) => return ready(Err(internal_error())),
+            };
+            // Clients send the credentials with every request, so the app
+            // password is tried first to spare them an Argon2 verification
+            // each time. It was created by a fully authenticated session, so
+            // it also works for accounts with two-factor authentication.
+            match actions::verify_caldav_password(username, password, &conn) {
+                Ok(Some(user)) => {
+                    return ready(Ok(Self {
+                        id: user.id,
+                        username: user.username,
+                    }))
+                }
+                Ok(None) => {}
+                Err(_) => return ready(Err(internal_error())),
+            }
+            let ip = auth::client_ip(req);
+            match actions::verify_credentials(username, password, ip.as_deref(), &conn) {
+                // Basic auth has no room for a second factor.
+                Ok(user) if user.totp_enabled_at.is_some() => ready(Err(HttpResponse::Forbidden()
+                    .json(serde_json::json!({
+                        "message": "Accounts with two-factor authentication log in to CalDAV with an app password from `POST /users/me/caldav-password` instead of the account password."
+                    }))
+                    .into())),
+                Ok(user) => ready(Ok(Self {
+                    id: user.id,
+                    username: user.username,
+                })),
+                Err(TodosError::TooManyLoginAttempts(seconds)) => {
+                    ready(Err(HttpResponse::TooManyRequests()
+                        .header(header::RETRY_AFTER, seconds.to_string())
+                        .json(serde_json::json!({
+                            "message": "Too many failed logins; try again later."
+                        }))
+                        .into()))
+                }
+                Err(_) => unauth_err("Bad credentials"),
+            }
+        } else {
+            unauth_err("Basic auth credentials not present or malformed.")
+        }
+    }
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Percent-encodes everything but RFC 3986 unreserved characters.
+fn encode_segment(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+fn home_href(username: &str) -> String {
+    format!("/dav/{}/", encode_segment(username))
+}
+
+fn collection_href(username: &str) -> String {
+    format!("{}{}/", home_href(username), COLLECTION)
+}
+
+fn todo_href(username: &str, todo: &models::Todo) -> String {
+    format!(
+        "{}{}.ics",
+        collection_href(username),
+        encode_segment(&todo.ical_uid)
+    )
+}
+
+fn etag(todo: &models::Todo) -> String {
+    format!("\"{}-{}\"", todo.id, todo.updated_at.timestamp_nanos())
+}
+
+/// Changes whenever a todo of the collection is created, updated or deleted.
+fn collection_tag(todos: &[models::Todo]) -> String {
+    let mut hasher = DefaultHasher::new();
+    for todo in todos {
+        (todo.id, todo.updated_at.timestamp_nanos()).hash(&mut hasher);
+    }
+    format!("{:016x}", hasher.finish())
+}
+
+/// What a sync token records about the collection: its latest `updated_at`,
+/// its highest todo id and how many todos it held. Todo ids only grow, so a
+/// todo changed since has a later `updated_at` or a higher id, and one
+/// deleted since leaves fewer todos at or below the recorded id.
+#[derive(Debug, PartialEq)]
+struct SyncState {
+    updated_at: i64,
+    max_id: i32,
+    count: usize,
+}
+
+impl SyncState {
+    const PREFIX: &'static str = "urn:todos:sync:";
+
+    fn of(todos: &[models::Todo]) -> Self {
+        Self {
+            updated_at: todos
+                .iter()
+                .map(|todo| todo.updated_at.timestamp_nanos())
+                .max()
+                .unwrap_or(0),
+            max_id: todos.iter().map(|todo| todo.id).max().unwrap_or(0),
+            count: todos.len(),
+        }
+    }
+
+    fn parse(token: &str) -> Option<Self> {
+        let mut parts = token.strip_prefix(Self::PREFIX)?.split('-');
+        let state = Self {
+            updated_at: parts.next()?.parse().ok()?,
+            max_id: parts.next()?.parse().ok()?,
+            count: parts.next()?.parse().ok()?,
+        };
+        match parts.next() {
+            Some(_) => None,
+            None => Some(state),
+        }
+    }
+
+    fn token(&self) -> String {
+        format!(
+            "{}{}-{}-{}",
+            Self::PREFIX,
+            self.updated_at,
+            self.max_id,
+            self.count
+        )
+    }
+
+    /// The todos changed since this state, or `None` if any were deleted,
+    /// which can't be reported without tombstones.
+    fn changes<'a>(&self, todos: &'a [models::Todo]) -> Option<Vec<&'a models::Todo>> {
+        let kept = todos.iter().filter(|todo| todo.id <= self.max_id).count();
+        if kept < self.count {
+            return None;
+        }
+        Some(
+            todos
+                .iter()
+                .filter(|todo| {
+                    todo.id > self.max_id || todo.updated_at.timestamp_nanos() > self.updated_at
+                })
+                .collect(),
+        )
+    }
+}
+
+fn dav_method(name: &'static [u8]) -> Method {
+    Method::from_bytes(name).expect("DAV method names are valid methods.")
+}
+
+fn multistatus(content: String) -> HttpResponse {
+    HttpResponse::build(StatusCode::MULTI_STATUS)
+        .content_type("application/xml; charset=utf-8")
+        .body(format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\
+             <d:multistatus xmlns:d=\"DAV:\" xmlns:c=\"urn:ietf:params:xml:ns:caldav\" \
+             xmlns:cs=\"http://calendarserver.org/ns/\">{}</d:multistatus>",
+            content
+        ))
+}
+
+fn response(href: &str, props: &str) -> String {
+    format!(
+        "<d:response><d:href>{}</d:href><d:propstat><d:prop>{}</d:prop>\
+         <d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>",
+        xml_escape(href),
+        props
+    )
+}
+
+fn not_found_response(href: &str) -> String {
+    format!(
+        "<d:response><d:href>{}</d:href><d:status>HTTP/1.1 404 Not Found</d:status></d:response>",
+        xml_escape(href)
+    )
+}
+
+fn principal_props(username: &str) -> String {
+    let home = xml_escape(&home_href(username));
+    format!(
+        "<d:resourcetype><d:collection/><d:principal/></d:resourcetype>\
+         <d:displayname>{}</d:displayname>\
+         <d:current-user-principal><d:href>{home}</d:href></d:current-user-principal>\
+         <d:principal-URL><d:href>{home}</d:href></d:principal-URL>\
+         <c:calendar-home-set><d:href>{home}</d:href></c:calendar-home-set>",
+        xml_escape(username),
+        home = home
+    )
+}
+
+fn collection_props(todos: &[models::Todo]) -> String {
+    format!(
+        "<d:resourcetype><d:collection/><c:calendar/></d:resourcetype>\
+         <d:displayname>Todos</d:displayname>\
+         <c:supported-calendar-component-set><c:comp name=\"VTODO\"/></c:supported-calendar-component-set>\
+         <d:current-user-privilege-set><d:privilege><d:read/></d:privilege>\
+         <d:privilege><d:write/></d:privilege></d:current-user-privilege-set>\
+         <cs:getctag>{}</cs:getctag><d:sync-token>{}</d:sync-token>",
+        collection_tag(todos),
+        SyncState::of(todos).token()
+    )
+}
+
+fn todo_props(todo: &models::Todo, with_data: bool) -> String {
+    let mut props = format!(
+        "<d:resourcetype/><d:getetag>{}</d:getetag>\
+         <d:getcontenttype>text/calendar; charset=utf-8; component=vtodo</d:getcontenttype>",
+        xml_escape(&etag(todo))
+    );
+    if with_data {
+        props.push_str(&format!(
+            "<c:calendar-data>{}</c:calendar-data>",
+            xml_escape(&ical::write_calendar(std::slice::from_ref(todo)))
+        ));
+    }
+    props
+}
+
+enum ReportKind {
+    CalendarQuery,
+    CalendarMultiget,
+    SyncCollection,
+}
+
+struct Report {
+    kind: ReportKind,
+    hrefs: Vec<String>,
+    sync_token: Option<String>,
+}
+
+fn parse_report(body: &str) -> Option<Report> {
+    let mut reader = Reader::from_str(body);
+    reader.trim_text(true);
+    let mut buf = Vec::new();
+    let mut report: Option<Report> = None;
+    let mut text_target: Option<&'static str> = None;
+    loop {
+        match reader.read_event(&mut buf).ok()? {
+            Event::Start(e) | Event::Empty(e) => {
+                let name = e.local_name();
+                if report.is_none() {
+                    let kind = match name {
+                        b"calendar-query" => ReportKind::CalendarQuery,
+                        b"calendar-multiget" => ReportKind::CalendarMultiget,
+                        b"sync-collection" => ReportKind::SyncCollection,
+                        _ => return None,
+                    };
+                    report = Some(Report {
+                        kind,
+                        hrefs: Vec::new(),
+                        sync_token: None,
+                    });
+                }
+                text_target = match name {
+                    b"href" => Some("href"),
+                    b"sync-token" => Some("sync-token"),
+                    _ => None,
+                };
+            }
+            Event::Text(e) => {
+                let text = e.unescape_and_decode(&reader).ok()?;
+                match (text_target.take(), report.as_mut()) {
+                    (Some("href"), Some(report)) => report.hrefs.push(text),
+                    (Some("sync-token"), Some(report)) => report.sync_token = Some(text),
+                    _ => {}
+                }
+            }
+            Event::End(_) => text_target = None,
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    report
+}
+
+/// Reads the last path segment of an href as the UID it was built from.
+fn uid_from_href(href: &str) -> Option<String> {
+    let name = href.trim_end_matches('/').rsplit('/').next()?;
+    let name = name.strip_suffix(".ics")?;
+    let mut bytes = Vec::with_capacity(name.len());
+    let mut chars = name.bytes();
+    while let Some(byte) = chars.next() {
+        if byte == b'%' {
+            let hex = [chars.next()?, chars.next()?];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+fn depth_is_zero(req: &HttpRequest) -> bool {
+    req.headers()
+        .get("Depth")
+        .and_then(|depth| depth.to_str().ok())
+        == Some("0")
+}
+
+fn message(status: StatusCode, message: &str) -> Error {
+    HttpResponse::build(status)
+        .json(serde_json::json!({ "message": message }))
+        .into()
+}
+
+fn internal_error() -> Error {
+    message(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Something went wrong while performing DB operations.",
+    )
+}
+
+/// Only the authenticated user's own principal is visible.
+fn check_owner(user: &DavUser, username: &str) -> Result<(), Error> {
+    if user.username == username {
+        Ok(())
+    } else {
+        Err(message(
+            StatusCode::NOT_FOUND,
+            "The calendar that you were trying to find does not exist.",
+        ))
+    }
+}
+
+async fn load_todos(pool: &web::Data<DbPool>, uid: i32) -> Result<Vec<models::Todo>, Error> {
+    let pool = pool.clone();
+    web::block(move || {
+        let conn = pool.get()?;
+        actions::get_all_todos(models::Tenant::Personal { user_id: uid }, &conn)
+    })
+    .await
+    .map_err(|_| internal_error())
+}
+
+async fn load_todo(
+    pool: &web::Data<DbPool>,
+    uid: i32,
+    resource_uid: String,
+) -> Result<Option<models::Todo>, Error> {
+    let pool = pool.clone();
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        actions::get_todo_by_ical_uid(
+            models::Tenant::Personal { user_id: uid },
+            &resource_uid,
+            &conn,
+        )
+    })
+    .await;
+    match result {
+        Ok(todo) => Ok(Some(todo)),
+        Err(e) => match e.into() {
+            TodosError::TodoNotFoundError => Ok(None),
+            _ => Err(internal_error()),
+        },
+    }
+}
+
+/// Checks `If-Match` and `If-None-Match` against the current state of a
+/// resource, so that clients don't overwrite each other's changes.
+fn check_preconditions(req: &HttpRequest, todo: Option<&models::Todo>) -> Result<(), Error> {
+    let header_value = |name| {
+        req.headers()
+            .get(name)
+            .and_then(|value: &header::HeaderValue| value.to_str().ok())
+    };
+    let current = todo.map(etag);
+    let failed = match (
+        header_value(header::IF_MATCH),
+        header_value(header::IF_NONE_MATCH),
+    ) {
+        (Some("*"), _) => current.is_none(),
+        (Some(expected), _) => current.as_deref() != Some(expected),
+        (None, Some("*")) => current.is_some(),
+        (None, Some(unexpected)) => current.as_deref() == Some(unexpected),
+        (None, None) => false,
+    };
+    if failed {
+        Err(message(
+            StatusCode::PRECONDITION_FAILED,
+            "The todo was changed by someone else.",
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+async fn options() -> HttpResponse {
+    HttpResponse::Ok()
+        .header("DAV", "1, 3, calendar-access")
+        .header(header::ALLOW, "OPTIONS, GET, PUT, DELETE, PROPFIND, REPORT")
+        .finish()
+}
+
+async fn well_known() -> HttpResponse {
+    HttpResponse::MovedPermanently()
+        .header(header::LOCATION, "/dav/")
+        .finish()
+}
+
+async fn propfind_root(user: DavUser) -> HttpResponse {
+    let home = xml_escape(&home_href(&user.username));
+    multistatus(response(
+        "/dav/",
+        &format!(
+            "<d:resourcetype><d:collection/></d:resourcetype>\
+             <d:current-user-principal><d:href>{}</d:href></d:current-user-principal>",
+            home
+        ),
+    ))
+}
+
+async fn propfind_home(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    username: web::Path<String>,
+    user: DavUser,
+) -> Result<HttpResponse, Error> {
+    check_owner(&user, &username)?;
+    let mut content = response(&home_href(&user.username), &principal_props(&user.username));
+    if !depth_is_zero(&req) {
+        let todos = load_todos(&pool, user.id).await?;
+        content.push_str(&response(
+            &collection_href(&user.username),
+            &collection_props(&todos),
+        ));
+    }
+    Ok(multistatus(content))
+}
+
+async fn propfind_collection(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    username: web::Path<String>,
+    user: DavUser,
+) -> Result<HttpResponse, Error> {
+    check_owner(&user, &username)?;
+    let todos = load_todos(&pool, user.id).await?;
+    let mut content = response(&collection_href(&user.username), &collection_props(&todos));
+    if !depth_is_zero(&req) {
+        for todo in &todos {
+            content.push_str(&response(
+                &todo_href(&user.username, todo),
+                &todo_props(todo, false),
+            ));
+        }
+    }
+    Ok(multistatus(content))
+}
+
+async fn report_collection(
+    pool: web::Data<DbPool>,
+    username: web::Path<String>,
+    body: String,
+    user: DavUser,
+) -> Result<HttpResponse, Error> {
+    check_owner(&user, &username)?;
+    let report = parse_report(&body).ok_or_else(|| {
+        message(
+            StatusCode::BAD_REQUEST,
+            "Only calendar-query, calendar-multiget and sync-collection reports are supported.",
+        )
+    })?;
+    let todos = load_todos(&pool, user.id).await?;
+    let mut content = String::new();
+    match report.kind {
+        ReportKind::CalendarQuery => {
+            for todo in &todos {
+                content.push_str(&response(
+                    &todo_href(&user.username, todo),
+                    &todo_props(todo, true),
+                ));
+            }
+        }
+        ReportKind::CalendarMultiget => {
+            for href in &report.hrefs {
+                let todo = uid_from_href(href)
+                    .and_then(|uid| todos.iter().find(|todo| todo.ical_uid == uid));
+                content.push_str(&match todo {
+                    Some(todo) => response(href, &todo_props(todo, true)),
+                    None => not_found_response(href),
+                });
+            }
+        }
+        ReportKind::SyncCollection => {
+            let changed = match report.sync_token.as_deref() {
+                None | Some("") => Some(todos.iter().collect()),
+                Some(token) => SyncState::parse(token).and_then(|state| state.changes(&todos)),
+            };
+            // An unknown token, or deletions since it was handed out, make
+            // the client fall back to a full sync (RFC 6578, section 3.2).
+            let changed = match changed {
+                Some(changed) => changed,
+                None => {
+                    return Ok(HttpResponse::Forbidden()
+                        .content_type("application/xml; charset=utf-8")
+                        .body(
+                            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\
+                             <d:error xmlns:d=\"DAV:\"><d:valid-sync-token/></d:error>",
+                        ))
+                }
+            };
+            for todo in changed {
+                content.push_str(&response(
+                    &todo_href(&user.username, todo),
+                    &todo_props(todo, false),
+                ));
+            }
+            content.push_str(&format!(
+                "<d:sync-token>{}</d:sync-token>",
+                xml_escape(&SyncState::of(&todos).token())
+            ));
+        }
+    }
+    Ok(multistatus(content))
+}
+
+async fn propfind_todo(
+    pool: web::Data<DbPool>,
+    path: web::Path<(String, String)>,
+    user: DavUser,
+) -> Result<HttpResponse, Error> {
+    let (username, name) = path.into_inner();
+    check_owner(&user, &username)?;
+    let href = format!("{}{}", collection_href(&user.username), name);
+    let uid = uid_from_href(&href).unwrap_or_default();
+    match load_todo(&pool, user.id, uid).await? {
+        Some(todo) => Ok(multistatus(response(&href, &todo_props(&todo, false)))),
+        None => Err(message(
+            StatusCode::NOT_FOUND,
+            "The todo that you were trying to find does not exist.",
+        )),
+    }
+}
+
+async fn get_todo(
+    pool: web::Data<DbPool>,
+    path: web::Path<(String, String)>,
+    user: DavUser,
+) -> Result<HttpResponse, Error> {
+    let (username, name) = path.into_inner();
+    check_owner(&user, &username)?;
+    let uid = uid_from_href(&name).unwrap_or_default();
+    match load_todo(&pool, user.id, uid).await? {
+        Some(todo) => Ok(HttpResponse::Ok()
+            .content_type("text/calendar; charset=utf-8")
+            .header(header::ETAG, etag(&todo))
+            .body(ical::write_calendar(std::slice::from_ref(&todo)))),
+        None => Err(message(
+            StatusCode::NOT_FOUND,
+            "The todo that you were trying to find does not exist.",
+        )),
+    }
+}
+
+async fn put_todo(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    broadcaster: web::Data<Broadcaster>,
+    path: web::Path<(String, String)>,
+    body: String,
+    user: DavUser,
+) -> Result<HttpResponse, Error> {
+    let (username, name) = path.into_inner();
+    check_owner(&user, &username)?;
+    let uid = uid_from_href(&name).unwrap_or_default();
+    let vtodo = match ical::parse_vtodos(&body).as_deref() {
+        Ok([vtodo]) if vtodo.uid == uid => vtodo.clone(),
+        _ => {
+            return Err(message(
+                StatusCode::BAD_REQUEST,
+                "The body must hold exactly one VTODO whose UID matches the resource name.",
+            ))
+        }
+    };
+    let existing_todo = load_todo(&pool, user.id, uid).await?;
+    check_preconditions(&req, existing_todo.as_ref())?;
+
+    let todo = web::block(move || {
+        let conn = pool.get()?;
+        actions::upsert_vtodo(models::Tenant::Personal { user_id: user.id }, vtodo, &conn)
+    })
+    .await
+    .map_err(|_| internal_error())?;
+    if existing_todo.is_some() {
+        broadcaster.publish(TodoEventKind::Updated, &todo);
+        Ok(HttpResponse::NoContent()
+            .header(header::ETAG, etag(&todo))
+            .finish())
+    } else {
+        broadcaster.publish(TodoEventKind::Created, &todo);
+        Ok(HttpResponse::Created()
+            .header(header::ETAG, etag(&todo))
+            .finish())
+    }
+}
+
+async fn delete_todo(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    broadcaster: web::Data<Broadcaster>,
+    path: web::Path<(String, String)>,
+    user: DavUser,
+) -> Result<HttpResponse, Error> {
+    let (username, name) = path.into_inner();
+    check_owner(&user, &username)?;
+    let uid = uid_from_href(&name).unwrap_or_default();
+    let existing_todo = match load_todo(&pool, user.id, uid).await? {
+        Some(todo) => todo,
+        None => {
+            return Err(message(
+                StatusCode::NOT_FOUND,
+                "The todo that you were trying to find does not exist.",
+            ))
+        }
+    };
+    check_preconditions(&req, Some(&existing_todo))?;
+
+    let todo = web::block(move || {
+        let conn = pool.get()?;
+        actions::delete_existing_todo(existing_todo, &conn)
+    })
+    .await
+    .map_err(|_| internal_error())?;
+    broadcaster.publish(TodoEventKind::Deleted, &todo);
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Registers the CalDAV routes.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    let propfind = || web::method(dav_method(b"PROPFIND"));
+    let report = || web::method(dav_method(b"REPORT"));
+    cfg.service(web::resource("/.well-known/caldav").to(well_known))
+        .service(
+            web::resource("/dav/")
+                .route(web::method(Method::OPTIONS).to(options))
+                .route(propfind().to(propfind_root)),
+        )
+        .service(
+            web::resource(["/dav/{username}", "/dav/{username}/"])
+                .route(web::method(Method::OPTIONS).to(options))
+                .route(propfind().to(propfind_home)),
+        )
+        .service(
+            web::resource(["/dav/{username}/todos", "/dav/{username}/todos/"])
+                .route(web::method(Method::OPTIONS).to(options))
+                .route(propfind().to(propfind_collection))
+                .route(report().to(report_collection)),
+        )
+        .service(
+            web::resource("/dav/{username}/todos/{name}")
+                .route(web::method(Method::OPTIONS).to(options))
+                .route(propfind().to(propfind_todo))
+                .route(web::get().to(get_todo))
+                .route(web::put().to(put_todo))
+                .route(web::delete().to(delete_todo)),
+        );
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn todo(id: i32, minute: u32) -> models::Todo {
+        let updated_at = NaiveDate::from_ymd(2026, 10, 18).and_hms(9, minute, 0);
+        models::Todo {
+            id,
+            text: format!("Todo {}", id),
+            done: false,
+            user_id: Some(1),
+            ical_uid: format!("{}@todos", id),
+            created_at: updated_at,
+            updated_at,
+            completed_at: None,
+            priority: None,
+            workspace_id: None,
+            projects: Vec::new(),
+            contexts: Vec::new(),
+        }
+    }
+
+    fn ids(todos: Option<Vec<&models::Todo>>) -> Option<Vec<i32>> {
+        todos.map(|todos| todos.iter().map(|todo| todo.id).collect())
+    }
+
+    #[test]
+    fn sync_tokens_round_trip() {
+        let state = SyncState::of(&[todo(3, 1), todo(7, 5)]);
+        assert_eq!(SyncState::parse(&state.token()), Some(state));
+        assert_eq!(
+            SyncState::parse(&SyncState::of(&[]).token()),
+            Some(SyncState::of(&[]))
+        );
+        for token in &[
+            "",
+            "urn:todos:sync:1-2",
+            "urn:todos:sync:1-2-3-4",
+            "urn:other:1-2-3",
+        ] {
+            assert_eq!(SyncState::parse(token), None, "{}", token);
+        }
+    }
+
+    #[test]
+    fn reports_created_and_updated_todos() {
+        let state = SyncState::of(&[todo(3, 1), todo(7, 5)]);
+        assert_eq!(ids(state.changes(&[todo(3, 1), todo(7, 5)])), Some(vec![]));
+        assert_eq!(
+            ids(state.changes(&[todo(3, 9), todo(7, 5), todo(8, 0)])),
+            Some(vec![3, 8])
+        );
+    }
+
+    #[test]
+    fn refuses_tokens_from_before_a_deletion() {
+        let state = SyncState::of(&[todo(3, 1), todo(7, 5)]);
+        assert_eq!(ids(state.changes(&[todo(7, 5), todo(8, 6)])), None);
+    }
+}
@@ -0,0 +1,302 @@
+//! CSV export with a fixed column schema, and import with configurable
+//! column names.
+
+use chrono::{NaiveDate, NaiveDateTime};
+
+use crate::{
+    error::TodosError,
+    models::{self, FileImportOptions, LineError},
+    todotxt::is_valid_priority,
+};
+
+/// The export's columns, in order. Changing them breaks users' spreadsheets,
+/// so only ever append new ones.
+pub const COLUMNS: &[&str] = &[
+    "id",
+    "text",
+    "done",
+    "priority",
+    "created_at",
+    "completed_at",
+    "uid",
+];
+
+const DATE_TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%SZ";
+
+pub fn write(todos: &[models::Todo]) -> Result<String, TodosError> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    let csv_err = |_| TodosError::DieselCrudError;
+    writer.write_record(COLUMNS).map_err(csv_err)?;
+    for todo in todos {
+        writer
+            .write_record(&[
+                todo.id.to_string(),
+                todo.text.clone(),
+                todo.done.to_string(),
+                todo.priority.clone().unwrap_or_default(),
+                todo.created_at.format(DATE_TIME_FORMAT).to_string(),
+                todo.completed_at
+                    .map(|date_time| date_time.format(DATE_TIME_FORMAT).to_string())
+                    .unwrap_or_default(),
+                todo.ical_uid.clone(),
+            ])
+            .map_err(csv_err)?;
+    }
+    let bytes = writer
+        .into_inner()
+        .map_err(|_| TodosError::DieselCrudError)?;
+    Ok(String::from_utf8(bytes).expect("CSV of valid strings is valid UTF-8."))
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "" | "false" | "0" | "no" | "n" => Some(false),
+        "true" | "1" | "yes" | "y" | "x" => Some(true),
+        _ => None,
+    }
+}
+
+fn parse_date_time(value: &str) -> Option<NaiveDateTime> {
+    let value = value.trim();
+    NaiveDateTime::parse_from_str(value, DATE_TIME_FORMAT)
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S"))
+        .ok()
+        .or_else(|| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .ok()
+                .map(|date| date.and_hms(0, 0, 0))
+        })
+}
+
+/// Reads every row into a new todo, collecting an error per bad line instead
+/// of stopping at the first one. Columns the options don't name are looked up
+/// by the export's own header.
+pub fn read(
+    input: &str,
+    mapping: &FileImportOptions,
+) -> Result<Vec<models::NewTodoReq>, TodosError> {
+    let mut reader = csv::Reader::from_reader(input.as_bytes());
+    let headers = reader
+        .headers()
+        .map_err(|e| {
+            TodosError::InvalidImport(vec![LineError {
+                line: 1,
+                message: e.to_string(),
+            }])
+        })?
+        .clone();
+    let column = |name: &Option<String>, default: &str| {
+        let name = name.as_deref().unwrap_or(default);
+        headers.iter().position(|header| header.trim() == name)
+    };
+    let text_column = column(&mapping.text_column, "text").ok_or_else(|| {
+        TodosError::InvalidImport(vec![LineError {
+            line: 1,
+            message: "There is no text column.".to_owned(),
+        }])
+    })?;
+    let done_column = column(&mapping.done_column, "done");
+    let priority_column = column(&mapping.priority_column, "priority");
+    let created_at_column = column(&mapping.created_at_column, "created_at");
+    let completed_at_column = column(&mapping.completed_at_column, "completed_at");
+
+    let mut todos = Vec::new();
+    let mut errors = Vec::new();
+    for record in reader.records() {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                let line = e.position().map_or(0, |position| position.line() as usize);
+                errors.push(LineError {
+                    line,
+                    message: e.to_string(),
+                });
+                continue;
+            }
+        };
+        let line = record
+            .position()
+            .map_or(0, |position| position.line() as usize);
+        let field = |column: Option<usize>| column.and_then(|column| record.get(column));
+        let mut fail = |message: String| errors.push(LineError { line, message });
+
+        let text = field(Some(text_column)).unwrap_or_default().trim();
+        if text.is_empty() {
+            fail("The text is empty.".to_owned());
+            continue;
+        }
+        let done = match field(done_column).map(parse_bool) {
+            Some(Some(done)) => Some(done),
+            Some(None) => {
+                fail(format!(
+                    "`{}` is not a boolean.",
+                    field(done_column).unwrap()
+                ));
+                continue;
+            }
+            None => None,
+        };
+        let priority = field(priority_column)
+            .map(str::trim)
+            .filter(|priority| !priority.is_empty())
+            .map(str::to_owned);
+        if let Some(priority) = priority.as_deref().filter(|p| !is_valid_priority(p)) {
+            fail(format!("`{}` is not a priority.", priority));
+            continue;
+        }
+        let date_time = |column: Option<usize>| match field(column).map(str::trim) {
+            None | Some("") => Ok(None),
+            Some(value) => parse_date_time(value)
+                .map(Some)
+                .ok_or_else(|| format!("`{}` is not a date.", value)),
+        };
+        let (created_at, completed_at) =
+            match (date_time(created_at_column), date_time(completed_at_column)) {
+                (Ok(created_at), Ok(completed_at)) => (created_at, completed_at),
+                (Err(message), _) | (_, Err(message)) => {
+                    fail(message);
+                    continue;
+                }
+            };
+        todos.push(models::NewTodoReq {
+            text: text.to_owned(),
+            done,
+            priority,
+            created_at,
+            completed_at,
+        });
+    }
+
+    if errors.is_empty() {
+        Ok(todos)
+    } else {
+        Err(TodosError::InvalidImport(errors))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+    use crate::models::FileFormat;
+
+    fn options() -> FileImportOptions {
+        FileImportOptions {
+            format: FileFormat::Csv,
+            text_column: None,
+            done_column: None,
+            priority_column: None,
+            created_at_column: None,
+            completed_at_column: None,
+        }
+    }
+
+    /// The line and message of each error of a failed import.
+    fn line_errors(result: Result<Vec<models::NewTodoReq>, TodosError>) -> Vec<(usize, String)> {
+        match result {
+            Err(TodosError::InvalidImport(errors)) => errors
+                .into_iter()
+                .map(|error| (error.line, error.message))
+                .collect(),
+            other => panic!(
+                "expected line errors, got {:?}",
+                other.map(|todos| todos.len())
+            ),
+        }
+    }
+
+    #[test]
+    fn reads_what_it_writes() {
+        let created_at = NaiveDate::from_ymd(2026, 10, 1).and_hms(8, 0, 0);
+        let completed_at = NaiveDate::from_ymd(2026, 10, 18).and_hms(9, 30, 0);
+        let todo = models::Todo {
+            id: 1,
+            text: "Quote \"this\", then\nbreak".to_owned(),
+            done: true,
+            user_id: Some(1),
+            ical_uid: "uid-1@todos".to_owned(),
+            created_at,
+            updated_at: completed_at,
+            completed_at: Some(completed_at),
+            priority: Some("B".to_owned()),
+            workspace_id: None,
+            projects: Vec::new(),
+            contexts: Vec::new(),
+        };
+        let csv = write(std::slice::from_ref(&todo)).unwrap();
+        assert!(csv.starts_with("id,text,done,priority,created_at,completed_at,uid\n"));
+        let todos = read(&csv, &options()).unwrap();
+        assert_eq!(todos.len(), 1);
+        assert_eq!(todos[0].text, todo.text);
+        assert_eq!(todos[0].done, Some(true));
+        assert_eq!(todos[0].priority.as_deref(), Some("B"));
+        assert_eq!(todos[0].created_at, Some(created_at));
+        assert_eq!(todos[0].completed_at, Some(completed_at));
+    }
+
+    #[test]
+    fn reads_named_columns_and_loose_values() {
+        let csv = "Task,Finished,Added\n\
+                   Buy milk,yes,2026-10-01\n\
+                   Call mom,,2026-10-02 17:45:00\n\
+                   Water plants,X,\n";
+        let mapping = FileImportOptions {
+            text_column: Some("Task".to_owned()),
+            done_column: Some("Finished".to_owned()),
+            created_at_column: Some("Added".to_owned()),
+            ..options()
+        };
+        let todos = read(csv, &mapping).unwrap();
+        let read = todos
+            .iter()
+            .map(|todo| (todo.text.as_str(), todo.done, todo.created_at))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            read,
+            vec![
+                (
+                    "Buy milk",
+                    Some(true),
+                    Some(NaiveDate::from_ymd(2026, 10, 1).and_hms(0, 0, 0))
+                ),
+                (
+                    "Call mom",
+                    Some(false),
+                    Some(NaiveDate::from_ymd(2026, 10, 2).and_hms(17, 45, 0))
+                ),
+                ("Water plants", Some(true), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_every_bad_line() {
+        let csv = "text,done,priority,created_at\n\
+                   Fine,no,A,2026-10-01\n\
+                   \x20 ,no,,\n\
+                   Maybe,perhaps,,\n\
+                   Ship,1,AA,\n\
+                   Plan,0,,yesterday\n\
+                   Too,few\n";
+        let errors = line_errors(read(csv, &options()));
+        assert_eq!(errors.len(), 5, "{:?}", errors);
+        assert_eq!(errors[0], (3, "The text is empty.".to_owned()));
+        assert_eq!(errors[1], (4, "`perhaps` is not a boolean.".to_owned()));
+        assert_eq!(errors[2], (5, "`AA` is not a priority.".to_owned()));
+        assert_eq!(errors[3], (6, "`yesterday` is not a date.".to_owned()));
+        assert_eq!(errors[4].0, 7);
+    }
+
+    #[test]
+    fn requires_a_text_column() {
+        let mapping = FileImportOptions {
+            text_column: Some("Title".to_owned()),
+            ..options()
+        };
+        assert_eq!(
+            line_errors(read("text,done\nBuy milk,no\n", &mapping)),
+            vec![(1, "There is no text column.".to_owned())]
+        );
+    }
+}
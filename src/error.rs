@@ -1,6 +1,7 @@
 use std::{error::Error, fmt::Display};
 
 use actix_web::error::BlockingError;
+use diesel::r2d2;
 
 #[derive(Debug)]
 pub enum TodosError {
@@ -9,6 +10,81 @@ pub enum TodosError {
     DieselCrudError,
     JwtTokenDecodeError,
     BadCreds,
+    InvalidCalendar,
+    CalendarTokenNotFound,
+    InvalidPriority,
+    InvalidTodoTxt(usize),
+    InvalidImport(Vec<crate::models::LineError>),
+    InvalidExport(String),
+    ImportJobNotFound,
+    InvalidArchive(String),
+    AccountNotFound,
+    AccountDisabled,
+    TokenRevoked,
+    MailError,
+    InvalidResetToken,
+    InvalidEmail,
+    EmailRequired,
+    InvalidVerificationToken,
+    NoEmail,
+    EmailAlreadyVerified,
+    EmailNotVerified,
+    /// Another account verified the address first.
+    EmailTaken,
+    TotpAlreadyEnabled,
+    TotpNotEnrolled,
+    InvalidTotpCode,
+    InvalidChallenge,
+    /// Too many failed logins; the number of seconds until the next try.
+    TooManyLoginAttempts(i64),
+    /// Too many password resets from the client address; the number of
+    /// seconds until the next may be asked for.
+    TooManyPasswordResets(i64),
+    OAuth(crate::oauth::OAuthError),
+    OAuthClientNotFound,
+    InvalidRedirectUri(String),
+    InvalidOAuthToken,
+    InsufficientScope,
+    OidcNotConfigured,
+    /// The OpenID Connect provider couldn't be reached or answered nonsense.
+    OidcProvider(String),
+    InvalidOidcState,
+    InvalidIdToken(String),
+    OidcAccountNotLinked,
+    /// An administrator disabled the account.
+    AccountSuspended,
+    PasswordResetRequired,
+    MissingPermission(crate::roles::Permission),
+    RoleNotFound,
+    RoleExists,
+    /// The role is built in, and can't be changed or deleted.
+    RoleBuiltIn,
+    /// Users still have the role.
+    RoleInUse,
+    InvalidRoleName,
+    /// Administrators can't disable their own account or change their own
+    /// role.
+    OwnAccount,
+    /// Also for workspaces the user isn't a member of.
+    WorkspaceNotFound,
+    /// The member's workspace role doesn't allow it.
+    WorkspaceForbidden,
+    InvalidWorkspace,
+    WorkspaceSlugTaken,
+    /// A workspace must keep at least one owner.
+    LastOwner,
+    /// Unknown, expired, accepted or meant for another address.
+    InvalidInvitation,
+    AlreadyMember,
+    /// Also for sessions that already ended.
+    SessionNotFound,
+    InvalidUsername,
+    /// Another account has the username, in some case.
+    UsernameTaken,
+    /// A profile field is malformed; which one and why.
+    InvalidProfile(String),
+    /// A password hash was made with a pepper that isn't configured; its id.
+    UnknownPepper(String),
 }
 
 impl Error for TodosError {}
@@ -31,16 +107,213 @@ impl Display for TodosError {
             Self::BadCreds => {
                 write!(f, "bad credentials")
             }
+            Self::InvalidCalendar => {
+                write!(f, "invalid iCalendar data")
+            }
+            Self::CalendarTokenNotFound => {
+                write!(f, "calendar token not found")
+            }
+            Self::InvalidPriority => {
+                write!(f, "priority must be a single uppercase letter")
+            }
+            Self::InvalidTodoTxt(line) => {
+                write!(f, "invalid todo.txt data on line {}", line)
+            }
+            Self::InvalidImport(errors) => {
+                write!(f, "{} lines of the imported file are invalid", errors.len())
+            }
+            Self::InvalidExport(reason) => {
+                write!(f, "invalid export file: {}", reason)
+            }
+            Self::ImportJobNotFound => {
+                write!(f, "import job not found")
+            }
+            Self::InvalidArchive(reason) => {
+                write!(f, "invalid account archive: {}", reason)
+            }
+            Self::AccountNotFound => {
+                write!(f, "account not found")
+            }
+            Self::AccountDisabled => {
+                write!(f, "account is scheduled for deletion")
+            }
+            Self::TokenRevoked => {
+                write!(
+                    f,
+                    "token was revoked by logging out, a password change or an administrator"
+                )
+            }
+            Self::MailError => {
+                write!(f, "sending email failed")
+            }
+            Self::InvalidResetToken => {
+                write!(f, "password reset token is invalid, used or expired")
+            }
+            Self::InvalidEmail => {
+                write!(f, "invalid email address")
+            }
+            Self::EmailRequired => {
+                write!(f, "an email address is required")
+            }
+            Self::InvalidVerificationToken => {
+                write!(f, "email verification token is invalid, used or expired")
+            }
+            Self::NoEmail => {
+                write!(f, "account has no email address")
+            }
+            Self::EmailAlreadyVerified => {
+                write!(f, "email address is already verified")
+            }
+            Self::EmailNotVerified => {
+                write!(f, "email address is not verified")
+            }
+            Self::EmailTaken => {
+                write!(f, "email address belongs to another account")
+            }
+            Self::TotpAlreadyEnabled => {
+                write!(f, "two-factor authentication is already enabled")
+            }
+            Self::TotpNotEnrolled => {
+                write!(f, "no two-factor enrollment is pending")
+            }
+            Self::InvalidTotpCode => {
+                write!(f, "invalid two-factor code")
+            }
+            Self::InvalidChallenge => {
+                write!(f, "two-factor challenge is invalid or expired")
+            }
+            Self::TooManyLoginAttempts(seconds) => {
+                write!(f, "too many failed logins, retry in {} seconds", seconds)
+            }
+            Self::TooManyPasswordResets(seconds) => {
+                write!(f, "too many password resets, retry in {} seconds", seconds)
+            }
+            Self::OAuth(error) => {
+                write!(f, "{}: {}", error.error.as_str(), error.error_description)
+            }
+            Self::OAuthClientNotFound => {
+                write!(f, "OAuth client not found")
+            }
+            Self::InvalidRedirectUri(uri) => {
+                write!(f, "invalid redirect URI: {}", uri)
+            }
+            Self::InvalidOAuthToken => {
+                write!(f, "OAuth token is invalid, expired or revoked")
+            }
+            Self::InsufficientScope => {
+                write!(f, "OAuth token lacks the required scope")
+            }
+            Self::OidcNotConfigured => {
+                write!(f, "OpenID Connect login is not configured")
+            }
+            Self::OidcProvider(message) => {
+                write!(f, "OpenID Connect provider error: {}", message)
+            }
+            Self::InvalidOidcState => {
+                write!(f, "OpenID Connect login state is invalid or expired")
+            }
+            Self::InvalidIdToken(reason) => {
+                write!(f, "invalid ID token: {}", reason)
+            }
+            Self::OidcAccountNotLinked => {
+                write!(f, "no account is linked to the OpenID Connect identity")
+            }
+            Self::AccountSuspended => {
+                write!(f, "account was disabled by an administrator")
+            }
+            Self::PasswordResetRequired => {
+                write!(f, "password has to be reset")
+            }
+            Self::MissingPermission(permission) => {
+                write!(f, "missing the {} permission", permission.as_str())
+            }
+            Self::RoleNotFound => {
+                write!(f, "role not found")
+            }
+            Self::RoleExists => {
+                write!(f, "role already exists")
+            }
+            Self::RoleBuiltIn => {
+                write!(f, "built-in roles can't be changed")
+            }
+            Self::RoleInUse => {
+                write!(f, "role is still given to users")
+            }
+            Self::InvalidRoleName => {
+                write!(f, "invalid role name")
+            }
+            Self::OwnAccount => {
+                write!(f, "administrators can't change their own account this way")
+            }
+            Self::WorkspaceNotFound => {
+                write!(f, "workspace not found")
+            }
+            Self::WorkspaceForbidden => {
+                write!(f, "workspace role doesn't allow this")
+            }
+            Self::InvalidWorkspace => {
+                write!(f, "invalid workspace name or slug")
+            }
+            Self::WorkspaceSlugTaken => {
+                write!(f, "workspace slug is taken")
+            }
+            Self::LastOwner => {
+                write!(f, "a workspace needs an owner")
+            }
+            Self::InvalidInvitation => {
+                write!(f, "invitation is invalid or expired")
+            }
+            Self::AlreadyMember => {
+                write!(f, "already a member of the workspace")
+            }
+            Self::SessionNotFound => {
+                write!(f, "session not found")
+            }
+            Self::InvalidUsername => {
+                write!(f, "invalid username")
+            }
+            Self::UsernameTaken => {
+                write!(f, "username is taken")
+            }
+            Self::InvalidProfile(reason) => {
+                write!(f, "invalid profile: {}", reason)
+            }
+            Self::UnknownPepper(id) => {
+                write!(
+                    f,
+                    "a password hash uses pepper {}, which isn't configured",
+                    id
+                )
+            }
         }
     }
 }
 
+/// Lets diesel transactions return `TodosError`. Only the query knows what
+/// wasn't found, so queries that may find nothing map `NotFound` themselves.
+impl From<diesel::result::Error> for TodosError {
+    fn from(_: diesel::result::Error) -> Self {
+        Self::DieselCrudError
+    }
+}
+
+/// Running out of pooled connections is a database failure like any other,
+/// but only the log says why.
+impl From<r2d2::PoolError> for TodosError {
+    fn from(e: r2d2::PoolError) -> Self {
+        log::error!("No database connection became free in time: {}", e);
+        Self::DieselCrudError
+    }
+}
+
 impl From<BlockingError<TodosError>> for TodosError {
     fn from(e: BlockingError<TodosError>) -> Self {
-        if let BlockingError::Error(e) = e {
-            e
-        } else {
-            panic!()
+        match e {
+            BlockingError::Error(e) => e,
+            BlockingError::Canceled => {
+                log::error!("The thread pool dropped a database task before it ran.");
+                Self::DieselCrudError
+            }
         }
     }
 }
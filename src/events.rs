@@ -0,0 +1,115 @@
+use std::{
+    collections::HashMap,
+    pin::Pin,
+    sync::{Arc, Mutex, Weak},
+    task::{Context, Poll},
+};
+
+use futures::{
+    channel::mpsc::{self, Receiver, Sender},
+    Stream,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::models;
+
+/// How many events a subscriber may fall behind before it is dropped.
+const SUBSCRIBER_BUFFER: usize = 64;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, ToSchema, juniper::GraphQLEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum TodoEventKind {
+    Created,
+    Updated,
+    Deleted,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct TodoEvent {
+    pub kind: TodoEventKind,
+    pub todo: models::Todo,
+}
+
+struct Subscriber {
+    tenant: models::Tenant,
+    sender: Sender<TodoEvent>,
+}
+
+#[derive(Default)]
+struct Subscribers {
+    next_id: u64,
+    by_id: HashMap<u64, Subscriber>,
+}
+
+/// Fans todo changes out to every open subscription of the todo's owner, or
+/// of the workspace it belongs to.
+///
+/// Shared between all workers, so it must be registered with `App::app_data`
+/// from a single `web::Data` created before the server starts.
+#[derive(Default)]
+pub struct Broadcaster {
+    subscribers: Arc<Mutex<Subscribers>>,
+}
+
+impl Broadcaster {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The changes to `tenant`'s todos from now on. Dropping the stream
+    /// unsubscribes; a subscriber that falls more than `SUBSCRIBER_BUFFER`
+    /// events behind is unsubscribed by the broadcaster, and its stream ends
+    /// once it has caught up.
+    pub fn subscribe(&self, tenant: models::Tenant) -> EventStream {
+        let (sender, receiver) = mpsc::channel(SUBSCRIBER_BUFFER);
+        let mut subscribers = self.subscribers.lock().unwrap();
+        let id = subscribers.next_id;
+        subscribers.next_id += 1;
+        subscribers.by_id.insert(id, Subscriber { tenant, sender });
+        EventStream {
+            id,
+            receiver,
+            subscribers: Arc::downgrade(&self.subscribers),
+        }
+    }
+
+    pub fn publish(&self, kind: TodoEventKind, todo: &models::Todo) {
+        let event = TodoEvent {
+            kind,
+            todo: todo.clone(),
+        };
+        self.subscribers
+            .lock()
+            .unwrap()
+            .by_id
+            .retain(|_, subscriber| {
+                !subscriber.tenant.owns(todo) || subscriber.sender.try_send(event.clone()).is_ok()
+            });
+    }
+}
+
+/// A subscription to the `Broadcaster`, see `Broadcaster::subscribe`.
+pub struct EventStream {
+    id: u64,
+    receiver: Receiver<TodoEvent>,
+    subscribers: Weak<Mutex<Subscribers>>,
+}
+
+impl Stream for EventStream {
+    type Item = TodoEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<TodoEvent>> {
+        Pin::new(&mut self.receiver).poll_next(cx)
+    }
+}
+
+impl Drop for EventStream {
+    fn drop(&mut self) {
+        if let Some(subscribers) = self.subscribers.upgrade() {
+            if let Ok(mut subscribers) = subscribers.lock() {
+                subscribers.by_id.remove(&self.id);
+            }
+        }
+    }
+}
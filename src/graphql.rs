@@ -0,0 +1,325 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+};
+
+use actix_web::web;
+use diesel::{
+    r2d2::{ConnectionManager, PooledConnection},
+    PgConnection,
+};
+use juniper::{
+    parser::{Lexer, Token},
+    Executor, FieldError, FieldResult, LookAheadMethods, RootNode,
+};
+
+use crate::{
+    actions,
+    auth::AuthUser,
+    error::TodosError,
+    events::{Broadcaster, TodoEvent, TodoEventKind},
+    models,
+};
+
+pub type Schema = RootNode<'static, Query, Mutation>;
+
+pub fn create_schema() -> Schema {
+    Schema::new(Query, Mutation)
+}
+
+/// juniper can parse subscriptions but not execute them, so the streaming
+/// endpoint runs a subscription as a query against this schema once per
+/// event, with the event in `Context::event`. It never runs mutations; the
+/// mutation root is only there because `EmptyMutation` isn't `Sync`.
+pub type SubscriptionSchema = RootNode<'static, Subscription, Mutation>;
+
+pub fn create_subscription_schema() -> SubscriptionSchema {
+    SubscriptionSchema::new(Subscription, Mutation)
+}
+
+/// Turns a document with a single subscription operation into the query that
+/// `SubscriptionSchema` executes for every event.
+pub fn subscription_as_query(document: &str) -> Result<String, &'static str> {
+    let mut depth = 0usize;
+    let mut at_definition = true;
+    let mut keyword = None;
+    for token in Lexer::new(document) {
+        let token = token.map_err(|_| "The query could not be parsed.")?;
+        match token.item {
+            Token::Name(name) if depth == 0 && at_definition => {
+                at_definition = false;
+                match name {
+                    "subscription" if keyword.is_none() => {
+                        keyword = Some(name.as_ptr() as usize - document.as_ptr() as usize)
+                    }
+                    "fragment" => {}
+                    _ => return Err("The document must contain exactly one subscription."),
+                }
+            }
+            Token::CurlyOpen if depth == 0 && at_definition => {
+                return Err("The document must contain exactly one subscription.")
+            }
+            Token::CurlyOpen | Token::ParenOpen | Token::BracketOpen => depth += 1,
+            Token::CurlyClose => {
+                depth = depth.saturating_sub(1);
+                at_definition = depth == 0;
+            }
+            Token::ParenClose | Token::BracketClose => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    let start = keyword.ok_or("The document must contain exactly one subscription.")?;
+    Ok(format!(
+        "{}query{}",
+        &document[..start],
+        &document[start + "subscription".len()..]
+    ))
+}
+
+pub struct Context {
+    pub conn: PooledConnection<ConnectionManager<PgConnection>>,
+    pub user: AuthUser,
+    /// The workspace the request selected, if any.
+    pub tenant: models::Tenant,
+    pub broadcaster: web::Data<Broadcaster>,
+    /// The change a subscription is being run for.
+    pub event: Option<TodoEvent>,
+    users: RefCell<HashMap<i32, models::User>>,
+}
+
+impl Context {
+    pub fn new(
+        conn: PooledConnection<ConnectionManager<PgConnection>>,
+        user: AuthUser,
+        tenant: models::Tenant,
+        broadcaster: web::Data<Broadcaster>,
+    ) -> Self {
+        Self {
+            conn,
+            user,
+            tenant,
+            broadcaster,
+            event: None,
+            users: RefCell::default(),
+        }
+    }
+
+    /// Loads the owners of `todos` with a single query when the current
+    /// selection asks for them, so that the `owner` of each todo is resolved
+    /// without querying once per todo.
+    fn preload_owners(
+        &self,
+        executor: &Executor<Context>,
+        todos: &[models::Todo],
+    ) -> Result<(), TodosError> {
+        if !executor.look_ahead().has_child("owner") {
+            return Ok(());
+        }
+        let mut users = self.users.borrow_mut();
+        let uids = todos
+            .iter()
+            .filter_map(|todo| todo.user_id)
+            .filter(|uid| !users.contains_key(uid))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        if !uids.is_empty() {
+            let loaded = actions::get_users_by_ids(&uids, &self.conn)?;
+            users.extend(loaded.into_iter().map(|user| (user.id, user)));
+        }
+        Ok(())
+    }
+
+    fn load_user(&self, uid: i32) -> FieldResult<models::User> {
+        if let Some(user) = self.users.borrow().get(&uid) {
+            return Ok(user.clone());
+        }
+        let user = actions::get_users_by_ids(&[uid], &self.conn)?
+            .pop()
+            .ok_or_else(|| FieldError::from("user not found"))?;
+        self.users.borrow_mut().insert(uid, user.clone());
+        Ok(user)
+    }
+}
+
+impl juniper::Context for Context {}
+
+#[juniper::object(Context = Context)]
+impl models::Todo {
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn text(&self) -> &str {
+        &self.text
+    }
+
+    fn done(&self) -> bool {
+        self.done
+    }
+
+    /// The `+project` tags of the text.
+    fn projects(&self) -> &[String] {
+        &self.projects
+    }
+
+    /// The `@context` tags of the text.
+    fn contexts(&self) -> &[String] {
+        &self.contexts
+    }
+
+    /// Who created the todo; none for workspace todos whose creator's
+    /// account was purged.
+    fn owner(&self, context: &Context) -> FieldResult<Option<models::User>> {
+        self.user_id.map(|uid| context.load_user(uid)).transpose()
+    }
+}
+
+#[juniper::object(Context = Context, name = "User")]
+impl models::User {
+    fn id(&self) -> i32 {
+        self.id
+    }
+
+    fn username(&self) -> &str {
+        &self.username
+    }
+
+    fn todos(&self, context: &Context) -> FieldResult<Vec<models::Todo>> {
+        if self.id != context.user.id {
+            return Err("only your own todos can be listed".into());
+        }
+        context.users.borrow_mut().insert(self.id, self.clone());
+        Ok(actions::get_all_todos(
+            models::Tenant::Personal { user_id: self.id },
+            &context.conn,
+        )?)
+    }
+}
+
+pub struct Query;
+
+#[juniper::object(Context = Context)]
+impl Query {
+    /// The user the request is authenticated as.
+    fn me(context: &Context) -> FieldResult<models::User> {
+        context.load_user(context.user.id)
+    }
+
+    /// All todos of the current user or workspace.
+    fn todos(context: &Context, executor: &Executor) -> FieldResult<Vec<models::Todo>> {
+        let todos = actions::get_all_todos(context.tenant, &context.conn)?;
+        context.preload_owners(executor, &todos)?;
+        Ok(todos)
+    }
+
+    /// A single todo of the current user or workspace, or `null` if there is no such todo.
+    fn todo(context: &Context, id: i32) -> FieldResult<Option<models::Todo>> {
+        match actions::get_todo_of_tenant(context.tenant, id, &context.conn) {
+            Ok(todo) => Ok(Some(todo)),
+            Err(TodosError::TodoNotFoundError) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[juniper::object(Context = Context, name = "TodoEvent")]
+impl TodoEvent {
+    fn kind(&self) -> TodoEventKind {
+        self.kind
+    }
+
+    fn todo(&self) -> &models::Todo {
+        &self.todo
+    }
+}
+
+pub struct Subscription;
+
+#[juniper::object(Context = Context)]
+impl Subscription {
+    /// Every todo of the current user or workspace that is created, updated or deleted.
+    fn todo_changed(context: &Context) -> FieldResult<&TodoEvent> {
+        context
+            .event
+            .as_ref()
+            .ok_or_else(|| FieldError::from("subscriptions are only served at /graphql/stream"))
+    }
+}
+
+pub struct Mutation;
+
+#[juniper::object(Context = Context)]
+impl Mutation {
+    fn create_todo(context: &Context, text: String) -> FieldResult<models::Todo> {
+        let todo = actions::create_new_todo(
+            context.tenant,
+            models::NewTodoReq {
+                text,
+                ..Default::default()
+            },
+            &context.conn,
+        )?;
+        context.broadcaster.publish(TodoEventKind::Created, &todo);
+        Ok(todo)
+    }
+
+    fn update_todo(
+        context: &Context,
+        id: i32,
+        text: Option<String>,
+        done: Option<bool>,
+    ) -> FieldResult<models::Todo> {
+        use models::UpdateTodo::*;
+        let data = match (text, done) {
+            (Some(text), Some(done)) => Both { text, done },
+            (Some(text), None) => TextOnly { text },
+            (None, Some(done)) => DoneOnly { done },
+            (None, None) => return Err("either `text` or `done` must be given".into()),
+        };
+        let existing_todo = actions::get_todo_of_tenant(context.tenant, id, &context.conn)?;
+        let todo = actions::update_existing_todo(existing_todo, data, &context.conn)?;
+        context.broadcaster.publish(TodoEventKind::Updated, &todo);
+        Ok(todo)
+    }
+
+    fn delete_todo(context: &Context, id: i32) -> FieldResult<models::Todo> {
+        let existing_todo = actions::get_todo_of_tenant(context.tenant, id, &context.conn)?;
+        let todo = actions::delete_existing_todo(existing_todo, &context.conn)?;
+        context.broadcaster.publish(TodoEventKind::Deleted, &todo);
+        Ok(todo)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::subscription_as_query;
+
+    #[test]
+    fn rewrites_the_subscription_keyword() {
+        assert_eq!(
+            subscription_as_query("subscription Changes { todoChanged { kind } }"),
+            Ok("query Changes { todoChanged { kind } }".to_string())
+        );
+        assert_eq!(
+            subscription_as_query(
+                "# subscription\nsubscription($x: Int) { ...F }\nfragment F on Subscription { subscription: todoChanged { kind } }"
+            ),
+            Ok("# subscription\nquery($x: Int) { ...F }\nfragment F on Subscription { subscription: todoChanged { kind } }".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_documents_without_exactly_one_subscription() {
+        for document in &[
+            "{ todoChanged { kind } }",
+            "query { me { id } }",
+            "subscription A { todoChanged { kind } } subscription B { todoChanged { kind } }",
+            "subscription A { todoChanged { kind } } mutation { deleteTodo(id: 1) { id } }",
+            "fragment F on Todo { id }",
+            "subscription ?",
+        ] {
+            assert!(subscription_as_query(document).is_err(), "{}", document);
+        }
+    }
+}
@@ -0,0 +1,260 @@
+// `tonic::Status` is what every tonic handler returns, however large it is.
+#![allow(clippy::result_large_err)]
+
+use std::{future::Future, net::SocketAddr, pin::Pin};
+
+use actix_web::web;
+use diesel::PgConnection;
+use futures::{Stream, StreamExt};
+use tonic::{transport::Server, Request, Response, Status};
+
+use crate::{
+    actions,
+    auth::{self, Claims},
+    error::TodosError,
+    events::{Broadcaster, TodoEventKind},
+    models, DbPool,
+};
+
+pub mod proto {
+    tonic::include_proto!("todos");
+}
+
+use proto::todo_service_server::{TodoService, TodoServiceServer};
+
+impl From<models::Todo> for proto::Todo {
+    fn from(todo: models::Todo) -> Self {
+        Self {
+            id: todo.id,
+            text: todo.text,
+            done: todo.done,
+            user_id: todo.user_id.unwrap_or_default(),
+        }
+    }
+}
+
+impl From<TodoEventKind> for proto::todo_event::Kind {
+    fn from(kind: TodoEventKind) -> Self {
+        match kind {
+            TodoEventKind::Created => Self::Created,
+            TodoEventKind::Updated => Self::Updated,
+            TodoEventKind::Deleted => Self::Deleted,
+        }
+    }
+}
+
+impl From<TodosError> for Status {
+    fn from(e: TodosError) -> Self {
+        match e {
+            TodosError::TodoNotFoundError => {
+                Status::not_found("The todo that you were trying to find does not exist.")
+            }
+            TodosError::AccountNotFound => Status::unauthenticated("The account no longer exists."),
+            TodosError::TokenRevoked => Status::unauthenticated(
+                "Token was revoked by logging out, a password change or an administrator.",
+            ),
+            TodosError::AccountDisabled => {
+                Status::permission_denied("The account is scheduled for deletion.")
+            }
+            TodosError::AccountSuspended => {
+                Status::permission_denied("The account was disabled by an administrator.")
+            }
+            _ => Status::internal("Something went wrong while performing DB operations."),
+        }
+    }
+}
+
+/// Decodes the JWT sent in the `authorization` metadata entry, the same way
+/// `AuthUser` decodes the `Authorization` header.
+fn decode_token<T>(request: &Request<T>) -> Result<Claims, Status> {
+    let value = request
+        .metadata()
+        .get("authorization")
+        .ok_or_else(|| Status::unauthenticated("Auth metadata not present."))?
+        .to_str()
+        .map_err(|_| {
+            Status::unauthenticated("Auth metadata is malformed or contains non-ASCII characters.")
+        })?;
+    let token = auth::bearer_token(value).map_err(Status::unauthenticated)?;
+    auth::authorize(token).map_err(|_| Status::unauthenticated("Token is invalid or expired."))
+}
+
+pub struct TodoGrpcService {
+    pool: DbPool,
+    broadcaster: web::Data<Broadcaster>,
+}
+
+impl TodoGrpcService {
+    pub fn new(pool: DbPool, broadcaster: web::Data<Broadcaster>) -> Self {
+        Self { pool, broadcaster }
+    }
+
+    /// Runs diesel calls on the blocking thread pool, like `web::block` does
+    /// for the REST handlers.
+    async fn block<F, T>(&self, f: F) -> Result<T, Status>
+    where
+        F: FnOnce(&PgConnection) -> Result<T, TodosError> + Send + 'static,
+        T: Send + 'static,
+    {
+        let pool = self.pool.clone();
+        tokio::task::spawn_blocking(move || {
+            let conn = pool.get().map_err(|_| TodosError::DieselCrudError)?;
+            f(&conn)
+        })
+        .await
+        .map_err(|_| Status::internal("Something went wrong while performing DB operations."))?
+        .map_err(Status::from)
+    }
+
+    /// Decodes the token and makes sure it and its account are still valid.
+    async fn authenticate<T>(&self, request: &Request<T>) -> Result<Claims, Status> {
+        let claims = decode_token(request)?;
+        self.block(move |conn| actions::check_claims(&claims, conn).map(|_| claims))
+            .await
+    }
+}
+
+#[tonic::async_trait]
+impl TodoService for TodoGrpcService {
+    async fn list(
+        &self,
+        request: Request<proto::ListTodosRequest>,
+    ) -> Result<Response<proto::ListTodosResponse>, Status> {
+        let claims = self.authenticate(&request).await?;
+        let todos = self
+            .block(move |conn| {
+                actions::get_all_todos(models::Tenant::Personal { user_id: claims.id }, conn)
+            })
+            .await?;
+        Ok(Response::new(proto::ListTodosResponse {
+            todos: todos.into_iter().map(Into::into).collect(),
+        }))
+    }
+
+    async fn get(
+        &self,
+        request: Request<proto::GetTodoRequest>,
+    ) -> Result<Response<proto::Todo>, Status> {
+        let claims = self.authenticate(&request).await?;
+        let todo_id = request.into_inner().id;
+        let todo = self
+            .block(move |conn| {
+                actions::get_todo_of_tenant(
+                    models::Tenant::Personal { user_id: claims.id },
+                    todo_id,
+                    conn,
+                )
+            })
+            .await?;
+        Ok(Response::new(todo.into()))
+    }
+
+    async fn create(
+        &self,
+        request: Request<proto::CreateTodoRequest>,
+    ) -> Result<Response<proto::Todo>, Status> {
+        let claims = self.authenticate(&request).await?;
+        let data = models::NewTodoReq {
+            text: request.into_inner().text,
+            ..Default::default()
+        };
+        let todo = self
+            .block(move |conn| {
+                actions::create_new_todo(
+                    models::Tenant::Personal { user_id: claims.id },
+                    data,
+                    conn,
+                )
+            })
+            .await?;
+        self.broadcaster.publish(TodoEventKind::Created, &todo);
+        Ok(Response::new(todo.into()))
+    }
+
+    async fn update(
+        &self,
+        request: Request<proto::UpdateTodoRequest>,
+    ) -> Result<Response<proto::Todo>, Status> {
+        use models::UpdateTodo::*;
+        let claims = self.authenticate(&request).await?;
+        let body = request.into_inner();
+        let todo_id = body.id;
+        let data = match (body.text, body.done) {
+            (Some(text), Some(done)) => Both { text, done },
+            (Some(text), None) => TextOnly { text },
+            (None, Some(done)) => DoneOnly { done },
+            (None, None) => {
+                return Err(Status::invalid_argument(
+                    "Either `text` or `done` must be given.",
+                ))
+            }
+        };
+        let todo = self
+            .block(move |conn| {
+                let existing_todo = actions::get_todo_of_tenant(
+                    models::Tenant::Personal { user_id: claims.id },
+                    todo_id,
+                    conn,
+                )?;
+                actions::update_existing_todo(existing_todo, data, conn)
+            })
+            .await?;
+        self.broadcaster.publish(TodoEventKind::Updated, &todo);
+        Ok(Response::new(todo.into()))
+    }
+
+    async fn delete(
+        &self,
+        request: Request<proto::DeleteTodoRequest>,
+    ) -> Result<Response<proto::Todo>, Status> {
+        let claims = self.authenticate(&request).await?;
+        let todo_id = request.into_inner().id;
+        let todo = self
+            .block(move |conn| {
+                let existing_todo = actions::get_todo_of_tenant(
+                    models::Tenant::Personal { user_id: claims.id },
+                    todo_id,
+                    conn,
+                )?;
+                actions::delete_existing_todo(existing_todo, conn)
+            })
+            .await?;
+        self.broadcaster.publish(TodoEventKind::Deleted, &todo);
+        Ok(Response::new(todo.into()))
+    }
+
+    type WatchStream =
+        Pin<Box<dyn Stream<Item = Result<proto::TodoEvent, Status>> + Send + Sync + 'static>>;
+
+    async fn watch(
+        &self,
+        request: Request<proto::WatchTodosRequest>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        let claims = self.authenticate(&request).await?;
+        let events = self
+            .broadcaster
+            .subscribe(models::Tenant::Personal { user_id: claims.id })
+            .map(|event| {
+                Ok(proto::TodoEvent {
+                    kind: proto::todo_event::Kind::from(event.kind) as i32,
+                    todo: Some(event.todo.into()),
+                })
+            });
+        Ok(Response::new(Box::pin(events)))
+    }
+}
+
+/// Serves `TodoService` on `addr`. Must be polled on a tokio runtime; actix
+/// runs its own, so `main` drives this one on a separate thread.
+pub fn serve(
+    addr: SocketAddr,
+    pool: DbPool,
+    broadcaster: web::Data<Broadcaster>,
+) -> impl Future<Output = Result<(), tonic::transport::Error>> {
+    Server::builder()
+        .add_service(TodoServiceServer::new(TodoGrpcService::new(
+            pool,
+            broadcaster,
+        )))
+        .serve(addr)
+}
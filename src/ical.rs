@@ -0,0 +1,314 @@
+//! Just enough of RFC 5545 to exchange todos as `VTODO` components.
+
+use chrono::NaiveDateTime;
+
+use crate::{error::TodosError, models};
+
+const PRODID: &str = "-//actix-todos//todos//EN";
+const DATE_TIME_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/// A `VTODO` read from an uploaded calendar.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VTodo {
+    pub uid: String,
+    pub summary: String,
+    pub done: bool,
+    pub completed_at: Option<NaiveDateTime>,
+}
+
+fn escape_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape_text(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') | Some('N') => unescaped.push('\n'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+/// Writes a content line, folding it so that no line exceeds 75 octets.
+fn push_line(out: &mut String, line: &str) {
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            out.push_str("\r\n ");
+            width = 1;
+        }
+        out.push(c);
+        width += c.len_utf8();
+    }
+    out.push_str("\r\n");
+}
+
+fn format_date_time(date_time: &NaiveDateTime) -> String {
+    date_time.format(DATE_TIME_FORMAT).to_string()
+}
+
+fn parse_date_time(value: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(value, DATE_TIME_FORMAT)
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S"))
+        .ok()
+}
+
+/// Renders one `VTODO` component, without the surrounding `VCALENDAR`.
+pub fn write_vtodo(out: &mut String, todo: &models::Todo) {
+    push_line(out, "BEGIN:VTODO");
+    push_line(out, &format!("UID:{}", escape_text(&todo.ical_uid)));
+    push_line(
+        out,
+        &format!("DTSTAMP:{}", format_date_time(&todo.updated_at)),
+    );
+    push_line(
+        out,
+        &format!("CREATED:{}", format_date_time(&todo.created_at)),
+    );
+    push_line(
+        out,
+        &format!("LAST-MODIFIED:{}", format_date_time(&todo.updated_at)),
+    );
+    push_line(out, &format!("SUMMARY:{}", escape_text(&todo.text)));
+    if todo.done {
+        push_line(out, "STATUS:COMPLETED");
+        push_line(out, "PERCENT-COMPLETE:100");
+        let completed_at = todo.completed_at.unwrap_or(todo.updated_at);
+        push_line(
+            out,
+            &format!("COMPLETED:{}", format_date_time(&completed_at)),
+        );
+    } else {
+        push_line(out, "STATUS:NEEDS-ACTION");
+    }
+    push_line(out, "END:VTODO");
+}
+
+/// Renders a whole `VCALENDAR` holding one `VTODO` per todo.
+pub fn write_calendar(todos: &[models::Todo]) -> String {
+    let mut out = String::new();
+    push_line(&mut out, "BEGIN:VCALENDAR");
+    push_line(&mut out, "VERSION:2.0");
+    push_line(&mut out, &format!("PRODID:{}", PRODID));
+    push_line(&mut out, "CALSCALE:GREGORIAN");
+    for todo in todos {
+        write_vtodo(&mut out, todo);
+    }
+    push_line(&mut out, "END:VCALENDAR");
+    out
+}
+
+#[derive(Default)]
+struct PartialVTodo {
+    uid: Option<String>,
+    summary: Option<String>,
+    done: bool,
+    completed_at: Option<NaiveDateTime>,
+}
+
+/// Joins folded lines back together.
+fn unfold(input: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in input.split('\n') {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if let Some(continuation) = line.strip_prefix(' ').or_else(|| line.strip_prefix('\t')) {
+            if let Some(last) = lines.last_mut() {
+                last.push_str(continuation);
+                continue;
+            }
+        }
+        if !line.is_empty() {
+            lines.push(line.to_owned());
+        }
+    }
+    lines
+}
+
+/// Splits a content line into its name (without parameters) and value.
+fn split_line(line: &str) -> Option<(String, &str)> {
+    let colon = line.find(':')?;
+    let name = line[..colon].split(';').next()?.to_ascii_uppercase();
+    Some((name, &line[colon + 1..]))
+}
+
+/// Reads every `VTODO` of an iCalendar stream. Entries without a `UID` or
+/// `SUMMARY` are rejected, since neither can be made up meaningfully.
+pub fn parse_vtodos(input: &str) -> Result<Vec<VTodo>, TodosError> {
+    let mut vtodos = Vec::new();
+    let mut current: Option<PartialVTodo> = None;
+    let mut nesting = 0;
+    let mut in_calendar = false;
+
+    for line in unfold(input) {
+        let (name, value) = split_line(&line).ok_or(TodosError::InvalidCalendar)?;
+        match (name.as_str(), value.to_ascii_uppercase().as_str()) {
+            ("BEGIN", "VCALENDAR") => in_calendar = true,
+            ("END", "VCALENDAR") => in_calendar = false,
+            ("BEGIN", "VTODO") if in_calendar && current.is_none() => {
+                current = Some(PartialVTodo::default());
+            }
+            ("END", "VTODO") if nesting == 0 => {
+                let todo = current.take().ok_or(TodosError::InvalidCalendar)?;
+                vtodos.push(VTodo {
+                    uid: todo.uid.ok_or(TodosError::InvalidCalendar)?,
+                    summary: todo.summary.ok_or(TodosError::InvalidCalendar)?,
+                    done: todo.done,
+                    completed_at: todo.completed_at,
+                });
+            }
+            // Nested components such as `VALARM` carry properties that must
+            // not be mistaken for the todo's own.
+            ("BEGIN", _) if current.is_some() => nesting += 1,
+            ("END", _) if current.is_some() => nesting -= 1,
+            _ => {
+                if let (Some(todo), 0) = (current.as_mut(), nesting) {
+                    match name.as_str() {
+                        "UID" => todo.uid = Some(unescape_text(value)),
+                        "SUMMARY" => todo.summary = Some(unescape_text(value)),
+                        "STATUS" => todo.done = value.eq_ignore_ascii_case("COMPLETED"),
+                        "COMPLETED" => {
+                            todo.done = true;
+                            todo.completed_at = parse_date_time(value);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+
+    if current.is_some() || in_calendar {
+        return Err(TodosError::InvalidCalendar);
+    }
+    Ok(vtodos)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn todo(text: &str, done: bool) -> models::Todo {
+        let created_at = NaiveDate::from_ymd(2026, 10, 18).and_hms(9, 30, 0);
+        models::Todo {
+            id: 1,
+            text: text.to_owned(),
+            done,
+            user_id: Some(1),
+            ical_uid: "uid-1@todos".to_owned(),
+            created_at,
+            updated_at: created_at,
+            completed_at: None,
+            priority: None,
+            workspace_id: None,
+            projects: Vec::new(),
+            contexts: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn escapes_and_unescapes_text() {
+        let text = "a\\b;c,d\ne";
+        assert_eq!(escape_text(text), r"a\\b\;c\,d\ne");
+        assert_eq!(unescape_text(&escape_text(text)), text);
+        assert_eq!(escape_text("line\r\nbreak"), "line\\nbreak");
+        assert_eq!(unescape_text("upper\\Ncase"), "upper\ncase");
+    }
+
+    #[test]
+    fn writes_crlf_lines_folded_at_75_octets() {
+        let calendar = write_calendar(&[todo(&"é".repeat(100), false)]);
+        assert!(calendar.ends_with("END:VCALENDAR\r\n"));
+        assert!(!calendar.replace("\r\n", "").contains('\n'));
+        for line in calendar.split("\r\n") {
+            assert!(line.len() <= 75, "{:?} is longer than 75 octets", line);
+        }
+        // `é` takes two octets and is never split.
+        assert!(calendar.contains("\r\n é"));
+    }
+
+    #[test]
+    fn writes_status_and_completion() {
+        let open = write_calendar(&[todo("Open", false)]);
+        assert!(open.contains("STATUS:NEEDS-ACTION\r\n"));
+        assert!(!open.contains("COMPLETED:"));
+        let done = write_calendar(&[todo("Done", true)]);
+        assert!(done.contains("STATUS:COMPLETED\r\n"));
+        assert!(done.contains("COMPLETED:20261018T093000Z\r\n"));
+    }
+
+    #[test]
+    fn parses_what_it_writes() {
+        let long = format!("Buy milk, eggs; and {}\nthen rest", "more ".repeat(30));
+        let calendar = write_calendar(&[todo(&long, false), todo("Done", true)]);
+        let vtodos = parse_vtodos(&calendar).unwrap();
+        assert_eq!(
+            vtodos,
+            vec![
+                VTodo {
+                    uid: "uid-1@todos".to_owned(),
+                    summary: long,
+                    done: false,
+                    completed_at: None,
+                },
+                VTodo {
+                    uid: "uid-1@todos".to_owned(),
+                    summary: "Done".to_owned(),
+                    done: true,
+                    completed_at: Some(NaiveDate::from_ymd(2026, 10, 18).and_hms(9, 30, 0)),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_bare_newlines_parameters_and_nested_components() {
+        let calendar = "BEGIN:VCALENDAR\n\
+            BEGIN:VTODO\n\
+            UID:a\n\
+            SUMMARY;LANGUAGE=en:Water\n  the plants\n\
+            BEGIN:VALARM\n\
+            SUMMARY:Not the todo\n\
+            END:VALARM\n\
+            COMPLETED:20261018T120000\n\
+            END:VTODO\n\
+            END:VCALENDAR\n";
+        let vtodos = parse_vtodos(calendar).unwrap();
+        assert_eq!(vtodos.len(), 1);
+        assert_eq!(vtodos[0].summary, "Water the plants");
+        assert!(vtodos[0].done);
+        assert!(vtodos[0].completed_at.is_some());
+    }
+
+    #[test]
+    fn rejects_incomplete_calendars() {
+        for calendar in &[
+            "BEGIN:VCALENDAR\r\nBEGIN:VTODO\r\nUID:a\r\nEND:VTODO\r\nEND:VCALENDAR\r\n",
+            "BEGIN:VCALENDAR\r\nBEGIN:VTODO\r\nUID:a\r\nSUMMARY:x\r\nEND:VCALENDAR\r\n",
+            "BEGIN:VCALENDAR\r\nBEGIN:VTODO\r\nUID:a\r\nSUMMARY:x\r\nEND:VTODO\r\n",
+            "BEGIN:VCALENDAR\r\nnot a content line\r\nEND:VCALENDAR\r\n",
+        ] {
+            assert!(parse_vtodos(calendar).is_err(), "{:?}", calendar);
+        }
+    }
+}
@@ -0,0 +1,308 @@
+//! Reading the exports of other todo apps. Todos have no projects, labels or
+//! due dates of their own, so those are kept in the text the way todo.txt
+//! spells them: `+project`, `@label` and `due:YYYY-MM-DD`. Checklist items
+//! become todos of their own, prefixed with their parent's title.
+
+mod microsoft_todo;
+mod todoist;
+mod trello;
+
+use std::io::{Cursor, Read};
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+
+use crate::{error::TodosError, models};
+
+/// How much an uploaded ZIP may inflate to, so that a small archive can't
+/// exhaust the server's memory.
+const MAX_UNZIPPED_SIZE: u64 = 256 * 1024 * 1024;
+
+fn invalid(message: impl std::fmt::Display) -> TodosError {
+    TodosError::InvalidExport(message.to_string())
+}
+
+/// Turns a project or label name into a single todo.txt token.
+fn tag(sigil: char, name: &str) -> Option<String> {
+    let name = name.split_whitespace().collect::<Vec<_>>().join("_");
+    if name.is_empty() {
+        None
+    } else {
+        Some(format!("{}{}", sigil, name))
+    }
+}
+
+/// A todo's text: its title followed by the metadata todos have no field for.
+fn todo_text(
+    title: &str,
+    project: Option<&str>,
+    labels: &[String],
+    due: Option<NaiveDate>,
+) -> String {
+    let mut words = vec![title.trim().replace('\n', " ")];
+    words.extend(project.and_then(|project| tag('+', project)));
+    words.extend(labels.iter().filter_map(|label| tag('@', label)));
+    words.extend(due.map(|due| format!("due:{}", due.format("%Y-%m-%d"))));
+    words.join(" ")
+}
+
+/// Reads the date out of a date or date-time, ignoring any time of day.
+fn parse_date(value: &str) -> Option<NaiveDate> {
+    value
+        .get(..10)
+        .and_then(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok())
+}
+
+/// Reads RFC 3339 timestamps as UTC, and timestamps without an offset as is.
+fn parse_date_time(value: &str) -> Option<NaiveDateTime> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|date_time| date_time.naive_utc())
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f"))
+        .ok()
+}
+
+/// The files of a ZIP archive that could hold an export, by file stem.
+fn unzip(input: &[u8]) -> Result<Vec<(String, Vec<u8>)>, TodosError> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(input)).map_err(invalid)?;
+    let mut files = Vec::new();
+    let mut budget = MAX_UNZIPPED_SIZE;
+    for index in 0..archive.len() {
+        let file = archive.by_index(index).map_err(invalid)?;
+        let path = file.name().to_owned();
+        let (stem, extension) = match path.rsplit('/').next().and_then(|n| n.rsplit_once('.')) {
+            Some((stem, extension)) => (stem.to_owned(), extension.to_ascii_lowercase()),
+            None => continue,
+        };
+        if file.is_dir() || !(extension == "json" || extension == "csv") {
+            continue;
+        }
+        let mut contents = Vec::new();
+        file.take(budget + 1)
+            .read_to_end(&mut contents)
+            .map_err(invalid)?;
+        budget = budget
+            .checked_sub(contents.len() as u64)
+            .ok_or_else(|| invalid("the archive is too large once unpacked"))?;
+        files.push((stem, contents));
+    }
+    Ok(files)
+}
+
+/// Reads a provider's export: either one of its files, or a ZIP archive of
+/// them, in which case each file's name stands in for a missing project name.
+pub fn parse(
+    provider: models::ImportProvider,
+    input: &[u8],
+) -> Result<Vec<models::NewTodoReq>, TodosError> {
+    let files = if input.starts_with(b"PK\x03\x04") {
+        unzip(input)?
+            .into_iter()
+            .map(|(stem, contents)| (Some(stem), contents))
+            .collect()
+    } else {
+        vec![(None, input.to_vec())]
+    };
+
+    let mut todos = Vec::new();
+    for (name, contents) in files {
+        let name = name.as_deref();
+        todos.extend(match provider {
+            models::ImportProvider::Todoist => todoist::parse(&contents, name)?,
+            models::ImportProvider::Trello => trello::parse(&contents)?,
+            models::ImportProvider::MicrosoftTodo => microsoft_todo::parse(&contents, name)?,
+        });
+    }
+    Ok(todos)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use chrono::NaiveDate;
+
+    use super::*;
+    use crate::models::ImportProvider;
+
+    fn date_time(day: u32, hour: u32, minute: u32, second: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd(2026, 10, day).and_hms(hour, minute, second)
+    }
+
+    /// The text, done flag and priority of each todo.
+    fn summary(todos: &[models::NewTodoReq]) -> Vec<(&str, Option<bool>, Option<&str>)> {
+        todos
+            .iter()
+            .map(|todo| (todo.text.as_str(), todo.done, todo.priority.as_deref()))
+            .collect()
+    }
+
+    fn zip(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, contents) in files {
+            writer
+                .start_file(*name, zip::write::FileOptions::default())
+                .unwrap();
+            writer.write_all(contents.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn reads_todoist_backups() {
+        let backup = r#"{
+            "projects": [{"id": "2203306141", "name": "Home Chores"}],
+            "items": [
+                {"content": "Water plants", "project_id": "2203306141", "labels": ["garden"],
+                 "priority": 4, "due": {"date": "2026-10-20"}, "checked": false,
+                 "added_at": "2026-10-01T08:00:00.000000Z"},
+                {"content": "Gone", "is_deleted": true},
+                {"content": "Pay rent", "priority": 1, "checked": true,
+                 "completed_at": "2026-10-02T10:00:00+02:00"}
+            ]
+        }"#;
+        let todos = parse(ImportProvider::Todoist, backup.as_bytes()).unwrap();
+        assert_eq!(
+            summary(&todos),
+            vec![
+                (
+                    "Water plants +Home_Chores @garden due:2026-10-20",
+                    Some(false),
+                    Some("A")
+                ),
+                ("Pay rent", Some(true), None),
+            ]
+        );
+        assert_eq!(todos[0].created_at, Some(date_time(1, 8, 0, 0)));
+        assert_eq!(todos[1].completed_at, Some(date_time(2, 8, 0, 0)));
+    }
+
+    #[test]
+    fn reads_todoist_csv_exports_named_after_their_project() {
+        let csv = "\u{feff}TYPE,CONTENT,PRIORITY,INDENT,DATE\n\
+                   section,Dairy,,,\n\
+                   task,Buy milk @errand,1,1,2026-10-21\n\
+                   note,Semi-skimmed,,,\n\
+                   task,Buy bread,4,1,\n";
+        let todos = parse(ImportProvider::Todoist, csv.as_bytes()).unwrap();
+        assert_eq!(
+            summary(&todos),
+            vec![
+                ("Buy milk @errand due:2026-10-21", Some(false), Some("A")),
+                ("Buy bread", Some(false), None),
+            ]
+        );
+
+        let archive = zip(&[("export/Weekly Shop.csv", csv), ("README.txt", "ignored")]);
+        let todos = parse(ImportProvider::Todoist, &archive).unwrap();
+        assert_eq!(todos[1].text, "Buy bread +Weekly_Shop");
+    }
+
+    #[test]
+    fn reads_trello_boards() {
+        let board = r#"{
+            "name": "Launch",
+            "lists": [{"id": "l1"}, {"id": "l2", "closed": true}],
+            "cards": [
+                {"id": "5f0c7c2a0000000000000000", "name": "Write post", "idList": "l1",
+                 "due": "2026-10-22T12:00:00.000Z", "dueComplete": true,
+                 "labels": [{"name": "", "color": "red"}, {"name": "Blog post"}],
+                 "dateLastActivity": "2026-10-18T09:00:00.000Z"},
+                {"id": "x", "name": "On an archived list", "idList": "l2"},
+                {"id": "y", "name": "Archived", "idList": "l1", "closed": true}
+            ],
+            "checklists": [{"idCard": "5f0c7c2a0000000000000000", "checkItems": [
+                {"name": "Draft", "state": "complete"},
+                {"name": "Proofread", "state": "incomplete"}
+            ]}]
+        }"#;
+        let todos = parse(ImportProvider::Trello, board.as_bytes()).unwrap();
+        assert_eq!(
+            summary(&todos),
+            vec![
+                (
+                    "Write post +Launch @red @Blog_post due:2026-10-22",
+                    Some(true),
+                    None
+                ),
+                (
+                    "Write post: Draft +Launch @red @Blog_post",
+                    Some(true),
+                    None
+                ),
+                (
+                    "Write post: Proofread +Launch @red @Blog_post",
+                    Some(false),
+                    None
+                ),
+            ]
+        );
+        let created_at = NaiveDate::from_ymd(2020, 7, 13).and_hms(15, 22, 18);
+        assert_eq!(todos[0].created_at, Some(created_at));
+        assert_eq!(todos[0].completed_at, Some(date_time(18, 9, 0, 0)));
+        assert_eq!(todos[2].created_at, Some(created_at));
+    }
+
+    #[test]
+    fn reads_microsoft_todo_lists() {
+        let lists = r#"{"value": [{"displayName": "Work", "tasks": [{
+            "title": "Send report", "status": "completed", "importance": "high",
+            "dueDateTime": {"dateTime": "2026-10-23T00:00:00.0000000", "timeZone": "UTC"},
+            "completedDateTime": {"dateTime": "2026-10-18T10:00:00.0000000", "timeZone": "UTC"},
+            "createdDateTime": "2026-10-01T08:00:00Z",
+            "categories": ["Blue category"],
+            "checklistItems": [{"displayName": "Attach numbers", "isChecked": true}]
+        }]}]}"#;
+        let todos = parse(ImportProvider::MicrosoftTodo, lists.as_bytes()).unwrap();
+        assert_eq!(
+            summary(&todos),
+            vec![
+                (
+                    "Send report +Work @Blue_category due:2026-10-23",
+                    Some(true),
+                    Some("A")
+                ),
+                (
+                    "Send report: Attach numbers +Work @Blue_category",
+                    Some(true),
+                    None
+                ),
+            ]
+        );
+        assert_eq!(todos[0].created_at, Some(date_time(1, 8, 0, 0)));
+        assert_eq!(todos[0].completed_at, Some(date_time(18, 10, 0, 0)));
+
+        let tasks = r#"{"value": [{"title": "Buy stamps", "status": "notStarted"}]}"#;
+        let archive = zip(&[("Errands.json", tasks)]);
+        let todos = parse(ImportProvider::MicrosoftTodo, &archive).unwrap();
+        assert_eq!(
+            summary(&todos),
+            vec![("Buy stamps +Errands", Some(false), None)]
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_exports() {
+        let inputs: &[(ImportProvider, &[u8])] = &[
+            (
+                ImportProvider::Todoist,
+                b"{\"items\": [{\"checked\": true}]}",
+            ),
+            (
+                ImportProvider::Todoist,
+                b"TYPE,CONTENT\ntask,Too,many,fields\n",
+            ),
+            (ImportProvider::Trello, b"[]"),
+            (
+                ImportProvider::MicrosoftTodo,
+                b"{\"value\": [{\"status\": \"completed\"}]}",
+            ),
+            (ImportProvider::MicrosoftTodo, b"PK\x03\x04 but not a ZIP"),
+        ];
+        for (provider, input) in inputs {
+            assert!(
+                matches!(parse(*provider, input), Err(TodosError::InvalidExport(_))),
+                "{:?}",
+                String::from_utf8_lossy(input)
+            );
+        }
+    }
+}
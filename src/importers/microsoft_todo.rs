@@ -0,0 +1,103 @@
+//! Microsoft To Do has no export of its own; this reads what the Graph API
+//! returns for it. A file is either `GET /me/todo/lists?$expand=tasks`, or
+//! the `GET /me/todo/lists/{id}/tasks` of one list, named after that list.
+
+use serde::Deserialize;
+
+use super::{invalid, parse_date, parse_date_time, todo_text};
+use crate::{error::TodosError, models};
+
+#[derive(Deserialize)]
+struct Page {
+    value: Vec<Entry>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Entry {
+    List(TaskList),
+    Task(Task),
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TaskList {
+    display_name: String,
+    #[serde(default)]
+    tasks: Vec<Task>,
+}
+
+#[derive(Deserialize)]
+struct DateTimeTimeZone {
+    #[serde(rename = "dateTime")]
+    date_time: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ChecklistItem {
+    display_name: String,
+    #[serde(default)]
+    is_checked: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Task {
+    title: String,
+    #[serde(default)]
+    status: String,
+    #[serde(default)]
+    importance: String,
+    due_date_time: Option<DateTimeTimeZone>,
+    completed_date_time: Option<DateTimeTimeZone>,
+    created_date_time: Option<String>,
+    #[serde(default)]
+    categories: Vec<String>,
+    #[serde(default)]
+    checklist_items: Vec<ChecklistItem>,
+}
+
+fn push_task(todos: &mut Vec<models::NewTodoReq>, task: Task, list: Option<&str>) {
+    let due = task
+        .due_date_time
+        .and_then(|due| parse_date(&due.date_time));
+    let created_at = task.created_date_time.as_deref().and_then(parse_date_time);
+    todos.push(models::NewTodoReq {
+        text: todo_text(&task.title, list, &task.categories, due),
+        done: Some(task.status == "completed"),
+        priority: match task.importance.as_str() {
+            "high" => Some("A".to_owned()),
+            _ => None,
+        },
+        created_at,
+        completed_at: task
+            .completed_date_time
+            .and_then(|completed| parse_date_time(&completed.date_time)),
+    });
+    for item in task.checklist_items {
+        let title = format!("{}: {}", task.title.trim(), item.display_name.trim());
+        todos.push(models::NewTodoReq {
+            text: todo_text(&title, list, &task.categories, None),
+            done: Some(item.is_checked),
+            created_at,
+            ..Default::default()
+        });
+    }
+}
+
+pub fn parse(input: &[u8], file_name: Option<&str>) -> Result<Vec<models::NewTodoReq>, TodosError> {
+    let page: Page = serde_json::from_slice(input).map_err(invalid)?;
+    let mut todos = Vec::new();
+    for entry in page.value {
+        match entry {
+            Entry::List(list) => {
+                for task in list.tasks {
+                    push_task(&mut todos, task, Some(&list.display_name));
+                }
+            }
+            Entry::Task(task) => push_task(&mut todos, task, file_name),
+        }
+    }
+    Ok(todos)
+}
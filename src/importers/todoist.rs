@@ -0,0 +1,135 @@
+//! Todoist's JSON backups (the shape of its Sync API) and its per-project CSV
+//! exports.
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use super::{invalid, parse_date, parse_date_time, todo_text};
+use crate::{error::TodosError, models};
+
+#[derive(Deserialize)]
+struct Backup {
+    #[serde(default)]
+    projects: Vec<Project>,
+    items: Vec<Item>,
+}
+
+#[derive(Deserialize)]
+struct Project {
+    id: Value,
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct Due {
+    date: String,
+}
+
+#[derive(Deserialize)]
+struct Item {
+    content: String,
+    project_id: Option<Value>,
+    #[serde(default)]
+    labels: Vec<String>,
+    /// 4 is the most urgent.
+    #[serde(default)]
+    priority: u8,
+    due: Option<Due>,
+    #[serde(default)]
+    checked: bool,
+    #[serde(default)]
+    is_deleted: bool,
+    added_at: Option<String>,
+    completed_at: Option<String>,
+}
+
+fn api_priority(priority: u8) -> Option<String> {
+    match priority {
+        4 => Some("A".to_owned()),
+        3 => Some("B".to_owned()),
+        2 => Some("C".to_owned()),
+        _ => None,
+    }
+}
+
+fn parse_backup(input: &[u8]) -> Result<Vec<models::NewTodoReq>, TodosError> {
+    let backup: Backup = serde_json::from_slice(input).map_err(invalid)?;
+    let projects: HashMap<String, String> = backup
+        .projects
+        .into_iter()
+        .map(|project| (project.id.to_string(), project.name))
+        .collect();
+
+    Ok(backup
+        .items
+        .into_iter()
+        .filter(|item| !item.is_deleted)
+        .map(|item| {
+            let project = item
+                .project_id
+                .and_then(|id| projects.get(&id.to_string()))
+                .map(String::as_str);
+            let due = item.due.and_then(|due| parse_date(&due.date));
+            models::NewTodoReq {
+                text: todo_text(&item.content, project, &item.labels, due),
+                done: Some(item.checked),
+                priority: api_priority(item.priority),
+                created_at: item.added_at.as_deref().and_then(parse_date_time),
+                completed_at: item.completed_at.as_deref().and_then(parse_date_time),
+            }
+        })
+        .collect())
+}
+
+/// Columns of the CSV export. Its labels are already part of `CONTENT` as
+/// `@label`, and completed tasks aren't exported at all.
+#[derive(Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+struct Row {
+    #[serde(rename = "TYPE")]
+    kind: String,
+    content: String,
+    /// 1 is the most urgent.
+    #[serde(default)]
+    priority: Option<u8>,
+    #[serde(default)]
+    date: String,
+}
+
+fn csv_priority(priority: Option<u8>) -> Option<String> {
+    match priority {
+        Some(1) => Some("A".to_owned()),
+        Some(2) => Some("B".to_owned()),
+        Some(3) => Some("C".to_owned()),
+        _ => None,
+    }
+}
+
+fn parse_csv(input: &[u8], project: Option<&str>) -> Result<Vec<models::NewTodoReq>, TodosError> {
+    let input = input.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(input);
+    let mut todos = Vec::new();
+    for row in csv::Reader::from_reader(input).deserialize() {
+        let row: Row = row.map_err(invalid)?;
+        if row.kind != "task" || row.content.trim().is_empty() {
+            continue;
+        }
+        todos.push(models::NewTodoReq {
+            text: todo_text(&row.content, project, &[], parse_date(&row.date)),
+            done: Some(false),
+            priority: csv_priority(row.priority),
+            ..Default::default()
+        });
+    }
+    Ok(todos)
+}
+
+pub fn parse(input: &[u8], file_name: Option<&str>) -> Result<Vec<models::NewTodoReq>, TodosError> {
+    let is_json = input.iter().find(|byte| !byte.is_ascii_whitespace()) == Some(&b'{');
+    if is_json {
+        parse_backup(input)
+    } else {
+        parse_csv(input, file_name)
+    }
+}
@@ -0,0 +1,127 @@
+//! Trello board exports ("Print and export" → JSON). The board becomes the
+//! project; archived cards and cards on archived lists are left out.
+
+use std::collections::{HashMap, HashSet};
+
+use chrono::NaiveDateTime;
+use serde::Deserialize;
+
+use super::{invalid, parse_date, parse_date_time, todo_text};
+use crate::{error::TodosError, models};
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Board {
+    name: String,
+    #[serde(default)]
+    lists: Vec<List>,
+    #[serde(default)]
+    cards: Vec<Card>,
+    #[serde(default)]
+    checklists: Vec<Checklist>,
+}
+
+#[derive(Deserialize)]
+struct List {
+    id: String,
+    #[serde(default)]
+    closed: bool,
+}
+
+#[derive(Deserialize)]
+struct Label {
+    #[serde(default)]
+    name: String,
+    color: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Card {
+    id: String,
+    name: String,
+    id_list: String,
+    #[serde(default)]
+    closed: bool,
+    due: Option<String>,
+    #[serde(default)]
+    due_complete: bool,
+    #[serde(default)]
+    labels: Vec<Label>,
+    date_last_activity: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Checklist {
+    id_card: String,
+    #[serde(default)]
+    check_items: Vec<CheckItem>,
+}
+
+#[derive(Deserialize)]
+struct CheckItem {
+    name: String,
+    state: String,
+}
+
+/// Trello ids are MongoDB object ids, which start with their creation time.
+fn id_timestamp(id: &str) -> Option<NaiveDateTime> {
+    let seconds = i64::from_str_radix(id.get(..8)?, 16).ok()?;
+    NaiveDateTime::from_timestamp_opt(seconds, 0)
+}
+
+pub fn parse(input: &[u8]) -> Result<Vec<models::NewTodoReq>, TodosError> {
+    let board: Board = serde_json::from_slice(input).map_err(invalid)?;
+    let closed_lists: HashSet<&str> = board
+        .lists
+        .iter()
+        .filter(|list| list.closed)
+        .map(|list| list.id.as_str())
+        .collect();
+    let mut checklists: HashMap<&str, Vec<&CheckItem>> = HashMap::new();
+    for checklist in &board.checklists {
+        checklists
+            .entry(checklist.id_card.as_str())
+            .or_default()
+            .extend(&checklist.check_items);
+    }
+
+    let mut todos = Vec::new();
+    for card in &board.cards {
+        if card.closed || closed_lists.contains(card.id_list.as_str()) {
+            continue;
+        }
+        let labels: Vec<String> = card
+            .labels
+            .iter()
+            .map(|label| match (label.name.trim(), &label.color) {
+                ("", Some(color)) => color.clone(),
+                (name, _) => name.to_owned(),
+            })
+            .collect();
+        let due = card.due.as_deref().and_then(parse_date);
+        let created_at = id_timestamp(&card.id);
+        todos.push(models::NewTodoReq {
+            text: todo_text(&card.name, Some(&board.name), &labels, due),
+            done: Some(card.due_complete),
+            created_at,
+            completed_at: card
+                .date_last_activity
+                .as_deref()
+                .and_then(parse_date_time)
+                .filter(|_| card.due_complete),
+            ..Default::default()
+        });
+        for item in checklists.get(card.id.as_str()).into_iter().flatten() {
+            let title = format!("{}: {}", card.name.trim(), item.name.trim());
+            todos.push(models::NewTodoReq {
+                text: todo_text(&title, Some(&board.name), &labels, None),
+                done: Some(item.state == "complete"),
+                created_at,
+                ..Default::default()
+            });
+        }
+    }
+    Ok(todos)
+}
@@ -0,0 +1,435 @@
+//! The keys tokens are signed and verified with.
+//!
+//! With `JWT_KEYS_DIR` set, every `{kid}.pub.pem` in it (an RSA or Ed25519
+//! public key in SPKI form, as `openssl pkey -pubout` writes them) verifies
+//! tokens whose `kid` header names it, and is published at
+//! `/.well-known/jwks.json`. Tokens are signed with the private key
+//! `{JWT_SIGNING_KID}.pem` next to it, as RS256 or EdDSA depending on its
+//! type. `JWT_SECRET` keeps HS512 tokens without a `kid` valid, so that
+//! switching from it doesn't log anyone out; without `JWT_KEYS_DIR`, it also
+//! signs new ones.
+
+use std::{collections::HashMap, path::Path, sync::OnceLock};
+
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use simple_asn1::{oid, ASN1Block};
+use utoipa::ToSchema;
+
+use crate::error::TodosError;
+
+/// A public key in JWK form (RFC 7517).
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Jwk {
+    /// `RSA` or `OKP`.
+    pub kty: String,
+    #[serde(rename = "use")]
+    pub key_use: String,
+    pub kid: String,
+    /// `RS256` or `EdDSA`.
+    pub alg: String,
+    /// The RSA modulus.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n: Option<String>,
+    /// The RSA public exponent.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub e: Option<String>,
+    /// `Ed25519`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crv: Option<String>,
+    /// The Ed25519 public key.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub x: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct JwkSet {
+    pub keys: Vec<Jwk>,
+}
+
+struct VerificationKey {
+    algorithm: Algorithm,
+    decoding_key: DecodingKey,
+    jwk: Jwk,
+}
+
+struct SigningKey {
+    kid: Option<String>,
+    algorithm: Algorithm,
+    encoding_key: EncodingKey,
+}
+
+pub struct Keyring {
+    signing_key: SigningKey,
+    verification_keys: HashMap<String, VerificationKey>,
+    /// The HS512 secret tokens without a `kid` are verified with.
+    secret: Option<DecodingKey>,
+}
+
+fn base64url(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+/// Reads an SPKI public key into its algorithm and JWK.
+fn parse_public_key(kid: &str, pem: &str) -> Result<(Algorithm, Jwk), String> {
+    let pem = pem::parse(pem).map_err(|e| e.to_string())?;
+    if pem.tag != "PUBLIC KEY" {
+        return Err(format!("expected a PUBLIC KEY, found {}", pem.tag));
+    }
+    let invalid = || "invalid SubjectPublicKeyInfo".to_owned();
+    let blocks = simple_asn1::from_der(&pem.contents).map_err(|e| e.to_string())?;
+    let (algorithm_oid, key) = match blocks.as_slice() {
+        [ASN1Block::Sequence(_, fields)] => match fields.as_slice() {
+            [ASN1Block::Sequence(_, algorithm), ASN1Block::BitString(_, _, key)] => {
+                match algorithm.first() {
+                    Some(ASN1Block::ObjectIdentifier(_, oid)) => (oid.clone(), key.clone()),
+                    _ => return Err(invalid()),
+                }
+            }
+            _ => return Err(invalid()),
+        },
+        _ => return Err(invalid()),
+    };
+
+    let mut jwk = Jwk {
+        kty: String::new(),
+        key_use: "sig".to_owned(),
+        kid: kid.to_owned(),
+        alg: String::new(),
+        n: None,
+        e: None,
+        crv: None,
+        x: None,
+    };
+    if algorithm_oid == oid!(1, 2, 840, 113549, 1, 1, 1) {
+        let blocks = simple_asn1::from_der(&key).map_err(|e| e.to_string())?;
+        match blocks.as_slice() {
+            [ASN1Block::Sequence(_, integers)] => match integers.as_slice() {
+                [ASN1Block::Integer(_, n), ASN1Block::Integer(_, e)] => {
+                    jwk.n = Some(base64url(&n.to_bytes_be().1));
+                    jwk.e = Some(base64url(&e.to_bytes_be().1));
+                }
+                _ => return Err("invalid RSA public key".to_owned()),
+            },
+            _ => return Err("invalid RSA public key".to_owned()),
+        }
+        "RSA".clone_into(&mut jwk.kty);
+        "RS256".clone_into(&mut jwk.alg);
+        Ok((Algorithm::RS256, jwk))
+    } else if algorithm_oid == oid!(1, 3, 101, 112) {
+        "OKP".clone_into(&mut jwk.kty);
+        "EdDSA".clone_into(&mut jwk.alg);
+        jwk.crv = Some("Ed25519".to_owned());
+        jwk.x = Some(base64url(&key));
+        Ok((Algorithm::EdDSA, jwk))
+    } else {
+        Err("only RSA and Ed25519 keys are supported".to_owned())
+    }
+}
+
+impl Keyring {
+    pub fn from_env() -> Result<Self, String> {
+        let secret = std::env::var("JWT_SECRET")
+            .ok()
+            .filter(|secret| !secret.is_empty());
+        let dir = match std::env::var("JWT_KEYS_DIR") {
+            Ok(dir) if !dir.is_empty() => dir,
+            _ => {
+                let secret = secret.ok_or("Set JWT_KEYS_DIR or JWT_SECRET.")?;
+                return Ok(Self::from_secret(&secret));
+            }
+        };
+
+        let kid = std::env::var("JWT_SIGNING_KID")
+            .map_err(|_| "JWT_SIGNING_KID is required with JWT_KEYS_DIR.")?;
+        let mut keyring = Self::from_dir(&dir, &kid)?;
+        keyring.secret = secret.map(|secret| DecodingKey::from_secret(secret.as_bytes()));
+        Ok(keyring)
+    }
+
+    /// Signs and verifies with HS512 and a shared secret.
+    fn from_secret(secret: &str) -> Self {
+        Self {
+            signing_key: SigningKey {
+                kid: None,
+                algorithm: Algorithm::HS512,
+                encoding_key: EncodingKey::from_secret(secret.as_bytes()),
+            },
+            verification_keys: HashMap::new(),
+            secret: Some(DecodingKey::from_secret(secret.as_bytes())),
+        }
+    }
+
+    /// Loads every `{kid}.pub.pem` in `dir`, and signs with the private key
+    /// `{signing_kid}.pem`.
+    pub fn from_dir(dir: &str, signing_kid: &str) -> Result<Self, String> {
+        let mut verification_keys = HashMap::new();
+        let entries = std::fs::read_dir(dir).map_err(|e| format!("{}: {}", dir, e))?;
+        for entry in entries {
+            let path = entry.map_err(|e| e.to_string())?.path();
+            let file_name = path.file_name().and_then(|name| name.to_str());
+            let kid = match file_name.and_then(|name| name.strip_suffix(".pub.pem")) {
+                Some(kid) => kid.to_owned(),
+                None => continue,
+            };
+            let pem =
+                std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            let (algorithm, jwk) =
+                parse_public_key(&kid, &pem).map_err(|e| format!("{}: {}", path.display(), e))?;
+            let decoding_key = match algorithm {
+                Algorithm::EdDSA => DecodingKey::from_ed_pem(pem.as_bytes()),
+                _ => DecodingKey::from_rsa_pem(pem.as_bytes()),
+            }
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+            verification_keys.insert(
+                kid,
+                VerificationKey {
+                    algorithm,
+                    decoding_key,
+                    jwk,
+                },
+            );
+        }
+
+        let algorithm = verification_keys
+            .get(signing_kid)
+            .ok_or_else(|| format!("{}/{}.pub.pem is missing.", dir, signing_kid))?
+            .algorithm;
+        let path = Path::new(dir).join(format!("{}.pem", signing_kid));
+        let pem = std::fs::read(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let encoding_key = match algorithm {
+            Algorithm::EdDSA => EncodingKey::from_ed_pem(&pem),
+            _ => EncodingKey::from_rsa_pem(&pem),
+        }
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        Ok(Self {
+            signing_key: SigningKey {
+                kid: Some(signing_kid.to_owned()),
+                algorithm,
+                encoding_key,
+            },
+            verification_keys,
+            secret: None,
+        })
+    }
+
+    pub fn encode<T: Serialize>(&self, claims: &T) -> Result<String, TodosError> {
+        let mut header = Header::new(self.signing_key.algorithm);
+        header.kid.clone_from(&self.signing_key.kid);
+        jsonwebtoken::encode(&header, claims, &self.signing_key.encoding_key)
+            .map_err(|_| TodosError::JwtTokenCreationError)
+    }
+
+    /// Verifies a token with the key its `kid` names, and only with the
+    /// algorithm that key is for.
+    pub fn decode<T: DeserializeOwned>(&self, token: &str) -> Result<T, TodosError> {
+        let header =
+            jsonwebtoken::decode_header(token).map_err(|_| TodosError::JwtTokenDecodeError)?;
+        let (algorithm, decoding_key) = match &header.kid {
+            Some(kid) => {
+                let key = self
+                    .verification_keys
+                    .get(kid)
+                    .ok_or(TodosError::JwtTokenDecodeError)?;
+                (key.algorithm, &key.decoding_key)
+            }
+            None => (
+                Algorithm::HS512,
+                self.secret
+                    .as_ref()
+                    .ok_or(TodosError::JwtTokenDecodeError)?,
+            ),
+        };
+        jsonwebtoken::decode::<T>(token, decoding_key, &Validation::new(algorithm))
+            .map(|decoded| decoded.claims)
+            .map_err(|_| TodosError::JwtTokenDecodeError)
+    }
+
+    /// The public keys, for other services to verify tokens with.
+    pub fn jwks(&self) -> JwkSet {
+        let mut keys = self
+            .verification_keys
+            .values()
+            .map(|key| key.jwk.clone())
+            .collect::<Vec<_>>();
+        keys.sort_by(|a, b| a.kid.cmp(&b.kid));
+        JwkSet { keys }
+    }
+}
+
+static KEYRING: OnceLock<Keyring> = OnceLock::new();
+
+/// Loads the keyring from the environment. The server does so before it
+/// starts listening, so that misconfigured keys stop it right away.
+pub fn init() -> Result<&'static Keyring, String> {
+    let keyring = Keyring::from_env()?;
+    Ok(KEYRING.get_or_init(|| keyring))
+}
+
+/// The keyring, loaded from the environment the first time it is needed.
+pub fn keyring() -> &'static Keyring {
+    KEYRING.get_or_init(|| Keyring::from_env().unwrap_or_else(|e| panic!("JWT keys: {}", e)))
+}
+
+/// Signs with a fixed secret, for tests that issue tokens without keys in
+/// the environment.
+#[cfg(test)]
+pub(crate) fn init_for_tests() {
+    KEYRING.get_or_init(|| Keyring::from_secret("test-secret"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `rsa-1` and `ed-1`, each a `{kid}.pem` private key and its
+    /// `{kid}.pub.pem`.
+    const KEYS_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/jwt");
+    const EC_PUBLIC_KEY: &str = "-----BEGIN PUBLIC KEY-----
+MFkwEwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAEwwm46d29eyggjTn5CPeA2TeziMnW
+7t1KnJu58WzuWLjZ0y1P6ZrQiIflK3Sqvgr6/Evy5CJWVM8Wvn6CfkVEKg==
+-----END PUBLIC KEY-----
+";
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Claims {
+        sub: String,
+        exp: i64,
+    }
+
+    fn claims() -> Claims {
+        Claims {
+            sub: "alice".to_owned(),
+            exp: chrono::Utc::now().timestamp() + 60,
+        }
+    }
+
+    fn fixture(name: &str) -> String {
+        std::fs::read_to_string(Path::new(KEYS_DIR).join(name)).unwrap()
+    }
+
+    /// Signs `claims()` with a private key of the fixtures, whatever kid the
+    /// header names.
+    fn sign(private_key: &str, algorithm: Algorithm, kid: Option<&str>) -> String {
+        let pem = fixture(private_key);
+        let key = match algorithm {
+            Algorithm::EdDSA => EncodingKey::from_ed_pem(pem.as_bytes()),
+            _ => EncodingKey::from_rsa_pem(pem.as_bytes()),
+        }
+        .unwrap();
+        let mut header = Header::new(algorithm);
+        header.kid = kid.map(str::to_owned);
+        jsonwebtoken::encode(&header, &claims(), &key).unwrap()
+    }
+
+    fn hs512(secret: &str) -> String {
+        jsonwebtoken::encode(
+            &Header::new(Algorithm::HS512),
+            &claims(),
+            &EncodingKey::from_secret(secret.as_bytes()),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn reads_rsa_and_ed25519_public_keys() {
+        let (algorithm, jwk) = parse_public_key("rsa-1", &fixture("rsa-1.pub.pem")).unwrap();
+        assert_eq!(algorithm, Algorithm::RS256);
+        assert_eq!((jwk.kty.as_str(), jwk.alg.as_str()), ("RSA", "RS256"));
+        assert_eq!(jwk.e.as_deref(), Some("AQAB"));
+        // A 2048-bit modulus, without the sign byte DER adds.
+        assert_eq!(
+            base64::decode_config(jwk.n.unwrap(), base64::URL_SAFE_NO_PAD)
+                .unwrap()
+                .len(),
+            256
+        );
+
+        let (algorithm, jwk) = parse_public_key("ed-1", &fixture("ed-1.pub.pem")).unwrap();
+        assert_eq!(algorithm, Algorithm::EdDSA);
+        assert_eq!(
+            (jwk.kty.as_str(), jwk.crv.as_deref()),
+            ("OKP", Some("Ed25519"))
+        );
+        assert_eq!(
+            base64::decode_config(jwk.x.unwrap(), base64::URL_SAFE_NO_PAD)
+                .unwrap()
+                .len(),
+            32
+        );
+    }
+
+    #[test]
+    fn rejects_other_keys() {
+        assert_eq!(
+            parse_public_key("ec", EC_PUBLIC_KEY).unwrap_err(),
+            "only RSA and Ed25519 keys are supported"
+        );
+        assert!(parse_public_key("rsa-1", &fixture("rsa-1.pem"))
+            .unwrap_err()
+            .starts_with("expected a PUBLIC KEY"));
+        assert!(parse_public_key("junk", "not a PEM").is_err());
+    }
+
+    #[test]
+    fn verifies_tokens_with_the_key_their_kid_names() {
+        let keyring = Keyring::from_dir(KEYS_DIR, "rsa-1").unwrap();
+        let kids = keyring
+            .jwks()
+            .keys
+            .into_iter()
+            .map(|jwk| jwk.kid)
+            .collect::<Vec<_>>();
+        assert_eq!(kids, vec!["ed-1", "rsa-1"]);
+
+        let token = keyring.encode(&claims()).unwrap();
+        let header = jsonwebtoken::decode_header(&token).unwrap();
+        assert_eq!(
+            (header.alg, header.kid.as_deref()),
+            (Algorithm::RS256, Some("rsa-1"))
+        );
+        assert_eq!(keyring.decode::<Claims>(&token).unwrap().sub, "alice");
+
+        let ed25519 = sign("ed-1.pem", Algorithm::EdDSA, Some("ed-1"));
+        assert_eq!(keyring.decode::<Claims>(&ed25519).unwrap().sub, "alice");
+    }
+
+    #[test]
+    fn rejects_unknown_kids_and_mismatched_keys() {
+        let keyring = Keyring::from_dir(KEYS_DIR, "ed-1").unwrap();
+        for token in &[
+            sign("rsa-1.pem", Algorithm::RS256, Some("rsa-2")),
+            // Signed with the RSA key, but claiming to be the Ed25519 one.
+            sign("rsa-1.pem", Algorithm::RS256, Some("ed-1")),
+            sign("ed-1.pem", Algorithm::EdDSA, Some("rsa-1")),
+        ] {
+            assert!(matches!(
+                keyring.decode::<Claims>(token),
+                Err(TodosError::JwtTokenDecodeError)
+            ));
+        }
+    }
+
+    #[test]
+    fn verifies_tokens_without_a_kid_only_with_the_secret() {
+        let mut keyring = Keyring::from_dir(KEYS_DIR, "rsa-1").unwrap();
+        let token = hs512("secret");
+        assert!(matches!(
+            keyring.decode::<Claims>(&token),
+            Err(TodosError::JwtTokenDecodeError)
+        ));
+
+        keyring.secret = Some(DecodingKey::from_secret(b"secret"));
+        assert_eq!(keyring.decode::<Claims>(&token).unwrap().sub, "alice");
+        assert!(matches!(
+            keyring.decode::<Claims>(&hs512("other secret")),
+            Err(TodosError::JwtTokenDecodeError)
+        ));
+        // An RSA token without a kid is never checked against the secret.
+        assert!(matches!(
+            keyring.decode::<Claims>(&sign("rsa-1.pem", Algorithm::RS256, None)),
+            Err(TodosError::JwtTokenDecodeError)
+        ));
+    }
+}
@@ -1,15 +1,39 @@
+// diesel 1.4's derives, `table!` and `sql_function!` expand to impls inside
+// functions, which newer compilers warn about.
+#![allow(non_local_definitions)]
+
 use diesel::{
     r2d2::{self, ConnectionManager},
     PgConnection,
 };
 
 pub type DbPool = r2d2::Pool<ConnectionManager<PgConnection>>;
+pub type DbConn = r2d2::PooledConnection<ConnectionManager<PgConnection>>;
 pub mod error;
 
 #[macro_use]
 extern crate diesel;
 
 pub mod actions;
+pub mod archive;
+pub mod audit;
 pub mod auth;
+pub mod caldav;
+pub mod csv_io;
+pub mod events;
+pub mod graphql;
+pub mod grpc;
+pub mod ical;
+pub mod importers;
+pub mod keys;
+pub mod mail;
+pub mod markdown;
 pub mod models;
+pub mod oauth;
+pub mod oidc;
+pub mod openapi;
+pub mod password;
+pub mod roles;
 mod schema;
+pub mod todotxt;
+pub mod totp;
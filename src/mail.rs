@@ -0,0 +1,118 @@
+//! Outgoing email. Which `Mailer` is used is decided by the environment: an
+//! SMTP server when `SMTP_HOST` is set, and otherwise a local outbox
+//! directory that tests and development setups can read the messages from.
+
+use std::sync::Arc;
+
+use lettre::{
+    message::Mailbox, transport::smtp::authentication::Credentials, FileTransport, SmtpTransport,
+    Transport,
+};
+
+use crate::error::TodosError;
+
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+pub trait Mailer: Send + Sync {
+    fn send(&self, email: &Email) -> Result<(), TodosError>;
+}
+
+fn build(from: &Mailbox, email: &Email) -> Result<lettre::Message, TodosError> {
+    lettre::Message::builder()
+        .from(from.clone())
+        .to(email.to.parse().map_err(|_| TodosError::MailError)?)
+        .subject(email.subject.as_str())
+        .body(email.body.clone())
+        .map_err(|_| TodosError::MailError)
+}
+
+/// Writes every message as an `.eml` file into a directory.
+pub struct OutboxMailer {
+    from: Mailbox,
+    transport: FileTransport,
+}
+
+impl OutboxMailer {
+    pub fn new(from: Mailbox, dir: &str) -> std::io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        Ok(Self {
+            from,
+            transport: FileTransport::new(dir),
+        })
+    }
+}
+
+impl Mailer for OutboxMailer {
+    fn send(&self, email: &Email) -> Result<(), TodosError> {
+        self.transport
+            .send(&build(&self.from, email)?)
+            .map(|_| ())
+            .map_err(|_| TodosError::MailError)
+    }
+}
+
+/// Relays messages to an SMTP server. The connection is unencrypted, so the
+/// server should be a local relay or mail catcher.
+pub struct SmtpMailer {
+    from: Mailbox,
+    transport: SmtpTransport,
+}
+
+impl SmtpMailer {
+    pub fn new(from: Mailbox, host: &str, port: u16, credentials: Option<Credentials>) -> Self {
+        let mut builder = SmtpTransport::builder_dangerous(host).port(port);
+        if let Some(credentials) = credentials {
+            builder = builder.credentials(credentials);
+        }
+        Self {
+            from,
+            transport: builder.build(),
+        }
+    }
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, email: &Email) -> Result<(), TodosError> {
+        self.transport
+            .send(&build(&self.from, email)?)
+            .map(|_| ())
+            .map_err(|_| TodosError::MailError)
+    }
+}
+
+/// Sets up the mailer described by `MAIL_FROM`, `SMTP_HOST`, `SMTP_PORT`,
+/// `SMTP_USERNAME`, `SMTP_PASSWORD` and `MAIL_OUTBOX_DIR`.
+pub fn from_env() -> Arc<dyn Mailer> {
+    let from = std::env::var("MAIL_FROM")
+        .unwrap_or_else(|_| "todos@localhost".to_owned())
+        .parse()
+        .expect("MAIL_FROM");
+    match std::env::var("SMTP_HOST")
+        .ok()
+        .filter(|host| !host.is_empty())
+    {
+        Some(host) => {
+            let port = std::env::var("SMTP_PORT")
+                .ok()
+                .filter(|port| !port.is_empty())
+                .map(|port| port.parse().expect("SMTP_PORT"))
+                .unwrap_or(25);
+            let credentials = std::env::var("SMTP_USERNAME")
+                .ok()
+                .filter(|username| !username.is_empty())
+                .map(|username| {
+                    Credentials::new(username, std::env::var("SMTP_PASSWORD").unwrap_or_default())
+                });
+            Arc::new(SmtpMailer::new(from, &host, port, credentials))
+        }
+        None => {
+            let dir = std::env::var("MAIL_OUTBOX_DIR").unwrap_or_else(|_| "outbox".to_owned());
+            Arc::new(OutboxMailer::new(from, &dir).expect("Failed to create the mail outbox."))
+        }
+    }
+}
@@ -1,287 +1,4118 @@
-use actix_web::{delete, get, patch, post, web, App, Error, HttpResponse, HttpServer};
+use actix_web::{
+    delete, get, http::StatusCode, patch, post, put, web, App, Error, HttpMessage, HttpRequest,
+    HttpResponse, HttpServer,
+};
 use diesel::{
     r2d2::{self, ConnectionManager},
     PgConnection,
 };
+use futures::{Stream, StreamExt};
+use juniper::http::{graphiql::graphiql_source, GraphQLRequest};
 use todos::{
     actions::{
-        create_new_todo, delete_existing_todo, get_all_todos, login_user, register_user,
-        update_existing_todo,
+        accept_workspace_invitation, change_password, change_username, complete_oidc_login,
+        complete_two_factor_login, confirm_totp, create_import_job, create_new_todo, create_role,
+        create_workspace, delete_existing_todo, delete_oauth_client, delete_role, delete_workspace,
+        ensure_still_signed_in, exchange_oauth_token, export_account, fail_interrupted_import_jobs,
+        finish_oauth_authorization, force_password_reset, get_all_todos, get_import_job,
+        get_profile, get_public_profile, get_todos_by_calendar_token, get_user_for_admin,
+        get_workspace, import_account, import_file, import_todotxt, import_vtodos,
+        introspect_oauth_token, invite_to_workspace, list_oauth_clients, list_roles, list_sessions,
+        list_workspace_invitations, list_workspace_members, list_workspaces, log_out, login_user,
+        pending_totp_uri, purge_deleted_accounts, purge_expired_sessions, purge_login_throttles,
+        purge_password_reset_requests, regenerate_caldav_password, regenerate_calendar_token,
+        register_oauth_client, register_user, remove_workspace_member, request_password_reset,
+        resend_email_verification, reset_password, restore_account, revoke_caldav_password,
+        revoke_oauth_token, revoke_session, revoke_user_sessions, run_import_job,
+        schedule_account_deletion, search_audit_events, search_users, security_log,
+        set_user_disabled, set_user_role, start_oauth_authorization, start_oidc_login,
+        start_totp_enrollment, take_oidc_login_state, update_existing_todo, update_profile,
+        update_role, update_workspace_member, user_stats, verify_email,
+    },
+    archive::{self, AccountArchive, ArchivedProfile, ArchivedTodo},
+    audit::{AuditEventKind, RequestInfo},
+    auth::{
+        authorize, basic_credentials, cleared_session_cookies, client_ip, create_session,
+        oidc_state_cookie, resolve_credential, session_cookies, AuthUser, Authorized, LoginBody,
+        RegisterResponse, TodoIsOfUser, VerifiedUser, OIDC_STATE_COOKIE,
     },
-    auth::{AuthUser, LoginBody, TodoIsOfUser},
+    caldav, csv_io,
     error::TodosError,
-    models::{self, UpdateTodo},
-    DbPool,
+    events::{Broadcaster, TodoEvent, TodoEventKind},
+    graphql::{self, Schema, SubscriptionSchema},
+    grpc, ical,
+    keys::{self, Jwk, JwkSet},
+    mail::{self, Mailer},
+    markdown,
+    models::{
+        self, AcceptInvitationBody, AccountDeletion, AccountExportOptions, AccountImportOptions,
+        AccountImportSummary, AdminUser, ArchiveFormat, AuditEvent, AuditEventPage,
+        AuditEventQuery, AuthorizeOutcome, AuthorizeRequest, CalDavPassword, CalendarSubscription,
+        ChangePasswordBody, ChangeUsernameBody, ConflictStrategy, ConsentForm, DeleteAccountBody,
+        ExportOptions, FileFormat, FileImportOptions, ForcedPasswordReset, GraphQLStreamQuery,
+        IdMapping, ImportJob, ImportOptions, ImportProvider, ImportSummary, IntrospectionResponse,
+        InvitationInfo, JwtUser, LineError, LoginMode, LoginModeQuery, LoginOutcome, MemberInfo,
+        NewInvitationBody, NewOAuthClientBody, NewRoleBody, NewTodoReq, NewUser, NewWorkspaceBody,
+        OAuthClientInfo, OidcCallbackQuery, PasswordResetBody, PasswordResetRequest, Preferences,
+        Profile, PublicProfile, QrFormat, QrOptions, RecoveryCodes, Role, SecurityLogQuery,
+        SessionInfo, SessionUser, SetRoleBody, Tenant, Todo, TodoSort, TodoTxtImport, TokenForm,
+        TokenRequest, TokenResponse, TotpCodeBody, TotpEnrollment, TwoFactorChallenge,
+        TwoFactorLoginBody, UpdateMemberBody, UpdatePreferences, UpdateProfileBody, UpdateRoleBody,
+        UpdateTodo, UserPage, UserSearchQuery, UserStats, VerifyEmailBody, WeekStart,
+        WorkspaceInfo, WorkspaceRole,
+    },
+    oauth::{self, OAuthError, OAuthErrorCode},
+    oidc,
+    openapi::{
+        GraphQLRequestBody, ImportErrorResponse, MessageResponse, SecurityAddon, SWAGGER_UI_ASSETS,
+        SWAGGER_UI_HTML,
+    },
+    roles::{self, Permission},
+    todotxt::{self, Task},
+    totp, DbPool,
 };
+use utoipa::OpenApi;
 
+#[utoipa::path(
+    get,
+    path = "/todos",
+    tag = "todos",
+    params(("X-Workspace" = Option<String>, Header, description = "The id or slug of a workspace to use instead of the personal todos.")),
+    responses(
+        (status = 200, description = "All todos of the current user or workspace.", body = [Todo]),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 404, description = "The workspace does not exist or you are not a member.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []), ("oauth2" = ["todos:read"]))
+)]
 #[get("/todos")]
-async fn get_todos(pool: web::Data<DbPool>, user: AuthUser) -> Result<HttpResponse, Error> {
-    let conn = pool.get().expect("Could not get db conn from pool.");
-    let result = web::block(move || get_all_todos(user.id, &conn)).await;
+async fn get_todos(pool: web::Data<DbPool>, tenant: Tenant) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        get_all_todos(tenant, &conn)
+    })
+    .await;
 
     match result {
         Err(e) => match e.into() {
-            TodosError::DieselCrudError => {
-                return Err(HttpResponse::InternalServerError()
-                    .json(serde_json::json!({
-                        "message": "Something went wrong while fetching the todo."
-                    }))
-                    .into())
-            }
-            TodosError::TodoNotFoundError => {
-                return Err(HttpResponse::NotFound()
-                    .json(serde_json::json!({
-                        "message": "The todo that you were trying to find does not exist."
-                    }))
-                    .into())
-            }
-            _ => unreachable!(),
+            TodosError::DieselCrudError => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while fetching the todo."
+                }))
+                .into()),
+            TodosError::TodoNotFoundError => Err(HttpResponse::NotFound()
+                .json(serde_json::json!({
+                    "message": "The todo that you were trying to find does not exist."
+                }))
+                .into()),
+            _ => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while fetching the todos."
+                }))
+                .into()),
         },
         Ok(todos) => Ok(HttpResponse::Ok().json(todos)),
     }
 }
 
+#[utoipa::path(
+    post,
+    path = "/todos",
+    tag = "todos",
+    params(("X-Workspace" = Option<String>, Header, description = "The id or slug of a workspace to use instead of the personal todos.")),
+    request_body = NewTodoReq,
+    responses(
+        (status = 201, description = "The created todo.", body = Todo),
+        (status = 400, description = "The priority is not a single uppercase letter.", body = MessageResponse),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []), ("oauth2" = ["todos:write"]))
+)]
 #[post("/todos")]
 async fn add_todo(
     pool: web::Data<DbPool>,
+    broadcaster: web::Data<Broadcaster>,
     body: web::Json<models::NewTodoReq>,
-    user: AuthUser,
+    tenant: Tenant,
 ) -> Result<HttpResponse, Error> {
-    let conn = pool.get().expect("Could not get db conn from pool.");
-    let result = web::block(move || create_new_todo(user.id, body.into_inner(), &conn)).await;
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        create_new_todo(tenant, body.into_inner(), &conn)
+    })
+    .await;
 
     match result {
         Err(e) => match e.into() {
-            TodosError::DieselCrudError => {
-                return Err(HttpResponse::InternalServerError()
-                    .json(serde_json::json!({
-                        "message": "Something went wrong while fetching the todo."
-                    }))
-                    .into())
-            }
-            TodosError::TodoNotFoundError => {
-                return Err(HttpResponse::NotFound()
-                    .json(serde_json::json!({
-                        "message": "The todo that you were trying to find does not exist."
-                    }))
-                    .into())
-            }
-            _ => unreachable!(),
+            TodosError::DieselCrudError => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while fetching the todo."
+                }))
+                .into()),
+            TodosError::TodoNotFoundError => Err(HttpResponse::NotFound()
+                .json(serde_json::json!({
+                    "message": "The todo that you were trying to find does not exist."
+                }))
+                .into()),
+            TodosError::InvalidPriority => Err(HttpResponse::BadRequest()
+                .json(serde_json::json!({
+                    "message": "The priority must be a single uppercase letter."
+                }))
+                .into()),
+            _ => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while creating the todo."
+                }))
+                .into()),
         },
-        Ok(todo) => Ok(HttpResponse::Created().json(todo)),
+        Ok(todo) => {
+            broadcaster.publish(TodoEventKind::Created, &todo);
+            Ok(HttpResponse::Created().json(todo))
+        }
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/todos/{todo_id}",
+    tag = "todos",
+    params(
+        ("todo_id" = i32, Path, description = "Id of the todo."),
+        ("X-Workspace" = Option<String>, Header, description = "The id or slug of a workspace to use instead of the personal todos."),
+    ),
+    responses(
+        (status = 200, description = "The todo.", body = Todo),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 404, description = "The todo or workspace does not exist, or is not yours.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []), ("oauth2" = ["todos:read"]))
+)]
 #[get("/todos/{todo_id}")]
 async fn get_todo(todo_result: TodoIsOfUser) -> Result<HttpResponse, Error> {
     match todo_result.result {
-        Err(e) => match e.into() {
-            TodosError::DieselCrudError => {
-                return Err(HttpResponse::InternalServerError()
-                    .json(serde_json::json!({
-                        "message": "Something went wrong while fetching the todo."
-                    }))
-                    .into())
-            }
-            TodosError::TodoNotFoundError => {
-                return Err(HttpResponse::NotFound()
-                    .json(serde_json::json!({
-                        "message": "The todo that you were trying to find does not exist."
-                    }))
-                    .into());
-            }
-            _ => unreachable!(),
+        Err(e) => match e {
+            TodosError::DieselCrudError => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while fetching the todo."
+                }))
+                .into()),
+            TodosError::TodoNotFoundError => Err(HttpResponse::NotFound()
+                .json(serde_json::json!({
+                    "message": "The todo that you were trying to find does not exist."
+                }))
+                .into()),
+            _ => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while fetching the todo."
+                }))
+                .into()),
         },
         Ok(todo) => Ok(HttpResponse::Ok().json(todo)),
     }
 }
 
+#[utoipa::path(
+    patch,
+    path = "/todos/{todo_id}",
+    tag = "todos",
+    params(
+        ("todo_id" = i32, Path, description = "Id of the todo."),
+        ("X-Workspace" = Option<String>, Header, description = "The id or slug of a workspace to use instead of the personal todos."),
+    ),
+    request_body = UpdateTodo,
+    responses(
+        (status = 200, description = "The updated todo.", body = Todo),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 404, description = "The todo or workspace does not exist, or is not yours.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []), ("oauth2" = ["todos:write"]))
+)]
 #[patch("/todos/{todo_id}")]
 async fn update_todo(
     pool: web::Data<DbPool>,
+    broadcaster: web::Data<Broadcaster>,
     body: web::Json<UpdateTodo>,
     todo_result: TodoIsOfUser,
 ) -> Result<HttpResponse, Error> {
-    let conn = pool.get().expect("Could not get db conn from pool.");
     let todo = match todo_result.result {
-        Err(e) => match e.into() {
-            TodosError::DieselCrudError => {
-                return Err(HttpResponse::InternalServerError()
-                    .json(serde_json::json!({
-                        "message": "Something went wrong while fetching the todo."
-                    }))
-                    .into())
-            }
-            TodosError::TodoNotFoundError => {
-                return Err(HttpResponse::NotFound()
-                    .json(serde_json::json!({
-                        "message": "The todo that you were trying to find does not exist."
-                    }))
-                    .into());
-            }
-            _ => unreachable!(),
+        Err(e) => match e {
+            TodosError::DieselCrudError => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while fetching the todo."
+                }))
+                .into()),
+            TodosError::TodoNotFoundError => Err(HttpResponse::NotFound()
+                .json(serde_json::json!({
+                    "message": "The todo that you were trying to find does not exist."
+                }))
+                .into()),
+            _ => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while fetching the todo."
+                }))
+                .into()),
         },
         Ok(todo) => Ok::<models::Todo, Error>(todo),
     }?;
-    let result = web::block(move || update_existing_todo(todo, body.into_inner(), &conn)).await;
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        update_existing_todo(todo, body.into_inner(), &conn)
+    })
+    .await;
     match result {
         Err(e) => match e.into() {
-            TodosError::DieselCrudError => {
-                return Err(HttpResponse::InternalServerError()
-                    .json(serde_json::json!({
-                        "message": "Something went wrong while fetching the todo."
-                    }))
-                    .into())
-            }
-            TodosError::TodoNotFoundError => {
-                return Err(HttpResponse::NotFound()
-                    .json(serde_json::json!({
-                        "message": "The todo that you were trying to find does not exist."
-                    }))
-                    .into());
-            }
-            _ => unreachable!(),
+            TodosError::DieselCrudError => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while fetching the todo."
+                }))
+                .into()),
+            TodosError::TodoNotFoundError => Err(HttpResponse::NotFound()
+                .json(serde_json::json!({
+                    "message": "The todo that you were trying to find does not exist."
+                }))
+                .into()),
+            _ => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while updating the todo."
+                }))
+                .into()),
         },
-        Ok(todo) => Ok(HttpResponse::Ok().json(todo)),
+        Ok(todo) => {
+            broadcaster.publish(TodoEventKind::Updated, &todo);
+            Ok(HttpResponse::Ok().json(todo))
+        }
     }
 }
 
+#[utoipa::path(
+    delete,
+    path = "/todos/{todo_id}",
+    tag = "todos",
+    params(
+        ("todo_id" = i32, Path, description = "Id of the todo."),
+        ("X-Workspace" = Option<String>, Header, description = "The id or slug of a workspace to use instead of the personal todos."),
+    ),
+    responses(
+        (status = 200, description = "The deleted todo.", body = Todo),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 404, description = "The todo or workspace does not exist, or is not yours.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []), ("oauth2" = ["todos:write"]))
+)]
 #[delete("/todos/{todo_id}")]
 async fn delete_todo(
     pool: web::Data<DbPool>,
+    broadcaster: web::Data<Broadcaster>,
     todo_result: TodoIsOfUser,
 ) -> Result<HttpResponse, Error> {
-    let conn = pool.get().expect("Could not get db conn from pool.");
-
     let todo = match todo_result.result {
-        Err(e) => match e.into() {
-            TodosError::DieselCrudError => {
-                return Err(HttpResponse::InternalServerError()
-                    .json(serde_json::json!({
-                        "message": "Something went wrong while fetching the todo."
-                    }))
-                    .into())
-            }
-            TodosError::TodoNotFoundError => {
-                return Err(HttpResponse::NotFound()
-                    .json(serde_json::json!({
-                        "message": "The todo that you were trying to find does not exist."
-                    }))
-                    .into());
-            }
-            _ => unreachable!(),
+        Err(e) => match e {
+            TodosError::DieselCrudError => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while fetching the todo."
+                }))
+                .into()),
+            TodosError::TodoNotFoundError => Err(HttpResponse::NotFound()
+                .json(serde_json::json!({
+                    "message": "The todo that you were trying to find does not exist."
+                }))
+                .into()),
+            _ => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while fetching the todo."
+                }))
+                .into()),
         },
         Ok(todo) => Ok::<models::Todo, Error>(todo),
     }?;
 
-    let result = web::block(move || delete_existing_todo(todo, &conn)).await;
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        delete_existing_todo(todo, &conn)
+    })
+    .await;
 
     match result {
         Err(e) => match e.into() {
-            TodosError::DieselCrudError => {
-                return Err(HttpResponse::InternalServerError()
-                    .json(serde_json::json!({
-                        "message": "Something went wrong while fetching the todo."
-                    }))
-                    .into())
-            }
-            TodosError::TodoNotFoundError => {
-                return Err(HttpResponse::NotFound()
-                    .json(serde_json::json!({
-                        "message": "The todo that you were trying to find does not exist."
-                    }))
-                    .into())
-            }
-            _ => unreachable!(),
+            TodosError::DieselCrudError => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while fetching the todo."
+                }))
+                .into()),
+            TodosError::TodoNotFoundError => Err(HttpResponse::NotFound()
+                .json(serde_json::json!({
+                    "message": "The todo that you were trying to find does not exist."
+                }))
+                .into()),
+            _ => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while deleting the todo."
+                }))
+                .into()),
         },
-        Ok(todo) => Ok(HttpResponse::Ok().json(todo)),
+        Ok(todo) => {
+            broadcaster.publish(TodoEventKind::Deleted, &todo);
+            Ok(HttpResponse::Ok().json(todo))
+        }
     }
 }
 
+#[utoipa::path(
+    post,
+    path = "/users",
+    tag = "users",
+    request_body = NewUser,
+    responses(
+        (status = 200, description = "The registered user and a token. A verification token was mailed to the email address, if there is one.", body = RegisterResponse),
+        (status = 400, description = "The email address is invalid, or missing while `REQUIRE_EMAIL` is set.", body = MessageResponse),
+        (status = 409, description = "Another account has the username, in some case.", body = MessageResponse),
+        (status = 500, description = "Registration failed.", body = MessageResponse),
+    )
+)]
 #[post("/users")]
 async fn register(
     pool: web::Data<DbPool>,
+    mailer: web::Data<dyn Mailer>,
     body: web::Json<models::NewUser>,
+    request: RequestInfo,
 ) -> Result<HttpResponse, Error> {
-    let conn = pool.get().expect("Could not get db conn from pool.");
-    let result = web::block(move || register_user(body.into_inner(), &conn)).await;
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        register_user(body.into_inner(), &request, &**mailer, &conn)
+    })
+    .await;
 
     match result {
-        Err(e) => {
-            match e.into() {
-                TodosError::DieselCrudError => return Err(HttpResponse::InternalServerError()
-                    .json(serde_json::json!({
-                        "message": "Something went wrong while registering. Please try again later."
-                    }))
-                    .into()),
-                TodosError::JwtTokenCreationError => {
-                    return Err(HttpResponse::InternalServerError()
-                        .json(serde_json::json!({
-                            "message": "Something went wrong!"
-                        }))
-                        .into())
-                }
-                _ => unreachable!(),
-            }
-        }
+        Err(e) => match e.into() {
+            TodosError::DieselCrudError => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while registering. Please try again later."
+                }))
+                .into()),
+            TodosError::JwtTokenCreationError => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong!"
+                }))
+                .into()),
+            TodosError::InvalidEmail => Err(HttpResponse::BadRequest()
+                .json(serde_json::json!({
+                    "message": "The email address is invalid."
+                }))
+                .into()),
+            TodosError::EmailRequired => Err(HttpResponse::BadRequest()
+                .json(serde_json::json!({
+                    "message": "An email address is required."
+                }))
+                .into()),
+            TodosError::UsernameTaken => Err(HttpResponse::Conflict()
+                .json(serde_json::json!({
+                    "message": "The username is taken."
+                }))
+                .into()),
+            TodosError::MailError => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while sending the verification email."
+                }))
+                .into()),
+            _ => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while registering. Please try again later."
+                }))
+                .into()),
+        },
         Ok(user) => Ok(HttpResponse::Ok().json(user)),
     }
 }
 
+#[utoipa::path(
+    post,
+    path = "/login",
+    tag = "users",
+    params(LoginModeQuery),
+    request_body = LoginBody,
+    responses(
+        (status = 200, description = "The user and a fresh token. `username` may also be the email address. In cookie mode, the token is in the `todos_session` cookie and the body is a `SessionUser` instead.", body = JwtUser),
+        (status = 202, description = "The password is right, but the account needs a TOTP or recovery code at `POST /login/2fa`.", body = TwoFactorChallenge),
+        (status = 403, description = "The account is scheduled for deletion or was disabled by an administrator, or its password has to be reset.", body = MessageResponse),
+        (status = 404, description = "Bad credentials.", body = MessageResponse),
+        (status = 429, description = "Too many failed logins for the account or from this address.", body = MessageResponse,
+            headers(("Retry-After" = i64, description = "Seconds until the next try."))),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    )
+)]
 #[post("/login")]
-async fn login(pool: web::Data<DbPool>, body: web::Json<LoginBody>) -> Result<HttpResponse, Error> {
-    let conn = pool.get().expect("Could not get db conn from pool.");
-    let result = web::block(move || login_user(body.into_inner(), &conn)).await;
+async fn login(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    query: web::Query<LoginModeQuery>,
+    body: web::Json<LoginBody>,
+    mailer: web::Data<dyn Mailer>,
+) -> Result<HttpResponse, Error> {
+    let request = RequestInfo::of(&req);
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        login_user(body.into_inner(), &request, &**mailer, &conn)
+    })
+    .await;
     match result {
         Err(e) => match e.into() {
-            TodosError::DieselCrudError => {
-                return Err(HttpResponse::InternalServerError()
+            TodosError::DieselCrudError => Err(HttpResponse::InternalServerError()
                     .json(serde_json::json!({
                         "message": "Something went wrong while performing DB operations."
                     }))
-                    .into())
-            }
-            TodosError::BadCreds => {
-                return Err(HttpResponse::NotFound()
+                    .into()),
+            TodosError::BadCreds => Err(HttpResponse::NotFound()
                     .json(serde_json::json!({
                         "message": "Bad credentials"
                     }))
-                    .into());
-            }
-            TodosError::JwtTokenCreationError => {
-                return Err(HttpResponse::InternalServerError()
+                    .into()),
+            TodosError::JwtTokenCreationError => Err(HttpResponse::InternalServerError()
                     .json(serde_json::json!({
                         "message": "Something went wrong while creating the token."
                     }))
-                    .into())
+                    .into()),
+            TodosError::AccountDisabled => Err(HttpResponse::Forbidden()
+                .json(serde_json::json!({
+                    "message": "The account is scheduled for deletion; restore it with POST /users/restore."
+                }))
+                .into()),
+            TodosError::AccountSuspended => Err(HttpResponse::Forbidden()
+                .json(serde_json::json!({
+                    "message": "The account was disabled by an administrator."
+                }))
+                .into()),
+            TodosError::PasswordResetRequired => Err(HttpResponse::Forbidden()
+                .json(serde_json::json!({
+                    "message": "An administrator asked you to choose a new password; reset it with POST /users/password-reset."
+                }))
+                .into()),
+            TodosError::TooManyLoginAttempts(seconds) => Err(too_many_login_attempts(seconds)),
+            TodosError::UnknownPepper(id) => {
+                log::error!(
+                    "A password hash uses pepper {}, which isn't configured; set ARGON2_PEPPER and ARGON2_PEPPER_ID.",
+                    id
+                );
+                Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "The password can't be checked until the server is configured with the pepper it was hashed with."
+                }))
+                .into())
             }
-            _ => unreachable!(),
+            _ => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while logging in."
+                }))
+                .into()),
+        },
+        Ok(outcome) => login_response(outcome, query.mode.unwrap_or(LoginMode::Token)),
+    }
+}
+
+fn login_response(outcome: LoginOutcome, mode: LoginMode) -> Result<HttpResponse, Error> {
+    match outcome {
+        LoginOutcome::LoggedIn(jwt_user) => logged_in_response(jwt_user, mode),
+        LoginOutcome::TwoFactorRequired(challenge) => Ok(HttpResponse::Accepted().json(challenge)),
+    }
+}
+
+/// Hands over the token in the body or, in cookie mode, in the session
+/// cookie.
+fn logged_in_response(jwt_user: JwtUser, mode: LoginMode) -> Result<HttpResponse, Error> {
+    match mode {
+        LoginMode::Token => Ok(HttpResponse::Ok().json(jwt_user)),
+        LoginMode::Cookie => {
+            let (token, csrf_token) = authorize(&jwt_user.token)
+                .and_then(|claims| {
+                    create_session(jwt_user.id, jwt_user.username.clone(), claims.sid)
+                })
+                .map_err(|_| {
+                    HttpResponse::InternalServerError().json(serde_json::json!({
+                        "message": "Something went wrong while creating the token."
+                    }))
+                })?;
+            let [session_cookie, csrf_cookie] = session_cookies(token, csrf_token.clone());
+            Ok(HttpResponse::Ok()
+                .cookie(session_cookie)
+                .cookie(csrf_cookie)
+                .json(SessionUser {
+                    username: jwt_user.username,
+                    id: jwt_user.id,
+                    csrf_token,
+                }))
+        }
+    }
+}
+
+fn too_many_login_attempts(seconds: i64) -> Error {
+    HttpResponse::TooManyRequests()
+        .header("Retry-After", seconds.to_string())
+        .json(serde_json::json!({
+            "message": "Too many failed logins; try again later."
+        }))
+        .into()
+}
+
+#[utoipa::path(
+    get,
+    path = "/.well-known/jwks.json",
+    tag = "users",
+    responses(
+        (status = 200, description = "The public keys tokens are signed with, by `kid`. Empty while tokens are signed with `JWT_SECRET`.", body = JwkSet),
+    )
+)]
+#[get("/.well-known/jwks.json")]
+async fn jwks() -> HttpResponse {
+    HttpResponse::Ok().json(keys::keyring().jwks())
+}
+
+#[utoipa::path(
+    post,
+    path = "/logout",
+    tag = "users",
+    responses(
+        (status = 204, description = "The session of the token sent along, in the session cookie or the `Authorization` header, ends, and the session cookies are cleared."),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    )
+)]
+#[post("/logout")]
+async fn logout(req: HttpRequest, pool: web::Data<DbPool>) -> Result<HttpResponse, Error> {
+    if let Ok(Some(credential)) = resolve_credential(&req) {
+        web::block(move || {
+            let conn = pool.get()?;
+            log_out(&credential.token, &conn)
+        })
+        .await
+        .map_err(|_| {
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "message": "Something went wrong while logging out."
+            }))
+        })?;
+    }
+    let mut response = HttpResponse::NoContent();
+    for cookie in cleared_session_cookies().iter() {
+        response.del_cookie(cookie);
+    }
+    Ok(response.finish())
+}
+
+#[utoipa::path(
+    post,
+    path = "/login/2fa",
+    tag = "users",
+    params(LoginModeQuery),
+    request_body = TwoFactorLoginBody,
+    responses(
+        (status = 200, description = "The user and a fresh token. In cookie mode, the token is in the `todos_session` cookie and the body is a `SessionUser` instead.", body = JwtUser),
+        (status = 401, description = "The challenge token is invalid or expired; log in again.", body = MessageResponse),
+        (status = 403, description = "The code is wrong or was already used.", body = MessageResponse),
+        (status = 429, description = "Too many failed logins for the account or from this address.", body = MessageResponse,
+            headers(("Retry-After" = i64, description = "Seconds until the next try."))),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    )
+)]
+#[post("/login/2fa")]
+async fn login_two_factor(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    query: web::Query<LoginModeQuery>,
+    body: web::Json<TwoFactorLoginBody>,
+    mailer: web::Data<dyn Mailer>,
+) -> Result<HttpResponse, Error> {
+    let request = RequestInfo::of(&req);
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        complete_two_factor_login(body.into_inner(), &request, &**mailer, &conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => match e.into() {
+            TodosError::InvalidChallenge => Err(HttpResponse::Unauthorized()
+                .json(serde_json::json!({
+                    "message": "The challenge token is invalid or expired."
+                }))
+                .into()),
+            TodosError::InvalidTotpCode => Err(HttpResponse::Forbidden()
+                .json(serde_json::json!({
+                    "message": "The code is wrong or was already used."
+                }))
+                .into()),
+            TodosError::TooManyLoginAttempts(seconds) => Err(too_many_login_attempts(seconds)),
+            _ => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while logging in."
+                }))
+                .into()),
+        },
+        Ok(jwt_user) => logged_in_response(jwt_user, query.mode.unwrap_or(LoginMode::Token)),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/users/me/totp",
+    tag = "users",
+    responses(
+        (status = 200, description = "A new TOTP secret. It is required at login once a code is confirmed at `POST /users/me/totp/confirm`.", body = TotpEnrollment),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 409, description = "Two-factor authentication is already enabled.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[post("/users/me/totp")]
+async fn enroll_totp(pool: web::Data<DbPool>, user: AuthUser) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        start_totp_enrollment(user.id, &conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => match e.into() {
+            TodosError::TotpAlreadyEnabled => Err(HttpResponse::Conflict()
+                .json(serde_json::json!({
+                    "message": "Two-factor authentication is already enabled."
+                }))
+                .into()),
+            _ => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while creating the TOTP secret."
+                }))
+                .into()),
+        },
+        Ok(enrollment) => Ok(HttpResponse::Ok().json(enrollment)),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/users/me/totp/qr",
+    tag = "users",
+    params(QrOptions),
+    responses(
+        (status = 200, description = "The pending enrollment's `otpauth://` URI as a QR code.", content_type = "image/svg+xml"),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 404, description = "There is no pending enrollment.", body = MessageResponse),
+        (status = 409, description = "Two-factor authentication is already enabled.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[get("/users/me/totp/qr")]
+async fn totp_qr_code(
+    pool: web::Data<DbPool>,
+    query: web::Query<QrOptions>,
+    user: AuthUser,
+) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        pending_totp_uri(user.id, &conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => match e.into() {
+            TodosError::TotpNotEnrolled => Err(HttpResponse::NotFound()
+                .json(serde_json::json!({
+                    "message": "Start an enrollment with POST /users/me/totp first."
+                }))
+                .into()),
+            TodosError::TotpAlreadyEnabled => Err(HttpResponse::Conflict()
+                .json(serde_json::json!({
+                    "message": "Two-factor authentication is already enabled."
+                }))
+                .into()),
+            _ => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while creating the QR code."
+                }))
+                .into()),
+        },
+        Ok(uri) => Ok(match query.format.unwrap_or(QrFormat::Svg) {
+            QrFormat::Svg => HttpResponse::Ok()
+                .content_type("image/svg+xml")
+                .body(totp::qr_svg(&uri)),
+            QrFormat::Png => HttpResponse::Ok()
+                .content_type("image/png")
+                .body(totp::qr_png(&uri)),
+        }),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/users/me/totp/confirm",
+    tag = "users",
+    request_body = TotpCodeBody,
+    responses(
+        (status = 200, description = "Two-factor authentication is enabled; the recovery codes are only shown this once.", body = RecoveryCodes),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 403, description = "The code is wrong.", body = MessageResponse),
+        (status = 404, description = "There is no pending enrollment.", body = MessageResponse),
+        (status = 409, description = "Two-factor authentication is already enabled.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[post("/users/me/totp/confirm")]
+async fn confirm_totp_enrollment(
+    pool: web::Data<DbPool>,
+    body: web::Json<TotpCodeBody>,
+    user: AuthUser,
+    request: RequestInfo,
+) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        confirm_totp(user.id, &body.code, &request, &conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => match e.into() {
+            TodosError::InvalidTotpCode => Err(HttpResponse::Forbidden()
+                .json(serde_json::json!({
+                    "message": "The code is wrong."
+                }))
+                .into()),
+            TodosError::TotpNotEnrolled => Err(HttpResponse::NotFound()
+                .json(serde_json::json!({
+                    "message": "Start an enrollment with POST /users/me/totp first."
+                }))
+                .into()),
+            TodosError::TotpAlreadyEnabled => Err(HttpResponse::Conflict()
+                .json(serde_json::json!({
+                    "message": "Two-factor authentication is already enabled."
+                }))
+                .into()),
+            _ => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while enabling two-factor authentication."
+                }))
+                .into()),
+        },
+        Ok(recovery_codes) => Ok(HttpResponse::Ok().json(recovery_codes)),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/users/me",
+    tag = "users",
+    responses(
+        (status = 200, description = "The user's account, profile and preferences.", body = Profile),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[get("/users/me")]
+async fn get_me(pool: web::Data<DbPool>, user: AuthUser) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        get_profile(user.id, &conn)
+    })
+    .await;
+
+    match result {
+        Err(_) => Err(HttpResponse::InternalServerError()
+            .json(serde_json::json!({
+                "message": "Something went wrong while reading the profile."
+            }))
+            .into()),
+        Ok(profile) => Ok(HttpResponse::Ok().json(profile)),
+    }
+}
+
+#[utoipa::path(
+    patch,
+    path = "/users/me",
+    tag = "users",
+    request_body = UpdateProfileBody,
+    responses(
+        (status = 200, description = "The profile with the changes.", body = Profile),
+        (status = 400, description = "A field is malformed; the message says which.", body = MessageResponse),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[patch("/users/me")]
+async fn update_me(
+    pool: web::Data<DbPool>,
+    body: web::Json<UpdateProfileBody>,
+    user: AuthUser,
+) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        update_profile(user.id, body.into_inner(), &conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => match e.into() {
+            TodosError::InvalidProfile(reason) => Err(HttpResponse::BadRequest()
+                .json(serde_json::json!({
+                    "message": format!("The profile is invalid: {}.", reason)
+                }))
+                .into()),
+            _ => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while updating the profile."
+                }))
+                .into()),
+        },
+        Ok(profile) => Ok(HttpResponse::Ok().json(profile)),
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/users/me/username",
+    tag = "users",
+    request_body = ChangeUsernameBody,
+    responses(
+        (status = 200, description = "The user was renamed; a fresh token with the new name, for the same session, which is extended to match it.", body = JwtUser),
+        (status = 400, description = "The username is too short, too long or has characters other than letters, digits, dots, underscores and dashes.", body = MessageResponse),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 409, description = "Another account has the username, in some case.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[put("/users/me/username")]
+async fn update_username(
+    pool: web::Data<DbPool>,
+    body: web::Json<ChangeUsernameBody>,
+    user: AuthUser,
+    request: RequestInfo,
+) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        change_username(user.id, user.session_id, &body.username, &request, &conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => match e.into() {
+            TodosError::InvalidUsername => Err(HttpResponse::BadRequest()
+                .json(serde_json::json!({
+                    "message": "Usernames are 3 to 32 letters, digits, dots, underscores and dashes."
+                }))
+                .into()),
+            TodosError::UsernameTaken => Err(HttpResponse::Conflict()
+                .json(serde_json::json!({
+                    "message": "The username is taken."
+                }))
+                .into()),
+            TodosError::TokenRevoked => Err(HttpResponse::Unauthorized()
+                .json(serde_json::json!({
+                    "message": "The session has ended."
+                }))
+                .into()),
+            _ => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while changing the username."
+                }))
+                .into()),
         },
         Ok(jwt_user) => Ok(HttpResponse::Ok().json(jwt_user)),
     }
 }
 
-#[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    dotenv::dotenv().ok();
-    let db_url = std::env::var("DATABASE_URL").expect("DATABASE_URL");
-    let manager = ConnectionManager::<PgConnection>::new(db_url);
-    let pool = r2d2::Pool::builder()
-        .build(manager)
-        .expect("Failed to create pool.");
+#[utoipa::path(
+    get,
+    path = "/profiles/{username}",
+    tag = "users",
+    params(("username" = String, Path, description = "The user's username.")),
+    responses(
+        (status = 200, description = "What other users see of the account.", body = PublicProfile),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 404, description = "No active account has the username.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[get("/profiles/{username}")]
+async fn get_user_profile(
+    pool: web::Data<DbPool>,
+    username: web::Path<String>,
+    _user: AuthUser,
+) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        get_public_profile(&username, &conn)
+    })
+    .await;
 
-    HttpServer::new(move || {
-        App::new()
-            .data(pool.clone())
-            .service(get_todos)
-            .service(get_todo)
-            .service(add_todo)
-            .service(update_todo)
-            .service(delete_todo)
-            .service(register)
-            .service(login)
+    match result {
+        Err(e) => match e.into() {
+            TodosError::AccountNotFound => Err(HttpResponse::NotFound()
+                .json(serde_json::json!({
+                    "message": "The user does not exist."
+                }))
+                .into()),
+            _ => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while reading the profile."
+                }))
+                .into()),
+        },
+        Ok(profile) => Ok(HttpResponse::Ok().json(profile)),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/users/me",
+    tag = "users",
+    request_body = DeleteAccountBody,
+    responses(
+        (status = 202, description = "The account is disabled and will be purged after the grace period.", body = AccountDeletion),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 403, description = "The password is wrong.", body = MessageResponse),
+        (status = 429, description = "Too many wrong passwords, as for `POST /login`.", body = MessageResponse,
+            headers(("Retry-After" = i64, description = "Seconds until the next try."))),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[delete("/users/me")]
+async fn delete_account(
+    pool: web::Data<DbPool>,
+    body: web::Json<DeleteAccountBody>,
+    user: AuthUser,
+    request: RequestInfo,
+) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        schedule_account_deletion(user.id, &body.password, &request, &conn)
     })
-    .bind(("127.0.0.1", 8080))?
-    .run()
-    .await
+    .await;
+
+    match result {
+        Err(e) => match e.into() {
+            TodosError::BadCreds => Err(HttpResponse::Forbidden()
+                .json(serde_json::json!({
+                    "message": "Bad credentials"
+                }))
+                .into()),
+            TodosError::TooManyLoginAttempts(seconds) => Err(too_many_login_attempts(seconds)),
+            _ => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while deleting the account."
+                }))
+                .into()),
+        },
+        Ok(deletion) => Ok(HttpResponse::Accepted().json(deletion)),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/users/restore",
+    tag = "users",
+    params(LoginModeQuery),
+    request_body = LoginBody,
+    responses(
+        (status = 200, description = "The account is active again; the user and a fresh token. In cookie mode, the token is in the `todos_session` cookie and the body is a `SessionUser` instead.", body = JwtUser),
+        (status = 202, description = "The account is active again, but needs a TOTP or recovery code at `POST /login/2fa`.", body = TwoFactorChallenge),
+        (status = 403, description = "The account was disabled by an administrator, or its password has to be reset.", body = MessageResponse),
+        (status = 404, description = "Bad credentials, or the account has already been purged.", body = MessageResponse),
+        (status = 429, description = "Too many failed logins for the account or from this address.", body = MessageResponse,
+            headers(("Retry-After" = i64, description = "Seconds until the next try."))),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    )
+)]
+#[post("/users/restore")]
+async fn restore(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    query: web::Query<LoginModeQuery>,
+    body: web::Json<LoginBody>,
+    mailer: web::Data<dyn Mailer>,
+) -> Result<HttpResponse, Error> {
+    let request = RequestInfo::of(&req);
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        restore_account(body.into_inner(), &request, &**mailer, &conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => match e.into() {
+            TodosError::BadCreds => Err(HttpResponse::NotFound()
+                .json(serde_json::json!({
+                    "message": "Bad credentials"
+                }))
+                .into()),
+            TodosError::AccountSuspended => Err(HttpResponse::Forbidden()
+                .json(serde_json::json!({
+                    "message": "The account was disabled by an administrator."
+                }))
+                .into()),
+            TodosError::PasswordResetRequired => Err(HttpResponse::Forbidden()
+                .json(serde_json::json!({
+                    "message": "An administrator asked you to choose a new password; reset it with POST /users/password-reset."
+                }))
+                .into()),
+            TodosError::TooManyLoginAttempts(seconds) => Err(too_many_login_attempts(seconds)),
+            _ => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while restoring the account."
+                }))
+                .into()),
+        },
+        Ok(outcome) => login_response(outcome, query.mode.unwrap_or(LoginMode::Token)),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/events",
+    tag = "todos",
+    params(
+        ("access_token" = Option<String>, Query, description = "The token, for clients such as `EventSource` that can't send an `Authorization` header."),
+        ("X-Workspace" = Option<String>, Header, description = "The id or slug of a workspace to use instead of the personal todos."),
+    ),
+    responses(
+        (status = 200, description = "A server-sent event stream whose `data` is a `TodoEvent` per change.", content_type = "text/event-stream", body = TodoEvent),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+    ),
+    security(("jwt" = []), ("oauth2" = ["todos:read"]))
+)]
+#[get("/events")]
+async fn todo_events(
+    pool: web::Data<DbPool>,
+    broadcaster: web::Data<Broadcaster>,
+    user: AuthUser,
+    tenant: Tenant,
+) -> HttpResponse {
+    let events = until_signed_out(broadcaster.subscribe(tenant), pool, user).map(|event| {
+        let data = serde_json::to_string(&event).expect("Todo events always serialize.");
+        Ok::<_, Error>(web::Bytes::from(format!("data: {}\n\n", data)))
+    });
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .header("Cache-Control", "no-store")
+        .streaming(Box::pin(events))
+}
+
+/// How often event streams check that they may still be read.
+const STREAM_RECHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// `events` until `user` logs out or has their session or OAuth token
+/// revoked, or the account is disabled, checked every
+/// `STREAM_RECHECK_INTERVAL`. Database failures don't end the stream.
+fn until_signed_out<S: Stream>(
+    events: S,
+    pool: web::Data<DbPool>,
+    user: AuthUser,
+) -> impl Stream<Item = S::Item> {
+    let signed_out = async move {
+        let mut checks = actix_web::rt::time::interval(STREAM_RECHECK_INTERVAL);
+        // The first tick is immediate, and the request was just authenticated.
+        checks.tick().await;
+        loop {
+            checks.tick().await;
+            let (pool, user) = (pool.clone(), user.clone());
+            let result = web::block(move || {
+                let conn = pool.get()?;
+                ensure_still_signed_in(&user, &conn)
+            })
+            .await;
+            match result.map_err(TodosError::from) {
+                Ok(()) | Err(TodosError::DieselCrudError) => {}
+                Err(_) => break,
+            }
+        }
+    };
+    events.take_until(signed_out)
+}
+
+#[utoipa::path(
+    post,
+    path = "/graphql",
+    tag = "graphql",
+    params(("X-Workspace" = Option<String>, Header, description = "The id or slug of a workspace to use instead of the personal todos.")),
+    request_body = GraphQLRequestBody,
+    responses(
+        (status = 200, description = "The GraphQL response."),
+        (status = 400, description = "The GraphQL response of a failed query."),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[post("/graphql")]
+async fn graphql_endpoint(
+    pool: web::Data<DbPool>,
+    schema: web::Data<Schema>,
+    broadcaster: web::Data<Broadcaster>,
+    body: web::Json<GraphQLRequest>,
+    user: AuthUser,
+    tenant: Tenant,
+) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let context = graphql::Context::new(pool.get()?, user, tenant, broadcaster);
+        let response = body.execute(&schema, &context);
+        let json = serde_json::to_string(&response).expect("GraphQL responses always serialize.");
+        Ok::<_, TodosError>((response.is_ok(), json))
+    })
+    .await;
+
+    match result {
+        Err(_) => Err(HttpResponse::InternalServerError()
+            .json(serde_json::json!({
+                "message": "Something went wrong while executing the query."
+            }))
+            .into()),
+        Ok((true, json)) => Ok(HttpResponse::Ok()
+            .content_type("application/json")
+            .body(json)),
+        Ok((false, json)) => Ok(HttpResponse::BadRequest()
+            .content_type("application/json")
+            .body(json)),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/graphql/stream",
+    tag = "graphql",
+    params(
+        GraphQLStreamQuery,
+        ("access_token" = Option<String>, Query, description = "The token, for clients such as `EventSource` that can't send an `Authorization` header."),
+        ("X-Workspace" = Option<String>, Header, description = "The id or slug of a workspace to use instead of the personal todos."),
+    ),
+    responses(
+        (status = 200, description = "A server-sent event stream with a `next` event per change, whose `data` is the GraphQL response for it.", content_type = "text/event-stream"),
+        (status = 400, description = "The document is not a single valid subscription.", body = MessageResponse),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[get("/graphql/stream")]
+async fn graphql_stream(
+    pool: web::Data<DbPool>,
+    schema: web::Data<SubscriptionSchema>,
+    broadcaster: web::Data<Broadcaster>,
+    query: web::Query<GraphQLStreamQuery>,
+    user: AuthUser,
+    tenant: Tenant,
+) -> Result<HttpResponse, Error> {
+    let query = query.into_inner();
+    let document = graphql::subscription_as_query(&query.query).map_err(|message| {
+        HttpResponse::BadRequest().json(serde_json::json!({ "message": message }))
+    })?;
+    let variables = match query
+        .variables
+        .as_deref()
+        .map(serde_json::from_str)
+        .transpose()
+    {
+        Ok(variables) => variables,
+        Err(_) => {
+            return Err(HttpResponse::BadRequest()
+                .json(serde_json::json!({ "message": "The variables are not a JSON object." }))
+                .into())
+        }
+    };
+    let request = std::sync::Arc::new(GraphQLRequest::new(
+        document,
+        query.operation_name,
+        variables,
+    ));
+
+    // Subscribe before validating, so no change made in between is missed.
+    let events = broadcaster.subscribe(tenant);
+    let validation = {
+        let (pool, schema, request, user, broadcaster) = (
+            pool.clone(),
+            schema.clone(),
+            request.clone(),
+            user.clone(),
+            broadcaster.clone(),
+        );
+        web::block(move || {
+            let context = graphql::Context::new(pool.get()?, user, tenant, broadcaster);
+            let response = request.execute(&schema, &context);
+            if response.is_ok() {
+                Ok::<_, TodosError>(None)
+            } else {
+                Ok(Some(
+                    serde_json::to_string(&response).expect("GraphQL responses always serialize."),
+                ))
+            }
+        })
+        .await
+    };
+    match validation {
+        Ok(None) => {}
+        Ok(Some(json)) => {
+            return Ok(HttpResponse::BadRequest()
+                .content_type("application/json")
+                .body(json))
+        }
+        Err(_) => {
+            return Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while executing the query."
+                }))
+                .into())
+        }
+    }
+
+    let stream = until_signed_out(events, pool.clone(), user.clone()).then(move |event| {
+        let (pool, schema, request, user, broadcaster) = (
+            pool.clone(),
+            schema.clone(),
+            request.clone(),
+            user.clone(),
+            broadcaster.clone(),
+        );
+        async move {
+            let result = web::block(move || {
+                let mut context = graphql::Context::new(pool.get()?, user, tenant, broadcaster);
+                context.event = Some(event);
+                let response = request.execute(&schema, &context);
+                Ok::<_, TodosError>(
+                    serde_json::to_string(&response).expect("GraphQL responses always serialize."),
+                )
+            })
+            .await;
+            let data = result.unwrap_or_else(|_| {
+                r#"{"errors":[{"message":"Something went wrong while executing the query."}]}"#
+                    .to_string()
+            });
+            Ok::<_, Error>(web::Bytes::from(format!("event: next\ndata: {}\n\n", data)))
+        }
+    });
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .header("Cache-Control", "no-store")
+        .streaming(Box::pin(stream)))
+}
+
+#[get("/graphiql")]
+async fn graphiql() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(graphiql_source("/graphql"))
+}
+
+#[utoipa::path(
+    get,
+    path = "/todos.ics",
+    tag = "calendar",
+    params(("X-Workspace" = Option<String>, Header, description = "The id or slug of a workspace to use instead of the personal todos.")),
+    responses(
+        (status = 200, description = "An iCalendar with a VTODO per todo of the current user.", content_type = "text/calendar", body = String),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []), ("oauth2" = ["todos:read"]))
+)]
+#[get("/todos.ics")]
+async fn export_ics(pool: web::Data<DbPool>, tenant: Tenant) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        get_all_todos(tenant, &conn)
+    })
+    .await;
+
+    match result {
+        Err(_) => Err(HttpResponse::InternalServerError()
+            .json(serde_json::json!({
+                "message": "Something went wrong while fetching the todos."
+            }))
+            .into()),
+        Ok(todos) => Ok(HttpResponse::Ok()
+            .content_type("text/calendar; charset=utf-8")
+            .body(ical::write_calendar(&todos))),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/users/me/calendar-token",
+    tag = "calendar",
+    responses(
+        (status = 200, description = "A new private feed URL; the previous one stops working.", body = CalendarSubscription),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 403, description = "The account has to verify its email address first.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[post("/users/me/calendar-token")]
+async fn create_calendar_token(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    user: VerifiedUser,
+) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        regenerate_calendar_token(user.id, &conn)
+    })
+    .await;
+
+    match result {
+        Err(_) => Err(HttpResponse::InternalServerError()
+            .json(serde_json::json!({
+                "message": "Something went wrong while creating the calendar token."
+            }))
+            .into()),
+        Ok(token) => {
+            let info = req.connection_info();
+            Ok(HttpResponse::Ok().json(models::CalendarSubscription {
+                url: format!(
+                    "{}://{}/calendars/{}/todos.ics",
+                    info.scheme(),
+                    info.host(),
+                    token
+                ),
+            }))
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/calendars/{token}/todos.ics",
+    tag = "calendar",
+    params(("token" = String, Path, description = "The token from `POST /users/me/calendar-token`.")),
+    responses(
+        (status = 200, description = "The same calendar as `GET /todos.ics`, for clients that cannot send headers.", content_type = "text/calendar", body = String),
+        (status = 404, description = "The token does not exist.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    )
+)]
+#[get("/calendars/{token}/todos.ics")]
+async fn calendar_feed(
+    pool: web::Data<DbPool>,
+    token: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        get_todos_by_calendar_token(&token, &conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => match e.into() {
+            TodosError::CalendarTokenNotFound => Err(HttpResponse::NotFound()
+                .json(serde_json::json!({
+                    "message": "The calendar that you were trying to find does not exist."
+                }))
+                .into()),
+            _ => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while fetching the todos."
+                }))
+                .into()),
+        },
+        Ok(todos) => Ok(HttpResponse::Ok()
+            .content_type("text/calendar; charset=utf-8")
+            .body(ical::write_calendar(&todos))),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/users/me/caldav-password",
+    tag = "calendar",
+    responses(
+        (status = 200, description = "A new CalDAV app password; the previous one stops working.", body = CalDavPassword),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 403, description = "The account has to verify its email address first.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[post("/users/me/caldav-password")]
+async fn create_caldav_password(
+    pool: web::Data<DbPool>,
+    user: VerifiedUser,
+) -> Result<HttpResponse, Error> {
+    let uid = user.id;
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        regenerate_caldav_password(uid, &conn)
+    })
+    .await;
+
+    match result {
+        Err(_) => Err(HttpResponse::InternalServerError()
+            .json(serde_json::json!({
+                "message": "Something went wrong while creating the CalDAV password."
+            }))
+            .into()),
+        Ok(password) => Ok(HttpResponse::Ok().json(models::CalDavPassword {
+            username: user.username,
+            password,
+        })),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/users/me/caldav-password",
+    tag = "calendar",
+    responses(
+        (status = 204, description = "The CalDAV app password no longer works."),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[delete("/users/me/caldav-password")]
+async fn delete_caldav_password(
+    pool: web::Data<DbPool>,
+    user: AuthUser,
+) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        revoke_caldav_password(user.id, &conn)
+    })
+    .await;
+
+    match result {
+        Err(_) => Err(HttpResponse::InternalServerError()
+            .json(serde_json::json!({
+                "message": "Something went wrong while revoking the CalDAV password."
+            }))
+            .into()),
+        Ok(()) => Ok(HttpResponse::NoContent().finish()),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/import/ics",
+    tag = "calendar",
+    params(("X-Workspace" = Option<String>, Header, description = "The id or slug of a workspace to use instead of the personal todos.")),
+    request_body(content = String, description = "An iCalendar stream; only its VTODO components are read.", content_type = "text/calendar"),
+    responses(
+        (status = 201, description = "The todos that were created; VTODOs with an already known UID are skipped.", body = ImportSummary),
+        (status = 400, description = "The body is not a valid iCalendar stream.", body = MessageResponse),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 403, description = "The account has to verify its email address first.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[post("/import/ics")]
+async fn import_ics(
+    pool: web::Data<DbPool>,
+    broadcaster: web::Data<Broadcaster>,
+    body: String,
+    _user: VerifiedUser,
+    tenant: Tenant,
+) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        let vtodos = ical::parse_vtodos(&body)?;
+        import_vtodos(tenant, vtodos, &conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => match e.into() {
+            TodosError::InvalidCalendar => Err(HttpResponse::BadRequest()
+                .json(serde_json::json!({
+                    "message": "The uploaded file is not a valid iCalendar file."
+                }))
+                .into()),
+            _ => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while importing the todos."
+                }))
+                .into()),
+        },
+        Ok(summary) => {
+            for todo in &summary.created {
+                broadcaster.publish(TodoEventKind::Created, todo);
+            }
+            Ok(HttpResponse::Created().json(summary))
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/export/todotxt",
+    tag = "import/export",
+    params(("X-Workspace" = Option<String>, Header, description = "The id or slug of a workspace to use instead of the personal todos.")),
+    responses(
+        (status = 200, description = "The current user's todos in todo.txt format.", content_type = "text/plain", body = String),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[get("/export/todotxt")]
+async fn export_todotxt(pool: web::Data<DbPool>, tenant: Tenant) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        get_all_todos(tenant, &conn)
+    })
+    .await;
+
+    match result {
+        Err(_) => Err(HttpResponse::InternalServerError()
+            .json(serde_json::json!({
+                "message": "Something went wrong while fetching the todos."
+            }))
+            .into()),
+        Ok(todos) => Ok(HttpResponse::Ok()
+            .content_type("text/plain; charset=utf-8")
+            .body(todotxt::format(&todos))),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/import/todotxt",
+    tag = "import/export",
+    params(ImportOptions, ("X-Workspace" = Option<String>, Header, description = "The id or slug of a workspace to use instead of the personal todos.")),
+    request_body(content = String, description = "A todo.txt file.", content_type = "text/plain"),
+    responses(
+        (status = 200, description = "What would be created (dry runs).", body = TodoTxtImport),
+        (status = 201, description = "The todos that were created.", body = TodoTxtImport),
+        (status = 400, description = "A line of the file could not be read; nothing was created.", body = MessageResponse),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 403, description = "The account has to verify its email address first.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[post("/import/todotxt")]
+async fn import_todotxt_file(
+    pool: web::Data<DbPool>,
+    broadcaster: web::Data<Broadcaster>,
+    options: web::Query<ImportOptions>,
+    body: String,
+    _user: VerifiedUser,
+    tenant: Tenant,
+) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        import_todotxt(tenant, &body, options.dry_run, &conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => match e.into() {
+            TodosError::InvalidTodoTxt(line) => Err(HttpResponse::BadRequest()
+                .json(serde_json::json!({
+                    "message": format!("Line {} of the file has no description.", line),
+                    "line": line
+                }))
+                .into()),
+            TodosError::InvalidPriority => Err(HttpResponse::BadRequest()
+                .json(serde_json::json!({
+                    "message": "The priority must be a single uppercase letter."
+                }))
+                .into()),
+            _ => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while importing the todos."
+                }))
+                .into()),
+        },
+        Ok(import) if import.dry_run => Ok(HttpResponse::Ok().json(import)),
+        Ok(import) => {
+            for todo in &import.created {
+                broadcaster.publish(TodoEventKind::Created, todo);
+            }
+            Ok(HttpResponse::Created().json(import))
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/export",
+    tag = "import/export",
+    params(ExportOptions, ("X-Workspace" = Option<String>, Header, description = "The id or slug of a workspace to use instead of the personal todos.")),
+    responses(
+        (status = 200, description = "The current user's todos as CSV or as a Markdown checklist.", content_type = "text/plain", body = String),
+        (status = 400, description = "Missing or unknown format.", body = MessageResponse),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[get("/export")]
+async fn export_file(
+    pool: web::Data<DbPool>,
+    options: web::Query<ExportOptions>,
+    tenant: Tenant,
+) -> Result<HttpResponse, Error> {
+    let format = options.format;
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        let todos = get_all_todos(tenant, &conn)?;
+        match format {
+            FileFormat::Csv => csv_io::write(&todos),
+            FileFormat::Markdown => Ok(markdown::write(&todos)),
+        }
+    })
+    .await;
+
+    match result {
+        Err(_) => Err(HttpResponse::InternalServerError()
+            .json(serde_json::json!({
+                "message": "Something went wrong while exporting the todos."
+            }))
+            .into()),
+        Ok(body) => Ok(HttpResponse::Ok()
+            .content_type(match format {
+                FileFormat::Csv => "text/csv; charset=utf-8",
+                FileFormat::Markdown => "text/markdown; charset=utf-8",
+            })
+            .body(body)),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/import",
+    tag = "import/export",
+    params(FileImportOptions, ("X-Workspace" = Option<String>, Header, description = "The id or slug of a workspace to use instead of the personal todos.")),
+    request_body(content = String, description = "A CSV file with a header row, or a Markdown checklist.", content_type = "text/plain"),
+    responses(
+        (status = 201, description = "The todos that were created.", body = ImportSummary),
+        (status = 400, description = "Some lines could not be read; nothing was created.", body = ImportErrorResponse),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 403, description = "The account has to verify its email address first.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[post("/import")]
+async fn import_file_upload(
+    pool: web::Data<DbPool>,
+    broadcaster: web::Data<Broadcaster>,
+    options: web::Query<FileImportOptions>,
+    body: String,
+    _user: VerifiedUser,
+    tenant: Tenant,
+) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        import_file(tenant, &body, &options, &conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => match e.into() {
+            TodosError::InvalidImport(errors) => Err(HttpResponse::BadRequest()
+                .json(serde_json::json!({
+                    "message": format!("{} lines of the file could not be read.", errors.len()),
+                    "errors": errors
+                }))
+                .into()),
+            _ => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while importing the todos."
+                }))
+                .into()),
+        },
+        Ok(created) => {
+            for todo in &created {
+                broadcaster.publish(TodoEventKind::Created, todo);
+            }
+            Ok(HttpResponse::Created().json(ImportSummary {
+                created,
+                skipped: 0,
+            }))
+        }
+    }
+}
+
+/// The largest file `POST /import/{provider}` and `POST /account/import`
+/// accept; actix's own limit of 256 KiB is too small for them.
+const MAX_UPLOAD_SIZE: usize = 64 * 1024 * 1024;
+
+async fn read_upload(mut payload: web::Payload) -> Result<web::BytesMut, Error> {
+    let mut body = web::BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk?;
+        if body.len() + chunk.len() > MAX_UPLOAD_SIZE {
+            return Err(HttpResponse::PayloadTooLarge()
+                .json(serde_json::json!({
+                    "message": "The file must not be larger than 64 MiB."
+                }))
+                .into());
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+#[utoipa::path(
+    post,
+    path = "/import/{provider}",
+    tag = "import/export",
+    params(
+        ("provider" = ImportProvider, Path, description = "The tool the export comes from."),
+        ("X-Workspace" = Option<String>, Header, description = "The id or slug of a workspace to use instead of the personal todos."),
+    ),
+    request_body(content = String, description = "A Todoist backup (JSON or a ZIP of project CSVs), a Trello board's JSON export, or Microsoft Graph To Do lists as JSON; any of them may be zipped.", content_type = "application/octet-stream"),
+    responses(
+        (status = 202, description = "The import was queued; poll the job for its outcome.", body = ImportJob),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 403, description = "The account has to verify its email address first.", body = MessageResponse),
+        (status = 413, description = "The export is larger than 64 MiB.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[post("/import/{provider}")]
+async fn import_provider_export(
+    pool: web::Data<DbPool>,
+    broadcaster: web::Data<Broadcaster>,
+    provider: web::Path<ImportProvider>,
+    payload: web::Payload,
+    _user: VerifiedUser,
+    tenant: Tenant,
+) -> Result<HttpResponse, Error> {
+    let body = read_upload(payload).await?;
+
+    let provider = provider.into_inner();
+    let queue_pool = pool.clone();
+    let result = web::block(move || {
+        let conn = queue_pool.get()?;
+        create_import_job(tenant, provider, &conn)
+    })
+    .await;
+    let job = match result {
+        Err(_) => {
+            return Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while queueing the import."
+                }))
+                .into())
+        }
+        Ok(job) => job,
+    };
+
+    // Large exports would hold one of actix's blocking threads for too long,
+    // so they get a thread of their own.
+    let pool = pool.get_ref().clone();
+    let broadcaster = broadcaster.clone();
+    let queued_job = job.clone();
+    std::thread::spawn(move || {
+        // Without a connection the job stays queued until the next start
+        // fails it.
+        let conn = match pool.get() {
+            Ok(conn) => conn,
+            Err(e) => return log::error!("Import job {} couldn't start: {}", queued_job.id, e),
+        };
+        if let Ok(created) = run_import_job(&queued_job, provider, &body, &conn) {
+            for todo in &created {
+                broadcaster.publish(TodoEventKind::Created, todo);
+            }
+        }
+    });
+
+    Ok(HttpResponse::Accepted()
+        .header("Location", format!("/import/jobs/{}", job.id))
+        .json(job))
+}
+
+#[utoipa::path(
+    get,
+    path = "/import/jobs/{id}",
+    tag = "import/export",
+    params(
+        ("id" = i32, Path, description = "The job's id."),
+        ("X-Workspace" = Option<String>, Header, description = "The id or slug of a workspace to use instead of the personal todos."),
+    ),
+    responses(
+        (status = 200, description = "The job's status.", body = ImportJob),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 404, description = "The current user or workspace has no such job.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[get("/import/jobs/{id}")]
+async fn get_import_job_status(
+    pool: web::Data<DbPool>,
+    job_id: web::Path<i32>,
+    tenant: Tenant,
+) -> Result<HttpResponse, Error> {
+    let job_id = job_id.into_inner();
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        get_import_job(tenant, job_id, &conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => match e.into() {
+            TodosError::ImportJobNotFound => Err(HttpResponse::NotFound()
+                .json(serde_json::json!({
+                    "message": "There is no such import job."
+                }))
+                .into()),
+            _ => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while fetching the import job."
+                }))
+                .into()),
+        },
+        Ok(job) => Ok(HttpResponse::Ok().json(job)),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/account/export",
+    tag = "account",
+    params(AccountExportOptions),
+    responses(
+        (status = 200, description = "The current user's profile, todos and import history.", body = AccountArchive),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[get("/account/export")]
+async fn export_account_archive(
+    pool: web::Data<DbPool>,
+    options: web::Query<AccountExportOptions>,
+    user: AuthUser,
+) -> Result<HttpResponse, Error> {
+    let format = options.format;
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        let account_archive = export_account(user.id, &conn)?;
+        match format {
+            ArchiveFormat::Json => Ok(archive::write_json(&account_archive)),
+            ArchiveFormat::Zip => archive::write_zip(&account_archive),
+        }
+    })
+    .await;
+
+    match result {
+        Err(_) => Err(HttpResponse::InternalServerError()
+            .json(serde_json::json!({
+                "message": "Something went wrong while exporting the account."
+            }))
+            .into()),
+        Ok(body) => {
+            let (content_type, extension) = match format {
+                ArchiveFormat::Json => ("application/json", "json"),
+                ArchiveFormat::Zip => ("application/zip", "zip"),
+            };
+            Ok(HttpResponse::Ok()
+                .content_type(content_type)
+                .header(
+                    "Content-Disposition",
+                    format!("attachment; filename=\"todos-account.{}\"", extension),
+                )
+                .body(body))
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/account/import",
+    tag = "account",
+    params(AccountImportOptions),
+    request_body(content = AccountArchive, description = "An archive from `GET /account/export`, zipped or not."),
+    responses(
+        (status = 200, description = "What was restored, and the ids the archived todos now have.", body = AccountImportSummary),
+        (status = 400, description = "The archive is invalid or from a newer version; nothing was restored.", body = MessageResponse),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 403, description = "The account has to verify its email address first.", body = MessageResponse),
+        (status = 413, description = "The archive is larger than 64 MiB.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[post("/account/import")]
+async fn import_account_archive(
+    pool: web::Data<DbPool>,
+    broadcaster: web::Data<Broadcaster>,
+    options: web::Query<AccountImportOptions>,
+    payload: web::Payload,
+    user: VerifiedUser,
+) -> Result<HttpResponse, Error> {
+    let body = read_upload(payload).await?;
+    let strategy = options.strategy;
+    let tenant = Tenant::Personal { user_id: user.id };
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        import_account(tenant, &body, strategy, &conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => match e.into() {
+            TodosError::InvalidArchive(reason) => Err(HttpResponse::BadRequest()
+                .json(serde_json::json!({
+                    "message": format!("The archive could not be read: {}.", reason)
+                }))
+                .into()),
+            TodosError::InvalidPriority => Err(HttpResponse::BadRequest()
+                .json(serde_json::json!({
+                    "message": "The priority must be a single uppercase letter."
+                }))
+                .into()),
+            _ => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while restoring the account."
+                }))
+                .into()),
+        },
+        Ok(summary) => {
+            for todo in &summary.created {
+                broadcaster.publish(TodoEventKind::Created, todo);
+            }
+            for todo in &summary.overwritten {
+                broadcaster.publish(TodoEventKind::Updated, todo);
+            }
+            Ok(HttpResponse::Ok().json(summary))
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/users/me/password",
+    tag = "users",
+    request_body = ChangePasswordBody,
+    responses(
+        (status = 200, description = "The password was changed. Every other token is revoked; use the one returned.", body = JwtUser),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 403, description = "The current password is wrong.", body = MessageResponse),
+        (status = 429, description = "Too many wrong passwords, as for `POST /login`.", body = MessageResponse,
+            headers(("Retry-After" = i64, description = "Seconds until the next try."))),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[post("/users/me/password")]
+async fn update_password(
+    pool: web::Data<DbPool>,
+    body: web::Json<ChangePasswordBody>,
+    user: AuthUser,
+    request: RequestInfo,
+) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        change_password(
+            user.id,
+            &body.current_password,
+            &body.new_password,
+            &request,
+            &conn,
+        )
+    })
+    .await;
+
+    match result {
+        Err(e) => match e.into() {
+            TodosError::BadCreds => Err(HttpResponse::Forbidden()
+                .json(serde_json::json!({
+                    "message": "Bad credentials"
+                }))
+                .into()),
+            TodosError::TooManyLoginAttempts(seconds) => Err(too_many_login_attempts(seconds)),
+            _ => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while changing the password."
+                }))
+                .into()),
+        },
+        Ok(jwt_user) => Ok(HttpResponse::Ok().json(jwt_user)),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/users/me/security-log",
+    tag = "users",
+    params(SecurityLogQuery),
+    responses(
+        (status = 200, description = "Logins, password changes and administrators' actions on the account, newest first.", body = AuditEventPage),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[get("/users/me/security-log")]
+async fn get_security_log(
+    pool: web::Data<DbPool>,
+    query: web::Query<SecurityLogQuery>,
+    user: AuthUser,
+) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        security_log(user.id, query.into_inner(), &conn)
+    })
+    .await;
+
+    match result {
+        Err(_) => Err(HttpResponse::InternalServerError()
+            .json(serde_json::json!({
+                "message": "Something went wrong while reading the security log."
+            }))
+            .into()),
+        Ok(page) => Ok(HttpResponse::Ok().json(page)),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/users/me/sessions",
+    tag = "users",
+    responses(
+        (status = 200, description = "The devices the user is logged in on, most recently used first.", body = [SessionInfo]),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[get("/users/me/sessions")]
+async fn get_sessions(pool: web::Data<DbPool>, user: AuthUser) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        list_sessions(user.id, user.session_id, &conn)
+    })
+    .await;
+
+    match result {
+        Err(_) => Err(HttpResponse::InternalServerError()
+            .json(serde_json::json!({
+                "message": "Something went wrong while listing the sessions."
+            }))
+            .into()),
+        Ok(sessions) => Ok(HttpResponse::Ok().json(sessions)),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/users/me/sessions/{session_id}",
+    tag = "users",
+    params(("session_id" = i32, Path, description = "The session's id.")),
+    responses(
+        (status = 204, description = "The session ended; its token no longer works."),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 404, description = "The user has no such session, or it already ended.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[delete("/users/me/sessions/{session_id}")]
+async fn delete_session(
+    pool: web::Data<DbPool>,
+    session_id: web::Path<i32>,
+    user: AuthUser,
+    request: RequestInfo,
+) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        revoke_session(
+            user.id,
+            &user.username,
+            session_id.into_inner(),
+            &request,
+            &conn,
+        )
+    })
+    .await;
+
+    match result {
+        Err(e) => match e.into() {
+            TodosError::SessionNotFound => Err(HttpResponse::NotFound()
+                .json(serde_json::json!({
+                    "message": "The session does not exist or already ended."
+                }))
+                .into()),
+            _ => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while ending the session."
+                }))
+                .into()),
+        },
+        Ok(()) => Ok(HttpResponse::NoContent().finish()),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/users/password-reset",
+    tag = "users",
+    request_body = PasswordResetRequest,
+    responses(
+        (status = 202, description = "If the user exists and has an email address, a reset token was mailed to it, unless three were in the last hour.", body = MessageResponse),
+        (status = 429, description = "The client address asked for too many resets in the last hour.", body = MessageResponse,
+            headers(("Retry-After" = i64, description = "Seconds until the next reset may be asked for."))),
+        (status = 500, description = "Database operations or sending the email failed.", body = MessageResponse),
+    )
+)]
+#[post("/users/password-reset")]
+async fn forgot_password(
+    pool: web::Data<DbPool>,
+    mailer: web::Data<dyn Mailer>,
+    body: web::Json<PasswordResetRequest>,
+    request: RequestInfo,
+) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        request_password_reset(&body.username, request.ip.as_deref(), &**mailer, &conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => match e.into() {
+            TodosError::TooManyPasswordResets(seconds) => Err(HttpResponse::TooManyRequests()
+                .header("Retry-After", seconds.to_string())
+                .json(serde_json::json!({
+                    "message": "Too many password resets; try again later."
+                }))
+                .into()),
+            _ => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while sending the reset email."
+                }))
+                .into()),
+        },
+        Ok(()) => Ok(HttpResponse::Accepted().json(serde_json::json!({
+            "message": "If the account has an email address, a reset token is on its way."
+        }))),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/users/password-reset/confirm",
+    tag = "users",
+    request_body = PasswordResetBody,
+    responses(
+        (status = 204, description = "The password was changed and every token of the user revoked."),
+        (status = 400, description = "The reset token is invalid, used or expired.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    )
+)]
+#[post("/users/password-reset/confirm")]
+async fn confirm_password_reset(
+    pool: web::Data<DbPool>,
+    body: web::Json<PasswordResetBody>,
+    request: RequestInfo,
+) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        reset_password(&body.token, &body.new_password, &request, &conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => match e.into() {
+            TodosError::InvalidResetToken => Err(HttpResponse::BadRequest()
+                .json(serde_json::json!({
+                    "message": "The reset token is invalid, used or expired."
+                }))
+                .into()),
+            _ => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while resetting the password."
+                }))
+                .into()),
+        },
+        Ok(()) => Ok(HttpResponse::NoContent().finish()),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/users/verify-email",
+    tag = "users",
+    request_body = VerifyEmailBody,
+    responses(
+        (status = 204, description = "The email address is verified."),
+        (status = 400, description = "The token is invalid, used or expired, or the address has changed since.", body = MessageResponse),
+        (status = 409, description = "Another account verified the address first.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    )
+)]
+#[post("/users/verify-email")]
+async fn verify_email_address(
+    pool: web::Data<DbPool>,
+    body: web::Json<VerifyEmailBody>,
+) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        verify_email(&body.token, &conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => match e.into() {
+            TodosError::InvalidVerificationToken => Err(HttpResponse::BadRequest()
+                .json(serde_json::json!({
+                    "message": "The verification token is invalid, used or expired."
+                }))
+                .into()),
+            TodosError::EmailTaken => Err(HttpResponse::Conflict()
+                .json(serde_json::json!({
+                    "message": "Another account already uses the email address."
+                }))
+                .into()),
+            _ => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while verifying the email address."
+                }))
+                .into()),
+        },
+        Ok(()) => Ok(HttpResponse::NoContent().finish()),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/users/me/email/verification",
+    tag = "users",
+    responses(
+        (status = 202, description = "A new verification token was mailed.", body = MessageResponse),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 409, description = "The account has no email address, or it is already verified.", body = MessageResponse),
+        (status = 500, description = "Database operations or sending the email failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[post("/users/me/email/verification")]
+async fn resend_verification(
+    pool: web::Data<DbPool>,
+    mailer: web::Data<dyn Mailer>,
+    user: AuthUser,
+) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        resend_email_verification(user.id, &**mailer, &conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => match e.into() {
+            TodosError::NoEmail => Err(HttpResponse::Conflict()
+                .json(serde_json::json!({
+                    "message": "The account has no email address."
+                }))
+                .into()),
+            TodosError::EmailAlreadyVerified => Err(HttpResponse::Conflict()
+                .json(serde_json::json!({
+                    "message": "The email address is already verified."
+                }))
+                .into()),
+            _ => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while sending the verification email."
+                }))
+                .into()),
+        },
+        Ok(()) => Ok(HttpResponse::Accepted().json(serde_json::json!({
+            "message": "A new verification token is on its way."
+        }))),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/oauth/clients",
+    tag = "oauth",
+    request_body = NewOAuthClientBody,
+    responses(
+        (status = 201, description = "The registered client; a confidential client's secret is only shown this once.", body = OAuthClientInfo),
+        (status = 400, description = "The name or a redirect URI is invalid.", body = MessageResponse),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 403, description = "The account has to verify its email address first.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[post("/oauth/clients")]
+async fn create_oauth_client(
+    pool: web::Data<DbPool>,
+    body: web::Json<NewOAuthClientBody>,
+    user: VerifiedUser,
+) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        register_oauth_client(user.id, body.into_inner(), &conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => match e.into() {
+            TodosError::OAuth(error) => Err(HttpResponse::BadRequest()
+                .json(serde_json::json!({ "message": error.error_description }))
+                .into()),
+            TodosError::InvalidRedirectUri(uri) => Err(HttpResponse::BadRequest()
+                .json(serde_json::json!({
+                    "message": format!("`{}` is not an HTTPS URI or an HTTP one on the loopback interface, or has a fragment.", uri)
+                }))
+                .into()),
+            _ => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while registering the client."
+                }))
+                .into()),
+        },
+        Ok(client) => Ok(HttpResponse::Created().json(client)),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/oauth/clients",
+    tag = "oauth",
+    responses(
+        (status = 200, description = "The clients the current user registered.", body = [OAuthClientInfo]),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[get("/oauth/clients")]
+async fn get_oauth_clients(pool: web::Data<DbPool>, user: AuthUser) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        list_oauth_clients(user.id, &conn)
+    })
+    .await;
+
+    match result {
+        Err(_) => Err(HttpResponse::InternalServerError()
+            .json(serde_json::json!({
+                "message": "Something went wrong while fetching the clients."
+            }))
+            .into()),
+        Ok(clients) => Ok(HttpResponse::Ok().json(clients)),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/oauth/clients/{client_id}",
+    tag = "oauth",
+    params(("client_id" = String, Path, description = "The client's public id.")),
+    responses(
+        (status = 204, description = "The client and every token issued to it are gone."),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 404, description = "The client does not exist or is not yours.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[delete("/oauth/clients/{client_id}")]
+async fn remove_oauth_client(
+    pool: web::Data<DbPool>,
+    client_id: web::Path<String>,
+    user: AuthUser,
+) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        delete_oauth_client(user.id, &client_id.into_inner(), &conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => match e.into() {
+            TodosError::OAuthClientNotFound => Err(HttpResponse::NotFound()
+                .json(serde_json::json!({
+                    "message": "The client does not exist."
+                }))
+                .into()),
+            _ => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while deleting the client."
+                }))
+                .into()),
+        },
+        Ok(()) => Ok(HttpResponse::NoContent().finish()),
+    }
+}
+
+/// An HTML page of the authorization flow, which no other site may frame.
+fn oauth_page(status: StatusCode, html: String) -> HttpResponse {
+    HttpResponse::build(status)
+        .content_type("text/html; charset=utf-8")
+        .header("X-Frame-Options", "DENY")
+        .header("Content-Security-Policy", "frame-ancestors 'none'")
+        .header("Cache-Control", "no-store")
+        .body(html)
+}
+
+fn authorize_response(result: Result<AuthorizeOutcome, TodosError>) -> HttpResponse {
+    match result {
+        Ok(AuthorizeOutcome::Redirect(uri)) => {
+            HttpResponse::SeeOther().header("Location", uri).finish()
+        }
+        Ok(AuthorizeOutcome::Consent(html)) => oauth_page(StatusCode::OK, html),
+        Err(TodosError::OAuthClientNotFound) => oauth_page(
+            StatusCode::BAD_REQUEST,
+            oauth::error_page("The app asking for access is unknown."),
+        ),
+        Err(TodosError::InvalidRedirectUri(_)) => oauth_page(
+            StatusCode::BAD_REQUEST,
+            oauth::error_page("The app asked to be answered at an address it didn't register."),
+        ),
+        Err(_) => oauth_page(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            oauth::error_page("Something went wrong; try again later."),
+        ),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/oauth/authorize",
+    tag = "oauth",
+    params(AuthorizeRequest),
+    responses(
+        (status = 200, description = "The consent page, where the user logs in and allows or denies access.", content_type = "text/html", body = String),
+        (status = 303, description = "Back to the app's redirect URI with an `error`.",
+            headers(("Location" = String, description = "The redirect URI."))),
+        (status = 400, description = "The client or its redirect URI is unknown.", content_type = "text/html", body = String),
+    )
+)]
+#[get("/oauth/authorize")]
+async fn oauth_authorize(
+    pool: web::Data<DbPool>,
+    query: web::Query<AuthorizeRequest>,
+) -> HttpResponse {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        start_oauth_authorization(query.into_inner(), &conn)
+    })
+    .await;
+    authorize_response(result.map_err(Into::into))
+}
+
+#[utoipa::path(
+    post,
+    path = "/oauth/authorize",
+    tag = "oauth",
+    request_body(content = ConsentForm, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "The consent page again, after a wrong password or two-factor code.", content_type = "text/html", body = String),
+        (status = 303, description = "Back to the app's redirect URI with a `code`, or an `error` if the user denied access.",
+            headers(("Location" = String, description = "The redirect URI."))),
+        (status = 400, description = "The client or its redirect URI is unknown.", content_type = "text/html", body = String),
+    )
+)]
+#[post("/oauth/authorize")]
+async fn oauth_consent(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    form: web::Form<ConsentForm>,
+) -> HttpResponse {
+    let ip = client_ip(&req);
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        finish_oauth_authorization(form.into_inner(), ip.as_deref(), &conn)
+    })
+    .await;
+    authorize_response(result.map_err(Into::into))
+}
+
+/// An RFC 6749 error response from the token, introspection or revocation
+/// endpoint.
+fn oauth_error_response(e: TodosError) -> Error {
+    match e {
+        TodosError::OAuth(error) if error.error == OAuthErrorCode::InvalidClient => {
+            HttpResponse::Unauthorized()
+                .header("WWW-Authenticate", "Basic realm=\"todos\"")
+                .header("Cache-Control", "no-store")
+                .json(error)
+                .into()
+        }
+        TodosError::OAuth(error) => HttpResponse::BadRequest()
+            .header("Cache-Control", "no-store")
+            .json(error)
+            .into(),
+        _ => HttpResponse::InternalServerError()
+            .json(serde_json::json!({
+                "message": "Something went wrong while performing DB operations."
+            }))
+            .into(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/oauth/token",
+    tag = "oauth",
+    request_body(content = TokenRequest, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "A new access token and refresh token; the refresh token sent, if any, no longer works.", body = TokenResponse),
+        (status = 400, description = "The grant is invalid, expired or was already used.", body = OAuthError),
+        (status = 401, description = "The client is unknown or its secret is wrong.", body = OAuthError),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    )
+)]
+#[post("/oauth/token")]
+async fn oauth_token(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    form: web::Form<TokenRequest>,
+) -> Result<HttpResponse, Error> {
+    let mut data = form.into_inner();
+    if let Some((client_id, client_secret)) = basic_credentials(&req) {
+        data.client_id = Some(client_id);
+        data.client_secret = Some(client_secret);
+    }
+    let request = RequestInfo::of(&req);
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        exchange_oauth_token(data, &request, &conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => Err(oauth_error_response(e.into())),
+        Ok(tokens) => Ok(HttpResponse::Ok()
+            .header("Cache-Control", "no-store")
+            .json(tokens)),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/oauth/introspect",
+    tag = "oauth",
+    request_body(content = TokenForm, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "Whether the token is active and, if so, what it grants. Only the client a token was issued to learns about it.", body = IntrospectionResponse),
+        (status = 401, description = "The client is unknown or its secret is wrong.", body = OAuthError),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    )
+)]
+#[post("/oauth/introspect")]
+async fn oauth_introspect(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    form: web::Form<TokenForm>,
+) -> Result<HttpResponse, Error> {
+    let mut data = form.into_inner();
+    if let Some((client_id, client_secret)) = basic_credentials(&req) {
+        data.client_id = Some(client_id);
+        data.client_secret = Some(client_secret);
+    }
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        introspect_oauth_token(data, &conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => Err(oauth_error_response(e.into())),
+        Ok(introspection) => Ok(HttpResponse::Ok()
+            .header("Cache-Control", "no-store")
+            .json(introspection)),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/oauth/revoke",
+    tag = "oauth",
+    request_body(content = TokenForm, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 200, description = "The token and the other token of its pair no longer work. Unknown tokens are ignored."),
+        (status = 401, description = "The client is unknown or its secret is wrong.", body = OAuthError),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    )
+)]
+#[post("/oauth/revoke")]
+async fn oauth_revoke(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    form: web::Form<TokenForm>,
+) -> Result<HttpResponse, Error> {
+    let mut data = form.into_inner();
+    if let Some((client_id, client_secret)) = basic_credentials(&req) {
+        data.client_id = Some(client_id);
+        data.client_secret = Some(client_secret);
+    }
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        revoke_oauth_token(data, &conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => Err(oauth_error_response(e.into())),
+        Ok(()) => Ok(HttpResponse::Ok().finish()),
+    }
+}
+
+fn oidc_provider_unavailable(e: TodosError) -> Error {
+    log::warn!("OpenID Connect login failed: {}", e);
+    HttpResponse::BadGateway()
+        .json(serde_json::json!({
+            "message": "The identity provider couldn't be reached or sent an unexpected answer."
+        }))
+        .into()
+}
+
+#[utoipa::path(
+    get,
+    path = "/oidc/login",
+    tag = "users",
+    responses(
+        (status = 303, description = "On to the identity provider to log in. The login can only be finished in the same browser.",
+            headers(
+                ("Location" = String, description = "The provider's authorization endpoint."),
+                ("Set-Cookie" = String, description = "`todos_oidc_state`, which the callback checks its `state` against."),
+            )),
+        (status = 404, description = "OpenID Connect login isn't configured.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+        (status = 502, description = "The identity provider couldn't be reached.", body = MessageResponse),
+    )
+)]
+#[get("/oidc/login")]
+async fn oidc_login(pool: web::Data<DbPool>) -> Result<HttpResponse, Error> {
+    let provider = oidc::provider().ok_or_else(oidc_not_configured)?;
+    let discovery = provider
+        .discover()
+        .await
+        .map_err(oidc_provider_unavailable)?;
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        start_oidc_login(provider, &discovery, &conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => match e.into() {
+            e @ TodosError::OidcProvider(_) => Err(oidc_provider_unavailable(e)),
+            _ => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while performing DB operations."
+                }))
+                .into()),
+        },
+        Ok((location, state_hash)) => Ok(HttpResponse::SeeOther()
+            .header("Location", location)
+            .cookie(oidc_state_cookie(state_hash))
+            .finish()),
+    }
+}
+
+fn oidc_not_configured() -> Error {
+    HttpResponse::NotFound()
+        .json(serde_json::json!({
+            "message": "OpenID Connect login isn't configured."
+        }))
+        .into()
+}
+
+#[utoipa::path(
+    get,
+    path = "/oidc/callback",
+    tag = "users",
+    params(OidcCallbackQuery),
+    responses(
+        (status = 200, description = "The user and a token, as `POST /login` returns them.", body = JwtUser),
+        (status = 202, description = "The account has two-factor authentication on; finish at `POST /login/2fa`.", body = TwoFactorChallenge),
+        (status = 400, description = "The login wasn't started here or in this browser, expired or was already finished.", body = MessageResponse),
+        (status = 401, description = "The provider refused the login or sent an invalid ID token.", body = MessageResponse),
+        (status = 403, description = "No account is linked to the identity and `OIDC_AUTO_PROVISION` is off, or the account is scheduled for deletion or was disabled by an administrator.", body = MessageResponse),
+        (status = 404, description = "OpenID Connect login isn't configured.", body = MessageResponse),
+        (status = 409, description = "The identity needs an account of its own, but every username made from its name is taken.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+        (status = 502, description = "The identity provider couldn't be reached.", body = MessageResponse),
+    )
+)]
+#[get("/oidc/callback")]
+async fn oidc_callback(
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    query: web::Query<OidcCallbackQuery>,
+    request: RequestInfo,
+    mailer: web::Data<dyn Mailer>,
+) -> Result<HttpResponse, Error> {
+    let provider = oidc::provider().ok_or_else(oidc_not_configured)?;
+    let query = query.into_inner();
+    if let Some(error) = query.error {
+        return Err(HttpResponse::Unauthorized()
+            .json(serde_json::json!({
+                "message": format!(
+                    "The identity provider refused the login: {}",
+                    query.error_description.unwrap_or(error)
+                )
+            }))
+            .into());
+    }
+    let (code, state) = match (query.code, query.state) {
+        (Some(code), Some(state)) => (code, state),
+        _ => {
+            return Err(HttpResponse::BadRequest()
+                .json(serde_json::json!({
+                    "message": "The callback needs a `code` and a `state`."
+                }))
+                .into())
+        }
+    };
+
+    let state_cookie = req
+        .cookie(OIDC_STATE_COOKIE)
+        .map(|cookie| cookie.value().to_owned());
+    let state_pool = pool.clone();
+    let result = web::block(move || {
+        let conn = state_pool.get()?;
+        take_oidc_login_state(&state, state_cookie.as_deref(), &conn)
+    })
+    .await;
+    let login_state = match result {
+        Ok(login_state) => login_state,
+        Err(e) => {
+            return match e.into() {
+                TodosError::InvalidOidcState => Err(HttpResponse::BadRequest()
+                    .json(serde_json::json!({
+                        "message": "The login wasn't started in this browser, expired or was already finished; start a new one at /oidc/login."
+                    }))
+                    .into()),
+                _ => Err(HttpResponse::InternalServerError()
+                    .json(serde_json::json!({
+                        "message": "Something went wrong while performing DB operations."
+                    }))
+                    .into()),
+            }
+        }
+    };
+    let discovery = provider
+        .discover()
+        .await
+        .map_err(oidc_provider_unavailable)?;
+    let claims = match provider
+        .exchange_code(
+            &discovery,
+            &code,
+            &login_state.code_verifier,
+            &login_state.nonce,
+        )
+        .await
+    {
+        Ok(claims) => claims,
+        Err(TodosError::InvalidIdToken(reason)) => {
+            log::warn!("Rejected an ID token: {}", reason);
+            return Err(HttpResponse::Unauthorized()
+                .json(serde_json::json!({
+                    "message": "The identity provider sent an invalid ID token."
+                }))
+                .into());
+        }
+        Err(e) => return Err(oidc_provider_unavailable(e)),
+    };
+
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        complete_oidc_login(&provider.issuer, claims, &request, &**mailer, &conn)
+    })
+    .await;
+    match result {
+        Err(e) => match e.into() {
+            TodosError::OidcAccountNotLinked => Err(HttpResponse::Forbidden()
+                .json(serde_json::json!({
+                    "message": "No account is linked to this identity."
+                }))
+                .into()),
+            TodosError::UsernameTaken => Err(HttpResponse::Conflict()
+                .json(serde_json::json!({
+                    "message": "Every username made from the identity's name is taken; ask the provider to send another one."
+                }))
+                .into()),
+            TodosError::AccountDisabled => Err(HttpResponse::Forbidden()
+                .json(serde_json::json!({
+                    "message": "The account is scheduled for deletion; restore it with POST /users/restore."
+                }))
+                .into()),
+            TodosError::AccountSuspended => Err(HttpResponse::Forbidden()
+                .json(serde_json::json!({
+                    "message": "The account was disabled by an administrator."
+                }))
+                .into()),
+            _ => Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({
+                    "message": "Something went wrong while logging in."
+                }))
+                .into()),
+        },
+        Ok(outcome) => {
+            // The login is finished; an empty state cookie matches no state.
+            let mut response = login_response(outcome, LoginMode::Token)?;
+            response.add_cookie(&oidc_state_cookie(String::new()))?;
+            Ok(response)
+        }
+    }
+}
+
+/// The error response of the admin endpoints; `action` completes "Something
+/// went wrong while ...".
+fn admin_error_response(e: TodosError, action: &str) -> Error {
+    let (mut response, message) = match e {
+        TodosError::AccountNotFound => (
+            HttpResponse::NotFound(),
+            "The user does not exist.".to_owned(),
+        ),
+        TodosError::RoleNotFound => (
+            HttpResponse::NotFound(),
+            "The role does not exist.".to_owned(),
+        ),
+        TodosError::RoleExists => (
+            HttpResponse::Conflict(),
+            "A role with this name already exists.".to_owned(),
+        ),
+        TodosError::RoleBuiltIn => (
+            HttpResponse::Conflict(),
+            "The `user` and `admin` roles can't be changed or deleted.".to_owned(),
+        ),
+        TodosError::RoleInUse => (
+            HttpResponse::Conflict(),
+            "Users still have this role; give them another one first.".to_owned(),
+        ),
+        TodosError::InvalidRoleName => (
+            HttpResponse::BadRequest(),
+            "Role names are 1 to 32 lowercase letters, digits, `-` and `_`.".to_owned(),
+        ),
+        TodosError::OwnAccount => (
+            HttpResponse::Conflict(),
+            "You can't disable your own account or change your own role.".to_owned(),
+        ),
+        TodosError::MailError => (
+            HttpResponse::InternalServerError(),
+            "Something went wrong while sending the reset email.".to_owned(),
+        ),
+        _ => (
+            HttpResponse::InternalServerError(),
+            format!("Something went wrong while {}.", action),
+        ),
+    };
+    response
+        .json(serde_json::json!({ "message": message }))
+        .into()
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/users",
+    tag = "admin",
+    params(UserSearchQuery),
+    responses(
+        (status = 200, description = "A page of the matching users.", body = UserPage),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 403, description = "The role lacks the `users:read` permission.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[get("/admin/users")]
+async fn admin_list_users(
+    pool: web::Data<DbPool>,
+    query: web::Query<UserSearchQuery>,
+    _admin: Authorized<roles::UsersRead>,
+) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        search_users(query.into_inner(), &conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => Err(admin_error_response(e.into(), "searching users")),
+        Ok(page) => Ok(HttpResponse::Ok().json(page)),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/users/{user_id}",
+    tag = "admin",
+    params(("user_id" = i32, Path, description = "The user's id.")),
+    responses(
+        (status = 200, description = "The user.", body = AdminUser),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 403, description = "The role lacks the `users:read` permission.", body = MessageResponse),
+        (status = 404, description = "The user does not exist.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[get("/admin/users/{user_id}")]
+async fn admin_get_user(
+    pool: web::Data<DbPool>,
+    user_id: web::Path<i32>,
+    _admin: Authorized<roles::UsersRead>,
+) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        get_user_for_admin(user_id.into_inner(), &conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => Err(admin_error_response(e.into(), "fetching the user")),
+        Ok(user) => Ok(HttpResponse::Ok().json(user)),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/users/{user_id}/stats",
+    tag = "admin",
+    params(("user_id" = i32, Path, description = "The user's id.")),
+    responses(
+        (status = 200, description = "How much the user keeps in their account.", body = UserStats),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 403, description = "The role lacks the `users:read` permission.", body = MessageResponse),
+        (status = 404, description = "The user does not exist.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[get("/admin/users/{user_id}/stats")]
+async fn admin_user_stats(
+    pool: web::Data<DbPool>,
+    user_id: web::Path<i32>,
+    _admin: Authorized<roles::UsersRead>,
+) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        user_stats(user_id.into_inner(), &conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => Err(admin_error_response(e.into(), "counting the user's data")),
+        Ok(stats) => Ok(HttpResponse::Ok().json(stats)),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/audit-events",
+    tag = "admin",
+    params(AuditEventQuery),
+    responses(
+        (status = 200, description = "The matching events of every account, newest first.", body = AuditEventPage),
+        (status = 400, description = "`event` names no kind of event.", body = MessageResponse),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 403, description = "The role lacks the `audit:read` permission.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[get("/admin/audit-events")]
+async fn admin_audit_events(
+    pool: web::Data<DbPool>,
+    query: web::Query<AuditEventQuery>,
+    _auditor: Authorized<roles::AuditRead>,
+) -> Result<HttpResponse, Error> {
+    let query = query.into_inner();
+    if let Some(name) = &query.event {
+        if AuditEventKind::parse(name).is_none() {
+            return Err(HttpResponse::BadRequest()
+                .json(serde_json::json!({
+                    "message": format!("There is no `{}` event.", name)
+                }))
+                .into());
+        }
+    }
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        search_audit_events(query, None, &conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => Err(admin_error_response(e.into(), "searching the security log")),
+        Ok(page) => Ok(HttpResponse::Ok().json(page)),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/users/{user_id}/disable",
+    tag = "admin",
+    params(("user_id" = i32, Path, description = "The user's id.")),
+    responses(
+        (status = 200, description = "The account is disabled: nobody can log in to it and its tokens are refused until it is enabled again.", body = AdminUser),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 403, description = "The role lacks the `users:manage` permission.", body = MessageResponse),
+        (status = 404, description = "The user does not exist.", body = MessageResponse),
+        (status = 409, description = "It is your own account.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[post("/admin/users/{user_id}/disable")]
+async fn admin_disable_user(
+    pool: web::Data<DbPool>,
+    user_id: web::Path<i32>,
+    admin: Authorized<roles::UsersManage>,
+    request: RequestInfo,
+) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        set_user_disabled(admin.id, user_id.into_inner(), true, &request, &conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => Err(admin_error_response(e.into(), "disabling the account")),
+        Ok(user) => Ok(HttpResponse::Ok().json(user)),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/users/{user_id}/enable",
+    tag = "admin",
+    params(("user_id" = i32, Path, description = "The user's id.")),
+    responses(
+        (status = 200, description = "The account can be used again.", body = AdminUser),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 403, description = "The role lacks the `users:manage` permission.", body = MessageResponse),
+        (status = 404, description = "The user does not exist.", body = MessageResponse),
+        (status = 409, description = "It is your own account.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[post("/admin/users/{user_id}/enable")]
+async fn admin_enable_user(
+    pool: web::Data<DbPool>,
+    user_id: web::Path<i32>,
+    admin: Authorized<roles::UsersManage>,
+    request: RequestInfo,
+) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        set_user_disabled(admin.id, user_id.into_inner(), false, &request, &conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => Err(admin_error_response(e.into(), "enabling the account")),
+        Ok(user) => Ok(HttpResponse::Ok().json(user)),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/users/{user_id}/password-reset",
+    tag = "admin",
+    params(("user_id" = i32, Path, description = "The user's id.")),
+    responses(
+        (status = 200, description = "The current password no longer logs in and every token of the user is revoked. A reset token was mailed if the user has a verified address.", body = ForcedPasswordReset),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 403, description = "The role lacks the `users:manage` permission.", body = MessageResponse),
+        (status = 404, description = "The user does not exist.", body = MessageResponse),
+        (status = 500, description = "Database operations or sending the email failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[post("/admin/users/{user_id}/password-reset")]
+async fn admin_force_password_reset(
+    pool: web::Data<DbPool>,
+    mailer: web::Data<dyn Mailer>,
+    user_id: web::Path<i32>,
+    admin: Authorized<roles::UsersManage>,
+    request: RequestInfo,
+) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        force_password_reset(admin.id, user_id.into_inner(), &request, &**mailer, &conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => Err(admin_error_response(e.into(), "resetting the password")),
+        Ok(reset) => Ok(HttpResponse::Ok().json(reset)),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/users/{user_id}/sessions",
+    tag = "admin",
+    params(("user_id" = i32, Path, description = "The user's id.")),
+    responses(
+        (status = 204, description = "Every token issued to the user so far, including those of their OAuth apps, is revoked."),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 403, description = "The role lacks the `users:manage` permission.", body = MessageResponse),
+        (status = 404, description = "The user does not exist.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[delete("/admin/users/{user_id}/sessions")]
+async fn admin_revoke_sessions(
+    pool: web::Data<DbPool>,
+    user_id: web::Path<i32>,
+    admin: Authorized<roles::UsersManage>,
+    request: RequestInfo,
+) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        revoke_user_sessions(admin.id, user_id.into_inner(), &request, &conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => Err(admin_error_response(e.into(), "revoking the sessions")),
+        Ok(()) => Ok(HttpResponse::NoContent().finish()),
+    }
+}
+
+#[utoipa::path(
+    put,
+    path = "/admin/users/{user_id}/role",
+    tag = "admin",
+    params(("user_id" = i32, Path, description = "The user's id.")),
+    request_body = SetRoleBody,
+    responses(
+        (status = 200, description = "The user, with the new role.", body = AdminUser),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 403, description = "The role lacks the `roles:manage` permission.", body = MessageResponse),
+        (status = 404, description = "The user or the role does not exist.", body = MessageResponse),
+        (status = 409, description = "It is your own account.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[put("/admin/users/{user_id}/role")]
+async fn admin_set_user_role(
+    pool: web::Data<DbPool>,
+    user_id: web::Path<i32>,
+    body: web::Json<SetRoleBody>,
+    admin: Authorized<roles::RolesManage>,
+    request: RequestInfo,
+) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        set_user_role(
+            Some(admin.id),
+            user_id.into_inner(),
+            &body.role,
+            &request,
+            &conn,
+        )
+    })
+    .await;
+
+    match result {
+        Err(e) => Err(admin_error_response(e.into(), "changing the role")),
+        Ok(user) => Ok(HttpResponse::Ok().json(user)),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/roles",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Every role, by name.", body = [Role]),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 403, description = "The role lacks the `roles:manage` permission.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[get("/admin/roles")]
+async fn admin_list_roles(
+    pool: web::Data<DbPool>,
+    _admin: Authorized<roles::RolesManage>,
+) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        list_roles(&conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => Err(admin_error_response(e.into(), "fetching the roles")),
+        Ok(roles) => Ok(HttpResponse::Ok().json(roles)),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/roles",
+    tag = "admin",
+    request_body = NewRoleBody,
+    responses(
+        (status = 201, description = "The new role.", body = Role),
+        (status = 400, description = "The name is invalid.", body = MessageResponse),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 403, description = "The role lacks the `roles:manage` permission.", body = MessageResponse),
+        (status = 409, description = "A role with this name already exists.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[post("/admin/roles")]
+async fn admin_create_role(
+    pool: web::Data<DbPool>,
+    body: web::Json<NewRoleBody>,
+    admin: Authorized<roles::RolesManage>,
+    request: RequestInfo,
+) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        create_role(admin.id, body.into_inner(), &request, &conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => Err(admin_error_response(e.into(), "creating the role")),
+        Ok(role) => Ok(HttpResponse::Created().json(role)),
+    }
+}
+
+#[utoipa::path(
+    patch,
+    path = "/admin/roles/{name}",
+    tag = "admin",
+    params(("name" = String, Path, description = "The role's name.")),
+    request_body = UpdateRoleBody,
+    responses(
+        (status = 200, description = "The changed role.", body = Role),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 403, description = "The role lacks the `roles:manage` permission.", body = MessageResponse),
+        (status = 404, description = "The role does not exist.", body = MessageResponse),
+        (status = 409, description = "The role is built in.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[patch("/admin/roles/{name}")]
+async fn admin_update_role(
+    pool: web::Data<DbPool>,
+    name: web::Path<String>,
+    body: web::Json<UpdateRoleBody>,
+    admin: Authorized<roles::RolesManage>,
+    request: RequestInfo,
+) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        update_role(admin.id, &name, body.into_inner(), &request, &conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => Err(admin_error_response(e.into(), "changing the role")),
+        Ok(role) => Ok(HttpResponse::Ok().json(role)),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/roles/{name}",
+    tag = "admin",
+    params(("name" = String, Path, description = "The role's name.")),
+    responses(
+        (status = 204, description = "The role is gone."),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 403, description = "The role lacks the `roles:manage` permission.", body = MessageResponse),
+        (status = 404, description = "The role does not exist.", body = MessageResponse),
+        (status = 409, description = "The role is built in, or users still have it.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[delete("/admin/roles/{name}")]
+async fn admin_delete_role(
+    pool: web::Data<DbPool>,
+    name: web::Path<String>,
+    admin: Authorized<roles::RolesManage>,
+    request: RequestInfo,
+) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        delete_role(admin.id, &name, &request, &conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => Err(admin_error_response(e.into(), "deleting the role")),
+        Ok(()) => Ok(HttpResponse::NoContent().finish()),
+    }
+}
+
+fn workspace_error_response(e: TodosError, action: &str) -> Error {
+    let (mut response, message) = match e {
+        TodosError::WorkspaceNotFound => (
+            HttpResponse::NotFound(),
+            "The workspace does not exist or you are not a member.".to_owned(),
+        ),
+        TodosError::TodoNotFoundError => (
+            HttpResponse::NotFound(),
+            "The user is not a member of the workspace.".to_owned(),
+        ),
+        TodosError::WorkspaceForbidden => (
+            HttpResponse::Forbidden(),
+            "Your role in the workspace doesn't allow this.".to_owned(),
+        ),
+        TodosError::InvalidWorkspace => (
+            HttpResponse::BadRequest(),
+            "Workspaces need a name, and slugs are up to 40 lowercase letters, digits and \
+             inner `-`, not only digits."
+                .to_owned(),
+        ),
+        TodosError::WorkspaceSlugTaken => (
+            HttpResponse::Conflict(),
+            "Another workspace has this slug.".to_owned(),
+        ),
+        TodosError::LastOwner => (
+            HttpResponse::Conflict(),
+            "The workspace needs another owner first.".to_owned(),
+        ),
+        TodosError::InvalidEmail => (
+            HttpResponse::BadRequest(),
+            "The email address is invalid.".to_owned(),
+        ),
+        TodosError::InvalidInvitation => (
+            HttpResponse::BadRequest(),
+            "The invitation is invalid, expired, already accepted or for another email \
+             address."
+                .to_owned(),
+        ),
+        TodosError::AlreadyMember => (
+            HttpResponse::Conflict(),
+            "You are already a member of the workspace.".to_owned(),
+        ),
+        TodosError::MailError => (
+            HttpResponse::InternalServerError(),
+            "Something went wrong while sending the invitation.".to_owned(),
+        ),
+        _ => (
+            HttpResponse::InternalServerError(),
+            format!("Something went wrong while {}.", action),
+        ),
+    };
+    response
+        .json(serde_json::json!({ "message": message }))
+        .into()
+}
+
+#[utoipa::path(
+    post,
+    path = "/workspaces",
+    tag = "workspaces",
+    request_body = NewWorkspaceBody,
+    responses(
+        (status = 201, description = "The workspace, with you as its owner.", body = WorkspaceInfo),
+        (status = 400, description = "The name is empty or the slug is invalid.", body = MessageResponse),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 409, description = "Another workspace has the slug.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[post("/workspaces")]
+async fn add_workspace(
+    pool: web::Data<DbPool>,
+    body: web::Json<NewWorkspaceBody>,
+    user: AuthUser,
+) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        create_workspace(user.id, body.into_inner(), &conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => Err(workspace_error_response(e.into(), "creating the workspace")),
+        Ok(workspace) => Ok(HttpResponse::Created().json(workspace)),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/workspaces",
+    tag = "workspaces",
+    responses(
+        (status = 200, description = "The workspaces you are a member of.", body = [WorkspaceInfo]),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[get("/workspaces")]
+async fn get_workspaces(pool: web::Data<DbPool>, user: AuthUser) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        list_workspaces(user.id, &conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => Err(workspace_error_response(e.into(), "listing the workspaces")),
+        Ok(workspaces) => Ok(HttpResponse::Ok().json(workspaces)),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/workspaces/{workspace_id}",
+    tag = "workspaces",
+    params(("workspace_id" = i32, Path, description = "The workspace's id.")),
+    responses(
+        (status = 200, description = "The workspace, with your role in it.", body = WorkspaceInfo),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 404, description = "The workspace does not exist or you are not a member.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[get("/workspaces/{workspace_id}")]
+async fn get_workspace_info(
+    pool: web::Data<DbPool>,
+    workspace_id: web::Path<i32>,
+    user: AuthUser,
+) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        get_workspace(user.id, workspace_id.into_inner(), &conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => Err(workspace_error_response(e.into(), "fetching the workspace")),
+        Ok(workspace) => Ok(HttpResponse::Ok().json(workspace)),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/workspaces/{workspace_id}",
+    tag = "workspaces",
+    params(("workspace_id" = i32, Path, description = "The workspace's id.")),
+    responses(
+        (status = 204, description = "The workspace and all of its todos were deleted."),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 403, description = "You are not an owner.", body = MessageResponse),
+        (status = 404, description = "The workspace does not exist or you are not a member.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[delete("/workspaces/{workspace_id}")]
+async fn remove_workspace(
+    pool: web::Data<DbPool>,
+    workspace_id: web::Path<i32>,
+    user: AuthUser,
+) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        delete_workspace(user.id, workspace_id.into_inner(), &conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => Err(workspace_error_response(e.into(), "deleting the workspace")),
+        Ok(()) => Ok(HttpResponse::NoContent().finish()),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/workspaces/{workspace_id}/members",
+    tag = "workspaces",
+    params(("workspace_id" = i32, Path, description = "The workspace's id.")),
+    responses(
+        (status = 200, description = "The workspace's members, in the order they joined.", body = [MemberInfo]),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 404, description = "The workspace does not exist or you are not a member.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[get("/workspaces/{workspace_id}/members")]
+async fn get_workspace_members(
+    pool: web::Data<DbPool>,
+    workspace_id: web::Path<i32>,
+    user: AuthUser,
+) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        list_workspace_members(user.id, workspace_id.into_inner(), &conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => Err(workspace_error_response(e.into(), "listing the members")),
+        Ok(members) => Ok(HttpResponse::Ok().json(members)),
+    }
+}
+
+#[utoipa::path(
+    patch,
+    path = "/workspaces/{workspace_id}/members/{user_id}",
+    tag = "workspaces",
+    params(
+        ("workspace_id" = i32, Path, description = "The workspace's id."),
+        ("user_id" = i32, Path, description = "The member's user id."),
+    ),
+    request_body = UpdateMemberBody,
+    responses(
+        (status = 200, description = "The member, with the new role.", body = MemberInfo),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 403, description = "You are not an owner.", body = MessageResponse),
+        (status = 404, description = "The workspace or the member does not exist.", body = MessageResponse),
+        (status = 409, description = "The member is the last owner.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[patch("/workspaces/{workspace_id}/members/{user_id}")]
+async fn update_workspace_member_role(
+    pool: web::Data<DbPool>,
+    path: web::Path<(i32, i32)>,
+    body: web::Json<UpdateMemberBody>,
+    user: AuthUser,
+) -> Result<HttpResponse, Error> {
+    let (workspace_id, member_id) = path.into_inner();
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        update_workspace_member(user.id, workspace_id, member_id, body.role, &conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => Err(workspace_error_response(e.into(), "changing the role")),
+        Ok(member) => Ok(HttpResponse::Ok().json(member)),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/workspaces/{workspace_id}/members/{user_id}",
+    tag = "workspaces",
+    params(
+        ("workspace_id" = i32, Path, description = "The workspace's id."),
+        ("user_id" = i32, Path, description = "The member's user id; your own to leave."),
+    ),
+    responses(
+        (status = 204, description = "The member was removed."),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 403, description = "Your role doesn't allow removing the member.", body = MessageResponse),
+        (status = 404, description = "The workspace or the member does not exist.", body = MessageResponse),
+        (status = 409, description = "The member is the last owner.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[delete("/workspaces/{workspace_id}/members/{user_id}")]
+async fn delete_workspace_member(
+    pool: web::Data<DbPool>,
+    path: web::Path<(i32, i32)>,
+    user: AuthUser,
+) -> Result<HttpResponse, Error> {
+    let (workspace_id, member_id) = path.into_inner();
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        remove_workspace_member(user.id, workspace_id, member_id, &conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => Err(workspace_error_response(e.into(), "removing the member")),
+        Ok(()) => Ok(HttpResponse::NoContent().finish()),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/workspaces/{workspace_id}/invitations",
+    tag = "workspaces",
+    params(("workspace_id" = i32, Path, description = "The workspace's id.")),
+    request_body = NewInvitationBody,
+    responses(
+        (status = 201, description = "The invitation; its token was mailed to the address.", body = InvitationInfo),
+        (status = 400, description = "The email address is invalid.", body = MessageResponse),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 403, description = "You are not an admin or owner, or tried to invite an owner.", body = MessageResponse),
+        (status = 404, description = "The workspace does not exist or you are not a member.", body = MessageResponse),
+        (status = 500, description = "Database operations failed or the email could not be sent.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[post("/workspaces/{workspace_id}/invitations")]
+async fn add_workspace_invitation(
+    pool: web::Data<DbPool>,
+    mailer: web::Data<dyn Mailer>,
+    workspace_id: web::Path<i32>,
+    body: web::Json<NewInvitationBody>,
+    user: AuthUser,
+) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        invite_to_workspace(
+            user.id,
+            workspace_id.into_inner(),
+            body.into_inner(),
+            &**mailer,
+            &conn,
+        )
+    })
+    .await;
+
+    match result {
+        Err(e) => Err(workspace_error_response(e.into(), "inviting")),
+        Ok(invitation) => Ok(HttpResponse::Created().json(invitation)),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/workspaces/{workspace_id}/invitations",
+    tag = "workspaces",
+    params(("workspace_id" = i32, Path, description = "The workspace's id.")),
+    responses(
+        (status = 200, description = "The workspace's invitations, newest first.", body = [InvitationInfo]),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 403, description = "You are not an admin or owner.", body = MessageResponse),
+        (status = 404, description = "The workspace does not exist or you are not a member.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[get("/workspaces/{workspace_id}/invitations")]
+async fn get_workspace_invitations(
+    pool: web::Data<DbPool>,
+    workspace_id: web::Path<i32>,
+    user: AuthUser,
+) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        list_workspace_invitations(user.id, workspace_id.into_inner(), &conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => Err(workspace_error_response(
+            e.into(),
+            "listing the invitations",
+        )),
+        Ok(invitations) => Ok(HttpResponse::Ok().json(invitations)),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/workspaces/invitations/accept",
+    tag = "workspaces",
+    request_body = AcceptInvitationBody,
+    responses(
+        (status = 200, description = "The workspace you joined.", body = WorkspaceInfo),
+        (status = 400, description = "The invitation is invalid, expired or for another address than your verified one.", body = MessageResponse),
+        (status = 401, description = "Missing, invalid or expired token.", body = MessageResponse),
+        (status = 409, description = "You are already a member.", body = MessageResponse),
+        (status = 500, description = "Database operations failed.", body = MessageResponse),
+    ),
+    security(("jwt" = []))
+)]
+#[post("/workspaces/invitations/accept")]
+async fn accept_invitation(
+    pool: web::Data<DbPool>,
+    body: web::Json<AcceptInvitationBody>,
+    user: AuthUser,
+) -> Result<HttpResponse, Error> {
+    let result = web::block(move || {
+        let conn = pool.get()?;
+        accept_workspace_invitation(user.id, &body.token, &conn)
+    })
+    .await;
+
+    match result {
+        Err(e) => Err(workspace_error_response(
+            e.into(),
+            "accepting the invitation",
+        )),
+        Ok(workspace) => Ok(HttpResponse::Ok().json(workspace)),
+    }
+}
+
+/// Lists the API's endpoints once: `api_routes` registers the handlers in
+/// `paths(...)`, in order, and `ApiDoc` documents the same ones.
+macro_rules! api {
+    (paths($($handler:ident),* $(,)?), $($openapi:tt)*) => {
+        fn api_routes(cfg: &mut web::ServiceConfig) {
+            $(cfg.service($handler);)*
+        }
+
+        #[derive(OpenApi)]
+        #[openapi(paths($($handler),*), $($openapi)*)]
+        struct ApiDoc;
+    };
+}
+
+api! {
+    paths(
+        get_todos,
+        get_todo,
+        add_todo,
+        update_todo,
+        delete_todo,
+        register,
+        login,
+        todo_events,
+        graphql_endpoint,
+        graphql_stream,
+        export_ics,
+        create_calendar_token,
+        create_caldav_password,
+        delete_caldav_password,
+        calendar_feed,
+        import_ics,
+        export_todotxt,
+        import_todotxt_file,
+        export_file,
+        import_file_upload,
+        import_provider_export,
+        get_import_job_status,
+        export_account_archive,
+        import_account_archive,
+        get_me,
+        update_me,
+        update_username,
+        get_user_profile,
+        delete_account,
+        restore,
+        update_password,
+        get_security_log,
+        get_sessions,
+        delete_session,
+        forgot_password,
+        confirm_password_reset,
+        verify_email_address,
+        resend_verification,
+        login_two_factor,
+        logout,
+        enroll_totp,
+        totp_qr_code,
+        confirm_totp_enrollment,
+        jwks,
+        create_oauth_client,
+        get_oauth_clients,
+        remove_oauth_client,
+        oauth_authorize,
+        oauth_consent,
+        oauth_token,
+        oauth_introspect,
+        oauth_revoke,
+        oidc_login,
+        oidc_callback,
+        admin_list_users,
+        admin_get_user,
+        admin_user_stats,
+        admin_audit_events,
+        admin_disable_user,
+        admin_enable_user,
+        admin_force_password_reset,
+        admin_revoke_sessions,
+        admin_set_user_role,
+        admin_list_roles,
+        admin_create_role,
+        admin_update_role,
+        admin_delete_role,
+        accept_invitation,
+        add_workspace,
+        get_workspaces,
+        get_workspace_info,
+        remove_workspace,
+        get_workspace_members,
+        update_workspace_member_role,
+        delete_workspace_member,
+        add_workspace_invitation,
+        get_workspace_invitations,
+    ),
+    components(schemas(
+        Todo,
+        NewTodoReq,
+        UpdateTodo,
+        NewUser,
+        JwtUser,
+        RegisterResponse,
+        LoginBody,
+        TodoEvent,
+        TodoEventKind,
+        GraphQLRequestBody,
+        MessageResponse,
+        CalendarSubscription,
+        CalDavPassword,
+        ImportSummary,
+        TodoTxtImport,
+        Task,
+        FileFormat,
+        LineError,
+        ImportErrorResponse,
+        ImportProvider,
+        ImportJob,
+        AccountArchive,
+        ArchivedProfile,
+        ArchivedTodo,
+        ArchiveFormat,
+        ConflictStrategy,
+        IdMapping,
+        AccountImportSummary,
+        DeleteAccountBody,
+        AccountDeletion,
+        ChangePasswordBody,
+        PasswordResetRequest,
+        PasswordResetBody,
+        VerifyEmailBody,
+        TwoFactorChallenge,
+        TwoFactorLoginBody,
+        LoginMode,
+        SessionUser,
+        TotpEnrollment,
+        TotpCodeBody,
+        RecoveryCodes,
+        QrFormat,
+        Jwk,
+        JwkSet,
+        NewOAuthClientBody,
+        OAuthClientInfo,
+        ConsentForm,
+        TokenRequest,
+        TokenResponse,
+        TokenForm,
+        IntrospectionResponse,
+        OAuthError,
+        OAuthErrorCode,
+        Permission,
+        Role,
+        NewRoleBody,
+        UpdateRoleBody,
+        SetRoleBody,
+        AdminUser,
+        UserPage,
+        UserStats,
+        AuditEvent,
+        AuditEventPage,
+        AuditEventKind,
+        SessionInfo,
+        Profile,
+        Preferences,
+        TodoSort,
+        WeekStart,
+        UpdateProfileBody,
+        UpdatePreferences,
+        ChangeUsernameBody,
+        PublicProfile,
+        ForcedPasswordReset,
+        WorkspaceRole,
+        NewWorkspaceBody,
+        WorkspaceInfo,
+        MemberInfo,
+        UpdateMemberBody,
+        NewInvitationBody,
+        InvitationInfo,
+        AcceptInvitationBody
+    )),
+    modifiers(&SecurityAddon)
+}
+
+#[get("/openapi.json")]
+async fn openapi_json() -> HttpResponse {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+#[get("/docs")]
+async fn docs() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(SWAGGER_UI_HTML)
+}
+
+#[get("/docs/{asset}")]
+async fn docs_asset(asset: web::Path<String>) -> HttpResponse {
+    match SWAGGER_UI_ASSETS
+        .iter()
+        .find(|(name, ..)| *name == asset.as_str())
+    {
+        Some((_, content_type, body)) => HttpResponse::Ok()
+            .content_type(*content_type)
+            .header("Cache-Control", "public, max-age=86400")
+            .body(*body),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    dotenv::dotenv().ok();
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    // Fail now rather than at the first login if the keys or the identity
+    // provider are misconfigured.
+    if let Err(e) = keys::init() {
+        log::error!("JWT keys: {}", e);
+        std::process::exit(1);
+    }
+    if let Err(e) = oidc::init() {
+        log::error!("OpenID Connect: {}", e);
+        std::process::exit(1);
+    }
+    let db_url = std::env::var("DATABASE_URL").expect("DATABASE_URL");
+    let manager = ConnectionManager::<PgConnection>::new(db_url);
+    let pool = r2d2::Pool::builder()
+        .build(manager)
+        .expect("Failed to create pool.");
+    fail_interrupted_import_jobs(&pool.get().expect("Could not get db conn from pool."))
+        .expect("Failed to clean up import jobs.");
+    let schema = web::Data::new(graphql::create_schema());
+    let subscription_schema = web::Data::new(graphql::create_subscription_schema());
+    let broadcaster = web::Data::new(Broadcaster::new());
+    let mailer: web::Data<dyn Mailer> = web::Data::from(mail::from_env());
+
+    let grpc_port = std::env::var("GRPC_PORT")
+        .map(|port| port.parse::<u16>().expect("GRPC_PORT"))
+        .unwrap_or(50051);
+    let grpc_pool = pool.clone();
+    let grpc_broadcaster = broadcaster.clone();
+    std::thread::spawn(move || {
+        let mut runtime = tokio::runtime::Runtime::new().expect("Failed to start gRPC runtime.");
+        runtime
+            .block_on(grpc::serve(
+                ([127, 0, 0, 1], grpc_port).into(),
+                grpc_pool,
+                grpc_broadcaster,
+            ))
+            .expect("gRPC server failed.");
+    });
+
+    // Accounts are purged some time after their grace period ends, and old
+    // failed logins some time after they stop counting; an hour late makes no
+    // difference.
+    let purge_pool = pool.clone();
+    std::thread::spawn(move || loop {
+        if let Ok(conn) = purge_pool.get() {
+            if let Err(e) = purge_deleted_accounts(&conn) {
+                log::error!("Purging deleted accounts failed: {}", e);
+            }
+            if let Err(e) = purge_login_throttles(&conn) {
+                log::error!("Purging old failed logins failed: {}", e);
+            }
+            if let Err(e) = purge_expired_sessions(&conn) {
+                log::error!("Purging expired sessions failed: {}", e);
+            }
+            if let Err(e) = purge_password_reset_requests(&conn) {
+                log::error!("Purging old password reset requests failed: {}", e);
+            }
+        }
+        std::thread::sleep(std::time::Duration::from_secs(60 * 60));
+    });
+
+    HttpServer::new(move || {
+        App::new()
+            .data(pool.clone())
+            .app_data(schema.clone())
+            .app_data(subscription_schema.clone())
+            .app_data(broadcaster.clone())
+            .app_data(mailer.clone())
+            .configure(api_routes)
+            .configure(caldav::configure)
+            .service(openapi_json)
+            .service(docs)
+            .service(docs_asset)
+            .configure(|cfg| {
+                // The GraphiQL playground is only served by debug builds.
+                if cfg!(debug_assertions) {
+                    cfg.service(graphiql);
+                }
+            })
+    })
+    .bind(("127.0.0.1", 8080))?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::Method, test, App};
+    use utoipa::OpenApi;
+
+    use super::{api_routes, ApiDoc};
+
+    /// The methods the spec may document, and one it never does, so every
+    /// path is requested at least once.
+    const METHODS: [Method; 6] = [
+        Method::GET,
+        Method::POST,
+        Method::PUT,
+        Method::PATCH,
+        Method::DELETE,
+        Method::TRACE,
+    ];
+
+    /// The path template with `1` for every parameter.
+    fn example_path(template: &str) -> String {
+        template
+            .split('/')
+            .map(|segment| {
+                if segment.starts_with('{') {
+                    "1"
+                } else {
+                    segment
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    /// `api!` registers exactly the documented handlers; this checks that the
+    /// app built from them routes each documented path to a route with that
+    /// pattern, and refuses the methods not documented for it. Only those are
+    /// requested, so no handler runs.
+    #[test]
+    fn openapi_spec_matches_registered_routes() {
+        actix_web::rt::System::new("routes").block_on(async {
+            let spec = serde_json::to_value(ApiDoc::openapi()).unwrap();
+            let paths = spec["paths"].as_object().unwrap();
+            assert!(!paths.is_empty());
+            let mut app = test::init_service(App::new().configure(api_routes)).await;
+            for (template, item) in paths {
+                let path = example_path(template);
+                for method in METHODS.iter() {
+                    if item.get(method.as_str().to_lowercase()).is_some() {
+                        continue;
+                    }
+                    let req = test::TestRequest::with_uri(&path)
+                        .method(method.clone())
+                        .to_request();
+                    let res = test::call_service(&mut app, req).await;
+                    assert_eq!(
+                        res.request().match_pattern().as_ref(),
+                        Some(template),
+                        "{} is routed to another pattern",
+                        template
+                    );
+                    assert_eq!(
+                        res.status(),
+                        actix_web::http::StatusCode::NOT_FOUND,
+                        "{} {} is served but not documented",
+                        method,
+                        template
+                    );
+                }
+            }
+        });
+    }
 }
@@ -0,0 +1,143 @@
+//! GitHub-flavoured Markdown task lists. Only a todo's text and whether it is
+//! done survive the trip; use CSV for everything else.
+
+use crate::{
+    error::TodosError,
+    models::{self, LineError},
+};
+
+/// Todos don't belong to lists yet, so everything ends up under one heading.
+const HEADING: &str = "# Todos";
+
+pub fn write(todos: &[models::Todo]) -> String {
+    let mut out = format!("{}\n\n", HEADING);
+    for todo in todos {
+        let checkbox = if todo.done { "[x]" } else { "[ ]" };
+        out.push_str(&format!(
+            "- {} {}\n",
+            checkbox,
+            todo.text.replace('\n', " ")
+        ));
+    }
+    out
+}
+
+/// Reads every checklist item. Headings, prose and blank lines are skipped;
+/// list items without a valid checkbox are reported by their 1-based line.
+pub fn read(input: &str) -> Result<Vec<models::NewTodoReq>, TodosError> {
+    let mut todos = Vec::new();
+    let mut errors = Vec::new();
+    for (index, line) in input.lines().enumerate() {
+        let item = match line
+            .trim_start()
+            .strip_prefix("- ")
+            .or_else(|| line.trim_start().strip_prefix("* "))
+        {
+            Some(item) => item.trim_start(),
+            None => continue,
+        };
+        let mut fail = |message: &str| {
+            errors.push(LineError {
+                line: index + 1,
+                message: message.to_owned(),
+            })
+        };
+        let (done, text) = match item.get(..3).map(str::to_ascii_lowercase).as_deref() {
+            Some("[ ]") => (false, &item[3..]),
+            Some("[x]") => (true, &item[3..]),
+            _ => {
+                fail("The list item has no `[ ]` or `[x]` checkbox.");
+                continue;
+            }
+        };
+        let text = text.trim();
+        if text.is_empty() {
+            fail("The text is empty.");
+            continue;
+        }
+        todos.push(models::NewTodoReq {
+            text: text.to_owned(),
+            done: Some(done),
+            ..Default::default()
+        });
+    }
+
+    if errors.is_empty() {
+        Ok(todos)
+    } else {
+        Err(TodosError::InvalidImport(errors))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn todo(text: &str, done: bool) -> models::Todo {
+        let created_at = NaiveDate::from_ymd(2026, 10, 18).and_hms(9, 30, 0);
+        models::Todo {
+            id: 1,
+            text: text.to_owned(),
+            done,
+            user_id: Some(1),
+            ical_uid: "uid-1@todos".to_owned(),
+            created_at,
+            updated_at: created_at,
+            completed_at: None,
+            priority: None,
+            workspace_id: None,
+            projects: Vec::new(),
+            contexts: Vec::new(),
+        }
+    }
+
+    fn items(todos: &[models::NewTodoReq]) -> Vec<(&str, Option<bool>)> {
+        todos
+            .iter()
+            .map(|todo| (todo.text.as_str(), todo.done))
+            .collect()
+    }
+
+    #[test]
+    fn reads_what_it_writes() {
+        let markdown = write(&[todo("Buy milk", false), todo("Two\nlines", true)]);
+        assert_eq!(markdown, "# Todos\n\n- [ ] Buy milk\n- [x] Two lines\n");
+        assert_eq!(
+            items(&read(&markdown).unwrap()),
+            vec![("Buy milk", Some(false)), ("Two lines", Some(true))]
+        );
+    }
+
+    #[test]
+    fn skips_everything_but_checklist_items() {
+        let markdown = "# Week\n\nSome prose.\n\n* [X] Done, upper case\n  -   [ ]  Indented\n";
+        assert_eq!(
+            items(&read(markdown).unwrap()),
+            vec![("Done, upper case", Some(true)), ("Indented", Some(false))]
+        );
+    }
+
+    #[test]
+    fn reports_every_bad_item() {
+        let markdown = "- [ ] Fine\n- No checkbox\n- [ ]   \n- [y] Wrong mark\n";
+        match read(markdown) {
+            Err(TodosError::InvalidImport(errors)) => assert_eq!(
+                errors
+                    .iter()
+                    .map(|error| (error.line, error.message.as_str()))
+                    .collect::<Vec<_>>(),
+                vec![
+                    (2, "The list item has no `[ ]` or `[x]` checkbox."),
+                    (3, "The text is empty."),
+                    (4, "The list item has no `[ ]` or `[x]` checkbox."),
+                ]
+            ),
+            other => panic!(
+                "expected line errors, got {:?}",
+                other.map(|todos| todos.len())
+            ),
+        }
+    }
+}
@@ -1,17 +1,48 @@
-use super::schema::{todos, users};
+use super::schema::{
+    audit_events, email_verification_tokens, import_jobs, login_throttles,
+    oauth_authorization_codes, oauth_clients, oauth_tokens, oidc_identities, oidc_login_states,
+    password_reset_requests, password_reset_tokens, roles, sessions, todos, totp_recovery_codes,
+    users, workspace_invitations, workspace_members, workspaces,
+};
+use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 
-#[derive(Queryable, Serialize, Deserialize, Debug, Identifiable)]
+#[derive(Queryable, Serialize, Deserialize, Debug, Clone, Identifiable, ToSchema)]
 pub struct Todo {
     pub id: i32,
     pub text: String,
     pub done: bool,
-    pub user_id: i32,
+    /// Who created the todo; workspace todos keep theirs after the creator's
+    /// account is purged, with none.
+    pub user_id: Option<i32>,
+    pub ical_uid: String,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+    pub completed_at: Option<NaiveDateTime>,
+    pub priority: Option<String>,
+    /// The workspace the todo belongs to; personal todos have none.
+    pub workspace_id: Option<i32>,
+    /// The todo.txt `+project` tags of the text, without the `+`.
+    pub projects: Vec<String>,
+    /// The todo.txt `@context` tags of the text, without the `@`.
+    pub contexts: Vec<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default, ToSchema)]
 pub struct NewTodoReq {
     pub text: String,
+    #[serde(default)]
+    pub done: Option<bool>,
+    /// A single uppercase letter, `A` being the highest priority.
+    #[serde(default)]
+    pub priority: Option<String>,
+    /// Only set by imports that know when a todo was created.
+    #[serde(skip)]
+    pub created_at: Option<NaiveDateTime>,
+    /// Only set by imports that know when a todo was completed.
+    #[serde(skip)]
+    pub completed_at: Option<NaiveDateTime>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Insertable)]
@@ -19,9 +50,17 @@ pub struct NewTodoReq {
 pub struct NewTodo {
     pub text: String,
     pub user_id: i32,
+    pub workspace_id: Option<i32>,
+    pub ical_uid: Option<String>,
+    pub done: Option<bool>,
+    pub created_at: Option<NaiveDateTime>,
+    pub completed_at: Option<NaiveDateTime>,
+    pub priority: Option<String>,
+    pub projects: Vec<String>,
+    pub contexts: Vec<String>,
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, ToSchema)]
 #[serde(untagged)]
 pub enum UpdateTodo {
     TextOnly { text: String },
@@ -29,23 +68,1312 @@ pub enum UpdateTodo {
     Both { text: String, done: bool },
 }
 
-#[derive(Queryable, Serialize, Deserialize, Debug)]
+#[derive(Queryable, Serialize, Deserialize, Debug, Clone)]
 pub struct User {
     pub id: i32,
     pub username: String,
     pub(crate) password: String,
+    pub(crate) calendar_token_hash: Option<String>,
+    /// When the user asked for the account to be deleted; it is disabled
+    /// until it is restored or purged.
+    pub deleted_at: Option<NaiveDateTime>,
+    /// Where password reset links are sent.
+    pub email: Option<String>,
+    /// Tokens issued before this are revoked.
+    pub(crate) password_changed_at: Option<NaiveDateTime>,
+    pub email_verified_at: Option<NaiveDateTime>,
+    pub(crate) totp_secret: Option<String>,
+    /// Set once two-factor authentication is on.
+    pub totp_enabled_at: Option<NaiveDateTime>,
+    pub(crate) totp_last_step: Option<i64>,
+    pub role: String,
+    /// When an administrator disabled the account.
+    pub disabled_at: Option<NaiveDateTime>,
+    /// Tokens issued before this are revoked.
+    pub(crate) sessions_revoked_at: Option<NaiveDateTime>,
+    /// Set by an administrator; the password can't be used until it is
+    /// reset.
+    pub password_reset_required: bool,
+    pub display_name: Option<String>,
+    pub avatar_url: Option<String>,
+    pub time_zone: Option<String>,
+    pub locale: Option<String>,
+    /// A `TodoSort`.
+    pub default_sort: String,
+    /// A `WeekStart`.
+    pub week_start: String,
+    pub(crate) caldav_password_hash: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Insertable)]
+impl User {
+    /// Whether the account may use everything; see `auth::VerifiedUser`.
+    pub fn is_verified(&self, email_required: bool) -> bool {
+        match &self.email {
+            Some(_) => self.email_verified_at.is_some(),
+            None => !email_required,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Insertable, ToSchema)]
 #[table_name = "users"]
 pub struct NewUser {
     pub username: String,
     pub(crate) password: String,
+    /// Needed to reset a forgotten password; required when `REQUIRE_EMAIL`
+    /// is set. A verification token is mailed to it.
+    #[serde(default)]
+    pub email: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct JwtUser {
     pub token: String,
     pub username: String,
     pub id: i32,
 }
+
+/// The order apps list todos in unless the user picks another.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum TodoSort {
+    #[default]
+    Created,
+    Updated,
+    Priority,
+    Text,
+}
+
+impl TodoSort {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Created => "created",
+            Self::Updated => "updated",
+            Self::Priority => "priority",
+            Self::Text => "text",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<TodoSort> {
+        match name {
+            "created" => Some(Self::Created),
+            "updated" => Some(Self::Updated),
+            "priority" => Some(Self::Priority),
+            "text" => Some(Self::Text),
+            _ => None,
+        }
+    }
+}
+
+/// The first day of the week in calendars.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum WeekStart {
+    #[default]
+    Monday,
+    Sunday,
+    Saturday,
+}
+
+impl WeekStart {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Monday => "monday",
+            Self::Sunday => "sunday",
+            Self::Saturday => "saturday",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<WeekStart> {
+        match name {
+            "monday" => Some(Self::Monday),
+            "sunday" => Some(Self::Sunday),
+            "saturday" => Some(Self::Saturday),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Preferences {
+    pub default_sort: TodoSort,
+    pub week_start: WeekStart,
+}
+
+/// The account as its user sees it.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct Profile {
+    pub id: i32,
+    pub username: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub two_factor_enabled: bool,
+    pub role: String,
+    pub display_name: Option<String>,
+    pub avatar_url: Option<String>,
+    /// An IANA time zone such as `Europe/Berlin`.
+    pub time_zone: Option<String>,
+    /// A BCP 47 language tag such as `en-GB`.
+    pub locale: Option<String>,
+    pub preferences: Preferences,
+}
+
+impl From<User> for Profile {
+    fn from(user: User) -> Self {
+        Self {
+            id: user.id,
+            email_verified: user.email_verified_at.is_some(),
+            two_factor_enabled: user.totp_enabled_at.is_some(),
+            preferences: Preferences {
+                default_sort: TodoSort::parse(&user.default_sort).unwrap_or_default(),
+                week_start: WeekStart::parse(&user.week_start).unwrap_or_default(),
+            },
+            username: user.username,
+            email: user.email,
+            role: user.role,
+            display_name: user.display_name,
+            avatar_url: user.avatar_url,
+            time_zone: user.time_zone,
+            locale: user.locale,
+        }
+    }
+}
+
+/// The body of `PATCH /users/me`. Fields left out stay as they are, and an
+/// empty string clears a text field.
+#[derive(Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct UpdateProfileBody {
+    /// At most 100 characters.
+    pub display_name: Option<String>,
+    /// An `http` or `https` URL.
+    pub avatar_url: Option<String>,
+    pub time_zone: Option<String>,
+    pub locale: Option<String>,
+    pub preferences: Option<UpdatePreferences>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct UpdatePreferences {
+    pub default_sort: Option<TodoSort>,
+    pub week_start: Option<WeekStart>,
+}
+
+/// The columns `PATCH /users/me` changes; `None` leaves one as it is.
+#[derive(AsChangeset, Default)]
+#[table_name = "users"]
+pub struct ProfileChanges {
+    pub display_name: Option<Option<String>>,
+    pub avatar_url: Option<Option<String>>,
+    pub time_zone: Option<Option<String>>,
+    pub locale: Option<Option<String>>,
+    pub default_sort: Option<String>,
+    pub week_start: Option<String>,
+}
+
+impl ProfileChanges {
+    pub fn is_empty(&self) -> bool {
+        self.display_name.is_none()
+            && self.avatar_url.is_none()
+            && self.time_zone.is_none()
+            && self.locale.is_none()
+            && self.default_sort.is_none()
+            && self.week_start.is_none()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ChangeUsernameBody {
+    /// 3 to 32 letters, digits, dots, underscores and dashes, not taken by
+    /// another account in any case.
+    pub username: String,
+}
+
+/// What other users see of an account.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PublicProfile {
+    pub id: i32,
+    pub username: String,
+    pub display_name: Option<String>,
+    pub avatar_url: Option<String>,
+}
+
+impl From<User> for PublicProfile {
+    fn from(user: User) -> Self {
+        Self {
+            id: user.id,
+            username: user.username,
+            display_name: user.display_name,
+            avatar_url: user.avatar_url,
+        }
+    }
+}
+
+/// How a login hands over the token.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum LoginMode {
+    /// In the response body, to send in the `Authorization` header.
+    Token,
+    /// In an HttpOnly session cookie, for browsers.
+    Cookie,
+}
+
+#[derive(Debug, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct LoginModeQuery {
+    /// `token` unless given.
+    pub mode: Option<LoginMode>,
+}
+
+/// What a login in cookie mode returns; the token is in the session cookie.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct SessionUser {
+    pub username: String,
+    pub id: i32,
+    /// Send it in the `X-CSRF-Token` header with every request that changes
+    /// something. It is also in the `todos_csrf` cookie.
+    pub csrf_token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CalendarSubscription {
+    pub url: String,
+}
+
+/// What a CalDAV client logs in with. The password is only shown once.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct CalDavPassword {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ImportSummary {
+    pub created: Vec<Todo>,
+    pub skipped: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TodoTxtImport {
+    pub dry_run: bool,
+    /// Everything that was read from the file.
+    pub tasks: Vec<crate::todotxt::Task>,
+    /// The todos that were created; always empty for dry runs.
+    pub created: Vec<Todo>,
+}
+
+/// A GraphQL subscription, as query parameters so that `EventSource` can send it.
+#[derive(Debug, Serialize, Deserialize, IntoParams)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
+pub struct GraphQLStreamQuery {
+    /// A document with a single `subscription` operation.
+    pub query: String,
+    pub operation_name: Option<String>,
+    /// The variables as a JSON object.
+    pub variables: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ImportOptions {
+    /// Only report what would be created.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum FileFormat {
+    /// One row per todo under a fixed header.
+    Csv,
+    /// A GitHub-style `- [ ]` / `- [x]` checklist.
+    Markdown,
+}
+
+#[derive(Debug, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportOptions {
+    pub format: FileFormat,
+}
+
+#[derive(Debug, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FileImportOptions {
+    pub format: FileFormat,
+    /// CSV only: the header of the column holding the text. Defaults to `text`.
+    pub text_column: Option<String>,
+    /// CSV only: defaults to `done`.
+    pub done_column: Option<String>,
+    /// CSV only: defaults to `priority`.
+    pub priority_column: Option<String>,
+    /// CSV only: defaults to `created_at`.
+    pub created_at_column: Option<String>,
+    /// CSV only: defaults to `completed_at`.
+    pub completed_at_column: Option<String>,
+}
+
+/// Why one line of an imported file was rejected.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct LineError {
+    /// 1-based, counting the header of CSV files.
+    pub line: usize,
+    pub message: String,
+}
+
+/// Tools whose exports `POST /import/{provider}` understands.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum ImportProvider {
+    Todoist,
+    Trello,
+    MicrosoftTodo,
+}
+
+impl ImportProvider {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Todoist => "todoist",
+            Self::Trello => "trello",
+            Self::MicrosoftTodo => "microsoft-todo",
+        }
+    }
+}
+
+#[derive(Queryable, Serialize, Deserialize, Debug, Clone, Identifiable, ToSchema)]
+pub struct ImportJob {
+    pub id: i32,
+    pub user_id: i32,
+    pub provider: String,
+    /// `pending`, `running`, `succeeded` or `failed`.
+    pub status: String,
+    pub created_count: i32,
+    /// Why the job failed.
+    pub error: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub finished_at: Option<NaiveDateTime>,
+    /// The workspace the todos are imported into.
+    pub workspace_id: Option<i32>,
+}
+
+impl ImportJob {
+    /// Whose todos the job creates.
+    pub fn tenant(&self) -> Tenant {
+        match self.workspace_id {
+            Some(workspace_id) => Tenant::Workspace {
+                workspace_id,
+                user_id: self.user_id,
+            },
+            None => Tenant::Personal {
+                user_id: self.user_id,
+            },
+        }
+    }
+}
+
+#[derive(Insertable)]
+#[table_name = "import_jobs"]
+pub struct NewImportJob<'a> {
+    pub user_id: i32,
+    pub workspace_id: Option<i32>,
+    pub provider: &'a str,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ArchiveFormat {
+    #[default]
+    Json,
+    /// The JSON document as `account.json` in a ZIP file.
+    Zip,
+}
+
+#[derive(Debug, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AccountExportOptions {
+    #[serde(default)]
+    pub format: ArchiveFormat,
+}
+
+/// What to do with an archived todo whose UID the account already has.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictStrategy {
+    /// Keep the existing todo.
+    #[default]
+    Skip,
+    /// Replace the existing todo's contents with the archived ones.
+    Overwrite,
+    /// Create the archived todo as well, under a new UID.
+    Duplicate,
+}
+
+#[derive(Debug, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AccountImportOptions {
+    #[serde(default)]
+    pub strategy: ConflictStrategy,
+}
+
+/// Which todo an archived todo ended up as.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct IdMapping {
+    /// The id in the archive.
+    pub archived: i32,
+    pub id: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct AccountImportSummary {
+    pub created: Vec<Todo>,
+    pub overwritten: Vec<Todo>,
+    pub skipped: usize,
+    /// Every archived todo that was created or overwritten.
+    pub ids: Vec<IdMapping>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct DeleteAccountBody {
+    /// The current password, to confirm the deletion.
+    pub password: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct AccountDeletion {
+    pub message: String,
+    /// Until then, `POST /users/restore` brings the account back.
+    pub purge_after: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ChangePasswordBody {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PasswordResetRequest {
+    pub username: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct PasswordResetBody {
+    /// The token from the reset email.
+    pub token: String,
+    pub new_password: String,
+}
+
+#[derive(Queryable, Debug, Clone, Identifiable)]
+pub struct PasswordResetToken {
+    pub id: i32,
+    pub user_id: i32,
+    pub token_hash: String,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub used_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[table_name = "password_reset_tokens"]
+pub struct NewPasswordResetToken {
+    pub user_id: i32,
+    pub token_hash: String,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "password_reset_requests"]
+pub struct NewPasswordResetRequest {
+    pub user_id: Option<i32>,
+    pub ip: Option<String>,
+}
+
+/// Failed logins for a `username` or an `ip`.
+#[derive(Queryable, Insertable, Debug, Clone)]
+pub struct LoginThrottle {
+    pub scope: String,
+    pub subject: String,
+    pub failures: i32,
+    pub last_failed_at: NaiveDateTime,
+    pub locked_until: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct VerifyEmailBody {
+    /// The token from the verification email.
+    pub token: String,
+}
+
+#[derive(Queryable, Debug, Clone, Identifiable)]
+pub struct EmailVerificationToken {
+    pub id: i32,
+    pub user_id: i32,
+    pub token_hash: String,
+    pub email: String,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub used_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[table_name = "email_verification_tokens"]
+pub struct NewEmailVerificationToken {
+    pub user_id: i32,
+    pub token_hash: String,
+    pub email: String,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TotpEnrollment {
+    /// The base32 secret, for apps that can't scan the QR code.
+    pub secret: String,
+    pub provisioning_uri: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TotpCodeBody {
+    pub code: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct RecoveryCodes {
+    /// Each can stand in for a TOTP code once. They aren't shown again.
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum QrFormat {
+    Svg,
+    Png,
+}
+
+#[derive(Debug, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QrOptions {
+    /// `svg` unless given.
+    pub format: Option<QrFormat>,
+}
+
+/// What `POST /login` returns when the password was right but the account
+/// also needs a second factor.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TwoFactorChallenge {
+    /// Exchange it with a code at `POST /login/2fa`.
+    pub challenge_token: String,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TwoFactorLoginBody {
+    pub challenge_token: String,
+    /// A TOTP code or one of the recovery codes.
+    pub code: String,
+}
+
+/// The result of checking a password.
+#[derive(Debug)]
+pub enum LoginOutcome {
+    LoggedIn(JwtUser),
+    TwoFactorRequired(TwoFactorChallenge),
+}
+
+#[derive(Insertable)]
+#[table_name = "totp_recovery_codes"]
+pub struct NewRecoveryCode {
+    pub user_id: i32,
+    pub code_hash: String,
+}
+
+#[derive(Queryable, Debug, Clone, Identifiable)]
+#[table_name = "oauth_clients"]
+pub struct OAuthClient {
+    pub id: i32,
+    pub client_id: String,
+    pub(crate) client_secret_hash: Option<String>,
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    pub user_id: i32,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "oauth_clients"]
+pub struct NewOAuthClient {
+    pub client_id: String,
+    pub client_secret_hash: Option<String>,
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    pub user_id: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct NewOAuthClientBody {
+    /// Shown to users on the consent page.
+    pub name: String,
+    /// HTTPS URIs, or HTTP ones on the loopback interface for native apps.
+    pub redirect_uris: Vec<String>,
+    /// Whether the app can keep a secret, i.e. runs on a server. Public
+    /// clients rely on PKCE alone.
+    #[serde(default)]
+    pub confidential: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct OAuthClientInfo {
+    pub client_id: String,
+    /// Only returned when the client is registered; it isn't shown again.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_secret: Option<String>,
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    pub confidential: bool,
+    pub created_at: NaiveDateTime,
+}
+
+impl From<OAuthClient> for OAuthClientInfo {
+    fn from(client: OAuthClient) -> Self {
+        Self {
+            client_id: client.client_id,
+            client_secret: None,
+            name: client.name,
+            redirect_uris: client.redirect_uris,
+            confidential: client.client_secret_hash.is_some(),
+            created_at: client.created_at,
+        }
+    }
+}
+
+/// The query of `GET /oauth/authorize`.
+#[derive(Debug, Clone, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AuthorizeRequest {
+    /// Must be `code`.
+    pub response_type: String,
+    pub client_id: String,
+    /// One of the client's redirect URIs; optional if it has only one.
+    pub redirect_uri: Option<String>,
+    /// Space-separated: `todos:read` (the default) and `todos:write`.
+    pub scope: Option<String>,
+    pub state: Option<String>,
+    /// The base64url SHA-256 of the code verifier.
+    pub code_challenge: Option<String>,
+    /// Must be `S256`.
+    pub code_challenge_method: Option<String>,
+}
+
+/// What the consent page posts to `POST /oauth/authorize`.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ConsentForm {
+    pub response_type: String,
+    pub client_id: String,
+    pub redirect_uri: Option<String>,
+    pub scope: Option<String>,
+    pub state: Option<String>,
+    pub code_challenge: Option<String>,
+    pub code_challenge_method: Option<String>,
+    /// `approve` or `deny`.
+    pub decision: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub two_factor_code: Option<String>,
+}
+
+impl ConsentForm {
+    pub fn request(&self) -> AuthorizeRequest {
+        AuthorizeRequest {
+            response_type: self.response_type.clone(),
+            client_id: self.client_id.clone(),
+            redirect_uri: self.redirect_uri.clone(),
+            scope: self.scope.clone(),
+            state: self.state.clone(),
+            code_challenge: self.code_challenge.clone(),
+            code_challenge_method: self.code_challenge_method.clone(),
+        }
+    }
+}
+
+/// Where `/oauth/authorize` sends the browser next.
+#[derive(Debug)]
+pub enum AuthorizeOutcome {
+    /// Back to the app, with a code or an error.
+    Redirect(String),
+    /// The consent page, with the HTML to show.
+    Consent(String),
+}
+
+#[derive(Queryable, Debug, Clone, Identifiable)]
+#[table_name = "oauth_authorization_codes"]
+pub struct OAuthAuthorizationCode {
+    pub id: i32,
+    pub code_hash: String,
+    pub oauth_client_id: i32,
+    pub user_id: i32,
+    pub redirect_uri: String,
+    pub scope: String,
+    pub code_challenge: String,
+    pub expires_at: NaiveDateTime,
+    pub used_at: Option<NaiveDateTime>,
+    /// Whether the authorization request named `redirect_uri`, which the
+    /// token request then has to repeat.
+    pub redirect_uri_sent: bool,
+}
+
+#[derive(Insertable)]
+#[table_name = "oauth_authorization_codes"]
+pub struct NewOAuthAuthorizationCode {
+    pub code_hash: String,
+    pub oauth_client_id: i32,
+    pub user_id: i32,
+    pub redirect_uri: String,
+    pub scope: String,
+    pub code_challenge: String,
+    pub expires_at: NaiveDateTime,
+    pub redirect_uri_sent: bool,
+}
+
+#[derive(Queryable, Debug, Clone, Identifiable)]
+#[table_name = "oauth_tokens"]
+pub struct OAuthToken {
+    pub id: i32,
+    pub oauth_client_id: i32,
+    pub user_id: i32,
+    pub authorization_code_id: Option<i32>,
+    pub access_token_hash: String,
+    pub refresh_token_hash: String,
+    pub scope: String,
+    pub access_expires_at: NaiveDateTime,
+    pub refresh_expires_at: NaiveDateTime,
+    pub created_at: NaiveDateTime,
+    pub revoked_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[table_name = "oauth_tokens"]
+pub struct NewOAuthToken {
+    pub oauth_client_id: i32,
+    pub user_id: i32,
+    pub authorization_code_id: Option<i32>,
+    pub access_token_hash: String,
+    pub refresh_token_hash: String,
+    pub scope: String,
+    pub access_expires_at: NaiveDateTime,
+    pub refresh_expires_at: NaiveDateTime,
+}
+
+/// The form of `POST /oauth/token`. Confidential clients authenticate with
+/// HTTP Basic or `client_secret`.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TokenRequest {
+    /// `authorization_code` or `refresh_token`.
+    pub grant_type: String,
+    pub code: Option<String>,
+    /// With `authorization_code`, required if the authorization request named
+    /// one, and then the same.
+    pub redirect_uri: Option<String>,
+    pub code_verifier: Option<String>,
+    pub refresh_token: Option<String>,
+    /// With `refresh_token`, narrows the scope of the new tokens.
+    pub scope: Option<String>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TokenResponse {
+    pub access_token: String,
+    /// Always `Bearer`.
+    pub token_type: String,
+    /// Seconds until the access token expires.
+    pub expires_in: i64,
+    pub refresh_token: String,
+    pub scope: String,
+}
+
+/// The form of `POST /oauth/introspect` and `POST /oauth/revoke`.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct TokenForm {
+    pub token: String,
+    /// `access_token` or `refresh_token`; the token's prefix decides anyway.
+    pub token_type_hint: Option<String>,
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+}
+
+/// RFC 7662's introspection response. Inactive tokens only have `active`.
+#[derive(Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct IntrospectionResponse {
+    pub active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exp: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iat: Option<i64>,
+    /// The user's id.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+}
+
+#[derive(Queryable, Debug, Clone, Identifiable)]
+#[table_name = "oidc_identities"]
+pub struct OidcIdentity {
+    pub id: i32,
+    pub user_id: i32,
+    pub issuer: String,
+    pub subject: String,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "oidc_identities"]
+pub struct NewOidcIdentity {
+    pub user_id: i32,
+    pub issuer: String,
+    pub subject: String,
+}
+
+#[derive(Queryable, Insertable, Debug)]
+#[table_name = "oidc_login_states"]
+pub struct OidcLoginState {
+    pub state_hash: String,
+    pub nonce: String,
+    pub code_verifier: String,
+    pub expires_at: NaiveDateTime,
+}
+
+/// The query the OpenID Connect provider sends the browser back to
+/// `GET /oidc/callback` with.
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct OidcCallbackQuery {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    /// Set instead of `code` if the login failed at the provider.
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+#[derive(Queryable, Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct Role {
+    pub name: String,
+    pub description: String,
+    /// See `Permission`; names this version doesn't know are ignored.
+    pub permissions: Vec<String>,
+    /// `user` and `admin`, which can't be changed or deleted.
+    pub built_in: bool,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "roles"]
+pub struct NewRole {
+    pub name: String,
+    pub description: String,
+    pub permissions: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct NewRoleBody {
+    /// Lowercase letters, digits, `-` and `_`.
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub permissions: Vec<crate::roles::Permission>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct UpdateRoleBody {
+    pub description: Option<String>,
+    /// Replaces the role's permissions.
+    pub permissions: Option<Vec<crate::roles::Permission>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct SetRoleBody {
+    pub role: String,
+}
+
+/// A user as administrators see them.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct AdminUser {
+    pub id: i32,
+    pub username: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    pub role: String,
+    pub two_factor_enabled: bool,
+    /// When an administrator disabled the account.
+    pub disabled_at: Option<NaiveDateTime>,
+    /// When the user asked for the account to be deleted.
+    pub deleted_at: Option<NaiveDateTime>,
+    pub password_reset_required: bool,
+}
+
+impl From<User> for AdminUser {
+    fn from(user: User) -> Self {
+        Self {
+            id: user.id,
+            username: user.username,
+            email: user.email,
+            email_verified: user.email_verified_at.is_some(),
+            role: user.role,
+            two_factor_enabled: user.totp_enabled_at.is_some(),
+            disabled_at: user.disabled_at,
+            deleted_at: user.deleted_at,
+            password_reset_required: user.password_reset_required,
+        }
+    }
+}
+
+/// The query of `GET /admin/users`.
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct UserSearchQuery {
+    /// Part of the username or email address, in any case.
+    pub q: Option<String>,
+    pub role: Option<String>,
+    /// Only disabled accounts, or only the others.
+    pub disabled: Option<bool>,
+    /// Starting at 1.
+    pub page: Option<i64>,
+    /// 50 by default, at most 200.
+    pub per_page: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct UserPage {
+    pub users: Vec<AdminUser>,
+    /// How many users match, on all pages.
+    pub total: i64,
+    pub page: i64,
+    pub per_page: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct UserStats {
+    pub user_id: i32,
+    pub todo_count: i64,
+    pub done_todo_count: i64,
+    pub import_job_count: i64,
+    pub oauth_client_count: i64,
+    /// OAuth tokens that are neither revoked nor past refreshing.
+    pub active_oauth_grant_count: i64,
+    pub linked_identity_count: i64,
+    /// When one of the user's todos last changed.
+    pub last_todo_change_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct ForcedPasswordReset {
+    /// Whether a reset token was mailed; without a verified address, the
+    /// user has to be sent one some other way.
+    pub email_sent: bool,
+}
+
+/// Whose todos a request sees: the user's own, or those of a workspace the
+/// user is a member of, selected with the `X-Workspace` header.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tenant {
+    Personal { user_id: i32 },
+    Workspace { workspace_id: i32, user_id: i32 },
+}
+
+impl Tenant {
+    /// The user acting, who creates todos in either case.
+    pub fn user_id(self) -> i32 {
+        match self {
+            Self::Personal { user_id } | Self::Workspace { user_id, .. } => user_id,
+        }
+    }
+
+    pub fn workspace_id(self) -> Option<i32> {
+        match self {
+            Self::Personal { .. } => None,
+            Self::Workspace { workspace_id, .. } => Some(workspace_id),
+        }
+    }
+
+    /// Whether `todo` is one of the tenant's todos.
+    pub fn owns(self, todo: &Todo) -> bool {
+        match self {
+            Self::Personal { user_id } => {
+                todo.workspace_id.is_none() && todo.user_id == Some(user_id)
+            }
+            Self::Workspace { workspace_id, .. } => todo.workspace_id == Some(workspace_id),
+        }
+    }
+}
+
+/// What a member may do in a workspace, from least to most.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum WorkspaceRole {
+    /// Working with the workspace's todos.
+    #[default]
+    Member,
+    /// Also inviting and removing members.
+    Admin,
+    /// Also changing roles and deleting the workspace.
+    Owner,
+}
+
+impl WorkspaceRole {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Member => "member",
+            Self::Admin => "admin",
+            Self::Owner => "owner",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<WorkspaceRole> {
+        match name {
+            "member" => Some(Self::Member),
+            "admin" => Some(Self::Admin),
+            "owner" => Some(Self::Owner),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Queryable, Debug, Clone, Identifiable)]
+#[table_name = "workspaces"]
+pub struct Workspace {
+    pub id: i32,
+    pub name: String,
+    pub slug: String,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[table_name = "workspaces"]
+pub struct NewWorkspace {
+    pub name: String,
+    pub slug: String,
+}
+
+#[derive(Queryable, Insertable, Debug, Clone)]
+#[table_name = "workspace_members"]
+pub struct WorkspaceMember {
+    pub workspace_id: i32,
+    pub user_id: i32,
+    pub role: String,
+    pub joined_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct NewWorkspaceBody {
+    pub name: String,
+    /// Lowercase letters, digits and `-`, not only digits; made from the
+    /// name if left out.
+    pub slug: Option<String>,
+}
+
+/// A workspace as one of its members sees it.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct WorkspaceInfo {
+    pub id: i32,
+    pub name: String,
+    pub slug: String,
+    /// The member's own role.
+    pub role: WorkspaceRole,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct MemberInfo {
+    pub user_id: i32,
+    pub username: String,
+    pub role: WorkspaceRole,
+    pub joined_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct UpdateMemberBody {
+    pub role: WorkspaceRole,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct NewInvitationBody {
+    /// Where the invitation is mailed; only a user with this verified
+    /// address can accept it.
+    pub email: String,
+    /// `member` or `admin`.
+    #[serde(default)]
+    pub role: WorkspaceRole,
+}
+
+#[derive(Queryable, Debug, Clone, Identifiable)]
+#[table_name = "workspace_invitations"]
+pub struct WorkspaceInvitation {
+    pub id: i32,
+    pub workspace_id: i32,
+    pub token_hash: String,
+    pub email: String,
+    pub role: String,
+    pub invited_by: i32,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub accepted_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[table_name = "workspace_invitations"]
+pub struct NewWorkspaceInvitation {
+    pub workspace_id: i32,
+    pub token_hash: String,
+    pub email: String,
+    pub role: String,
+    pub invited_by: i32,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct InvitationInfo {
+    pub id: i32,
+    pub email: String,
+    pub role: WorkspaceRole,
+    pub invited_by: i32,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub accepted_at: Option<NaiveDateTime>,
+}
+
+impl From<WorkspaceInvitation> for InvitationInfo {
+    fn from(invitation: WorkspaceInvitation) -> Self {
+        Self {
+            id: invitation.id,
+            email: invitation.email,
+            role: WorkspaceRole::parse(&invitation.role).unwrap_or_default(),
+            invited_by: invitation.invited_by,
+            created_at: invitation.created_at,
+            expires_at: invitation.expires_at,
+            accepted_at: invitation.accepted_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct AcceptInvitationBody {
+    /// The token from the invitation mail.
+    pub token: String,
+}
+
+/// An entry of the security log; see `audit::AuditEventKind` for `event`.
+#[derive(Queryable, Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct AuditEvent {
+    pub id: i64,
+    pub occurred_at: NaiveDateTime,
+    pub event: String,
+    /// The account the event is about, if any; it may since be deleted.
+    pub user_id: Option<i32>,
+    /// The account's name at the time, or the login that was tried.
+    pub username: Option<String>,
+    /// The administrator who did it, for events about someone else.
+    pub actor_id: Option<i32>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub details: Option<String>,
+}
+
+#[derive(Insertable, Debug, Default)]
+#[table_name = "audit_events"]
+pub struct NewAuditEvent {
+    pub event: String,
+    pub user_id: Option<i32>,
+    pub username: Option<String>,
+    pub actor_id: Option<i32>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub details: Option<String>,
+}
+
+/// The query of `GET /users/me/security-log`.
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SecurityLogQuery {
+    /// Starting at 1.
+    pub page: Option<i64>,
+    /// 50 by default, at most 200.
+    pub per_page: Option<i64>,
+}
+
+/// The query of `GET /admin/audit-events`.
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AuditEventQuery {
+    /// Events about this account, or done by it as an administrator.
+    pub user_id: Option<i32>,
+    /// Such as `login.failed`.
+    pub event: Option<String>,
+    pub ip: Option<String>,
+    /// Events at or after this time, in UTC.
+    pub since: Option<NaiveDateTime>,
+    /// Events before this time, in UTC.
+    pub until: Option<NaiveDateTime>,
+    /// Starting at 1.
+    pub page: Option<i64>,
+    /// 50 by default, at most 200.
+    pub per_page: Option<i64>,
+}
+
+/// Events, newest first.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct AuditEventPage {
+    pub events: Vec<AuditEvent>,
+    /// How many events match, on all pages.
+    pub total: i64,
+    pub page: i64,
+    pub per_page: i64,
+}
+
+#[derive(Queryable, Debug, Clone, Identifiable)]
+pub struct Session {
+    pub id: i32,
+    pub user_id: i32,
+    pub created_at: NaiveDateTime,
+    pub last_seen_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub client_name: Option<String>,
+    pub revoked_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[table_name = "sessions"]
+pub struct NewSession {
+    pub user_id: i32,
+    pub expires_at: NaiveDateTime,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub client_name: Option<String>,
+}
+
+/// A device the user is logged in on.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct SessionInfo {
+    pub id: i32,
+    /// When the user logged in.
+    pub created_at: NaiveDateTime,
+    /// When the token was last used, to the minute.
+    pub last_seen_at: NaiveDateTime,
+    /// When the token handed out at login expires.
+    pub expires_at: NaiveDateTime,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    /// What the client called itself in the `X-Client-Name` header.
+    pub client_name: Option<String>,
+    /// Whether this is the session of the token the list was asked for with.
+    pub current: bool,
+}
@@ -0,0 +1,337 @@
+//! The parts of the OAuth 2.0 authorization server (RFC 6749) that don't
+//! touch the database: scopes, PKCE (RFC 7636), error responses, redirect
+//! URIs and the consent page.
+
+use std::fmt::Write;
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::models::AuthorizeRequest;
+
+/// Access and refresh tokens are opaque; the prefixes tell them apart from
+/// each other and from our own JWTs.
+pub const ACCESS_TOKEN_PREFIX: &str = "todos_at_";
+pub const REFRESH_TOKEN_PREFIX: &str = "todos_rt_";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Scope {
+    /// Listing and reading todos, including the calendar export and events.
+    TodosRead,
+    /// Creating, changing and deleting todos.
+    TodosWrite,
+}
+
+impl Scope {
+    pub const ALL: &'static [Scope] = &[Scope::TodosRead, Scope::TodosWrite];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::TodosRead => "todos:read",
+            Self::TodosWrite => "todos:write",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::TodosRead => "See your todos",
+            Self::TodosWrite => "Create, change and delete your todos",
+        }
+    }
+
+    /// Parses a space-separated scope list, `todos:read` if empty.
+    pub fn parse_list(list: Option<&str>) -> Result<Vec<Scope>, OAuthError> {
+        let mut scopes = Vec::new();
+        for name in list
+            .unwrap_or_default()
+            .split(' ')
+            .filter(|s| !s.is_empty())
+        {
+            let scope = Self::ALL
+                .iter()
+                .copied()
+                .find(|scope| scope.as_str() == name)
+                .ok_or_else(|| {
+                    OAuthError::new(
+                        OAuthErrorCode::InvalidScope,
+                        format!("`{}` is not a scope.", name),
+                    )
+                })?;
+            scopes.push(scope);
+        }
+        if scopes.is_empty() {
+            scopes.push(Self::TodosRead);
+        }
+        scopes.sort();
+        scopes.dedup();
+        Ok(scopes)
+    }
+
+    pub fn join(scopes: &[Scope]) -> String {
+        scopes
+            .iter()
+            .map(|scope| scope.as_str())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum OAuthErrorCode {
+    InvalidRequest,
+    InvalidClient,
+    InvalidGrant,
+    UnauthorizedClient,
+    UnsupportedGrantType,
+    UnsupportedResponseType,
+    InvalidScope,
+    AccessDenied,
+}
+
+impl OAuthErrorCode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::InvalidRequest => "invalid_request",
+            Self::InvalidClient => "invalid_client",
+            Self::InvalidGrant => "invalid_grant",
+            Self::UnauthorizedClient => "unauthorized_client",
+            Self::UnsupportedGrantType => "unsupported_grant_type",
+            Self::UnsupportedResponseType => "unsupported_response_type",
+            Self::InvalidScope => "invalid_scope",
+            Self::AccessDenied => "access_denied",
+        }
+    }
+}
+
+/// An error response as RFC 6749 defines them.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct OAuthError {
+    pub error: OAuthErrorCode,
+    pub error_description: String,
+}
+
+impl OAuthError {
+    pub fn new(error: OAuthErrorCode, description: impl Into<String>) -> Self {
+        Self {
+            error,
+            error_description: description.into(),
+        }
+    }
+}
+
+/// The S256 code challenge of a PKCE code verifier.
+pub fn pkce_challenge(code_verifier: &str) -> String {
+    use sha2::Digest;
+    let digest = sha2::Sha256::digest(code_verifier.as_bytes());
+    base64::encode_config(digest, base64::URL_SAFE_NO_PAD)
+}
+
+/// Checks an S256 code challenge against the verifier the client sends with
+/// the code.
+pub fn verify_pkce(code_challenge: &str, code_verifier: &str) -> bool {
+    let valid_verifier = (43..=128).contains(&code_verifier.len())
+        && code_verifier
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"-._~".contains(&byte));
+    valid_verifier && pkce_challenge(code_verifier) == code_challenge
+}
+
+/// Whether a client may register a redirect URI: HTTPS, or plain HTTP to the
+/// loopback interface for native apps (RFC 8252), without a fragment.
+pub fn is_valid_redirect_uri(uri: &str) -> bool {
+    match url::Url::parse(uri) {
+        Ok(url) => {
+            let loopback = matches!(
+                url.host_str(),
+                Some("localhost") | Some("127.0.0.1") | Some("[::1]")
+            );
+            url.fragment().is_none()
+                && (url.scheme() == "https" || (url.scheme() == "http" && loopback))
+        }
+        Err(_) => false,
+    }
+}
+
+/// `redirect_uri` with `params` added to its query.
+pub fn redirect_uri_with(redirect_uri: &str, params: &[(&str, &str)]) -> String {
+    let mut url = url::Url::parse(redirect_uri).expect("Registered redirect URIs are valid.");
+    url.query_pairs_mut().extend_pairs(params);
+    url.to_string()
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn page(title: &str, body: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{title}</title>
+<style>
+body {{ font-family: sans-serif; max-width: 26rem; margin: 3rem auto; padding: 0 1rem; }}
+label, input, button {{ display: block; width: 100%; margin-top: .5rem; box-sizing: border-box; }}
+.error {{ color: #b00020; }}
+</style>
+</head>
+<body>
+{body}
+</body>
+</html>
+"#,
+        title = html_escape(title),
+        body = body
+    )
+}
+
+/// The page asking the user to log in and allow an app access. `message` is
+/// shown above the form, after a failed attempt.
+pub fn consent_page(
+    client_name: &str,
+    request: &AuthorizeRequest,
+    scopes: &[Scope],
+    message: Option<&str>,
+) -> String {
+    let hidden = |name: &str, value: Option<&str>| match value {
+        Some(value) => format!(
+            r#"<input type="hidden" name="{}" value="{}">"#,
+            name,
+            html_escape(value)
+        ),
+        None => String::new(),
+    };
+    let scope_list = scopes.iter().fold(String::new(), |mut list, scope| {
+        let _ = write!(list, "<li>{}</li>", scope.description());
+        list
+    });
+    let body = format!(
+        r#"<h1>Allow {client} access?</h1>
+<p><strong>{client}</strong> would like to:</p>
+<ul>{scope_list}</ul>
+{message}
+<form method="post" action="/oauth/authorize">
+{fields}
+<label>Username or email address <input name="username" autocomplete="username" required></label>
+<label>Password <input name="password" type="password" autocomplete="current-password" required></label>
+<label>Two-factor code, if enabled <input name="two_factor_code" autocomplete="one-time-code"></label>
+<button name="decision" value="approve">Allow</button>
+<button name="decision" value="deny" formnovalidate>Deny</button>
+</form>
+"#,
+        client = html_escape(client_name),
+        scope_list = scope_list,
+        message = message
+            .map(|message| format!(r#"<p class="error">{}</p>"#, html_escape(message)))
+            .unwrap_or_default(),
+        fields = [
+            hidden("response_type", Some(&request.response_type)),
+            hidden("client_id", Some(&request.client_id)),
+            hidden("redirect_uri", request.redirect_uri.as_deref()),
+            hidden("scope", Some(&Scope::join(scopes))),
+            hidden("state", request.state.as_deref()),
+            hidden("code_challenge", request.code_challenge.as_deref()),
+            hidden(
+                "code_challenge_method",
+                request.code_challenge_method.as_deref()
+            ),
+        ]
+        .concat(),
+    );
+    page(&format!("Allow {} access?", client_name), &body)
+}
+
+/// The page shown when the request can't be sent back to the app, because
+/// the app or its redirect URI are unknown.
+pub fn error_page(message: &str) -> String {
+    page(
+        "Authorization failed",
+        &format!(
+            "<h1>Authorization failed</h1>\n<p>{}</p>",
+            html_escape(message)
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verifies_rfc_7636_s256_example() {
+        let verifier = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+        let challenge = "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM";
+        assert_eq!(pkce_challenge(verifier), challenge);
+        assert!(verify_pkce(challenge, verifier));
+        assert!(!verify_pkce(
+            challenge,
+            "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXl"
+        ));
+    }
+
+    #[test]
+    fn bounds_verifier_length() {
+        for (length, valid) in &[(42, false), (43, true), (128, true), (129, false)] {
+            let verifier = "a".repeat(*length);
+            assert_eq!(
+                verify_pkce(&pkce_challenge(&verifier), &verifier),
+                *valid,
+                "length {}",
+                length
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_verifier_characters_outside_unreserved() {
+        let verifier = format!("{}+/=", "a".repeat(43));
+        assert!(!verify_pkce(&pkce_challenge(&verifier), &verifier));
+        let verifier = format!("{}-._~", "A0".repeat(22));
+        assert!(verify_pkce(&pkce_challenge(&verifier), &verifier));
+    }
+
+    #[test]
+    fn accepts_https_and_loopback_http_redirects() {
+        for uri in &[
+            "https://app.example.com/callback",
+            "https://app.example.com/callback?source=todos",
+            "http://localhost/callback",
+            "http://localhost:8123/callback",
+            "http://127.0.0.1:8123/callback",
+            "http://[::1]:8123/callback",
+        ] {
+            assert!(is_valid_redirect_uri(uri), "{}", uri);
+        }
+    }
+
+    #[test]
+    fn rejects_other_redirects() {
+        for uri in &[
+            "http://app.example.com/callback",
+            "http://localhost.example.com/callback",
+            "https://app.example.com/callback#fragment",
+            "ftp://app.example.com/callback",
+            "com.example.app:/callback",
+            "/callback",
+            "",
+        ] {
+            assert!(!is_valid_redirect_uri(uri), "{}", uri);
+        }
+    }
+
+    #[test]
+    fn escapes_html() {
+        assert_eq!(
+            html_escape(r#"<a href="x" title='y'>Tom & Jerry</a>"#),
+            "&lt;a href=&quot;x&quot; title=&#39;y&#39;&gt;Tom &amp; Jerry&lt;/a&gt;"
+        );
+        assert_eq!(html_escape("plain text"), "plain text");
+    }
+}
@@ -0,0 +1,394 @@
+//! Logging in with an external OpenID Connect provider: discovery, the
+//! authorization code flow with PKCE, and checking ID tokens against the
+//! provider's published keys.
+//!
+//! The provider is configured with `OIDC_ISSUER`, `OIDC_CLIENT_ID`,
+//! `OIDC_CLIENT_SECRET` (unless it registered us as a public client) and
+//! `OIDC_REDIRECT_URI`, the address of `GET /oidc/callback` as browsers reach
+//! it. `OIDC_SCOPES` defaults to `openid email profile`. Any issuer works,
+//! including one on plain HTTP such as the `mock-idp` binary.
+
+use std::{sync::OnceLock, time::Duration};
+
+use jsonwebtoken::{jwk::Jwk, Algorithm, DecodingKey, Validation};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer};
+
+use crate::error::TodosError;
+
+/// The algorithms ID tokens may be signed with. Tokens signed with a shared
+/// secret are refused; the provider's keys are public.
+const ALGORITHMS: &[Algorithm] = &[
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::ES384,
+    Algorithm::EdDSA,
+];
+
+pub struct Provider {
+    pub issuer: String,
+    client_id: String,
+    client_secret: Option<String>,
+    redirect_uri: String,
+    scopes: String,
+}
+
+/// The parts of the provider's discovery document that are used.
+#[derive(Debug, Deserialize)]
+pub struct Discovery {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// The provider's keys, kept as JSON so that a key of a type we can't read
+/// doesn't hide the others.
+#[derive(Deserialize)]
+struct KeySet {
+    keys: Vec<serde_json::Value>,
+}
+
+/// The claims of an ID token that identify the user.
+#[derive(Debug, Deserialize)]
+pub struct IdTokenClaims {
+    pub sub: String,
+    pub nonce: Option<String>,
+    pub email: Option<String>,
+    /// Some providers send it as a string.
+    #[serde(default, deserialize_with = "bool_or_string")]
+    pub email_verified: bool,
+    pub preferred_username: Option<String>,
+}
+
+fn bool_or_string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum BoolOrString {
+        Bool(bool),
+        String(String),
+    }
+    Ok(match BoolOrString::deserialize(deserializer)? {
+        BoolOrString::Bool(value) => value,
+        BoolOrString::String(value) => value == "true",
+    })
+}
+
+fn client() -> awc::Client {
+    awc::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .finish()
+}
+
+async fn get_json<T: DeserializeOwned>(url: &str) -> Result<T, TodosError> {
+    let provider_error =
+        |e: &dyn std::fmt::Display| TodosError::OidcProvider(format!("{}: {}", url, e));
+    let mut response = client()
+        .get(url)
+        .header("Accept", "application/json")
+        .send()
+        .await
+        .map_err(|e| provider_error(&e))?;
+    if !response.status().is_success() {
+        return Err(provider_error(&response.status()));
+    }
+    response
+        .json::<T>()
+        .limit(1 << 20)
+        .await
+        .map_err(|e| provider_error(&e))
+}
+
+impl Provider {
+    /// The provider from the environment, if `OIDC_ISSUER` is set. Fails if
+    /// it is, but the client ID or redirect URI aren't.
+    pub fn from_env() -> Result<Option<Self>, TodosError> {
+        let var = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+        let issuer = match var("OIDC_ISSUER") {
+            Some(issuer) => issuer,
+            None => return Ok(None),
+        };
+        let required = |name: &str| {
+            var(name).ok_or_else(|| {
+                log::error!("OIDC_ISSUER is set, but {} isn't.", name);
+                TodosError::OidcNotConfigured
+            })
+        };
+        Ok(Some(Self {
+            issuer,
+            client_id: required("OIDC_CLIENT_ID")?,
+            client_secret: var("OIDC_CLIENT_SECRET"),
+            redirect_uri: required("OIDC_REDIRECT_URI")?,
+            scopes: var("OIDC_SCOPES").unwrap_or_else(|| "openid email profile".to_owned()),
+        }))
+    }
+
+    /// Fetches the provider's discovery document. It isn't cached, so that
+    /// changes at the provider apply to the next login.
+    pub async fn discover(&self) -> Result<Discovery, TodosError> {
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            self.issuer.trim_end_matches('/')
+        );
+        let discovery = get_json::<Discovery>(&url).await?;
+        if discovery.issuer != self.issuer {
+            return Err(TodosError::OidcProvider(format!(
+                "{} is the discovery document of {}",
+                url, discovery.issuer
+            )));
+        }
+        Ok(discovery)
+    }
+
+    /// Where to send the browser to log in at the provider.
+    pub fn authorization_url(
+        &self,
+        discovery: &Discovery,
+        state: &str,
+        nonce: &str,
+        code_challenge: &str,
+    ) -> Result<String, TodosError> {
+        url::Url::parse_with_params(
+            &discovery.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", &self.client_id),
+                ("redirect_uri", &self.redirect_uri),
+                ("scope", &self.scopes),
+                ("state", state),
+                ("nonce", nonce),
+                ("code_challenge", code_challenge),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .map(String::from)
+        .map_err(|e| {
+            TodosError::OidcProvider(format!("{}: {}", discovery.authorization_endpoint, e))
+        })
+    }
+
+    /// Trades an authorization code for an ID token, and checks the token
+    /// against the provider's keys and the nonce the login was started with.
+    pub async fn exchange_code(
+        &self,
+        discovery: &Discovery,
+        code: &str,
+        code_verifier: &str,
+        nonce: &str,
+    ) -> Result<IdTokenClaims, TodosError> {
+        let url = &discovery.token_endpoint;
+        let provider_error =
+            |e: &dyn std::fmt::Display| TodosError::OidcProvider(format!("{}: {}", url, e));
+        let mut request = client().post(url).header("Accept", "application/json");
+        if let Some(secret) = &self.client_secret {
+            request = request.basic_auth(&self.client_id, Some(secret));
+        }
+        let mut response = request
+            .send_form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", &self.redirect_uri),
+                ("code_verifier", code_verifier),
+                ("client_id", &self.client_id),
+            ])
+            .await
+            .map_err(|e| provider_error(&e))?;
+        if !response.status().is_success() {
+            return Err(provider_error(&response.status()));
+        }
+        let tokens = response
+            .json::<TokenResponse>()
+            .limit(1 << 20)
+            .await
+            .map_err(|e| provider_error(&e))?;
+
+        let keys = get_json::<KeySet>(&discovery.jwks_uri).await?;
+        self.verify_id_token(&tokens.id_token, &keys.keys, nonce)
+    }
+
+    fn verify_id_token(
+        &self,
+        id_token: &str,
+        keys: &[serde_json::Value],
+        nonce: &str,
+    ) -> Result<IdTokenClaims, TodosError> {
+        let invalid = |reason: &str| TodosError::InvalidIdToken(reason.to_owned());
+        let header = jsonwebtoken::decode_header(id_token)
+            .map_err(|e| TodosError::InvalidIdToken(e.to_string()))?;
+        if !ALGORITHMS.contains(&header.alg) {
+            return Err(invalid("it isn't signed with a public key"));
+        }
+        let jwk = keys
+            .iter()
+            .filter_map(|key| serde_json::from_value::<Jwk>(key.clone()).ok())
+            .find(|jwk| {
+                let kid_matches = match &header.kid {
+                    Some(kid) => jwk.common.key_id.as_ref() == Some(kid),
+                    None => true,
+                };
+                kid_matches && jwk.common.algorithm.unwrap_or(header.alg) == header.alg
+            })
+            .ok_or_else(|| invalid("none of the provider's keys signed it"))?;
+        let decoding_key =
+            DecodingKey::from_jwk(&jwk).map_err(|e| TodosError::InvalidIdToken(e.to_string()))?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&self.issuer]);
+        validation.set_audience(&[&self.client_id]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        let claims = jsonwebtoken::decode::<IdTokenClaims>(id_token, &decoding_key, &validation)
+            .map_err(|e| TodosError::InvalidIdToken(e.to_string()))?
+            .claims;
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err(invalid("the nonce doesn't match"));
+        }
+        Ok(claims)
+    }
+}
+
+static PROVIDER: OnceLock<Option<Provider>> = OnceLock::new();
+
+/// Reads the provider from the environment, so that a misconfigured one
+/// stops the server at startup rather than failing the first login.
+pub fn init() -> Result<Option<&'static Provider>, TodosError> {
+    let provider = Provider::from_env()?;
+    Ok(PROVIDER.get_or_init(|| provider).as_ref())
+}
+
+/// The provider, read from the environment the first time it is needed;
+/// `None` if OpenID Connect login isn't configured, or is misconfigured.
+pub fn provider() -> Option<&'static Provider> {
+    PROVIDER
+        .get_or_init(|| Provider::from_env().ok().flatten())
+        .as_ref()
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonwebtoken::{EncodingKey, Header};
+    use serde_json::json;
+
+    use super::*;
+    use crate::keys::Keyring;
+
+    const KEYS_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/jwt");
+
+    fn provider() -> Provider {
+        Provider {
+            issuer: "https://idp.example.com".to_owned(),
+            client_id: "todos".to_owned(),
+            client_secret: None,
+            redirect_uri: "https://todos.example.com/oidc/callback".to_owned(),
+            scopes: "openid email profile".to_owned(),
+        }
+    }
+
+    /// The provider's signing key, `rsa-1` of the fixtures.
+    fn keyring() -> Keyring {
+        Keyring::from_dir(KEYS_DIR, "rsa-1").unwrap()
+    }
+
+    fn published_keys() -> Vec<serde_json::Value> {
+        keyring()
+            .jwks()
+            .keys
+            .iter()
+            .map(|jwk| serde_json::to_value(jwk).unwrap())
+            .collect()
+    }
+
+    /// Claims that pass, with `changes` merged in.
+    fn id_token(changes: serde_json::Value) -> String {
+        let mut claims = json!({
+            "iss": "https://idp.example.com",
+            "aud": "todos",
+            "sub": "248289761001",
+            "exp": chrono::Utc::now().timestamp() + 300,
+            "nonce": "n-0S6_WzA2Mj",
+            "email": "alice@example.com",
+            "email_verified": "true",
+        });
+        for (name, value) in changes.as_object().unwrap() {
+            claims[name] = value.clone();
+        }
+        keyring().encode(&claims).unwrap()
+    }
+
+    fn verify(id_token: &str) -> Result<IdTokenClaims, TodosError> {
+        provider().verify_id_token(id_token, &published_keys(), "n-0S6_WzA2Mj")
+    }
+
+    #[test]
+    fn accepts_a_valid_id_token() {
+        let claims = verify(&id_token(json!({}))).unwrap();
+        assert_eq!(claims.sub, "248289761001");
+        assert_eq!(claims.email.as_deref(), Some("alice@example.com"));
+        assert!(claims.email_verified);
+    }
+
+    #[test]
+    fn rejects_other_issuers_and_audiences() {
+        for changes in &[
+            json!({ "iss": "https://evil.example.com" }),
+            json!({ "aud": "another-client" }),
+        ] {
+            assert!(
+                matches!(
+                    verify(&id_token(changes.clone())),
+                    Err(TodosError::InvalidIdToken(_))
+                ),
+                "{}",
+                changes
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_expired_id_tokens() {
+        let expired = json!({ "exp": chrono::Utc::now().timestamp() - 3600 });
+        assert!(matches!(
+            verify(&id_token(expired)),
+            Err(TodosError::InvalidIdToken(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_other_or_missing_nonces() {
+        for changes in &[
+            json!({ "nonce": "another-login" }),
+            json!({ "nonce": null }),
+        ] {
+            assert!(
+                matches!(
+                    verify(&id_token(changes.clone())),
+                    Err(TodosError::InvalidIdToken(_))
+                ),
+                "{}",
+                changes
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_id_tokens_signed_with_a_shared_secret() {
+        let id_token = jsonwebtoken::encode(
+            &Header::new(Algorithm::HS256),
+            &json!({ "iss": "https://idp.example.com", "aud": "todos", "sub": "1" }),
+            &EncodingKey::from_secret(b"todos"),
+        )
+        .unwrap();
+        assert!(matches!(
+            verify(&id_token),
+            Err(TodosError::InvalidIdToken(_))
+        ));
+    }
+}
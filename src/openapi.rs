@@ -0,0 +1,99 @@
+use serde::{Deserialize, Serialize};
+use utoipa::{
+    openapi::{
+        security::{
+            AuthorizationCode, Flow, HttpAuthScheme, HttpBuilder, OAuth2, Scopes, SecurityScheme,
+        },
+        OpenApi,
+    },
+    Modify, ToSchema,
+};
+
+/// The body of every error response.
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct MessageResponse {
+    pub message: String,
+}
+
+/// The body of `400` responses to imports that reject some of their lines.
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct ImportErrorResponse {
+    pub message: String,
+    pub errors: Vec<crate::models::LineError>,
+}
+
+/// What `POST /graphql` accepts; mirrors `juniper::http::GraphQLRequest`.
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GraphQLRequestBody {
+    pub query: String,
+    pub operation_name: Option<String>,
+    #[schema(value_type = Option<Object>)]
+    pub variables: Option<serde_json::Value>,
+}
+
+/// Registers the `jwt` and `oauth2` security schemes that authenticated paths
+/// refer to.
+pub struct SecurityAddon;
+
+impl Modify for SecurityAddon {
+    fn modify(&self, openapi: &mut OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "jwt",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .description(Some(
+                        "The token returned by `POST /users` or `POST /login`, as `Authorization: Bearer <token>`.",
+                    ))
+                    .build(),
+            ),
+        );
+        let scopes = crate::oauth::Scope::ALL
+            .iter()
+            .map(|scope| (scope.as_str(), scope.description()))
+            .collect::<Scopes>();
+        components.add_security_scheme(
+            "oauth2",
+            SecurityScheme::OAuth2(OAuth2::new([Flow::AuthorizationCode(
+                AuthorizationCode::new("/oauth/authorize", "/oauth/token", scopes),
+            )])),
+        );
+    }
+}
+
+/// Swagger UI page pointed at `/openapi.json`.
+pub const SWAGGER_UI_HTML: &str = r##"<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta charset="utf-8" />
+    <title>Todos API</title>
+    <link rel="stylesheet" href="/docs/swagger-ui.css" />
+  </head>
+  <body>
+    <div id="swagger-ui"></div>
+    <script src="/docs/swagger-ui-bundle.js"></script>
+    <script>
+      window.ui = SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" });
+    </script>
+  </body>
+</html>
+"##;
+
+/// The files `SWAGGER_UI_HTML` loads from `/docs/{asset}`, by name, with
+/// their content type. Vendored from swagger-ui-dist 5.17.14 so the docs work
+/// without reaching a CDN.
+pub const SWAGGER_UI_ASSETS: &[(&str, &str, &[u8])] = &[
+    (
+        "swagger-ui.css",
+        "text/css; charset=utf-8",
+        include_bytes!("../static/swagger-ui/swagger-ui.css"),
+    ),
+    (
+        "swagger-ui-bundle.js",
+        "text/javascript; charset=utf-8",
+        include_bytes!("../static/swagger-ui/swagger-ui-bundle.js"),
+    ),
+];
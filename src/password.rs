@@ -0,0 +1,149 @@
+//! Argon2 password hashing with parameters from the environment:
+//!
+//! - `ARGON2_VARIANT`: `argon2id` (the default), `argon2i` or `argon2d`
+//! - `ARGON2_MEMORY_KIB`, `ARGON2_ITERATIONS` and `ARGON2_PARALLELISM`: the
+//!   costs, by default the `argon2` crate's (4096 KiB, 3 and 1)
+//! - `ARGON2_PEPPER`: an optional secret mixed into every hash, and
+//!   `ARGON2_PEPPER_ID` (`1` by default) to tell hashes made with it apart
+//!
+//! Hashes record the parameters they were made with, so changing them only
+//! affects new hashes; older ones are replaced as their users log in.
+
+use std::convert::TryFrom;
+
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Algorithm, Argon2, Params, ParamsBuilder, Version,
+};
+
+use crate::error::TodosError;
+
+fn env_cost(name: &str, default: u32) -> u32 {
+    std::env::var(name)
+        .map(|cost| cost.parse().expect(name))
+        .unwrap_or(default)
+}
+
+fn algorithm() -> Algorithm {
+    std::env::var("ARGON2_VARIANT")
+        .map(|variant| variant.parse().expect("ARGON2_VARIANT"))
+        .unwrap_or_default()
+}
+
+/// The pepper and the id new hashes are tagged with, if there is one.
+fn pepper() -> Option<(Vec<u8>, String)> {
+    let pepper = std::env::var("ARGON2_PEPPER")
+        .ok()
+        .filter(|pepper| !pepper.is_empty())?;
+    let id = std::env::var("ARGON2_PEPPER_ID").unwrap_or_else(|_| "1".to_owned());
+    Some((pepper.into_bytes(), id))
+}
+
+fn params(pepper_id: Option<&str>) -> Params {
+    let mut builder = ParamsBuilder::new();
+    builder
+        .m_cost(env_cost("ARGON2_MEMORY_KIB", Params::DEFAULT_M_COST))
+        .and_then(|builder| builder.t_cost(env_cost("ARGON2_ITERATIONS", Params::DEFAULT_T_COST)))
+        .and_then(|builder| builder.p_cost(env_cost("ARGON2_PARALLELISM", Params::DEFAULT_P_COST)))
+        .expect("ARGON2_MEMORY_KIB, ARGON2_ITERATIONS or ARGON2_PARALLELISM is out of range.");
+    if let Some(id) = pepper_id {
+        builder
+            .keyid(id.as_bytes())
+            .expect("ARGON2_PEPPER_ID is longer than 8 bytes.");
+    }
+    builder.params().expect("Invalid Argon2 parameters.")
+}
+
+pub fn hash(pass: &str) -> String {
+    let salt = SaltString::generate(&mut rand_core::OsRng);
+    let pepper = pepper();
+    let params = params(pepper.as_ref().map(|(_, id)| id.as_str()));
+    let argon2 = match &pepper {
+        Some((secret, _)) => Argon2::new_with_secret(secret, algorithm(), Version::V0x13, params)
+            .expect("ARGON2_PEPPER is too long."),
+        None => Argon2::new(algorithm(), Version::V0x13, params),
+    };
+    argon2
+        .hash_password(pass.as_bytes(), &salt)
+        .unwrap()
+        .to_string()
+}
+
+/// Checks a password against a stored hash, with the pepper the hash was
+/// tagged with. Malformed hashes match no password; hashes tagged with
+/// another pepper than the current one can't be checked at all.
+pub fn verify(hash: &str, pass: &str) -> Result<bool, TodosError> {
+    let parsed_hash = match PasswordHash::new(hash) {
+        Ok(parsed_hash) => parsed_hash,
+        Err(_) => return Ok(false),
+    };
+    let keyid = Params::try_from(&parsed_hash)
+        .map(|params| params.keyid().to_vec())
+        .unwrap_or_default();
+    let verified = if keyid.is_empty() {
+        Argon2::default().verify_password(pass.as_bytes(), &parsed_hash)
+    } else {
+        match pepper() {
+            Some((secret, id)) if id.as_bytes() == keyid.as_slice() => Argon2::new_with_secret(
+                &secret,
+                Algorithm::default(),
+                Version::default(),
+                Params::default(),
+            )
+            .expect("ARGON2_PEPPER is too long.")
+            .verify_password(pass.as_bytes(), &parsed_hash),
+            _ => {
+                return Err(TodosError::UnknownPepper(
+                    String::from_utf8_lossy(&keyid).into_owned(),
+                ))
+            }
+        }
+    };
+    Ok(verified.is_ok())
+}
+
+/// Whether a hash was made with other parameters or another pepper than new
+/// ones would be.
+pub fn needs_rehash(hash: &str) -> bool {
+    let parsed_hash = match PasswordHash::new(hash) {
+        Ok(parsed_hash) => parsed_hash,
+        Err(_) => return true,
+    };
+    let stored = match Params::try_from(&parsed_hash) {
+        Ok(stored) => stored,
+        Err(_) => return true,
+    };
+    let pepper = pepper();
+    let current = params(pepper.as_ref().map(|(_, id)| id.as_str()));
+    parsed_hash.algorithm != algorithm().ident()
+        || parsed_hash.version != Some(Version::V0x13.into())
+        || stored.m_cost() != current.m_cost()
+        || stored.t_cost() != current.t_cost()
+        || stored.p_cost() != current.p_cost()
+        || stored.keyid() != current.keyid()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn malformed_hashes_match_no_password() {
+        for hash in &["", "plaintext", "$argon2id$v=19$m=4096"] {
+            assert!(matches!(verify(hash, "plaintext"), Ok(false)), "{:?}", hash);
+        }
+    }
+
+    #[test]
+    fn checks_passwords_against_their_hash() {
+        let hashed = hash("Correct-horse-battery-9");
+        assert!(matches!(
+            verify(&hashed, "Correct-horse-battery-9"),
+            Ok(true)
+        ));
+        assert!(matches!(
+            verify(&hashed, "correct-horse-battery-9"),
+            Ok(false)
+        ));
+    }
+}
@@ -0,0 +1,86 @@
+//! Roles and the permissions they grant. Every account has one role:
+//! `user`, which grants nothing beyond using one's own account, `admin`,
+//! which grants everything, or a custom role made at `POST /admin/roles`.
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// The role new accounts get.
+pub const DEFAULT_ROLE: &str = "user";
+pub const ADMIN_ROLE: &str = "admin";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
+pub enum Permission {
+    /// Listing and searching users, and their usage statistics.
+    #[serde(rename = "users:read")]
+    UsersRead,
+    /// Disabling and enabling accounts, forcing password resets and revoking
+    /// sessions.
+    #[serde(rename = "users:manage")]
+    UsersManage,
+    /// Creating and deleting roles, and giving users roles.
+    #[serde(rename = "roles:manage")]
+    RolesManage,
+    /// Reading everyone's security log.
+    #[serde(rename = "audit:read")]
+    AuditRead,
+}
+
+impl Permission {
+    pub const ALL: &'static [Permission] = &[
+        Permission::UsersRead,
+        Permission::UsersManage,
+        Permission::RolesManage,
+        Permission::AuditRead,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::UsersRead => "users:read",
+            Self::UsersManage => "users:manage",
+            Self::RolesManage => "roles:manage",
+            Self::AuditRead => "audit:read",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Permission> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|permission| permission.as_str() == name)
+    }
+}
+
+/// A permission an `auth::Authorized` extractor requires, as a type.
+pub trait RequiredPermission {
+    const PERMISSION: Permission;
+}
+
+pub struct UsersRead;
+pub struct UsersManage;
+pub struct RolesManage;
+pub struct AuditRead;
+
+impl RequiredPermission for UsersRead {
+    const PERMISSION: Permission = Permission::UsersRead;
+}
+
+impl RequiredPermission for UsersManage {
+    const PERMISSION: Permission = Permission::UsersManage;
+}
+
+impl RequiredPermission for RolesManage {
+    const PERMISSION: Permission = Permission::RolesManage;
+}
+
+impl RequiredPermission for AuditRead {
+    const PERMISSION: Permission = Permission::AuditRead;
+}
+
+/// Whether `name` can name a role: lowercase letters, digits, `-` and `_`.
+pub fn is_valid_role_name(name: &str) -> bool {
+    (1..=32).contains(&name.len())
+        && name
+            .bytes()
+            .all(|byte| byte.is_ascii_lowercase() || byte.is_ascii_digit() || b"-_".contains(&byte))
+}
@@ -1,9 +1,182 @@
+table! {
+    audit_events (id) {
+        id -> Int8,
+        occurred_at -> Timestamp,
+        event -> Varchar,
+        user_id -> Nullable<Int4>,
+        username -> Nullable<Varchar>,
+        actor_id -> Nullable<Int4>,
+        ip -> Nullable<Varchar>,
+        user_agent -> Nullable<Varchar>,
+        details -> Nullable<Varchar>,
+    }
+}
+
+table! {
+    email_verification_tokens (id) {
+        id -> Int4,
+        user_id -> Int4,
+        token_hash -> Varchar,
+        email -> Varchar,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    import_jobs (id) {
+        id -> Int4,
+        user_id -> Int4,
+        provider -> Varchar,
+        status -> Varchar,
+        created_count -> Int4,
+        error -> Nullable<Text>,
+        created_at -> Timestamp,
+        finished_at -> Nullable<Timestamp>,
+        workspace_id -> Nullable<Int4>,
+    }
+}
+
+table! {
+    login_throttles (scope, subject) {
+        scope -> Varchar,
+        subject -> Varchar,
+        failures -> Int4,
+        last_failed_at -> Timestamp,
+        locked_until -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    oauth_authorization_codes (id) {
+        id -> Int4,
+        code_hash -> Varchar,
+        oauth_client_id -> Int4,
+        user_id -> Int4,
+        redirect_uri -> Varchar,
+        scope -> Varchar,
+        code_challenge -> Varchar,
+        expires_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+        redirect_uri_sent -> Bool,
+    }
+}
+
+table! {
+    oauth_clients (id) {
+        id -> Int4,
+        client_id -> Varchar,
+        client_secret_hash -> Nullable<Varchar>,
+        name -> Varchar,
+        redirect_uris -> Array<Text>,
+        user_id -> Int4,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    oauth_tokens (id) {
+        id -> Int4,
+        oauth_client_id -> Int4,
+        user_id -> Int4,
+        authorization_code_id -> Nullable<Int4>,
+        access_token_hash -> Varchar,
+        refresh_token_hash -> Varchar,
+        scope -> Varchar,
+        access_expires_at -> Timestamp,
+        refresh_expires_at -> Timestamp,
+        created_at -> Timestamp,
+        revoked_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    oidc_identities (id) {
+        id -> Int4,
+        user_id -> Int4,
+        issuer -> Varchar,
+        subject -> Varchar,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    oidc_login_states (state_hash) {
+        state_hash -> Varchar,
+        nonce -> Varchar,
+        code_verifier -> Varchar,
+        expires_at -> Timestamp,
+    }
+}
+
+table! {
+    password_reset_requests (id) {
+        id -> Int4,
+        user_id -> Nullable<Int4>,
+        ip -> Nullable<Varchar>,
+        requested_at -> Timestamp,
+    }
+}
+
+table! {
+    password_reset_tokens (id) {
+        id -> Int4,
+        user_id -> Int4,
+        token_hash -> Varchar,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    roles (name) {
+        name -> Varchar,
+        description -> Text,
+        permissions -> Array<Text>,
+        built_in -> Bool,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    sessions (id) {
+        id -> Int4,
+        user_id -> Int4,
+        created_at -> Timestamp,
+        last_seen_at -> Timestamp,
+        expires_at -> Timestamp,
+        ip -> Nullable<Varchar>,
+        user_agent -> Nullable<Varchar>,
+        client_name -> Nullable<Varchar>,
+        revoked_at -> Nullable<Timestamp>,
+    }
+}
+
 table! {
     todos (id) {
         id -> Int4,
         text -> Varchar,
         done -> Bool,
+        user_id -> Nullable<Int4>,
+        ical_uid -> Varchar,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        completed_at -> Nullable<Timestamp>,
+        priority -> Nullable<Varchar>,
+        workspace_id -> Nullable<Int4>,
+        projects -> Array<Text>,
+        contexts -> Array<Text>,
+    }
+}
+
+table! {
+    totp_recovery_codes (id) {
+        id -> Int4,
         user_id -> Int4,
+        code_hash -> Varchar,
+        used_at -> Nullable<Timestamp>,
     }
 }
 
@@ -12,7 +185,100 @@ table! {
         id -> Int4,
         username -> Varchar,
         password -> Varchar,
+        calendar_token_hash -> Nullable<Varchar>,
+        deleted_at -> Nullable<Timestamp>,
+        email -> Nullable<Varchar>,
+        password_changed_at -> Nullable<Timestamp>,
+        email_verified_at -> Nullable<Timestamp>,
+        totp_secret -> Nullable<Varchar>,
+        totp_enabled_at -> Nullable<Timestamp>,
+        totp_last_step -> Nullable<Int8>,
+        role -> Varchar,
+        disabled_at -> Nullable<Timestamp>,
+        sessions_revoked_at -> Nullable<Timestamp>,
+        password_reset_required -> Bool,
+        display_name -> Nullable<Varchar>,
+        avatar_url -> Nullable<Varchar>,
+        time_zone -> Nullable<Varchar>,
+        locale -> Nullable<Varchar>,
+        default_sort -> Varchar,
+        week_start -> Varchar,
+        caldav_password_hash -> Nullable<Varchar>,
+    }
+}
+
+table! {
+    workspace_invitations (id) {
+        id -> Int4,
+        workspace_id -> Int4,
+        token_hash -> Varchar,
+        email -> Varchar,
+        role -> Varchar,
+        invited_by -> Int4,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        accepted_at -> Nullable<Timestamp>,
     }
 }
 
-allow_tables_to_appear_in_same_query!(todos, users,);
+table! {
+    workspace_members (workspace_id, user_id) {
+        workspace_id -> Int4,
+        user_id -> Int4,
+        role -> Varchar,
+        joined_at -> Timestamp,
+    }
+}
+
+table! {
+    workspaces (id) {
+        id -> Int4,
+        name -> Varchar,
+        slug -> Varchar,
+        created_at -> Timestamp,
+    }
+}
+
+joinable!(email_verification_tokens -> users (user_id));
+joinable!(import_jobs -> users (user_id));
+joinable!(import_jobs -> workspaces (workspace_id));
+joinable!(oauth_authorization_codes -> oauth_clients (oauth_client_id));
+joinable!(oauth_authorization_codes -> users (user_id));
+joinable!(oauth_clients -> users (user_id));
+joinable!(oauth_tokens -> oauth_authorization_codes (authorization_code_id));
+joinable!(oauth_tokens -> oauth_clients (oauth_client_id));
+joinable!(oauth_tokens -> users (user_id));
+joinable!(oidc_identities -> users (user_id));
+joinable!(password_reset_requests -> users (user_id));
+joinable!(password_reset_tokens -> users (user_id));
+joinable!(sessions -> users (user_id));
+joinable!(todos -> users (user_id));
+joinable!(todos -> workspaces (workspace_id));
+joinable!(totp_recovery_codes -> users (user_id));
+joinable!(users -> roles (role));
+joinable!(workspace_invitations -> users (invited_by));
+joinable!(workspace_invitations -> workspaces (workspace_id));
+joinable!(workspace_members -> users (user_id));
+joinable!(workspace_members -> workspaces (workspace_id));
+
+allow_tables_to_appear_in_same_query!(
+    audit_events,
+    email_verification_tokens,
+    import_jobs,
+    login_throttles,
+    oauth_authorization_codes,
+    oauth_clients,
+    oauth_tokens,
+    oidc_identities,
+    oidc_login_states,
+    password_reset_requests,
+    password_reset_tokens,
+    roles,
+    sessions,
+    todos,
+    totp_recovery_codes,
+    users,
+    workspace_invitations,
+    workspace_members,
+    workspaces,
+);
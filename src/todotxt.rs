@@ -0,0 +1,275 @@
+//! Reading and writing the `todo.txt` format (see
+//! <https://github.com/todotxt/todo.txt>). `+project` and `@context` tokens are
+//! part of a task's description and therefore stay in the todo's text; every
+//! write of a todo's text also stores them as its `projects` and `contexts`.
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{error::TodosError, models};
+
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct Task {
+    pub done: bool,
+    pub priority: Option<String>,
+    pub completion_date: Option<NaiveDate>,
+    pub creation_date: Option<NaiveDate>,
+    pub description: String,
+    pub projects: Vec<String>,
+    pub contexts: Vec<String>,
+}
+
+impl Task {
+    pub fn into_new_todo(self) -> models::NewTodoReq {
+        models::NewTodoReq {
+            text: self.description,
+            done: Some(self.done),
+            priority: self.priority,
+            created_at: self.creation_date.map(|date| date.and_hms(0, 0, 0)),
+            completed_at: self.completion_date.map(|date| date.and_hms(0, 0, 0)),
+        }
+    }
+}
+
+/// Priorities are single uppercase letters, `A` being the highest.
+pub fn is_valid_priority(priority: &str) -> bool {
+    matches!(priority.as_bytes(), [b'A'..=b'Z'])
+}
+
+fn parse_priority(token: &str) -> Option<String> {
+    token
+        .strip_prefix('(')
+        .and_then(|token| token.strip_suffix(')'))
+        .filter(|priority| is_valid_priority(priority))
+        .map(str::to_owned)
+}
+
+fn split_token(rest: &str) -> (&str, &str) {
+    match rest.split_once(' ') {
+        Some((token, tail)) => (token, tail.trim_start()),
+        None => (rest, ""),
+    }
+}
+
+fn parse_date(token: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(token, DATE_FORMAT).ok()
+}
+
+fn tags(description: &str, sigil: char) -> Vec<String> {
+    description
+        .split_whitespace()
+        .filter_map(|word| word.strip_prefix(sigil))
+        .filter(|tag| !tag.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// The `+project` tags of a description, in order.
+pub fn projects(description: &str) -> Vec<String> {
+    tags(description, '+')
+}
+
+/// The `@context` tags of a description, in order.
+pub fn contexts(description: &str) -> Vec<String> {
+    tags(description, '@')
+}
+
+/// Parses one line; `None` for blank lines.
+pub fn parse_line(line: &str) -> Option<Task> {
+    let mut rest = line.trim();
+    if rest.is_empty() {
+        return None;
+    }
+    let mut done = false;
+    let mut priority = None;
+    let mut completion_date = None;
+    let mut creation_date = None;
+
+    let (token, tail) = split_token(rest);
+    if token == "x" {
+        done = true;
+        rest = tail;
+        let (token, tail) = split_token(rest);
+        if let Some(date) = parse_date(token) {
+            completion_date = Some(date);
+            rest = tail;
+        }
+    } else if let Some(p) = parse_priority(token) {
+        priority = Some(p);
+        rest = tail;
+    }
+    let (token, tail) = split_token(rest);
+    if let Some(date) = parse_date(token) {
+        creation_date = Some(date);
+        rest = tail;
+    }
+
+    // Completed tasks keep their priority as a `pri:` tag at the end.
+    let mut description = rest;
+    if let Some((head, p)) = rest.rsplit_once(" pri:") {
+        if done && priority.is_none() && is_valid_priority(p) {
+            priority = Some(p.to_owned());
+            description = head;
+        }
+    }
+    let description = description.trim().to_owned();
+
+    Some(Task {
+        done,
+        priority,
+        completion_date,
+        creation_date,
+        projects: projects(&description),
+        contexts: contexts(&description),
+        description,
+    })
+}
+
+/// Parses a whole file. Fails on the first line that has no description,
+/// reporting its 1-based line number.
+pub fn parse(input: &str) -> Result<Vec<Task>, TodosError> {
+    let mut tasks = Vec::new();
+    for (index, line) in input.lines().enumerate() {
+        if let Some(task) = parse_line(line) {
+            if task.description.is_empty() {
+                return Err(TodosError::InvalidTodoTxt(index + 1));
+            }
+            tasks.push(task);
+        }
+    }
+    Ok(tasks)
+}
+
+pub fn format_todo(todo: &models::Todo) -> String {
+    let mut line = String::new();
+    if todo.done {
+        line.push_str("x ");
+        let completed_at = todo.completed_at.unwrap_or(todo.updated_at);
+        line.push_str(&format!("{} ", completed_at.format(DATE_FORMAT)));
+    } else if let Some(priority) = &todo.priority {
+        line.push_str(&format!("({}) ", priority));
+    }
+    line.push_str(&format!("{} ", todo.created_at.format(DATE_FORMAT)));
+    line.push_str(&todo.text.replace('\n', " "));
+    if let (true, Some(priority)) = (todo.done, &todo.priority) {
+        line.push_str(&format!(" pri:{}", priority));
+    }
+    line
+}
+
+pub fn format(todos: &[models::Todo]) -> String {
+    todos.iter().map(|todo| format_todo(todo) + "\n").collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn todo(text: &str, done: bool, priority: Option<&str>) -> models::Todo {
+        let created_at = NaiveDate::from_ymd(2026, 10, 1).and_hms(8, 0, 0);
+        let updated_at = NaiveDate::from_ymd(2026, 10, 18).and_hms(9, 30, 0);
+        models::Todo {
+            id: 1,
+            text: text.to_owned(),
+            done,
+            user_id: Some(1),
+            ical_uid: "uid-1@todos".to_owned(),
+            created_at,
+            updated_at,
+            completed_at: if done { Some(updated_at) } else { None },
+            priority: priority.map(str::to_owned),
+            workspace_id: None,
+            projects: projects(text),
+            contexts: contexts(text),
+        }
+    }
+
+    /// `format_todo` followed by `parse` gives back everything the line holds.
+    fn assert_round_trip(todo: &models::Todo) {
+        let line = format_todo(todo);
+        let tasks = parse(&line).unwrap();
+        assert_eq!(
+            tasks,
+            vec![Task {
+                done: todo.done,
+                priority: todo.priority.clone(),
+                completion_date: todo.completed_at.map(|date| date.date()),
+                creation_date: Some(todo.created_at.date()),
+                description: todo.text.clone(),
+                projects: todo.projects.clone(),
+                contexts: todo.contexts.clone(),
+            }],
+            "{:?}",
+            line
+        );
+    }
+
+    #[test]
+    fn formats_and_parses_back_todos() {
+        for text in &[
+            "Call mom",
+            "Buy milk +groceries @store +errands",
+            "x marks the spot",
+            "x 2026-01-01 is not a completion date",
+            "(A) is not a priority",
+            "2026-01-01 is not a creation date",
+            "Review pri:B in the middle",
+            "Spaces  are   kept",
+        ] {
+            for (done, priority) in &[
+                (false, None),
+                (false, Some("B")),
+                (true, None),
+                (true, Some("C")),
+            ] {
+                assert_round_trip(&todo(text, *done, *priority));
+            }
+        }
+    }
+
+    #[test]
+    fn formats_todos() {
+        assert_eq!(
+            format_todo(&todo("Plan +trip @home", false, Some("A"))),
+            "(A) 2026-10-01 Plan +trip @home"
+        );
+        assert_eq!(
+            format_todo(&todo("Plan +trip", true, Some("A"))),
+            "x 2026-10-18 2026-10-01 Plan +trip pri:A"
+        );
+    }
+
+    #[test]
+    fn parses_lines() {
+        let task = parse_line("x 2026-10-18 2026-10-01 Book +trip @phone pri:B").unwrap();
+        assert!(task.done);
+        assert_eq!(task.priority.as_deref(), Some("B"));
+        assert_eq!(
+            task.completion_date,
+            Some(NaiveDate::from_ymd(2026, 10, 18))
+        );
+        assert_eq!(task.creation_date, Some(NaiveDate::from_ymd(2026, 10, 1)));
+        assert_eq!(task.description, "Book +trip @phone");
+        assert_eq!(task.projects, vec!["trip"]);
+        assert_eq!(task.contexts, vec!["phone"]);
+
+        let task = parse_line("(Z) Water plants + @").unwrap();
+        assert_eq!(task.priority.as_deref(), Some("Z"));
+        assert!(task.projects.is_empty() && task.contexts.is_empty());
+        assert_eq!(parse_line("(a) lowercase").unwrap().priority, None);
+        assert_eq!(parse_line("   "), None);
+    }
+
+    #[test]
+    fn reports_lines_without_description() {
+        assert!(matches!(
+            parse("First\n\nx 2026-10-18\n"),
+            Err(TodosError::InvalidTodoTxt(3))
+        ));
+    }
+}
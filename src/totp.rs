@@ -0,0 +1,208 @@
+//! RFC 6238 time-based one-time passwords with the parameters every
+//! authenticator app supports: HMAC-SHA1, 6 digits and 30 second steps.
+
+use hmac::{Hmac, Mac};
+use qrcode::{render::svg, Color, QrCode};
+use rand_core::RngCore;
+use sha1::Sha1;
+
+const DIGITS: u32 = 6;
+const STEP_SECONDS: i64 = 30;
+/// How many steps a code may be off, to allow for clock drift.
+const SKEW: i64 = 1;
+const ALPHABET: base32::Alphabet = base32::Alphabet::RFC4648 { padding: false };
+
+/// A new random 160-bit secret, base32-encoded as authenticator apps expect.
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; 20];
+    rand_core::OsRng.fill_bytes(&mut bytes);
+    base32::encode(ALPHABET, &bytes)
+}
+
+/// The RFC 4226 HOTP value of a secret for one counter value.
+fn hotp(secret: &[u8], counter: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC takes keys of any size.");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    binary % 10u32.pow(DIGITS)
+}
+
+/// Compares two byte strings in time that only depends on their length.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Checks a code of exactly `DIGITS` digits, spaces aside, against the steps
+/// around `unix_time`, returning the step it matched so that callers can
+/// refuse to accept it twice. Every step is compared, so that the time taken
+/// tells nothing about which one matched.
+pub fn verify(secret: &str, code: &str, unix_time: i64) -> Option<i64> {
+    let secret = base32::decode(ALPHABET, secret)?;
+    let code = code.trim().replace(' ', "");
+    if code.len() != DIGITS as usize || !code.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    let current = unix_time / STEP_SECONDS;
+    let mut matched = None;
+    for step in (current - SKEW..=current + SKEW).filter(|&step| step >= 0) {
+        let expected = format!(
+            "{:0width$}",
+            hotp(&secret, step as u64),
+            width = DIGITS as usize
+        );
+        if constant_time_eq(expected.as_bytes(), code.as_bytes()) && matched.is_none() {
+            matched = Some(step);
+        }
+    }
+    matched
+}
+
+fn encode_component(component: &str) -> String {
+    let mut encoded = String::with_capacity(component.len());
+    for byte in component.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// The `otpauth://` URI that authenticator apps import, usually as a QR code.
+pub fn provisioning_uri(issuer: &str, account: &str, secret: &str) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        encode_component(issuer),
+        encode_component(account),
+        secret,
+        encode_component(issuer),
+        DIGITS,
+        STEP_SECONDS
+    )
+}
+
+pub fn qr_svg(data: &str) -> String {
+    QrCode::new(data.as_bytes())
+        .expect("Provisioning URIs fit into a QR code.")
+        .render::<svg::Color>()
+        .min_dimensions(200, 200)
+        .build()
+}
+
+/// Renders a QR code as a grayscale PNG, scaling every module to a square of
+/// pixels and keeping the quiet zone scanners need around it.
+pub fn qr_png(data: &str) -> Vec<u8> {
+    const SCALE: usize = 8;
+    const QUIET_ZONE: usize = 4;
+    let code = QrCode::new(data.as_bytes()).expect("Provisioning URIs fit into a QR code.");
+    let width = code.width();
+    let colors = code.to_colors();
+    let size = (width + 2 * QUIET_ZONE) * SCALE;
+
+    let mut pixels = vec![0xffu8; size * size];
+    for (index, color) in colors.iter().enumerate() {
+        if *color == Color::Dark {
+            let (x, y) = (index % width + QUIET_ZONE, index / width + QUIET_ZONE);
+            for row in y * SCALE..(y + 1) * SCALE {
+                pixels[row * size + x * SCALE..row * size + (x + 1) * SCALE].fill(0);
+            }
+        }
+    }
+
+    let mut out = Vec::new();
+    let mut encoder = png::Encoder::new(&mut out, size as u32, size as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&pixels))
+        .expect("Writing a PNG to memory can't fail.");
+    out
+}
+
+/// Ten single-use recovery codes such as `k3v9q-x7m2p`.
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..10)
+        .map(|_| {
+            let mut bytes = [0u8; 7];
+            rand_core::OsRng.fill_bytes(&mut bytes);
+            let code = base32::encode(ALPHABET, &bytes).to_lowercase();
+            format!("{}-{}", &code[..5], &code[5..10])
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The SHA-1 seed of RFC 6238 Appendix B, `12345678901234567890`.
+    const SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    /// The SHA-1 vectors of RFC 6238 Appendix B, cut to their last six
+    /// digits.
+    const VECTORS: &[(i64, &str)] = &[
+        (59, "287082"),
+        (1_111_111_109, "081804"),
+        (1_111_111_111, "050471"),
+        (1_234_567_890, "005924"),
+        (2_000_000_000, "279037"),
+        (20_000_000_000, "353130"),
+    ];
+
+    #[test]
+    fn matches_the_rfc_6238_vectors() {
+        for &(time, code) in VECTORS {
+            assert_eq!(
+                verify(SECRET, code, time),
+                Some(time / STEP_SECONDS),
+                "{}",
+                time
+            );
+        }
+    }
+
+    #[test]
+    fn accepts_one_step_of_drift_either_way() {
+        let (time, code) = (1_234_567_890, "005924");
+        let step = time / STEP_SECONDS;
+        for drift in &[-1, 1] {
+            let other_time = time + drift * STEP_SECONDS;
+            assert_eq!(verify(SECRET, code, other_time), Some(step));
+        }
+        for drift in &[-2, 2] {
+            assert_eq!(verify(SECRET, code, time + drift * STEP_SECONDS), None);
+        }
+    }
+
+    #[test]
+    fn requires_exactly_six_ascii_digits() {
+        let time = 1_234_567_890;
+        assert_eq!(verify(SECRET, " 005 924 ", time), Some(time / STEP_SECONDS));
+        for code in &[
+            "5924",
+            "+005924",
+            "0005924",
+            "-05924",
+            "00592a",
+            "００５９２４",
+            "",
+        ] {
+            assert_eq!(verify(SECRET, code, time), None, "{:?}", code);
+        }
+    }
+
+    #[test]
+    fn rejects_secrets_that_are_not_base32() {
+        assert_eq!(verify("not base32!", "005924", 1_234_567_890), None);
+    }
+}